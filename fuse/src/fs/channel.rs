@@ -9,6 +9,7 @@ use {
 	},
 	std::{
 		ffi::OsStr,
+		fmt,
 		os::unix::ffi::OsStrExt,
 		path::Path,
 		time::{Duration, Instant, SystemTime},
@@ -26,12 +27,39 @@ impl FsChannel {
 		self.channel.send_blocking(job).unwrap()
 	}
 
-	pub fn commit(&mut self) -> bool {
+	/// Finish the current transaction and wait for the result.
+	pub fn commit(&mut self) -> Result<(), CommitError> {
 		let when = Instant::now().checked_add(Duration::from_secs(1)).unwrap();
-		self.channel.send_blocking(Job::Sync(when)).is_ok()
+		let (reply, recv) = async_channel::bounded(1);
+		self.channel
+			.send_blocking(Job::Sync(Sync { when, reply }))
+			.map_err(|_| CommitError::Closed)?;
+		recv.recv_blocking()
+			.map_err(|_| CommitError::Closed)?
+			.map_err(CommitError::Failed)
 	}
 }
 
+/// Error returned by [`FsChannel::commit`].
+#[derive(Debug, PartialEq, Eq)]
+pub enum CommitError {
+	/// The filesystem handler has stopped.
+	Closed,
+	/// The transaction could not be finished.
+	Failed(String),
+}
+
+impl fmt::Display for CommitError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			Self::Closed => "filesystem handler stopped".fmt(f),
+			Self::Failed(e) => write!(f, "failed to finish transaction: {}", e),
+		}
+	}
+}
+
+impl std::error::Error for CommitError {}
+
 impl Filesystem for FsChannel {
 	fn init(&mut self, _req: &Request<'_>, config: &mut KernelConfig) -> Result<(), i32> {
 		use fuser::consts::*;
@@ -58,7 +86,7 @@ impl Filesystem for FsChannel {
 		self.send(Job::Lookup(Lookup {
 			parent,
 			name: name.as_bytes().into(),
-			reply,
+			reply: reply.into(),
 		}));
 	}
 
@@ -67,7 +95,7 @@ impl Filesystem for FsChannel {
 	}
 
 	fn getattr(&mut self, _: &Request<'_>, ino: u64, reply: ReplyAttr) {
-		self.send(Job::GetAttr(GetAttr { ino, reply }));
+		self.send(Job::GetAttr(GetAttr { ino, reply: reply.into() }));
 	}
 
 	fn setattr(
@@ -95,7 +123,7 @@ impl Filesystem for FsChannel {
 			gid,
 			size,
 			mtime,
-			reply,
+			reply: reply.into(),
 		}));
 	}
 
@@ -104,7 +132,7 @@ impl Filesystem for FsChannel {
 			ino,
 			name: name.as_bytes().into(),
 			size,
-			reply,
+			reply: reply.into(),
 		}));
 	}
 
@@ -124,19 +152,19 @@ impl Filesystem for FsChannel {
 			value: value.into(),
 			flags,
 			position,
-			reply,
+			reply: reply.into(),
 		}));
 	}
 
 	fn listxattr(&mut self, _: &Request<'_>, ino: u64, size: u32, reply: ReplyXattr) {
-		self.send(Job::ListXAttr(ListXAttr { ino, size, reply }));
+		self.send(Job::ListXAttr(ListXAttr { ino, size, reply: reply.into() }));
 	}
 
 	fn removexattr(&mut self, _: &Request<'_>, ino: u64, name: &OsStr, reply: ReplyEmpty) {
 		self.send(Job::RemoveXAttr(RemoveXAttr {
 			ino,
 			name: name.as_bytes().into(),
-			reply,
+			reply: reply.into(),
 		}));
 	}
 
@@ -151,7 +179,7 @@ impl Filesystem for FsChannel {
 		_lock: Option<u64>,
 		reply: ReplyData,
 	) {
-		self.send(Job::Read(Read { ino, offset, size, reply }));
+		self.send(Job::Read(Read { ino, offset, size, reply: reply.into() }));
 	}
 
 	fn write(
@@ -166,11 +194,16 @@ impl Filesystem for FsChannel {
 		_lock_owner: Option<u64>,
 		reply: ReplyWrite,
 	) {
-		self.send(Job::Write(Write { ino, offset, data: data.into(), reply }));
+		self.send(Job::Write(Write {
+			ino,
+			offset,
+			data: data.into(),
+			reply: reply.into(),
+		}));
 	}
 
 	fn readlink(&mut self, _req: &Request<'_>, ino: u64, reply: ReplyData) {
-		self.send(Job::ReadLink(ReadLink { ino, reply }));
+		self.send(Job::ReadLink(ReadLink { ino, reply: reply.into() }));
 	}
 
 	fn readdir(
@@ -181,7 +214,7 @@ impl Filesystem for FsChannel {
 		offset: i64,
 		reply: ReplyDirectory,
 	) {
-		self.send(Job::ReadDir(ReadDir { ino, offset, reply }));
+		self.send(Job::ReadDir(ReadDir { ino, offset, reply: reply.into() }));
 	}
 
 	fn create(
@@ -200,7 +233,7 @@ impl Filesystem for FsChannel {
 			parent,
 			name: name.as_bytes().into(),
 			mode,
-			reply,
+			reply: reply.into(),
 		}));
 	}
 
@@ -214,7 +247,11 @@ impl Filesystem for FsChannel {
 		_mode: i32,
 		reply: ReplyEmpty,
 	) {
-		self.send(Job::FAllocate(FAllocate { ino, length, reply }));
+		self.send(Job::FAllocate(FAllocate {
+			ino,
+			length,
+			reply: reply.into(),
+		}));
 	}
 
 	fn symlink(
@@ -231,7 +268,7 @@ impl Filesystem for FsChannel {
 			parent,
 			name: name.as_bytes().into(),
 			link: link.as_os_str().as_bytes().into(),
-			reply,
+			reply: reply.into(),
 		}));
 	}
 
@@ -250,7 +287,7 @@ impl Filesystem for FsChannel {
 			parent,
 			name: name.as_bytes().into(),
 			mode,
-			reply,
+			reply: reply.into(),
 		}));
 	}
 
@@ -269,7 +306,7 @@ impl Filesystem for FsChannel {
 			name: name.as_bytes().into(),
 			newparent,
 			newname: newname.as_bytes().into(),
-			reply,
+			reply: reply.into(),
 		}));
 	}
 
@@ -278,7 +315,7 @@ impl Filesystem for FsChannel {
 		self.send(Job::Unlink(Unlink {
 			parent,
 			name: name.as_bytes().into(),
-			reply,
+			reply: reply.into(),
 		}));
 	}
 
@@ -286,7 +323,7 @@ impl Filesystem for FsChannel {
 		self.send(Job::RmDir(RmDir {
 			parent,
 			name: name.as_bytes().into(),
-			reply,
+			reply: reply.into(),
 		}));
 	}
 
@@ -306,7 +343,7 @@ impl Filesystem for FsChannel {
 	}
 
 	fn statfs(&mut self, _: &Request<'_>, _: u64, reply: ReplyStatfs) {
-		self.send(Job::StatFs(StatFs { reply }))
+		self.send(Job::StatFs(StatFs { reply: reply.into() }))
 	}
 
 	fn ioctl(
@@ -326,7 +363,7 @@ impl Filesystem for FsChannel {
 			cmd,
 			in_data: in_data.into(),
 			out_size,
-			reply,
+			reply: reply.into(),
 		}))
 	}
 
@@ -348,7 +385,7 @@ impl Filesystem for FsChannel {
 			mode,
 			umask,
 			rdev,
-			reply,
+			reply: reply.into(),
 		}))
 	}
 
@@ -356,3 +393,24 @@ impl Filesystem for FsChannel {
 		self.send(Job::Destroy);
 	}
 }
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn commit_error() {
+		let (send, recv) = async_channel::bounded(1);
+		let mut channel = FsChannel { channel: send };
+		let handler = std::thread::spawn(move || {
+			let Ok(Job::Sync(sync)) = recv.recv_blocking() else { panic!("expected sync") };
+			sync.reply.try_send(Err("not enough space".into())).unwrap();
+		});
+		assert_eq!(
+			channel.commit(),
+			Err(CommitError::Failed("not enough space".into()))
+		);
+		handler.join().unwrap();
+		assert_eq!(channel.commit(), Err(CommitError::Closed));
+	}
+}
@@ -2,18 +2,27 @@ mod channel;
 mod inode;
 mod ops;
 
-pub use channel::FsChannel;
+pub use channel::{CommitError, FsChannel};
 
 use {
-	crate::{dev::Dev, job::Job},
+	crate::{
+		dev::Dev,
+		job::{AbortHandle, Job},
+	},
 	async_channel::{self, Receiver},
 	fuser::*,
-	futures_util::{FutureExt, StreamExt},
+	futures_util::{
+		future::{self, Either},
+		stream::FuturesUnordered,
+		FutureExt, StreamExt,
+	},
 	inode::InodeStore,
 	nrfs::Nrfs,
 	std::{
 		cell::{Cell, RefCell, RefMut},
 		fs,
+		future::Future,
+		pin::{pin, Pin},
 		time::{Duration, SystemTime, UNIX_EPOCH},
 	},
 	util::task::lock_set::{LockSet, LockSetExclusiveGuard, LockSetInclusiveGuard},
@@ -38,6 +47,8 @@ pub struct Fs {
 	///
 	/// It is a `ì64` for convenience, but it must always be positive!
 	gen_interval: Cell<i64>,
+	/// Maximum time a single operation may take before `EIO` is returned.
+	op_timeout: Option<Duration>,
 }
 
 impl Fs {
@@ -46,6 +57,8 @@ impl Fs {
 		io: impl Iterator<Item = fs::File>,
 		key: Option<[u8; 32]>,
 		cache_size: usize,
+		cache_policy: nrfs::CachePolicy,
+		op_timeout: Option<Duration>,
 	) -> (Self, FsChannel) {
 		let retrieve_key = &mut |use_password| {
 			if let Some(key) = key {
@@ -59,7 +72,13 @@ impl Fs {
 		};
 
 		let devices = io.map(|f| Dev::new(f)).collect();
-		let conf = nrfs::LoadConfig { retrieve_key, devices, cache_size, allow_repair: true };
+		let conf = nrfs::LoadConfig {
+			retrieve_key,
+			devices,
+			cache_size,
+			cache_policy,
+			allow_repair: true,
+		};
 		eprintln!("Mounting filesystem");
 		let fs = Nrfs::load(conf).await.unwrap();
 
@@ -80,6 +99,7 @@ impl Fs {
 				default_mode: permissions,
 				ino_locks: Default::default(),
 				gen_interval: (15 * 60 * 1_000_000).into(),
+				op_timeout,
 			},
 			FsChannel { channel: send },
 		)
@@ -90,10 +110,15 @@ impl Fs {
 		self.fs
 			.run(async {
 				use log::trace;
-				let mut jobs = futures_util::stream::FuturesUnordered::new();
+				let mut jobs = FuturesUnordered::new();
+				let mut stalled = FuturesUnordered::new();
 				loop {
 					let job = futures_util::select_biased! {
-						() = jobs.select_next_some() => continue,
+						r = jobs.select_next_some() => {
+							stalled.extend(r);
+							continue;
+						}
+						() = stalled.select_next_some() => continue,
 						job = self.channel.recv().fuse() => job.unwrap(),
 					};
 					macro_rules! switch {
@@ -103,30 +128,54 @@ impl Fs {
 								Job::FSync(_) | Job::Sync(_) | Job::Destroy => unreachable!(),
 							};
 							trace!("{} start", name);
-							match $job {
-								$(Job::$v(job) => self.$f(job).await,)*
-								Job::FSync(_) | Job::Sync(_) | Job::Destroy => unreachable!(),
+							let abort = $job.abort_handle();
+							let fut = async {
+								match $job {
+									$(Job::$v(job) => self.$f(job).await,)*
+									Job::FSync(_) | Job::Sync(_) | Job::Destroy => unreachable!(),
+								}
+							};
+							let r = run_op(name, self.op_timeout, abort, fut).await;
+							if r.is_none() {
+								trace!("{} end", name);
 							}
-							trace!("{} end", name);
+							r
 						}};
 					}
 					match job {
 						Job::FSync(fsync) => {
 							trace!("fsync");
-							while let Some(()) = jobs.next().await {}
-							self.fs.finish_transaction().await.unwrap();
-							fsync.reply.ok();
+							drain(&mut jobs, &mut stalled).await;
+							match self.fs.finish_transaction().await {
+								Ok(()) => fsync.reply.ok(),
+								Err(nrfs::Error::Nros(nros::Error::NotEnoughSpace)) => {
+									fsync.reply.error(libc::ENOSPC)
+								}
+								Err(e) => {
+									eprintln!("fsync failed: {:?}", e);
+									fsync.reply.error(libc::EIO)
+								}
+							}
 							trace!("fsync end");
 						}
-						Job::Sync(when) => {
+						Job::Sync(sync) => {
 							trace!("sync");
 							let now = std::time::Instant::now();
-							if when >= now {
-								while let Some(()) = jobs.next().await {}
-								self.fs.finish_transaction().await.unwrap();
+							let r = if sync.when >= now {
+								drain(&mut jobs, &mut stalled).await;
+								self.fs
+									.finish_transaction()
+									.await
+									.map_err(|e| e.to_string())
 							} else {
-								eprintln!("Skipping Job::Sync (when: {:?}, now: {:?})", when, now);
-							}
+								eprintln!(
+									"Skipping Job::Sync (when: {:?}, now: {:?})",
+									sync.when, now
+								);
+								Ok(())
+							};
+							// The sender may have stopped waiting, which is fine.
+							let _ = sync.reply.try_send(r);
 							trace!("sync end");
 						}
 						Job::Destroy => break,
@@ -159,7 +208,11 @@ impl Fs {
 						}),
 					}
 				}
-				while let Some(()) = jobs.next().await {}
+				drain(&mut jobs, &mut stalled).await;
+				if !stalled.is_empty() {
+					eprintln!("Abandoning {} operations that timed out", stalled.len());
+				}
+				drop(stalled);
 				self.destroy().await;
 				Ok::<_, nrfs::Error<_>>(())
 			})
@@ -171,19 +224,13 @@ impl Fs {
 
 	/// Convert [`ItemData`] et al. to [`FileAttr`].
 	fn attr(&self, ino: u64, ty: FileType, len: u64, attr: ops::Attrs) -> FileAttr {
-		let mtime = attr.modified.time;
 		let uid = attr.uid.unwrap_or(self.default_uid);
 		let gid = attr.gid.unwrap_or(self.default_gid);
 		let perm = attr.mode.unwrap_or(self.default_mode) & 0o777;
 
-		let mtime = mtime.max(i64::MIN.into()).min(i64::MAX.into());
-		let mtime = i64::try_from(mtime).unwrap();
-		let mtime = if mtime > 0 {
-			UNIX_EPOCH.checked_add(Duration::from_micros(mtime as _))
-		} else {
-			UNIX_EPOCH.checked_sub(Duration::from_micros(-mtime as _))
-		}
-		.unwrap();
+		let mtime = sys_time(attr.modified.time);
+		let ctime = attr.ctime.map_or(UNIX_EPOCH, sys_time);
+		let atime = attr.atime.map_or(UNIX_EPOCH, sys_time);
 
 		let blksize = 1u32 << self.fs.block_size().to_raw();
 
@@ -194,9 +241,9 @@ impl Fs {
 		let blocks = blocks << (self.fs.block_size().to_raw() - 9);
 
 		FileAttr {
-			atime: UNIX_EPOCH,
+			atime,
 			mtime,
-			ctime: UNIX_EPOCH,
+			ctime,
 			crtime: UNIX_EPOCH,
 			perm,
 			nlink: 1,
@@ -263,6 +310,65 @@ impl Fs {
 	}
 }
 
+/// Run a future, aborting it if it does not finish before the given timeout.
+///
+/// Returns `None` if the future was aborted.
+///
+/// If `timeout` is `None` the future always runs to completion.
+async fn deadline<F: Future>(timeout: Option<Duration>, fut: F) -> Option<F::Output> {
+	let Some(timeout) = timeout else { return Some(fut.await) };
+	match future::select(pin!(fut), async_io::Timer::after(timeout)).await {
+		Either::Left((r, _)) => Some(r),
+		Either::Right(_) => None,
+	}
+}
+
+/// An operation that did not finish before its deadline.
+type Stalled<'a> = Pin<Box<dyn Future<Output = ()> + 'a>>;
+
+/// Run an operation, replying with `EIO` if it does not finish before the deadline.
+///
+/// The operation is not dropped but returned if the deadline is exceeded,
+/// as aborting it halfway may leave the filesystem in an inconsistent state.
+/// Any reply it makes after the deadline is ignored.
+async fn run_op<'a>(
+	name: &str,
+	timeout: Option<Duration>,
+	abort: Option<AbortHandle>,
+	fut: impl Future<Output = ()> + 'a,
+) -> Option<Stalled<'a>> {
+	let Some(timeout) = timeout else {
+		fut.await;
+		return None;
+	};
+	let mut fut = Box::pin(fut);
+	if deadline(Some(timeout), fut.as_mut()).await.is_some() {
+		return None;
+	}
+	eprintln!("{} timed out", name);
+	if let Some(abort) = abort {
+		abort(libc::EIO);
+	}
+	Some(fut)
+}
+
+/// Wait for all jobs to finish or exceed their deadline.
+///
+/// Operations that exceed their deadline are moved to `stalled`.
+/// These are polled while waiting but never waited for,
+/// as they may be stuck on a device that doesn't respond.
+async fn drain<'a, F>(jobs: &mut FuturesUnordered<F>, stalled: &mut FuturesUnordered<Stalled<'a>>)
+where
+	F: Future<Output = Option<Stalled<'a>>>,
+{
+	while !jobs.is_empty() {
+		futures_util::select_biased! {
+			r = jobs.select_next_some() => stalled.extend(r),
+			() = stalled.select_next_some() => {}
+		}
+	}
+}
+
 fn mtime_now() -> i64 {
 	mtime_sys(SystemTime::now())
 }
@@ -273,3 +379,116 @@ fn mtime_sys(t: SystemTime) -> i64 {
 		|t| t.as_micros().try_into().unwrap_or(i64::MAX),
 	)
 }
+
+/// Convert a time stamp in microseconds since the UNIX epoch to [`SystemTime`].
+fn sys_time(t: i64) -> SystemTime {
+	if t > 0 {
+		UNIX_EPOCH.checked_add(Duration::from_micros(t as _))
+	} else {
+		UNIX_EPOCH.checked_sub(Duration::from_micros(-t as _))
+	}
+	.unwrap()
+}
+
+#[cfg(test)]
+mod test {
+	use {
+		super::*,
+		crate::job::{ErrorReply, Reply},
+		futures_executor::block_on,
+		std::rc::Rc,
+	};
+
+	#[test]
+	fn deadline_finish() {
+		let r = block_on(deadline(Some(Duration::from_secs(10)), async { 42 }));
+		assert_eq!(r, Some(42));
+	}
+
+	#[test]
+	fn deadline_timeout() {
+		let slow = async_io::Timer::after(Duration::from_secs(10));
+		let r = block_on(deadline(Some(Duration::from_millis(10)), slow));
+		assert!(r.is_none());
+	}
+
+	#[test]
+	fn deadline_none() {
+		let fut = async_io::Timer::after(Duration::from_millis(10));
+		assert!(block_on(deadline(None, fut)).is_some());
+	}
+
+	/// Records the error it is replied with.
+	struct TestReply(Rc<Cell<Option<libc::c_int>>>);
+
+	impl ErrorReply for TestReply {
+		fn error(self, err: libc::c_int) {
+			assert!(self.0.replace(Some(err)).is_none(), "replied twice");
+		}
+	}
+
+	#[test]
+	fn run_op_slow() {
+		let replied = Rc::new(Cell::new(None));
+		let reply = Reply::from(TestReply(replied.clone()));
+		let abort = reply.abort_handle();
+		let finished = Cell::new(false);
+		let slow = async {
+			async_io::Timer::after(Duration::from_millis(50)).await;
+			finished.set(true);
+			reply.error(libc::ENOENT);
+		};
+		let stalled = block_on(run_op(
+			"slow",
+			Some(Duration::from_millis(10)),
+			Some(abort),
+			slow,
+		));
+		assert_eq!(replied.get(), Some(libc::EIO));
+		block_on(stalled.expect("deadline not exceeded"));
+		assert!(finished.get());
+		assert_eq!(replied.get(), Some(libc::EIO));
+	}
+
+	#[test]
+	fn run_op_fast() {
+		let replied = Rc::new(Cell::new(None));
+		let reply = Reply::from(TestReply(replied.clone()));
+		let abort = reply.abort_handle();
+		let fast = async { reply.error(libc::ENOENT) };
+		let stalled = block_on(run_op(
+			"fast",
+			Some(Duration::from_secs(10)),
+			Some(abort),
+			fast,
+		));
+		assert!(stalled.is_none());
+		assert_eq!(replied.get(), Some(libc::ENOENT));
+	}
+
+	/// Syncing must not wait for an operation stuck on a device read that never completes.
+	#[test]
+	fn drain_stuck_read() {
+		let replied = Rc::new(Cell::new(None));
+		let reply = Reply::from(TestReply(replied.clone()));
+		let abort = reply.abort_handle();
+		let stuck = async {
+			future::pending::<()>().await;
+			reply.error(libc::ENOENT);
+		};
+		let finished = Cell::new(false);
+		let fast = async { finished.set(true) };
+
+		let timeout = Some(Duration::from_millis(10));
+		let mut jobs = FuturesUnordered::new();
+		jobs.push(run_op("stuck", timeout, Some(abort), stuck.boxed_local()));
+		jobs.push(run_op("fast", timeout, None, fast.boxed_local()));
+		let mut stalled = FuturesUnordered::new();
+		block_on(drain(&mut jobs, &mut stalled));
+
+		assert!(jobs.is_empty());
+		assert_eq!(stalled.len(), 1);
+		assert!(finished.get());
+		assert_eq!(replied.get(), Some(libc::EIO));
+	}
+}
@@ -11,7 +11,7 @@ impl Fs {
 		};
 
 		match dir.create_file(name).await.unwrap() {
-			Ok(f) => {
+			Ok((f, _)) => {
 				let attrs = self
 					.init_attrs(&f, job.uid, job.gid, Some(job.mode as u16 & 0o777))
 					.await;
@@ -23,6 +23,7 @@ impl Fs {
 			}
 			Err(CreateError::Duplicate) => job.reply.error(libc::EEXIST),
 			Err(CreateError::Full) => job.reply.error(libc::ENOSPC),
+			Err(CreateError::Occupied) | Err(CreateError::TooDeep) => unreachable!(),
 		}
 	}
 }
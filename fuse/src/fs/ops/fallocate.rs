@@ -7,10 +7,12 @@ impl Fs {
 			Get::Key(Key::Dir(_), ..) => job.reply.error(libc::EISDIR),
 			Get::Key(Key::File(f), ..) => {
 				let f = self.fs.file(f);
-				if f.resize(job.length as _).await.unwrap().is_err() {
-					job.reply.error(libc::EFBIG)
-				} else {
-					job.reply.ok()
+				match f.resize(job.length as _).await {
+					Err(nrfs::Error::QuotaExceeded) => job.reply.error(libc::EDQUOT),
+					r => match r.unwrap() {
+						Ok(()) => job.reply.ok(),
+						Err(_) => job.reply.error(libc::EFBIG),
+					},
 				}
 			}
 			// TODO which error should we return?
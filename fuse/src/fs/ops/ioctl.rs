@@ -1,6 +1,10 @@
 use super::*;
 
 const SET_GEN_INTERVAL: u32 = 1;
+/// Write the modified data in a range of a file, like `sync_file_range`.
+///
+/// Takes the offset and length as two little-endian `u64`s.
+const SYNC_RANGE: u32 = 2;
 
 // <asm/ioctl.h>
 const _IOC_NRBITS: u8 = 8;
@@ -46,6 +50,23 @@ impl Fs {
 				self.gen_interval.set(t);
 				job.reply.ioctl(0, &[]);
 			}
+			SYNC_RANGE => {
+				let Ok(r) = <[u8; 16]>::try_from(&*job.in_data) else {
+					return job.reply.error(libc::EINVAL);
+				};
+				let offset = u64::from_le_bytes(r[..8].try_into().unwrap());
+				let len = u64::from_le_bytes(r[8..].try_into().unwrap());
+				let _lock = self.lock(job.ino).await;
+				let f = match self.ino().get(job.ino).unwrap() {
+					Get::Key(Key::File(f), ..) => self.fs.file(f),
+					Get::Key(..) => return job.reply.error(libc::EINVAL),
+					Get::Stale => return job.reply.error(libc::ESTALE),
+				};
+				if f.sync_range(offset, len).await.is_err() {
+					return job.reply.error(libc::EIO);
+				}
+				job.reply.ioctl(0, &[]);
+			}
 			_ => job.reply.error(libc::EINVAL),
 		}
 	}
@@ -27,10 +27,8 @@ impl Fs {
 			ItemTy::File | ItemTy::EmbedFile => (FileType::RegularFile, Key::File(item.key)),
 			ItemTy::Sym | ItemTy::EmbedSym => (FileType::Symlink, Key::Sym(item.key)),
 		};
-		let m = self.fs.item(item.key).modified().await.unwrap();
-		let ino = self.ino().add(key, job.parent, m.gen);
-
 		let attrs = get_attrs(&self.fs.item(item.key)).await;
+		let ino = self.ino().add(key, job.parent, attrs.modified.gen);
 		job.reply.entry(&TTL, &self.attr(ino, ty, len, attrs), 0)
 	}
 }
@@ -22,6 +22,8 @@ impl Fs {
 			}
 			Err(CreateError::Duplicate) => job.reply.error(libc::EEXIST),
 			Err(CreateError::Full) => job.reply.error(libc::ENOSPC),
+			Err(CreateError::Occupied) => unreachable!(),
+			Err(CreateError::TooDeep) => job.reply.error(libc::EMLINK),
 		}
 	}
 }
@@ -28,7 +28,7 @@ use {
 		mtime_now, mtime_sys, Dev, Fs, TTL,
 	},
 	fuser::{FileType, TimeOrNow},
-	nrfs::{CreateError, Item, ItemTy, Modified},
+	nrfs::{CreateError, Item, ItemTy, Modified, Nrfs},
 	std::os::unix::ffi::OsStrExt,
 	util::task::lock_set::{LockSetExclusiveGuard, LockSetInclusiveGuard},
 };
@@ -54,6 +54,8 @@ pub(super) struct Attrs {
 	pub uid: Option<libc::uid_t>,
 	pub gid: Option<libc::gid_t>,
 	pub mode: Option<u16>,
+	pub ctime: Option<i64>,
+	pub atime: Option<i64>,
 }
 
 macro_rules! attr {
@@ -75,6 +77,11 @@ async fn set_mtime(item: &Item<'_, Dev>, mtime: i64) {
 	item.set_modified_time(mtime).await.unwrap();
 }
 
+async fn set_ctime(item: &Item<'_, Dev>, ctime: i64) {
+	// The root directory cannot have attributes.
+	let _ = item.set_ctime(ctime).await.unwrap();
+}
+
 impl Fs {
 	async fn init_attrs(
 		&self,
@@ -86,12 +93,25 @@ impl Fs {
 		let modified = Modified { time: mtime_now(), gen: self.gen() };
 		let mtime = mtime_now();
 		set_mtime(item, mtime).await;
-		set_uid(item, uid).await;
-		set_gid(item, gid).await;
-		if let Some(mode) = mode {
-			set_mode(item, mode).await;
+		let (uid_b, gid_b, ctime_b) = (uid.to_le_bytes(), gid.to_le_bytes(), mtime.to_le_bytes());
+		let mode_b = mode.map(u16::to_le_bytes);
+		let mut attrs: Vec<(&nrfs::Key, &[u8])> = vec![
+			(b"nrfs.uid".into(), encode_u(&uid_b)),
+			(b"nrfs.gid".into(), encode_u(&gid_b)),
+			(b"nrfs.ctime".into(), &ctime_b[..]),
+		];
+		if let Some(mode_b) = &mode_b {
+			attrs.push((b"nrfs.unixmode".into(), encode_u(mode_b)));
+		}
+		item.set_attrs(&attrs).await.unwrap().unwrap();
+		Attrs {
+			modified,
+			uid: Some(uid),
+			gid: Some(gid),
+			mode,
+			ctime: Some(mtime),
+			atime: None,
 		}
-		Attrs { modified, uid: Some(uid), gid: Some(gid), mode }
 	}
 
 	async fn dir(
@@ -121,18 +141,46 @@ impl Fs {
 	}
 }
 
-async fn get_u(item: &Item<'_, Dev>, key: &nrfs::Key) -> Option<u128> {
-	item.attr(key).await.unwrap().map(|b| decode_u(&b))
-}
-
 async fn get_attrs(item: &Item<'_, Dev>) -> Attrs {
+	let keys: [&nrfs::Key; 5] = [
+		b"nrfs.uid".into(),
+		b"nrfs.gid".into(),
+		b"nrfs.unixmode".into(),
+		b"nrfs.ctime".into(),
+		b"nrfs.atime".into(),
+	];
+	let values = item.attrs(&keys).await.unwrap();
+	let [uid, gid, mode, ctime, atime] = <[_; 5]>::try_from(values).unwrap();
+	let u = |v: Option<Vec<u8>>| v.map(|b| decode_u(&b));
 	let f = |n: u128| n.try_into().unwrap_or(0);
 	let g = |n: u128| n.try_into().unwrap_or(0);
+	// Times with an unexpected length are treated as absent, like Item::ctime.
+	let t = |v: Option<Vec<u8>>| v.and_then(|b| b.try_into().ok()).map(i64::from_le_bytes);
 	Attrs {
 		modified: item.modified().await.unwrap(),
-		uid: get_u(item, b"nrfs.uid".into()).await.map(f),
-		gid: get_u(item, b"nrfs.gid".into()).await.map(f),
-		mode: get_u(item, b"nrfs.unixmode".into()).await.map(g),
+		uid: u(uid).map(f),
+		gid: u(gid).map(f),
+		mode: u(mode).map(g),
+		ctime: t(ctime),
+		atime: t(atime),
+	}
+}
+
+/// Update the access time of an item after its data has been read.
+///
+/// Like `relatime`, the access time is only updated if it is not newer than
+/// the modification or change time or if it is more than a day old.
+/// This avoids a write on every read.
+async fn touch_atime(item: &Item<'_, Dev>, attrs: &Attrs) {
+	const DAY: i64 = 24 * 60 * 60 * 1_000_000;
+	let now = mtime_now();
+	let changed = attrs.modified.time.max(attrs.ctime.unwrap_or(i64::MIN));
+	let fresh = attrs
+		.atime
+		.is_some_and(|atime| atime > changed && now.saturating_sub(atime) < DAY);
+	if !fresh {
+		// The root directory cannot have attributes.
+		let _ = item.set_atime(now).await.unwrap();
 	}
 }
 
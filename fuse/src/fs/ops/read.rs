@@ -12,5 +12,6 @@ impl Fs {
 		let mut buf = vec![0; job.size as _];
 		let l = f.read(job.offset as _, &mut buf).await.unwrap();
 		job.reply.data(&buf[..l]);
+		touch_atime(&f, &get_attrs(&f).await).await;
 	}
 }
@@ -9,8 +9,9 @@ impl Fs {
 			Get::Stale => return job.reply.error(libc::ESTALE),
 		};
 
-		let mut buf = [0; 1 << 15];
-		let l = f.read(0, &mut buf).await.unwrap();
-		job.reply.data(&buf[..l]);
+		match f.read_link().await.unwrap() {
+			Ok(target) => job.reply.data(&target),
+			Err(_) => job.reply.error(libc::EINVAL),
+		}
 	}
 }
@@ -53,11 +53,11 @@ impl Fs {
 			}
 		}
 
-		match from_d
-			.transfer(from_item.key, &to_d, to_name)
-			.await
-			.unwrap()
-		{
+		let r = match from_d.transfer(from_item.key, &to_d, to_name).await {
+			Err(nrfs::Error::QuotaExceeded) => return job.reply.error(libc::EDQUOT),
+			r => r.unwrap(),
+		};
+		match r {
 			Ok(key) => {
 				let ino = self.ino().get_ino(from_item.key);
 				ino.map(|ino| self.ino().set(ino, key, job.newparent));
@@ -73,7 +73,9 @@ impl Fs {
 				}
 			}
 			Err(nrfs::TransferError::Duplicate) => unreachable!(),
-			Err(nrfs::TransferError::Full) => todo!(),
+			Err(nrfs::TransferError::Full) => job.reply.error(libc::ENOSPC),
+			Err(nrfs::TransferError::TooDeep) => job.reply.error(libc::EMLINK),
+			Err(nrfs::TransferError::TooLarge) => job.reply.error(libc::EFBIG),
 		}
 	}
 }
@@ -40,6 +40,10 @@ impl Fs {
 			set_mode(&item, mode).await
 		}
 
+		let ctime = mtime_now();
+		set_ctime(&item, ctime).await;
+		attrs.ctime = Some(ctime);
+
 		job.reply.attr(&TTL, &self.attr(job.ino, ty, size, attrs));
 		self.update_gen(job.ino, lock).await;
 	}
@@ -5,12 +5,13 @@ impl Fs {
 		let stat = self.fs.statistics();
 		let store = &stat.object_store.storage;
 		let alloc = &store.allocation;
+		let (used_inodes, free_inodes) = self.fs.inode_stats();
 		job.reply.statfs(
 			alloc.total_blocks,
 			alloc.total_blocks - alloc.used_blocks,
 			alloc.total_blocks - alloc.used_blocks,
-			1 << 58,
-			(1 << 58) - stat.object_store.used_objects,
+			used_inodes + free_inodes,
+			free_inodes,
 			1 << store.block_size.to_raw(),
 			255,
 			0,
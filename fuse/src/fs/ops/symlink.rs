@@ -3,6 +3,9 @@ use super::*;
 impl Fs {
 	pub async fn symlink(&self, job: crate::job::SymLink) {
 		let Ok(name) = (&*job.name).try_into() else { return job.reply.error(libc::ENAMETOOLONG) };
+		if job.link.len() > Nrfs::<Dev>::MAX_LINK_LEN {
+			return job.reply.error(libc::ENAMETOOLONG);
+		}
 
 		let (dir, lock) = match self.dir_mut(job.parent).await {
 			Ok(r) => r,
@@ -14,7 +17,14 @@ impl Fs {
 				let ino = self.ino().add(Key::File(f.key()), job.parent, self.gen());
 				f.set_modified_gen(self.gen()).await.unwrap();
 				let link = job.link;
-				f.write_grow(0, &link).await.unwrap().unwrap();
+				match f.set_link_target(&link).await {
+					Err(nrfs::Error::QuotaExceeded) => {
+						dir.remove(f.key()).await.unwrap().unwrap();
+						self.ino().mark_stale(ino);
+						return job.reply.error(libc::EDQUOT);
+					}
+					r => r.unwrap().unwrap(),
+				}
 				let attrs = self.init_attrs(&f, job.uid, job.gid, None).await;
 				let attr = self.attr(
 					ino,
@@ -27,6 +37,7 @@ impl Fs {
 			}
 			Err(CreateError::Duplicate) => job.reply.error(libc::EEXIST),
 			Err(CreateError::Full) => job.reply.error(libc::ENOSPC),
+			Err(CreateError::Occupied) | Err(CreateError::TooDeep) => unreachable!(),
 		}
 	}
 }
@@ -9,11 +9,20 @@ impl Fs {
 			Get::Stale => return job.reply.error(libc::ESTALE),
 		};
 
-		f.write_grow(job.offset as _, &job.data)
-			.await
-			.unwrap()
-			.unwrap();
-		job.reply.written(job.data.len() as _);
+		// If the quota is exceeded partway, report how much has been written.
+		// The kernel will retry the remainder, which then fails with EDQUOT
+		// as no data can be written at all.
+		//
+		// Running out of space is generally only detected when the data is flushed,
+		// in which case the error is reported by fsync.
+		let n = match f.append_partial(job.offset as _, &job.data).await {
+			Err(nrfs::Error::QuotaExceeded) => return job.reply.error(libc::EDQUOT),
+			Err(nrfs::Error::Nros(nros::Error::NotEnoughSpace)) => {
+				return job.reply.error(libc::ENOSPC)
+			}
+			r => r.unwrap().unwrap(),
+		};
+		job.reply.written(n as _);
 		self.update_gen(job.ino, lock).await;
 	}
 }
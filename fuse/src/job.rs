@@ -1,9 +1,14 @@
 use {
+	async_channel::Sender,
 	fuser::{
-		ReplyAttr, ReplyCreate, ReplyData, ReplyDirectory, ReplyEmpty, ReplyEntry, ReplyIoctl,
-		ReplyStatfs, ReplyWrite, ReplyXattr, TimeOrNow,
+		FileAttr, FileType, ReplyAttr, ReplyCreate, ReplyData, ReplyDirectory, ReplyEmpty,
+		ReplyEntry, ReplyIoctl, ReplyStatfs, ReplyWrite, ReplyXattr, TimeOrNow,
+	},
+	std::{
+		ffi::OsStr,
+		sync::{Arc, Mutex},
+		time::{Duration, Instant},
 	},
-	std::time::Instant,
 };
 
 /// A job to be completed by the filesystem handler.
@@ -33,14 +38,193 @@ pub enum Job {
 	IoCtl(IoCtl),
 	MkNod(MkNod),
 	Destroy,
-	Sync(Instant),
+	Sync(Sync),
+}
+
+impl Job {
+	/// Get a handle to reply to this job with an error without taking ownership of it.
+	///
+	/// Returns `None` if the job does not expect a reply.
+	pub fn abort_handle(&self) -> Option<AbortHandle> {
+		Some(match self {
+			Job::Lookup(j) => j.reply.abort_handle(),
+			Job::Forget(_) => return None,
+			Job::GetAttr(j) => j.reply.abort_handle(),
+			Job::SetAttr(j) => j.reply.abort_handle(),
+			Job::GetXAttr(j) => j.reply.abort_handle(),
+			Job::SetXAttr(j) => j.reply.abort_handle(),
+			Job::ListXAttr(j) => j.reply.abort_handle(),
+			Job::RemoveXAttr(j) => j.reply.abort_handle(),
+			Job::Read(j) => j.reply.abort_handle(),
+			Job::Write(j) => j.reply.abort_handle(),
+			Job::ReadLink(j) => j.reply.abort_handle(),
+			Job::ReadDir(j) => j.reply.abort_handle(),
+			Job::Create(j) => j.reply.abort_handle(),
+			Job::FAllocate(j) => j.reply.abort_handle(),
+			Job::SymLink(j) => j.reply.abort_handle(),
+			Job::MkDir(j) => j.reply.abort_handle(),
+			Job::Rename(j) => j.reply.abort_handle(),
+			Job::Unlink(j) => j.reply.abort_handle(),
+			Job::RmDir(j) => j.reply.abort_handle(),
+			Job::StatFs(j) => j.reply.abort_handle(),
+			Job::IoCtl(j) => j.reply.abort_handle(),
+			Job::MkNod(j) => j.reply.abort_handle(),
+			Job::FSync(_) | Job::Sync(_) | Job::Destroy => return None,
+		})
+	}
+}
+
+/// Reply to a FUSE request that may be answered early.
+///
+/// Whoever replies first wins, later replies are ignored.
+/// This allows replying with an error if an operation takes too long
+/// while still letting the operation run to completion.
+#[derive(Debug)]
+pub struct Reply<R>(Arc<Mutex<Option<R>>>);
+
+/// Handle to reply to a request with an error.
+pub type AbortHandle = Box<dyn FnOnce(libc::c_int)>;
+
+/// Reply types that can be answered with an error.
+pub trait ErrorReply {
+	fn error(self, err: libc::c_int);
+}
+
+macro_rules! error_reply {
+	($($ty:ident)*) => {
+		$(
+			impl ErrorReply for $ty {
+				fn error(self, err: libc::c_int) {
+					$ty::error(self, err)
+				}
+			}
+		)*
+	};
+}
+
+error_reply! {
+	ReplyAttr ReplyCreate ReplyData ReplyDirectory ReplyEmpty
+	ReplyEntry ReplyIoctl ReplyStatfs ReplyWrite ReplyXattr
+}
+
+impl<R> From<R> for Reply<R> {
+	fn from(reply: R) -> Self {
+		Self(Arc::new(Mutex::new(Some(reply))))
+	}
+}
+
+impl<R> Reply<R> {
+	/// Reply if nobody has replied yet.
+	fn reply(self, f: impl FnOnce(R)) {
+		let r = self.0.lock().unwrap().take();
+		if let Some(r) = r {
+			f(r)
+		}
+	}
+}
+
+impl<R: ErrorReply + 'static> Reply<R> {
+	pub fn error(self, err: libc::c_int) {
+		self.reply(|r| r.error(err))
+	}
+
+	pub fn abort_handle(&self) -> AbortHandle {
+		let r = Self(self.0.clone());
+		Box::new(move |err| r.error(err))
+	}
+}
+
+impl Reply<ReplyEmpty> {
+	pub fn ok(self) {
+		self.reply(|r| r.ok())
+	}
+}
+
+impl Reply<ReplyEntry> {
+	pub fn entry(self, ttl: &Duration, attr: &FileAttr, generation: u64) {
+		self.reply(|r| r.entry(ttl, attr, generation))
+	}
+}
+
+impl Reply<ReplyAttr> {
+	pub fn attr(self, ttl: &Duration, attr: &FileAttr) {
+		self.reply(|r| r.attr(ttl, attr))
+	}
+}
+
+impl Reply<ReplyCreate> {
+	pub fn created(self, ttl: &Duration, attr: &FileAttr, generation: u64, fh: u64, flags: u32) {
+		self.reply(|r| r.created(ttl, attr, generation, fh, flags))
+	}
+}
+
+impl Reply<ReplyXattr> {
+	pub fn size(self, size: u32) {
+		self.reply(|r| r.size(size))
+	}
+
+	pub fn data(self, data: &[u8]) {
+		self.reply(|r| r.data(data))
+	}
+}
+
+impl Reply<ReplyData> {
+	pub fn data(self, data: &[u8]) {
+		self.reply(|r| r.data(data))
+	}
+}
+
+impl Reply<ReplyWrite> {
+	pub fn written(self, size: u32) {
+		self.reply(|r| r.written(size))
+	}
+}
+
+impl Reply<ReplyIoctl> {
+	pub fn ioctl(self, result: i32, data: &[u8]) {
+		self.reply(|r| r.ioctl(result, data))
+	}
+}
+
+impl Reply<ReplyStatfs> {
+	#[allow(clippy::too_many_arguments)]
+	pub fn statfs(
+		self,
+		blocks: u64,
+		bfree: u64,
+		bavail: u64,
+		files: u64,
+		ffree: u64,
+		bsize: u32,
+		namelen: u32,
+		frsize: u32,
+	) {
+		self.reply(|r| r.statfs(blocks, bfree, bavail, files, ffree, bsize, namelen, frsize))
+	}
+}
+
+impl Reply<ReplyDirectory> {
+	/// Add an entry to the reply.
+	///
+	/// Returns `true` if the buffer is full or if the request has already been answered.
+	pub fn add<T: AsRef<OsStr>>(&mut self, ino: u64, offset: i64, kind: FileType, name: T) -> bool {
+		self.0
+			.lock()
+			.unwrap()
+			.as_mut()
+			.map_or(true, |r| r.add(ino, offset, kind, name))
+	}
+
+	pub fn ok(self) {
+		self.reply(|r| r.ok())
+	}
 }
 
 #[derive(Debug)]
 pub struct Lookup {
 	pub parent: u64,
 	pub name: Box<[u8]>,
-	pub reply: ReplyEntry,
+	pub reply: Reply<ReplyEntry>,
 }
 
 #[derive(Debug)]
@@ -52,7 +236,7 @@ pub struct Forget {
 #[derive(Debug)]
 pub struct GetAttr {
 	pub ino: u64,
-	pub reply: ReplyAttr,
+	pub reply: Reply<ReplyAttr>,
 }
 
 #[derive(Debug)]
@@ -63,7 +247,7 @@ pub struct SetAttr {
 	pub gid: Option<u32>,
 	pub size: Option<u64>,
 	pub mtime: Option<TimeOrNow>,
-	pub reply: ReplyAttr,
+	pub reply: Reply<ReplyAttr>,
 }
 
 #[derive(Debug)]
@@ -71,7 +255,7 @@ pub struct GetXAttr {
 	pub ino: u64,
 	pub name: Box<[u8]>,
 	pub size: u32,
-	pub reply: ReplyXattr,
+	pub reply: Reply<ReplyXattr>,
 }
 
 #[derive(Debug)]
@@ -81,21 +265,21 @@ pub struct SetXAttr {
 	pub value: Box<[u8]>,
 	pub flags: i32,
 	pub position: u32,
-	pub reply: ReplyEmpty,
+	pub reply: Reply<ReplyEmpty>,
 }
 
 #[derive(Debug)]
 pub struct ListXAttr {
 	pub ino: u64,
 	pub size: u32,
-	pub reply: ReplyXattr,
+	pub reply: Reply<ReplyXattr>,
 }
 
 #[derive(Debug)]
 pub struct RemoveXAttr {
 	pub ino: u64,
 	pub name: Box<[u8]>,
-	pub reply: ReplyEmpty,
+	pub reply: Reply<ReplyEmpty>,
 }
 
 #[derive(Debug)]
@@ -103,7 +287,7 @@ pub struct Read {
 	pub ino: u64,
 	pub offset: i64,
 	pub size: u32,
-	pub reply: ReplyData,
+	pub reply: Reply<ReplyData>,
 }
 
 #[derive(Debug)]
@@ -111,20 +295,20 @@ pub struct Write {
 	pub ino: u64,
 	pub offset: i64,
 	pub data: Box<[u8]>,
-	pub reply: ReplyWrite,
+	pub reply: Reply<ReplyWrite>,
 }
 
 #[derive(Debug)]
 pub struct ReadLink {
 	pub ino: u64,
-	pub reply: ReplyData,
+	pub reply: Reply<ReplyData>,
 }
 
 #[derive(Debug)]
 pub struct ReadDir {
 	pub ino: u64,
 	pub offset: i64,
-	pub reply: ReplyDirectory,
+	pub reply: Reply<ReplyDirectory>,
 }
 
 #[derive(Debug)]
@@ -134,14 +318,14 @@ pub struct Create {
 	pub parent: u64,
 	pub name: Box<[u8]>,
 	pub mode: u32,
-	pub reply: ReplyCreate,
+	pub reply: Reply<ReplyCreate>,
 }
 
 #[derive(Debug)]
 pub struct FAllocate {
 	pub ino: u64,
 	pub length: i64,
-	pub reply: ReplyEmpty,
+	pub reply: Reply<ReplyEmpty>,
 }
 
 #[derive(Debug)]
@@ -151,7 +335,7 @@ pub struct SymLink {
 	pub parent: u64,
 	pub name: Box<[u8]>,
 	pub link: Box<[u8]>,
-	pub reply: ReplyEntry,
+	pub reply: Reply<ReplyEntry>,
 }
 
 #[derive(Debug)]
@@ -161,7 +345,7 @@ pub struct MkDir {
 	pub parent: u64,
 	pub name: Box<[u8]>,
 	pub mode: u32,
-	pub reply: ReplyEntry,
+	pub reply: Reply<ReplyEntry>,
 }
 
 #[derive(Debug)]
@@ -170,21 +354,21 @@ pub struct Rename {
 	pub name: Box<[u8]>,
 	pub newparent: u64,
 	pub newname: Box<[u8]>,
-	pub reply: ReplyEmpty,
+	pub reply: Reply<ReplyEmpty>,
 }
 
 #[derive(Debug)]
 pub struct Unlink {
 	pub parent: u64,
 	pub name: Box<[u8]>,
-	pub reply: ReplyEmpty,
+	pub reply: Reply<ReplyEmpty>,
 }
 
 #[derive(Debug)]
 pub struct RmDir {
 	pub parent: u64,
 	pub name: Box<[u8]>,
-	pub reply: ReplyEmpty,
+	pub reply: Reply<ReplyEmpty>,
 }
 
 #[derive(Debug)]
@@ -192,9 +376,17 @@ pub struct FSync {
 	pub reply: ReplyEmpty,
 }
 
+#[derive(Debug)]
+pub struct Sync {
+	/// The job is skipped if it is processed after this point in time.
+	pub when: Instant,
+	/// Result of the commit.
+	pub reply: Sender<Result<(), String>>,
+}
+
 #[derive(Debug)]
 pub struct StatFs {
-	pub reply: ReplyStatfs,
+	pub reply: Reply<ReplyStatfs>,
 }
 
 #[derive(Debug)]
@@ -204,7 +396,7 @@ pub struct IoCtl {
 	pub cmd: u32,
 	pub in_data: Box<[u8]>,
 	pub out_size: u32,
-	pub reply: ReplyIoctl,
+	pub reply: Reply<ReplyIoctl>,
 }
 
 #[derive(Debug)]
@@ -216,5 +408,5 @@ pub struct MkNod {
 	pub mode: u32,
 	pub umask: u32,
 	pub rdev: u32,
-	pub reply: ReplyEntry,
+	pub reply: Reply<ReplyEntry>,
 }
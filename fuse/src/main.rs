@@ -25,8 +25,35 @@ struct Args {
 	/// Soft limit on the cache size.
 	#[arg(long, default_value_t = 1 << 27)]
 	cache_size: usize,
+	/// Policy to select cache entries for eviction with.
+	#[arg(long, value_enum, default_value_t = CachePolicy::Lru)]
+	cache_policy: CachePolicy,
 	#[arg(long, default_value_t = 15)]
 	sync_interval: u32,
+	/// Maximum time in seconds a single operation may take.
+	///
+	/// Operations exceeding this time return `EIO`.
+	///
+	/// The operation itself still runs to completion in the background.
+	#[arg(long)]
+	op_timeout: Option<u32>,
+}
+
+#[derive(Clone, Copy, Debug, clap::ValueEnum)]
+enum CachePolicy {
+	Lru,
+	Fifo,
+	Clock,
+}
+
+impl From<CachePolicy> for nrfs::CachePolicy {
+	fn from(policy: CachePolicy) -> Self {
+		match policy {
+			CachePolicy::Lru => nrfs::CachePolicy::Lru,
+			CachePolicy::Fifo => nrfs::CachePolicy::Fifo,
+			CachePolicy::Clock => nrfs::CachePolicy::Clock,
+		}
+	}
 }
 
 fn main() -> Result<(), Box<dyn Error>> {
@@ -57,8 +84,17 @@ fn main() -> Result<(), Box<dyn Error>> {
 		})
 		.try_collect::<Vec<_>>()?;
 
-	let (f, channel) =
-		futures_executor::block_on(fs::Fs::new(0o755, f.into_iter(), key, args.cache_size));
+	let op_timeout = args
+		.op_timeout
+		.map(|t| std::time::Duration::from_secs(t.into()));
+	let (f, channel) = futures_executor::block_on(fs::Fs::new(
+		0o755,
+		f.into_iter(),
+		key,
+		args.cache_size,
+		args.cache_policy.into(),
+		op_timeout,
+	));
 	let mut sync_channel = channel.clone();
 
 	let mut opts = vec![
@@ -76,7 +112,11 @@ fn main() -> Result<(), Box<dyn Error>> {
 	let sync_interval = args.sync_interval;
 	std::thread::spawn(move || loop {
 		std::thread::sleep(std::time::Duration::from_secs(sync_interval.into()));
-		sync_channel.commit();
+		match sync_channel.commit() {
+			Ok(()) => {}
+			Err(fs::CommitError::Closed) => break,
+			Err(e) => eprintln!("{}", e),
+		}
 	});
 
 	futures_executor::block_on(f.run()).unwrap();
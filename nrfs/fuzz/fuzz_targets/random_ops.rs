@@ -34,6 +34,7 @@ fn new_cap(size: usize, block_size: BlockSize, max_record_size: MaxRecordSize) -
 		block_size,
 		max_record_size,
 		compression: Compression::None,
+		embed_factor: 0,
 		cache_size: 4096,
 	}))
 	.unwrap()
@@ -204,7 +205,7 @@ impl<'a> Test<'a> {
 								let dir = self.fs.dir(r.key).await.unwrap();
 
 								match dir.create_file(name).await.unwrap() {
-									Ok(file) => {
+									Ok((file, _)) => {
 										let prev = r.children.insert(name, idx);
 										assert!(prev.is_none(), "name already present");
 										self.map.push(Some(Ref::File(RefFile {
@@ -367,6 +368,8 @@ impl<'a> Test<'a> {
 										(*r.parent(), *r.key(), *r.name()) = (to_idx, key, to);
 									}
 									Err(TransferError::Full) => todo!(),
+									Err(TransferError::TooDeep) => {}
+									Err(TransferError::TooLarge) => unreachable!("same filesystem"),
 									Err(TransferError::Duplicate) => {
 										let Ref::Dir(to_r) = get!(to_idx) else { unreachable!() };
 										assert!(
@@ -443,6 +446,7 @@ impl<'a> Test<'a> {
 			self.fs = block_on(Nrfs::load(LoadConfig {
 				devices,
 				cache_size: 1 << 12,
+				cache_policy: Default::default(),
 				allow_repair: true,
 				retrieve_key: &mut |_| unreachable!(),
 			}))
@@ -1,4 +1,6 @@
-use crate::{BlockSize, CipherType, Compression, Dev, KeyDeriver, KeyPassword, MaxRecordSize};
+use crate::{
+	BlockSize, CachePolicy, CipherType, Compression, Dev, KeyDeriver, KeyPassword, MaxRecordSize,
+};
 
 pub struct NewConfig<'a, D: Dev> {
 	/// Mirror of chains of devices.
@@ -17,6 +19,12 @@ pub struct NewConfig<'a, D: Dev> {
 	pub max_record_size: MaxRecordSize,
 	/// Compression to apply.
 	pub compression: Compression,
+	/// How many multiples of the block size a file may be before its data is moved
+	/// to a separate object.
+	///
+	/// If `0`, [`Nrfs::DEFAULT_EMBED_FACTOR`](crate::Nrfs::DEFAULT_EMBED_FACTOR) is used.
+	/// Must not exceed [`Nrfs::MAX_EMBED_FACTOR`](crate::Nrfs::MAX_EMBED_FACTOR).
+	pub embed_factor: u8,
 	/// Size of the cache.
 	///
 	/// This is a soft limit.
@@ -32,6 +40,8 @@ pub struct LoadConfig<'a, D: Dev> {
 	/// This is a soft limit.
 	/// Real usage may exceed this.
 	pub cache_size: usize,
+	/// Policy to select cache entries for eviction with.
+	pub cache_policy: CachePolicy,
 	/// Whether to allow repair of this filesystem.
 	///
 	/// If `false`, errors will not be corrected.
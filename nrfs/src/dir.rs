@@ -1,11 +1,14 @@
 use crate::HDR_ROOT_OFFT;
 
 use {
-	crate::{item::ITEM_LEN, Dev, Error, File, Item, ItemInfo, ItemKey, ItemTy, Nrfs, Store},
+	crate::{
+		item::ITEM_LEN, Dev, Error, File, Item, ItemInfo, ItemKey, ItemTy, LengthTooLong, Nrfs,
+		Store,
+	},
 	core::{cell::RefCell, fmt, ops::Deref},
-	nrkv::Key,
+	nrkv::{Hasher, InsertAtError, Key, Tag},
 	nros::Resource,
-	std::borrow::Cow,
+	std::{borrow::Cow, collections::BTreeSet},
 };
 
 /// Helper structure for working with directories.
@@ -15,7 +18,77 @@ pub struct Dir<'a, D: Dev> {
 	pub(crate) id: u64,
 }
 
-pub(crate) type Kv<'a, D> = nrkv::Nrkv<Store<'a, D>, nrkv::StaticConf<0, ITEM_LEN>>;
+/// Length of the header in front of the key-value store of a directory.
+///
+/// # Layout
+///
+/// +------+-----------------+
+/// | Offt | Field           |
+/// +======+=================+
+/// |    0 | Parent ID       |
+/// +------+-----------------+
+/// |    8 | Quota           |
+/// +------+-----------------+
+/// |   16 | Usage           |
+/// +------+-----------------+
+/// |   24 | Hasher          |
+/// +------+-----------------+
+/// |   25 | Fold case       |
+/// +------+-----------------+
+/// |   26 | Tag generation  |
+/// +------+-----------------+
+/// |   28 | Reserved        |
+/// +------+-----------------+
+///
+/// * The parent ID is `u64::MAX` for the root directory.
+/// * A quota of `u64::MAX` indicates there is no quota.
+/// * Usage is the total length of all files in the directory and its descendants.
+/// * The hasher is the [`Hasher`] used to index the names of items.
+/// * If fold case is not 0, names are indexed and compared with ASCII letters folded
+///   to lowercase, i.e. case-insensitively.
+/// * The tag generation is a 16-bit integer used by the key-value store
+///   to tell tags of removed items apart from those of new items.
+pub(crate) const DIR_HDR_LEN: u64 = 32;
+
+const HDR_PARENT_OFFT: u64 = 0;
+const HDR_QUOTA_OFFT: u64 = 8;
+const HDR_USAGE_OFFT: u64 = 16;
+const HDR_HASHER_OFFT: u64 = 24;
+const HDR_FOLD_CASE_OFFT: u64 = 25;
+const HDR_GENERATION_OFFT: u64 = 26;
+
+pub(crate) type Kv<'a, D> = nrkv::Nrkv<Store<'a, D>, DirConf>;
+
+/// Configuration of the key-value store of a directory.
+#[derive(Debug)]
+pub(crate) struct DirConf {
+	/// Whether the key-value store is preceded by a directory header.
+	///
+	/// See [`Nrfs::has_dir_header`].
+	has_header: bool,
+}
+
+impl nrkv::Conf for DirConf {
+	fn header_offset(&self) -> u64 {
+		if self.has_header { DIR_HDR_LEN } else { 0 }
+	}
+
+	fn item_offset(&self) -> u16 {
+		ITEM_LEN
+	}
+
+	fn hasher_offset(&self) -> Option<u64> {
+		self.has_header.then_some(HDR_HASHER_OFFT)
+	}
+
+	fn fold_case_offset(&self) -> Option<u64> {
+		self.has_header.then_some(HDR_FOLD_CASE_OFFT)
+	}
+
+	fn generation_offset(&self) -> Option<u64> {
+		self.has_header.then_some(HDR_GENERATION_OFFT)
+	}
+}
 
 impl<'a, D: Dev> Dir<'a, D> {
 	/// Create a [`Dir`] helper structure.
@@ -24,14 +97,33 @@ impl<'a, D: Dev> Dir<'a, D> {
 	}
 
 	/// Create a new directory.
-	pub(crate) async fn init(fs: &'a Nrfs<D>) -> Result<u64, Error<D>> {
-		trace!("Dir::init");
+	///
+	/// `parent` is the ID of the parent directory or `u64::MAX` for the root directory.
+	///
+	/// If `fold_case` is `true`, names are matched case-insensitively.
+	pub(crate) async fn init(
+		fs: &'a Nrfs<D>,
+		parent: u64,
+		hasher: Hasher,
+		fold_case: bool,
+	) -> Result<u64, Error<D>> {
+		trace!("Dir::init {:#x} {:?} {}", parent, hasher, fold_case);
 		let dir = fs.storage.create().await?;
 		let id = dir.id();
 		trace!("--> {:#x}", id);
+		let has_header = fs.has_dir_header();
+		if has_header {
+			let hdr = &mut [0; 24];
+			hdr[HDR_PARENT_OFFT as usize..][..8].copy_from_slice(&parent.to_le_bytes());
+			hdr[HDR_QUOTA_OFFT as usize..][..8].copy_from_slice(&u64::MAX.to_le_bytes());
+			dir.write(0, hdr).await?;
+		}
 		let mut key = [0; 16];
 		fs.resource().crng_fill(&mut key);
-		Kv::init_with_key(Store { fs, id }, nrkv::StaticConf, key).await?;
+		Kv::init_with_key_and_hasher(Store { fs, id }, DirConf { has_header }, key, hasher).await?;
+		if fold_case {
+			fs.get(id).write(HDR_FOLD_CASE_OFFT, &[1]).await?;
+		}
 		Ok(id)
 	}
 
@@ -39,20 +131,31 @@ impl<'a, D: Dev> Dir<'a, D> {
 	///
 	/// This fails if an item with the given name already exists.
 	///
+	/// If `at` is `Some`, the item is created with that tag.
+	///
 	/// # Returns
 	///
 	/// The index of the item as well as an ID if a directory has been created.
 	async fn create(
 		&self,
 		key: &Key,
+		at: Option<Tag>,
 	) -> Result<Result<(ItemKey, Kv<'a, D>), CreateError>, Error<D>> {
-		trace!("create {:#x} {:?}", self.id, key);
+		trace!("create {:#x} {:?} {:?}", self.id, key, at);
 		assert!(!self.fs.read_only, "read only");
 
 		let lock = self.fs.lock_dir_mut(self.id).await;
 		let mut kv = self.kv();
-		let Ok(tag) = kv.insert(key, &[]).await? else {
-			return Ok(Err(CreateError::Duplicate))
+		let tag = match at {
+			None => match kv.insert(key, &[]).await? {
+				Ok(tag) => tag,
+				Err(_) => return Ok(Err(CreateError::Duplicate)),
+			},
+			Some(tag) => match kv.insert_at(key, &[], tag).await? {
+				Ok(()) => tag,
+				Err(InsertAtError::Duplicate(_)) => return Ok(Err(CreateError::Duplicate)),
+				Err(InsertAtError::Occupied) => return Ok(Err(CreateError::Occupied)),
+			},
 		};
 		drop(lock);
 
@@ -63,14 +166,85 @@ impl<'a, D: Dev> Dir<'a, D> {
 	/// Create a new directory.
 	///
 	/// This fails if an item with the given name already exists.
+	///
+	/// Directories do not need to be sized in advance:
+	/// the root of the hash map has a fixed size and items are never rehashed or moved,
+	/// so the keys of existing items remain valid as the directory grows.
 	pub async fn create_dir(
 		&self,
 		name: &Key,
 	) -> Result<Result<Dir<'a, D>, CreateError>, Error<D>> {
-		match self.create(name).await? {
+		self.create_dir_inner(name, None, Hasher::default(), false)
+			.await
+	}
+
+	/// Create a new directory which uses a specific hash algorithm for indexing names.
+	///
+	/// [`Hasher::SipHasher13`] should be used for directories with names from untrusted
+	/// sources.
+	///
+	/// This fails if an item with the given name already exists.
+	pub async fn create_dir_with_hasher(
+		&self,
+		name: &Key,
+		hasher: Hasher,
+	) -> Result<Result<Dir<'a, D>, CreateError>, Error<D>> {
+		self.create_dir_inner(name, None, hasher, false).await
+	}
+
+	/// Create a new directory which matches names case-insensitively.
+	///
+	/// Only ASCII letters are folded.
+	/// Names are stored as given, but searching for `foo` finds `Foo`.
+	///
+	/// This fails if an item with the given name already exists,
+	/// ignoring case.
+	pub async fn create_dir_case_insensitive(
+		&self,
+		name: &Key,
+		hasher: Hasher,
+	) -> Result<Result<Dir<'a, D>, CreateError>, Error<D>> {
+		self.create_dir_inner(name, None, hasher, true).await
+	}
+
+	/// Create a new directory with a specific key.
+	///
+	/// This is useful to restore items such that previously recorded keys remain valid.
+	///
+	/// This fails if an item with the given name already exists
+	/// or if the key is already in use.
+	///
+	/// # Panics
+	///
+	/// If the dir of the key does not match the ID of this directory.
+	pub async fn create_dir_at(
+		&self,
+		name: &Key,
+		key: ItemKey,
+	) -> Result<Result<Dir<'a, D>, CreateError>, Error<D>> {
+		assert_eq!(key.dir, self.id, "dir mismatch");
+		self.create_dir_inner(name, Some(key.tag), Hasher::default(), false)
+			.await
+	}
+
+	async fn create_dir_inner(
+		&self,
+		name: &Key,
+		at: Option<Tag>,
+		hasher: Hasher,
+		fold_case: bool,
+	) -> Result<Result<Dir<'a, D>, CreateError>, Error<D>> {
+		if self.depth().await? >= self.fs.max_depth() {
+			return Ok(Err(CreateError::TooDeep));
+		}
+		// The hasher and fold case are stored in the directory header.
+		if !self.fs.has_dir_header() && (hasher != Hasher::SipHasher13 || fold_case) {
+			return Err(Error::UnsupportedVersion { version: 0 });
+		}
+		match self.create(name, at).await? {
 			Err(e) => Ok(Err(e)),
 			Ok((key, mut kv)) => {
-				let id = Dir::init(self.fs).await?;
+				let id = Dir::init(self.fs, self.id, hasher, fold_case).await?;
 				kv.write_user_data(key.tag, 0, &(id << 5 | 1).to_le_bytes())
 					.await?;
 				Ok(Ok(Dir::new(self.fs, key, id)))
@@ -81,11 +255,42 @@ impl<'a, D: Dev> Dir<'a, D> {
 	/// Create a new file.
 	///
 	/// This fails if an item with the given name already exists.
+	///
+	/// Also returns whether the data of the file is embedded in the directory.
+	/// New files are empty and hence always start out embedded.
+	/// The data is moved to a separate object once it grows too large.
 	pub async fn create_file(
 		&self,
 		name: &Key,
+	) -> Result<Result<(File<'a, D>, bool), CreateError>, Error<D>> {
+		Ok(self.create_file_inner(name, None).await?.map(|f| (f, true)))
+	}
+
+	/// Create a new file with a specific key.
+	///
+	/// This is useful to restore items such that previously recorded keys remain valid.
+	///
+	/// This fails if an item with the given name already exists
+	/// or if the key is already in use.
+	///
+	/// # Panics
+	///
+	/// If the dir of the key does not match the ID of this directory.
+	pub async fn create_file_at(
+		&self,
+		name: &Key,
+		key: ItemKey,
 	) -> Result<Result<File<'a, D>, CreateError>, Error<D>> {
-		match self.create(name).await? {
+		assert_eq!(key.dir, self.id, "dir mismatch");
+		self.create_file_inner(name, Some(key.tag)).await
+	}
+
+	async fn create_file_inner(
+		&self,
+		name: &Key,
+		at: Option<Tag>,
+	) -> Result<Result<File<'a, D>, CreateError>, Error<D>> {
+		match self.create(name, at).await? {
 			Err(e) => Ok(Err(e)),
 			Ok((key, mut kv)) => {
 				kv.write_user_data(key.tag, 0, &[4]).await?;
@@ -101,7 +306,69 @@ impl<'a, D: Dev> Dir<'a, D> {
 		&self,
 		name: &Key,
 	) -> Result<Result<File<'a, D>, CreateError>, Error<D>> {
-		match self.create(name).await? {
+		self.create_sym_inner(name, None).await
+	}
+
+	/// Create a new symlink with a specific key.
+	///
+	/// This is useful to restore items such that previously recorded keys remain valid.
+	///
+	/// This fails if an item with the given name already exists
+	/// or if the key is already in use.
+	///
+	/// # Panics
+	///
+	/// If the dir of the key does not match the ID of this directory.
+	pub async fn create_sym_at(
+		&self,
+		name: &Key,
+		key: ItemKey,
+	) -> Result<Result<File<'a, D>, CreateError>, Error<D>> {
+		assert_eq!(key.dir, self.id, "dir mismatch");
+		self.create_sym_inner(name, Some(key.tag)).await
+	}
+
+	/// Create a new symlink and set its target.
+	///
+	/// If the target cannot be written the symlink is removed again,
+	/// so no empty symlink is left behind.
+	///
+	/// This fails if an item with the given name already exists.
+	///
+	/// # Errors
+	///
+	/// [`Error::QuotaExceeded`] if the target would exceed the quota of any ancestor.
+	///
+	/// [`LengthTooLong`] if the target is longer than [`Nrfs::MAX_LINK_LEN`].
+	pub async fn create_sym_with_target(
+		&self,
+		name: &Key,
+		target: &[u8],
+	) -> Result<Result<Result<File<'a, D>, CreateError>, LengthTooLong>, Error<D>> {
+		trace!("create_sym_with_target {:#x} {:?}", self.id, name);
+		if target.len() > Nrfs::<D>::MAX_LINK_LEN {
+			return Ok(Err(LengthTooLong));
+		}
+		let f = match self.create_sym_inner(name, None).await? {
+			Ok(f) => f,
+			Err(e) => return Ok(Ok(Err(e))),
+		};
+		match f.write_grow(0, target).await {
+			Ok(r) => r.expect("length is checked in advance"),
+			Err(e) => {
+				self.remove(f.key()).await?.unwrap();
+				return Err(e);
+			}
+		}
+		Ok(Ok(Ok(f)))
+	}
+
+	async fn create_sym_inner(
+		&self,
+		name: &Key,
+		at: Option<Tag>,
+	) -> Result<Result<File<'a, D>, CreateError>, Error<D>> {
+		match self.create(name, at).await? {
 			Err(e) => Ok(Err(e)),
 			Ok((key, mut kv)) => {
 				kv.write_user_data(key.tag, 0, &[5]).await?;
@@ -125,6 +392,25 @@ impl<'a, D: Dev> Dir<'a, D> {
 		}))
 	}
 
+	/// Search for an item by name and get a handle to it.
+	///
+	/// This avoids a second lookup compared to using [`Dir::search`] followed by
+	/// [`Nrfs::dir`] or [`Nrfs::file`].
+	pub async fn open(&self, name: &Key) -> Result<Option<Entry<'a, D>>, Error<D>> {
+		trace!("open {:#x} {:?}", self.id, name);
+		let _lock = self.fs.lock_dir(self.id).await;
+		let mut kv = self.kv();
+		let Some(tag) = kv.find(name).await? else { return Ok(None) };
+		let a = &mut [0; 8];
+		kv.read_user_data(tag, 0, a).await?;
+		let key = ItemKey { dir: self.id, tag };
+		Ok(Some(match ItemTy::from_raw(a[0] & 7).unwrap() {
+			ItemTy::Dir => Entry::Dir(Dir::new(self.fs, key, u64::from_le_bytes(*a) >> 5)),
+			ItemTy::File | ItemTy::EmbedFile => Entry::File(self.fs.file(key)),
+			ItemTy::Sym | ItemTy::EmbedSym => Entry::Sym(self.fs.file(key)),
+		}))
+	}
+
 	/// Remove an item.
 	///
 	/// # Panics
@@ -139,6 +425,7 @@ impl<'a, D: Dev> Dir<'a, D> {
 		assert_eq!(key.dir, self.id, "dir mismatch");
 
 		let lock = self.fs.lock_dir_mut(self.id).await;
+		let usage = Item::new(self.fs, key).usage().await?;
 		if !Item::new(self.fs, key).destroy().await? {
 			return Ok(Err(RemoveError::NotEmpty));
 		}
@@ -146,15 +433,195 @@ impl<'a, D: Dev> Dir<'a, D> {
 		drop(lock);
 
 		self.update_item_count(false).await?;
+		discharge(self.fs, self.id, usage).await?;
 		Ok(Ok(()))
 	}
 
+	/// Remove this directory and all of its descendants.
+	///
+	/// Descendants are removed depth-first.
+	/// Items that could not be removed are skipped
+	/// and the remaining items are still removed.
+	///
+	/// The root directory itself is never removed, only emptied.
+	///
+	/// # Errors
+	///
+	/// The keys of items that could not be removed,
+	/// e.g. because an item was added to a directory while it was being emptied
+	/// because a directory appears more than once in the tree
+	/// or because a directory is nested deeper than [`Nrfs::max_depth`].
+	pub async fn remove_tree(&self) -> Result<Result<(), Vec<ItemKey>>, Error<D>> {
+		trace!("remove_tree {:#x}", self.id);
+		let mut failed = vec![];
+		let mut visited = BTreeSet::from([self.id]);
+		let max_depth = usize::try_from(self.fs.max_depth()).unwrap();
+
+		// Use an explicit stack to avoid deep recursion with nested directories.
+		let mut stack = vec![Dir::new(self.fs, self.key, self.id)];
+		while let Some(dir) = stack.last() {
+			let mut child = None;
+			let mut state = 0;
+			while let Some((info, next)) = dir.next_from(state).await? {
+				state = next;
+				if failed.contains(&info.key) {
+					continue;
+				}
+				if info.ty == ItemTy::Dir {
+					let d = self.fs.dir(info.key).await?;
+					// Guard against cycles and excessive nesting,
+					// which may be present if the filesystem is corrupt.
+					if stack.len() > max_depth || !visited.insert(d.id) {
+						failed.push(info.key);
+						continue;
+					}
+					child = Some(d);
+					break;
+				}
+				if dir.remove(info.key).await?.is_err() {
+					failed.push(info.key);
+				}
+			}
+			if let Some(child) = child {
+				stack.push(child);
+				continue;
+			}
+
+			let dir = stack.pop().unwrap();
+			if let Some(parent) = stack.last() {
+				if parent.remove(dir.key()).await?.is_err() {
+					failed.push(dir.key());
+				}
+			}
+		}
+
+		if failed.is_empty() {
+			if let Some(parent) = self.parent().await? {
+				if parent.remove(self.key()).await?.is_err() {
+					failed.push(self.key());
+				}
+			}
+		}
+
+		Ok(if failed.is_empty() {
+			Ok(())
+		} else {
+			Err(failed)
+		})
+	}
+
+	/// Get the nesting depth of this directory.
+	///
+	/// The root directory is at depth 0.
+	/// Counting stops after exceeding [`Nrfs::max_depth`],
+	/// which protects against cycles in corrupt filesystems.
+	async fn depth(&self) -> Result<u32, Error<D>> {
+		let max = self.fs.max_depth();
+		let (mut depth, mut id) = (0, self.id);
+		while depth <= max {
+			id = header(self.fs, id).await?.parent;
+			if id == u64::MAX {
+				break;
+			}
+			depth += 1;
+		}
+		Ok(depth)
+	}
+
+	/// Get the nesting depth of the deepest descendant of this directory,
+	/// relative to this directory.
+	///
+	/// A directory without subdirectories has a height of 0.
+	/// Counting stops after exceeding `max`,
+	/// which protects against cycles in corrupt filesystems.
+	async fn height(&self, max: u32) -> Result<u32, Error<D>> {
+		let mut height = 0;
+		let mut stack = vec![(Dir::new(self.fs, self.key, self.id), 0)];
+		while let Some((dir, state)) = stack.last_mut() {
+			let Some((info, next)) = dir.next_from(*state).await? else {
+				stack.pop();
+				continue;
+			};
+			*state = next;
+			if info.ty == ItemTy::Dir {
+				height = height.max(stack.len() as u32);
+				if height > max {
+					break;
+				}
+				stack.push((self.fs.dir(info.key).await?, 0));
+			}
+		}
+		Ok(height)
+	}
+
+	/// Get the parent of this directory.
+	///
+	/// Returns `None` if this is the root directory.
+	async fn parent(&self) -> Result<Option<Dir<'a, D>>, Error<D>> {
+		if self.key.dir == u64::MAX {
+			return Ok(None);
+		}
+		let root = self.fs.root_dir();
+		if self.key.dir == root.id {
+			return Ok(Some(root));
+		}
+
+		// The key of the parent is not stored, so look it up in the grandparent.
+		// Without a directory header the grandparent isn't known either,
+		// so search the entire tree instead.
+		let (ancestor, max_depth) = if self.fs.has_dir_header() {
+			let grandparent = header(self.fs, self.key.dir).await?.parent;
+			if grandparent == u64::MAX {
+				return Ok(Some(root));
+			}
+			(Dir::new(self.fs, ItemKey::INVAL, grandparent), 0)
+		} else {
+			(root, self.fs.max_depth())
+		};
+		let Some(d) = ancestor.find_dir(self.key.dir, max_depth).await?
+			else { panic!("parent {:#x} not found", self.key.dir) };
+		Ok(Some(d))
+	}
+
+	/// Find a descendant directory by object ID.
+	///
+	/// Directories more than `max_depth` levels below this directory are not searched.
+	async fn find_dir(&self, id: u64, max_depth: u32) -> Result<Option<Dir<'a, D>>, Error<D>> {
+		let max_depth = usize::try_from(max_depth).unwrap();
+		let mut stack = vec![(Dir::new(self.fs, self.key, self.id), 0)];
+		while let Some((dir, state)) = stack.last_mut() {
+			let Some((info, next)) = dir.next_from(*state).await? else {
+				stack.pop();
+				continue;
+			};
+			*state = next;
+			if info.ty == ItemTy::Dir {
+				let d = self.fs.dir(info.key).await?;
+				if d.id == id {
+					return Ok(Some(d));
+				}
+				if stack.len() <= max_depth {
+					stack.push((d, 0));
+				}
+			}
+		}
+		Ok(None)
+	}
+
 	/// Move an entry to another directory.
 	///
 	/// # Panics
 	///
 	/// If the dir of the key does not match the ID of this directory.
 	///
+	/// # Errors
+	///
+	/// [`Error::QuotaExceeded`] if the item does not fit in the quota of the target directory
+	/// or any of its ancestors.
+	///
+	/// [`TransferError::TooDeep`] if a directory would end up nested deeper than
+	/// [`Nrfs::max_depth`].
+	///
 	/// # Warning
 	///
 	/// This does not check for cycles!
@@ -176,15 +643,44 @@ impl<'a, D: Dev> Dir<'a, D> {
 			None
 		};
 
-		if to_dir.kv().find(to_name).await?.is_some() {
-			return Ok(Err(TransferError::Duplicate));
+		// In a case-insensitive directory the item itself is found
+		// if only the case of its name changes.
+		match to_dir.kv().find(to_name).await? {
+			Some(tag) if to_dir.id == self.id && tag == key.tag => {}
+			Some(_) => return Ok(Err(TransferError::Duplicate)),
+			None => {}
+		}
+
+		if self.id != to_dir.id && self.kv_ty(key).await? == ItemTy::Dir {
+			let max = self.fs.max_depth();
+			let depth = to_dir.depth().await? + 1;
+			let height = self.fs.dir(key).await?.height(max).await?;
+			if depth.saturating_add(height) > max {
+				return Ok(Err(TransferError::TooDeep));
+			}
+		}
+
+		if self.id != to_dir.id {
+			let usage = self.fs.item(key).usage().await?;
+			discharge(self.fs, self.id, usage).await?;
+			if !charge(self.fs, to_dir.id, usage, true).await? {
+				charge(self.fs, self.id, usage, false).await?;
+				return Err(Error::QuotaExceeded);
+			}
 		}
 
 		let item = &mut [0; ITEM_LEN as _];
 		self.kv().read_user_data(key.tag, 0, item).await?;
+		if let Some(len) = self.fs.pending_lengths.borrow_mut().remove(&key) {
+			item[8..16].copy_from_slice(&len.to_le_bytes());
+		}
+		self.fs.item(key).realloc(to_dir, item).await?;
 		self.kv().remove(key.tag).await?;
 
-		self.fs.item(key).realloc(to_dir, item).await?;
+		if item[0] & 7 == 1 {
+			let id = u64::from_le_bytes(item[..8].try_into().unwrap()) >> 5;
+			write_header(self.fs, id, HDR_PARENT_OFFT, to_dir.id).await?;
+		}
 
 		let tag = to_dir.kv().insert(to_name, item).await?.unwrap();
 
@@ -196,6 +692,178 @@ impl<'a, D: Dev> Dir<'a, D> {
 		Ok(Ok(ItemKey { dir: to_dir.id, tag }))
 	}
 
+	/// Rename an item in this directory.
+	///
+	/// Unlike [`Dir::transfer`], only this directory is locked
+	/// and the usage and item count are left untouched.
+	/// The attributes and embedded data of the item remain in place.
+	///
+	/// Returns `None` if no item with the name `from` exists.
+	/// If `from` and `to` are equal, nothing is changed.
+	///
+	/// # Warning
+	///
+	/// The key of the item changes, like with [`Dir::transfer`].
+	pub async fn rename_within(
+		&self,
+		from: &Key,
+		to: &Key,
+	) -> Result<Option<Result<ItemKey, TransferError>>, Error<D>> {
+		trace!("rename_within {:#x} {:?} -> {:?}", self.id, from, to);
+		assert!(!self.fs.read_only, "read only");
+
+		let _lock = self.fs.lock_dir_mut(self.id).await;
+		let mut kv = self.kv();
+		let Some(tag) = kv.find(from).await? else { return Ok(None) };
+		let key = ItemKey { dir: self.id, tag };
+		if from == to {
+			return Ok(Some(Ok(key)));
+		}
+		// In a case-insensitive directory the item itself is found
+		// if only the case of its name changes.
+		if kv.find(to).await?.is_some_and(|t| t != tag) {
+			return Ok(Some(Err(TransferError::Duplicate)));
+		}
+
+		let item = &mut [0; ITEM_LEN as _];
+		kv.read_user_data(tag, 0, item).await?;
+		if let Some(len) = self.fs.pending_lengths.borrow_mut().remove(&key) {
+			item[8..16].copy_from_slice(&len.to_le_bytes());
+		}
+		kv.remove(tag).await?;
+		let tag = kv.insert(to, item).await?.unwrap();
+
+		Ok(Some(Ok(ItemKey { dir: self.id, tag })))
+	}
+
+	/// Move an entry to another directory, which may be on another filesystem.
+	///
+	/// If both directories are on the same filesystem this is equivalent to [`Dir::transfer`].
+	/// Otherwise the entry is copied to the other filesystem and then removed.
+	///
+	/// If copying fails, everything that has been copied so far is removed again
+	/// and the entry is left in place.
+	/// Items that are added to a directory while it is being moved are left in place too.
+	///
+	/// # Panics
+	///
+	/// If the dir of the key does not match the ID of this directory.
+	pub async fn move_to(
+		&self,
+		key: ItemKey,
+		to_dir: &Dir<'_, D>,
+		to_name: &Key,
+	) -> Result<Result<ItemKey, TransferError>, Error<D>> {
+		trace!("move_to {:?} -> {:#x} {:?}", key, to_dir.id, to_name);
+		assert_eq!(key.dir, self.id, "dir mismatch");
+
+		if core::ptr::eq(self.fs, to_dir.fs) {
+			let to_dir = Dir::new(self.fs, to_dir.key, to_dir.id);
+			return self.transfer(key, &to_dir, to_name).await;
+		}
+
+		let is_dir = self.kv_ty(key).await? == ItemTy::Dir;
+		let to_key = match self.create_copy(key, to_dir, to_name).await? {
+			Ok(k) => k,
+			Err(e) => return Ok(Err(e)),
+		};
+		let r = self.fill_copy(key, to_dir.fs, to_key).await;
+		if !matches!(r, Ok(Ok(()))) {
+			if is_dir {
+				// This only fails if items are added concurrently, which are left alone.
+				let _ = to_dir.fs.dir(to_key).await?.remove_tree().await?;
+			} else {
+				to_dir.remove(to_key).await?.unwrap();
+			}
+			return r.map(|r| r.map(|()| to_key));
+		}
+
+		if is_dir {
+			let _ = self.fs.dir(key).await?.remove_tree().await?;
+		} else {
+			self.remove(key).await?.unwrap();
+		}
+		Ok(Ok(to_key))
+	}
+
+	/// Get the type of an item in this directory.
+	async fn kv_ty(&self, key: ItemKey) -> Result<ItemTy, Error<D>> {
+		let ty = &mut [0];
+		self.kv().read_user_data(key.tag, 0, ty).await?;
+		Ok(ItemTy::from_raw(ty[0] & 7).unwrap())
+	}
+
+	/// Create an empty copy of an item in a directory on another filesystem.
+	///
+	/// Only the metadata is copied.
+	async fn create_copy(
+		&self,
+		key: ItemKey,
+		to_dir: &Dir<'_, D>,
+		to_name: &Key,
+	) -> Result<Result<ItemKey, TransferError>, Error<D>> {
+		let from = self.fs.item(key);
+		let to = match self.kv_ty(key).await? {
+			ItemTy::Dir => {
+				let d = self.fs.dir(key).await?;
+				let (hasher, fold_case) = (d.hasher().await?, d.is_case_insensitive().await?);
+				to_dir
+					.create_dir_inner(to_name, None, hasher, fold_case)
+					.await?
+					.map(|d| d.item)
+			}
+			ItemTy::Sym | ItemTy::EmbedSym => to_dir.create_sym(to_name).await?.map(|f| f.item),
+			ItemTy::File | ItemTy::EmbedFile => to_dir
+				.create_file_inner(to_name, None)
+				.await?
+				.map(|f| f.item),
+		};
+		let to = match to {
+			Ok(to) => to,
+			Err(e) => return Ok(Err(e.into())),
+		};
+		copy_meta(&from, &to).await?;
+		Ok(Ok(to.key()))
+	}
+
+	/// Copy the data or descendants of an item to a copy made with [`Dir::create_copy`].
+	async fn fill_copy(
+		&self,
+		key: ItemKey,
+		to_fs: &Nrfs<D>,
+		to_key: ItemKey,
+	) -> Result<Result<(), TransferError>, Error<D>> {
+		if self.kv_ty(key).await? == ItemTy::Dir {
+			let (from, to) = (self.fs.dir(key).await?, to_fs.dir(to_key).await?);
+			let mut state = 0;
+			while let Some((info, next)) = from.next_from(state).await? {
+				state = next;
+				let k = match from.create_copy(info.key, &to, &info.name).await? {
+					Ok(k) => k,
+					Err(e) => return Ok(Err(e)),
+				};
+				if let Err(e) = util::box_fut(from.fill_copy(info.key, to_fs, k)).await? {
+					return Ok(Err(e));
+				}
+			}
+		} else {
+			let (from, to) = (self.fs.file(key), to_fs.file(to_key));
+			let buf = &mut vec![0; 1 << 16];
+			let mut offt = 0;
+			loop {
+				let l = from.read(offt, buf).await?;
+				if l == 0 {
+					break;
+				}
+				if to.write_grow(offt, &buf[..l]).await?.is_err() {
+					return Ok(Err(TransferError::TooLarge));
+				}
+				offt += u64::try_from(l).unwrap();
+			}
+		}
+		Ok(Ok(()))
+	}
+
 	/// Retrieve the entry with an index equal or greater than `index`.
 	///
 	/// Returns an item and next index if any is found.
@@ -205,33 +873,195 @@ impl<'a, D: Dev> Dir<'a, D> {
 	) -> Result<Option<(ItemInfo<'static>, u64)>, Error<D>> {
 		trace!("next_from {:#x}", state);
 		let _lock = self.fs.lock_dir(self.id).await;
+		self.next_from_unlocked(state).await
+	}
 
+	/// Retrieve the entry with an index equal or greater than `index`
+	/// without locking the directory.
+	async fn next_from_unlocked(
+		&self,
+		state: u64,
+	) -> Result<Option<(ItemInfo<'static>, u64)>, Error<D>> {
 		let val = &RefCell::new(None);
 		let kv = &mut self.kv();
 		let kv = &nrkv::ShareNrkv::new(kv);
 		let mut state = nrkv::IterState::from_u64(state);
 		kv.next_batch(&mut state, move |tag| async move {
-			let len = kv.borrow_mut().read_key(tag, &mut []).await?;
-			if len == 0 {
-				return Ok(true);
-			}
-			let mut key = vec![0; len.into()];
-			kv.borrow_mut().read_key(tag, &mut key).await?;
-			let ty = &mut [0];
-			kv.borrow_mut().read_user_data(tag, 0, ty).await?;
-			val.replace(Some(ItemInfo {
-				name: Cow::Owned(Box::<Key>::try_from(key.into_boxed_slice()).unwrap()),
-				key: ItemKey { dir: self.id, tag },
-				ty: ItemTy::from_raw(ty[0] & 7).unwrap(),
-			}));
+			let Some(info) = self.read_info(kv, tag).await? else { return Ok(true) };
+			val.replace(Some(info));
 			Ok(false)
 		})
 		.await?;
 		Ok(val.take().map(|v| (v, state.into_u64())))
 	}
 
+	/// Retrieve all entries.
+	///
+	/// Unlike [`Self::next_from`] every entry is visited exactly once,
+	/// but iteration cannot be resumed.
+	pub(crate) async fn all_entries(&self) -> Result<Vec<ItemInfo<'static>>, Error<D>> {
+		trace!("all_entries");
+		let _lock = self.fs.lock_dir(self.id).await;
+		let entries = &RefCell::new(Vec::new());
+		let kv = &mut self.kv();
+		let kv = &nrkv::ShareNrkv::new(kv);
+		kv.iter_all(move |tag| async move {
+			if let Some(info) = self.read_info(kv, tag).await? {
+				entries.borrow_mut().push(info);
+			}
+			Ok(true)
+		})
+		.await?;
+		Ok(entries.take())
+	}
+
+	/// Read the name and type of the item with the given tag.
+	///
+	/// Returns `None` if the item has no name.
+	async fn read_info(
+		&self,
+		kv: &nrkv::ShareNrkv<'_, Store<'a, D>, DirConf>,
+		tag: Tag,
+	) -> Result<Option<ItemInfo<'static>>, Error<D>> {
+		let len = kv.borrow_mut().read_key(tag, &mut []).await?;
+		if len == 0 {
+			return Ok(None);
+		}
+		let mut key = vec![0; len.into()];
+		kv.borrow_mut().read_key(tag, &mut key).await?;
+		let ty = &mut [0];
+		kv.borrow_mut().read_user_data(tag, 0, ty).await?;
+		Ok(Some(ItemInfo {
+			name: Cow::Owned(Box::<Key>::try_from(key.into_boxed_slice()).unwrap()),
+			key: ItemKey { dir: self.id, tag },
+			ty: ItemTy::from_raw(ty[0] & 7).unwrap(),
+		}))
+	}
+
+	/// Move the key-value store of this directory to a new object.
+	///
+	/// The tags of all items in this directory remain valid,
+	/// but keys of those items must be updated to refer to the ID of the returned directory.
+	pub async fn relocate(self) -> Result<Dir<'a, D>, Error<D>> {
+		trace!("relocate {:#x}", self.id);
+		let _lock = self.fs.lock_dir_mut(self.id).await;
+
+		let id = self.fs.storage.create().await?.id();
+		self.kv().relocate(Store { fs: self.fs, id }).await?;
+
+		// Point child directories to the new object.
+		let mut state = 0;
+		while let Some((info, next)) = self.next_from_unlocked(state).await? {
+			state = next;
+			if info.ty == ItemTy::Dir {
+				let d = self.fs.dir(ItemKey { dir: id, ..info.key }).await?;
+				write_header(self.fs, d.id, HDR_PARENT_OFFT, id).await?;
+			}
+		}
+
+		// Point the item of this directory to the new object.
+		let f = |a: [u8; 8]| (u64::from_le_bytes(a) & 0x1f | id << 5).to_le_bytes();
+		if self.key.dir == u64::MAX {
+			let b = &mut self.fs.storage.header_data_mut()[HDR_ROOT_OFFT..][..8];
+			let b = <&mut [u8; 8]>::try_from(b).unwrap();
+			*b = f(*b);
+		} else {
+			let mut kv = Dir::new(self.fs, ItemKey::INVAL, self.key.dir).kv();
+			self.check_live(&mut kv).await?;
+			let a = &mut [0; 8];
+			kv.read_user_data(self.key.tag, 0, a).await?;
+			kv.write_user_data(self.key.tag, 0, &f(*a)).await?;
+		}
+
+		{
+			let mut pending = self.fs.pending_lengths.borrow_mut();
+			let moved = pending
+				.iter()
+				.filter(|(k, _)| k.dir == self.id)
+				.map(|(&k, &len)| (k, len))
+				.collect::<Vec<_>>();
+			for (k, len) in moved {
+				pending.remove(&k);
+				pending.insert(ItemKey { dir: id, ..k }, len);
+			}
+		}
+
+		self.fs.dir_headers.borrow_mut().remove(&self.id);
+		self.fs.get(self.id).dealloc().await?;
+		Ok(Dir::new(self.fs, self.key, id))
+	}
+
+	/// Free the space used by removed items in the key-value store of this directory.
+	///
+	/// Released space at the end of the object is zeroed, which frees the underlying records.
+	/// Keys of removed items remain stale even if their space is reused.
+	///
+	/// Returns the amount of bytes released from the end of the store.
+	pub async fn compact(&self) -> Result<u64, Error<D>> {
+		trace!("compact {:#x}", self.id);
+		let _lock = self.fs.lock_dir_mut(self.id).await;
+		self.kv().compact().await
+	}
+
+	/// Get the quota of this directory.
+	///
+	/// Returns `None` if there is no quota.
+	pub async fn quota(&self) -> Result<Option<u64>, Error<D>> {
+		trace!("quota {:#x}", self.id);
+		let quota = header(self.fs, self.id).await?.quota;
+		Ok((quota != u64::MAX).then_some(quota))
+	}
+
+	/// Set the maximum total length of all files in this directory and its descendants.
+	///
+	/// `None` removes the quota.
+	///
+	/// The quota may be lower than the current usage,
+	/// in which case files can only shrink until usage falls below the quota.
+	///
+	/// # Errors
+	///
+	/// [`Error::UnsupportedVersion`] if the filesystem has format version 0,
+	/// which has no quotas.
+	pub async fn set_quota(&self, quota: Option<u64>) -> Result<(), Error<D>> {
+		trace!("set_quota {:#x} {:?}", self.id, quota);
+		assert!(!self.fs.read_only, "read only");
+		if !self.fs.has_dir_header() {
+			return Err(Error::UnsupportedVersion { version: 0 });
+		}
+		write_header(self.fs, self.id, HDR_QUOTA_OFFT, quota.unwrap_or(u64::MAX)).await
+	}
+
+	/// Get the total length of all files in this directory and its descendants.
+	///
+	/// This is always 0 if the filesystem has format version 0.
+	pub async fn usage(&self) -> Result<u64, Error<D>> {
+		trace!("usage {:#x}", self.id);
+		Ok(header(self.fs, self.id).await?.usage)
+	}
+
+	/// Get the amount of items in this directory.
+	///
+	/// The count is kept in the item of this directory,
+	/// so no iteration is necessary.
+	pub async fn len(&self) -> Result<u64, Error<D>> {
+		trace!("len {:#x}", self.id);
+		self.item.len().await
+	}
+
+	/// Get the hash algorithm used to index the names of items.
+	pub async fn hasher(&self) -> Result<Hasher, Error<D>> {
+		self.kv().hasher().await
+	}
+
+	/// Whether names are matched case-insensitively.
+	pub async fn is_case_insensitive(&self) -> Result<bool, Error<D>> {
+		self.kv().fold_case().await
+	}
+
 	pub(crate) fn kv(&self) -> Kv<'a, D> {
-		nrkv::Nrkv::wrap(Store { fs: self.fs, id: self.id }, nrkv::StaticConf)
+		let conf = DirConf { has_header: self.fs.has_dir_header() };
+		nrkv::Nrkv::wrap(Store { fs: self.fs, id: self.id }, conf)
 	}
 
 	async fn update_item_count(&self, incr: bool) -> Result<(), Error<D>> {
@@ -258,6 +1088,132 @@ impl<'a, D: Dev> Dir<'a, D> {
 	}
 }
 
+/// Copy the attributes and modification time of an item to another item.
+pub(crate) async fn copy_meta<D: Dev>(
+	from: &Item<'_, D>,
+	to: &Item<'_, D>,
+) -> Result<(), Error<D>> {
+	for k in from.attr_keys().await? {
+		let v = from.attr(&k).await?.unwrap();
+		to.set_attr(&k, &v).await?.unwrap();
+	}
+	to.set_modified(from.modified().await?).await
+}
+
+/// Fields of the header of a directory.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct DirHeader {
+	parent: u64,
+	quota: u64,
+	usage: u64,
+}
+
+/// Add to the usage of a directory and all its ancestors.
+///
+/// If `check` is `true` and the usage would exceed the quota of any of the directories,
+/// no usage is updated and `false` is returned.
+pub(crate) async fn charge<D: Dev>(
+	fs: &Nrfs<D>,
+	mut id: u64,
+	amount: u64,
+	check: bool,
+) -> Result<bool, Error<D>> {
+	trace!("charge {:#x} {}", id, amount);
+	if amount == 0 {
+		return Ok(true);
+	}
+	// Concurrent updates would otherwise overwrite each other's usage.
+	let _lock = fs.usage_lock.lock_exclusive().await;
+	let mut chain = vec![];
+	while id != u64::MAX {
+		let hdr = header(fs, id).await?;
+		let usage = hdr.usage.saturating_add(amount);
+		if check && usage > hdr.quota {
+			return Ok(false);
+		}
+		chain.push((id, usage));
+		id = hdr.parent;
+	}
+	for (id, usage) in chain {
+		set_usage(fs, id, usage).await?;
+	}
+	Ok(true)
+}
+
+/// Subtract from the usage of a directory and all its ancestors.
+pub(crate) async fn discharge<D: Dev>(
+	fs: &Nrfs<D>,
+	mut id: u64,
+	amount: u64,
+) -> Result<(), Error<D>> {
+	trace!("discharge {:#x} {}", id, amount);
+	if amount == 0 {
+		return Ok(());
+	}
+	let _lock = fs.usage_lock.lock_exclusive().await;
+	while id != u64::MAX {
+		let hdr = header(fs, id).await?;
+		set_usage(fs, id, hdr.usage.saturating_sub(amount)).await?;
+		id = hdr.parent;
+	}
+	Ok(())
+}
+
+/// Get the header of a directory.
+///
+/// Headers are cached until the transaction finishes.
+///
+/// Directories without a header have no parent, no quota and no usage.
+async fn header<D: Dev>(fs: &Nrfs<D>, id: u64) -> Result<DirHeader, Error<D>> {
+	if !fs.has_dir_header() {
+		return Ok(DirHeader { parent: u64::MAX, quota: u64::MAX, usage: 0 });
+	}
+	if let Some(&hdr) = fs.dir_headers.borrow().get(&id) {
+		return Ok(hdr);
+	}
+	let buf = &mut [0; 24];
+	fs.get(id).read(0, buf).await?;
+	let f = |offt: u64| u64::from_le_bytes(buf[offt as usize..][..8].try_into().unwrap());
+	let hdr = DirHeader {
+		parent: f(HDR_PARENT_OFFT),
+		quota: f(HDR_QUOTA_OFFT),
+		usage: f(HDR_USAGE_OFFT),
+	};
+	fs.dir_headers.borrow_mut().insert(id, hdr);
+	Ok(hdr)
+}
+
+/// Write the usage of a directory.
+async fn set_usage<D: Dev>(fs: &Nrfs<D>, id: u64, usage: u64) -> Result<(), Error<D>> {
+	if !fs.has_dir_header() {
+		return Ok(());
+	}
+	fs.get(id)
+		.write(HDR_USAGE_OFFT, &usage.to_le_bytes())
+		.await?;
+	if let Some(hdr) = fs.dir_headers.borrow_mut().get_mut(&id) {
+		hdr.usage = usage;
+	}
+	Ok(())
+}
+
+/// Write a field to the header of a directory.
+///
+/// The cached header is discarded.
+async fn write_header<D: Dev>(
+	fs: &Nrfs<D>,
+	id: u64,
+	offset: u64,
+	value: u64,
+) -> Result<(), Error<D>> {
+	if !fs.has_dir_header() {
+		return Ok(());
+	}
+	fs.dir_headers.borrow_mut().remove(&id);
+	fs.get(id).write(offset, &value.to_le_bytes()).await?;
+	Ok(())
+}
+
 impl<'a, D: Dev> Deref for Dir<'a, D> {
 	type Target = Item<'a, D>;
 
@@ -266,6 +1222,14 @@ impl<'a, D: Dev> Deref for Dir<'a, D> {
 	}
 }
 
+/// A handle to an item returned by [`Dir::open`].
+#[derive(Debug)]
+pub enum Entry<'a, D: Dev> {
+	Dir(Dir<'a, D>),
+	File(File<'a, D>),
+	Sym(File<'a, D>),
+}
+
 /// An error that occured while trying to insert an entry.
 #[derive(Clone, Debug)]
 pub enum CreateError {
@@ -273,6 +1237,10 @@ pub enum CreateError {
 	Duplicate,
 	/// The directory is full.
 	Full,
+	/// The requested key is already in use.
+	Occupied,
+	/// The directory would exceed the maximum nesting depth.
+	TooDeep,
 }
 
 #[derive(Clone, Debug)]
@@ -287,6 +1255,21 @@ pub enum TransferError {
 	Duplicate,
 	/// The target directory is full.
 	Full,
+	/// The directory would exceed the maximum nesting depth.
+	TooDeep,
+	/// A file is too large for the target filesystem.
+	TooLarge,
+}
+
+impl From<CreateError> for TransferError {
+	fn from(err: CreateError) -> Self {
+		match err {
+			CreateError::Duplicate => Self::Duplicate,
+			CreateError::Full => Self::Full,
+			CreateError::Occupied => unreachable!("no key was requested"),
+			CreateError::TooDeep => Self::TooDeep,
+		}
+	}
 }
 
 impl fmt::Display for CreateError {
@@ -294,6 +1277,8 @@ impl fmt::Display for CreateError {
 		match self {
 			Self::Duplicate => "duplicate",
 			Self::Full => "full",
+			Self::Occupied => "occupied",
+			Self::TooDeep => "too deep",
 		}
 		.fmt(f)
 	}
@@ -313,6 +1298,8 @@ impl fmt::Display for TransferError {
 		match self {
 			Self::Duplicate => "duplicate",
 			Self::Full => "full",
+			Self::TooDeep => "too deep",
+			Self::TooLarge => "too large",
 		}
 		.fmt(f)
 	}
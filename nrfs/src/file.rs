@@ -4,35 +4,13 @@ use crate::Item;
 
 use {
 	crate::{
-		dir::{Dir, Kv},
-		Dev, Error, ItemKey,
+		dir::{self, Dir, Kv},
+		CreateError, Dev, Error, ItemKey, Nrfs,
 	},
 	core::fmt,
+	nrkv::Key,
 };
 
-/// How many multiples of the block size a file should be before it is unembedded.
-///
-/// Waste calculation: `waste = 1 - blocks / (blocks + 1)`
-///
-/// Some factors for reference:
-///
-/// +--------+------------------------------+--------------------+-------------------+
-/// | Factor | Maximum waste (Uncompressed) | Maximum size (512) | Maximum size (4K) |
-/// +========+==============================+====================+===================+
-/// |      1 |                          50% |                512 |                4K |
-/// +--------+------------------------------+--------------------+-------------------+
-/// |      2 |                          33% |                 1K |                8K |
-/// +--------+------------------------------+--------------------+-------------------+
-/// |      3 |                          25% |               1.5K |               12K |
-/// +--------+------------------------------+--------------------+-------------------+
-/// |      4 |                          20% |                 2K |               16K |
-/// +--------+------------------------------+--------------------+-------------------+
-/// |      5 |                        16.6% |               2.5K |               20K |
-/// +--------+------------------------------+--------------------+-------------------+
-///
-/// * Maximum waste = how much data may be padding if stored as an object.
-const EMBED_FACTOR: u64 = 4;
-
 /// Helper structure for working with files.
 #[derive(Debug)]
 pub struct File<'a, D: Dev> {
@@ -100,6 +78,11 @@ impl<'a, D: Dev> File<'a, D> {
 
 	/// Write an exact amount of data,
 	/// growing the object if necessary.
+	///
+	/// # Errors
+	///
+	/// [`Error::QuotaExceeded`] if growing the file would exceed the quota of any ancestor.
+	/// The file is left unchanged.
 	pub async fn write_grow(
 		&self,
 		offset: u64,
@@ -126,51 +109,201 @@ impl<'a, D: Dev> File<'a, D> {
 				Data::Embed { offset: offt, .. } => kv.write(offt + offset, data).await?,
 			}
 		} else {
-			match &mut dat {
-				Data::Object { id, length, .. } => {
-					self.fs.get(*id).write(offset, data).await?;
-					*length = end;
-				}
-				Data::Embed { offset: offt, length, capacity, .. }
-					if u64::from(*capacity) >= end =>
-				{
-					kv.write(*offt + offset, data).await?;
-					*length = end.try_into().unwrap();
-				}
-				Data::Embed { offset: offt, length, capacity, .. }
-					if self.embed_factor() >= end =>
-				{
-					let _dir_lock = self.fs.lock_dir_mut(self.key.dir).await;
-					let keep_len = u64::from(*length).min(end).try_into().unwrap();
-					let new_cap = (end * 3 / 2).min(u16::MAX.into());
-					let mut buf = vec![0; keep_len];
-					kv.read(*offt, &mut buf).await?;
-					kv.dealloc(*offt, (*capacity).into()).await?;
-					let o = kv.alloc(new_cap).await?;
-					kv.write(o.get(), &buf).await?;
-					kv.write(o.get() + offset, data).await?;
-					*offt = o.get();
-					*length = end.try_into().unwrap();
-					*capacity = new_cap.try_into().unwrap();
-				}
-				&mut Data::Embed { offset: offt, length, capacity, is_sym } => {
-					let _dir_lock = self.fs.lock_dir_mut(self.key.dir).await;
-					let keep_len = u64::from(length).min(end).try_into().unwrap();
-					let mut buf = vec![0; keep_len];
-					kv.read(offt, &mut buf).await?;
-					kv.dealloc(offt, capacity.into()).await?;
-					let obj = self.fs.storage.create().await?;
-					obj.write(0, &buf).await?;
-					obj.write(offset, data).await?;
-					dat = Data::Object { is_sym, id: obj.id(), length: end };
+			let grown = end - dat.len();
+			if !dir::charge(self.fs, self.key.dir, grown, true).await? {
+				return Err(Error::QuotaExceeded);
+			}
+			let r = async {
+				match &mut dat {
+					Data::Object { id, length, .. } => {
+						self.fs.get(*id).write(offset, data).await?;
+						*length = end;
+					}
+					Data::Embed { offset: offt, length, capacity, .. }
+						if u64::from(*capacity) >= end =>
+					{
+						kv.write(*offt + offset, data).await?;
+						*length = end.try_into().unwrap();
+					}
+					Data::Embed { offset: offt, length, capacity, .. }
+						if self.embed_factor() >= end =>
+					{
+						let _dir_lock = self.fs.lock_dir_mut(self.key.dir).await;
+						let keep_len = u64::from(*length).min(end).try_into().unwrap();
+						let new_cap = (end * 3 / 2).min(u16::MAX.into());
+						let mut buf = vec![0; keep_len];
+						kv.read(*offt, &mut buf).await?;
+						kv.dealloc(*offt, (*capacity).into()).await?;
+						let o = kv.alloc(new_cap).await?;
+						kv.write(o.get(), &buf).await?;
+						kv.write(o.get() + offset, data).await?;
+						*offt = o.get();
+						*length = end.try_into().unwrap();
+						*capacity = new_cap.try_into().unwrap();
+					}
+					&mut Data::Embed { offset: offt, length, capacity, is_sym } => {
+						let _dir_lock = self.fs.lock_dir_mut(self.key.dir).await;
+						let keep_len = u64::from(length).min(end).try_into().unwrap();
+						let mut buf = vec![0; keep_len];
+						kv.read(offt, &mut buf).await?;
+						kv.dealloc(offt, capacity.into()).await?;
+						let obj = self.fs.storage.create().await?;
+						obj.write(0, &buf).await?;
+						obj.write(offset, data).await?;
+						dat = Data::Object { is_sym, id: obj.id(), length: end };
+					}
 				}
+				self.set_data(kv, dat).await
+			};
+			if let Err(e) = r.await {
+				dir::discharge(self.fs, self.key.dir, grown).await?;
+				return Err(e);
 			}
-			self.set_data(kv, dat).await?;
 		}
 		Ok(Ok(()))
 	}
 
+	/// Write an exact amount of data at or near the end of the file,
+	/// growing the file if necessary.
+	///
+	/// Unlike [`File::write_grow`], the new length of an unembedded file is only kept in memory.
+	/// It is written to the descriptor on [`File::flush`] or when the transaction finishes,
+	/// which avoids rewriting the descriptor for every append.
+	///
+	/// # Errors
+	///
+	/// [`Error::QuotaExceeded`] if growing the file would exceed the quota of any ancestor.
+	/// The file is left unchanged.
+	pub async fn append(
+		&self,
+		offset: u64,
+		data: &[u8],
+	) -> Result<Result<(), LengthTooLong>, Error<D>> {
+		trace!("append {} (len: {})", offset, data.len());
+		assert!(!self.fs.read_only, "read only");
+		if data.is_empty() {
+			return Ok(Ok(()));
+		}
+
+		let Some(end) = calc_end(offset, data.len()) else { return Ok(Err(LengthTooLong)) };
+		if end > self.fs.storage.obj_max_len() {
+			return Ok(Err(LengthTooLong));
+		}
+
+		let (_, dat) = self.data().await?;
+		let Data::Object { id, length, .. } = dat else {
+			return self.write_grow(offset, data).await;
+		};
+		if end <= length {
+			self.fs.get(id).write(offset, data).await?;
+			return Ok(Ok(()));
+		}
+
+		if !dir::charge(self.fs, self.key.dir, end - length, true).await? {
+			return Err(Error::QuotaExceeded);
+		}
+		if let Err(e) = self.fs.get(id).write(offset, data).await {
+			dir::discharge(self.fs, self.key.dir, end - length).await?;
+			return Err(e.into());
+		}
+		self.fs
+			.pending_lengths
+			.borrow_mut()
+			.entry(self.key)
+			.and_modify(|l| *l = (*l).max(end))
+			.or_insert(end);
+		Ok(Ok(()))
+	}
+
+	/// Write data like [`File::append`],
+	/// but stop early instead of failing if the quota of an ancestor would be exceeded.
+	///
+	/// The data is first written at once.
+	/// Only if that fails it is written in blocks, as many as fit.
+	/// The returned value indicates how many bytes were written.
+	///
+	/// Data is written to the cache,
+	/// so the store running out of space is generally only detected when it is flushed,
+	/// e.g. by [`Nrfs::finish_transaction`].
+	/// Hence a partial write in practice only happens when a quota is reached.
+	///
+	/// # Errors
+	///
+	/// [`Error::QuotaExceeded`] or [`nros::Error::NotEnoughSpace`] if no data could be written
+	/// at all.
+	pub async fn append_partial(
+		&self,
+		offset: u64,
+		data: &[u8],
+	) -> Result<Result<usize, LengthTooLong>, Error<D>> {
+		trace!("append_partial {} (len: {})", offset, data.len());
+		let Some(end) = calc_end(offset, data.len()) else { return Ok(Err(LengthTooLong)) };
+		if end > self.fs.storage.obj_max_len() {
+			return Ok(Err(LengthTooLong));
+		}
+
+		match self.append(offset, data).await {
+			Ok(r) => return Ok(r.map(|()| data.len())),
+			Err(e) if is_full(&e) => {}
+			Err(e) => return Err(e),
+		}
+
+		let block_size = 1 << self.fs.block_size().to_raw();
+		let mut done = 0;
+		while done < data.len() {
+			// Align chunks to blocks.
+			let offt = offset + u64::try_from(done).unwrap();
+			let n = block_size - usize::try_from(offt % block_size as u64).unwrap();
+			let d = &data[done..(done + n).min(data.len())];
+			match self.append(offt, d).await {
+				Ok(r) => r.unwrap(),
+				Err(e) if done > 0 && is_full(&e) => break,
+				Err(e) => return Err(e),
+			}
+			done += d.len();
+		}
+		Ok(Ok(done))
+	}
+
+	/// Write the length of the file to its descriptor if it has been deferred by
+	/// [`File::append`].
+	pub async fn flush(&self) -> Result<(), Error<D>> {
+		trace!("flush");
+		if !self.fs.pending_lengths.borrow().contains_key(&self.key) {
+			return Ok(());
+		}
+		let (kv, dat) = self.data().await?;
+		self.set_data(kv, dat).await
+	}
+
+	/// Write the modified data in a byte range to the devices.
+	///
+	/// Other modified data of the file is left in the cache.
+	/// Embedded data is part of the directory and is left as is.
+	/// The range is clamped to the length of the file.
+	///
+	/// The data only becomes part of the filesystem when the transaction finishes.
+	pub async fn sync_range(&self, offset: u64, len: u64) -> Result<(), Error<D>> {
+		trace!("sync_range {} (len: {})", offset, len);
+		match self.data().await?.1 {
+			Data::Object { id, length, .. } => {
+				let end = offset.saturating_add(len).min(length);
+				self.fs.get(id).flush_range(offset..end).await?;
+			}
+			Data::Embed { .. } => {}
+		}
+		Ok(())
+	}
+
 	/// Resize the file.
+	///
+	/// Data past the new length is zeroed when shrinking,
+	/// so growing the file again never exposes stale data.
+	///
+	/// # Errors
+	///
+	/// [`Error::QuotaExceeded`] if growing the file would exceed the quota of any ancestor.
+	/// The file is left unchanged.
 	pub async fn resize(&self, new_len: u64) -> Result<Result<(), LengthTooLong>, Error<D>> {
 		trace!("resize {}", new_len);
 		assert!(!self.fs.read_only, "read only");
@@ -184,6 +317,7 @@ impl<'a, D: Dev> File<'a, D> {
 			return Ok(Ok(()));
 		}
 		if dat.len() > new_len {
+			dir::discharge(self.fs, self.key.dir, dat.len() - new_len).await?;
 			match &mut dat {
 				&mut Data::Object { id, is_sym, .. } if new_len == 0 => {
 					self.fs.get(id).dealloc().await?;
@@ -199,36 +333,172 @@ impl<'a, D: Dev> File<'a, D> {
 					*length = new_len.try_into().unwrap();
 				}
 			}
+			self.set_data(kv, dat).await?;
 		} else {
-			match &mut dat {
-				Data::Object { length, .. } => *length = new_len,
-				Data::Embed { length, capacity, .. } if u64::from(*capacity) >= new_len => {
-					*length = new_len.try_into().unwrap()
+			let grown = new_len - dat.len();
+			if !dir::charge(self.fs, self.key.dir, grown, true).await? {
+				return Err(Error::QuotaExceeded);
+			}
+			let r = async {
+				match &mut dat {
+					Data::Object { length, .. } => *length = new_len,
+					Data::Embed { length, capacity, .. } if u64::from(*capacity) >= new_len => {
+						*length = new_len.try_into().unwrap()
+					}
+					Data::Embed { length, capacity, offset, .. }
+						if self.embed_factor() >= new_len =>
+					{
+						let _dir_lock = self.fs.lock_dir_mut(self.key.dir).await;
+						let mut buf = vec![0; (*length).into()];
+						kv.read(*offset, &mut buf).await?;
+						kv.dealloc(*offset, (*capacity).into()).await?;
+						let offt = kv.alloc(new_len).await?;
+						kv.write(offt.get(), &buf).await?;
+						*offset = offt.get();
+						*length = new_len.try_into().unwrap();
+						*capacity = new_len.try_into().unwrap();
+					}
+					&mut Data::Embed { length, capacity, offset, is_sym } => {
+						let _dir_lock = self.fs.lock_dir_mut(self.key.dir).await;
+						let mut buf = vec![0; length.into()];
+						kv.read(offset, &mut buf).await?;
+						kv.dealloc(offset, capacity.into()).await?;
+						let obj = self.fs.storage.create().await?;
+						obj.write(0, &buf).await?;
+						dat = Data::Object { is_sym, id: obj.id(), length: new_len };
+					}
 				}
-				Data::Embed { length, capacity, offset, .. } if self.embed_factor() >= new_len => {
-					let _dir_lock = self.fs.lock_dir_mut(self.key.dir).await;
-					let mut buf = vec![0; (*length).into()];
-					kv.read(*offset, &mut buf).await?;
-					kv.dealloc(*offset, (*capacity).into()).await?;
-					let offt = kv.alloc(new_len).await?;
-					kv.write(offt.get(), &buf).await?;
-					*offset = offt.get();
-					*length = new_len.try_into().unwrap();
-					*capacity = new_len.try_into().unwrap();
+				self.set_data(kv, dat).await
+			};
+			if let Err(e) = r.await {
+				dir::discharge(self.fs, self.key.dir, grown).await?;
+				return Err(e);
+			}
+		}
+		Ok(Ok(()))
+	}
+
+	/// Read the target of a symbolic link.
+	///
+	/// # Errors
+	///
+	/// [`LinkError::LengthTooLong`] if the target is longer than [`Nrfs::MAX_LINK_LEN`],
+	/// which can only happen if the filesystem is corrupt.
+	pub async fn read_link(&self) -> Result<Result<Vec<u8>, LinkError>, Error<D>> {
+		trace!("read_link");
+		let (_, dat) = self.data().await?;
+		if !dat.is_sym() {
+			return Ok(Err(LinkError::NotSymlink));
+		}
+		let len = match usize::try_from(dat.len()) {
+			Ok(l) if l <= Nrfs::<D>::MAX_LINK_LEN => l,
+			_ => return Ok(Err(LinkError::LengthTooLong)),
+		};
+		let mut buf = vec![0; len];
+		let l = self.read(0, &mut buf).await?;
+		buf.truncate(l);
+		Ok(Ok(buf))
+	}
+
+	/// Set the target of a symbolic link.
+	///
+	/// This replaces the previous target.
+	///
+	/// # Errors
+	///
+	/// [`LinkError::LengthTooLong`] if the target is longer than [`Nrfs::MAX_LINK_LEN`].
+	pub async fn set_link_target(&self, target: &[u8]) -> Result<Result<(), LinkError>, Error<D>> {
+		trace!("set_link_target (len: {})", target.len());
+		let (_, dat) = self.data().await?;
+		if !dat.is_sym() {
+			return Ok(Err(LinkError::NotSymlink));
+		}
+		if target.len() > Nrfs::<D>::MAX_LINK_LEN {
+			return Ok(Err(LinkError::LengthTooLong));
+		}
+		let len = u64::try_from(target.len()).unwrap();
+		if self.resize(len).await?.is_err() {
+			return Ok(Err(LinkError::LengthTooLong));
+		}
+		self.write(0, target).await?;
+		Ok(Ok(()))
+	}
+
+	/// Copy this file to a new entry in a directory.
+	///
+	/// The attributes and modification time are copied too.
+	///
+	/// If the data is stored in a separate object, records are copied directly
+	/// as much as possible instead of copying every byte through a buffer.
+	///
+	/// This fails if an item with the given name already exists.
+	///
+	/// # Errors
+	///
+	/// [`Error::QuotaExceeded`] if the copy would exceed the quota of any ancestor of `dir`.
+	/// No entry is created in this case.
+	///
+	/// # Panics
+	///
+	/// If `dir` is on another filesystem.
+	pub async fn copy_to(
+		&self,
+		dir: &Dir<'a, D>,
+		name: &Key,
+	) -> Result<Result<File<'a, D>, CreateError>, Error<D>> {
+		trace!("copy_to {:?} -> {:#x} {:?}", self.key, dir.id, name);
+		assert!(core::ptr::eq(self.fs, dir.fs), "different filesystem");
+		assert!(!self.fs.read_only, "read only");
+
+		let (_, dat) = self.data().await?;
+		let to = if dat.is_sym() {
+			dir.create_sym(name).await?
+		} else {
+			dir.create_file(name).await?.map(|(f, _)| f)
+		};
+		let to = match to {
+			Ok(f) => f,
+			Err(e) => return Ok(Err(e)),
+		};
+
+		if let Err(e) = self.copy_data(&to, dat).await {
+			dir.remove(to.key()).await?.unwrap();
+			return Err(e);
+		}
+		Ok(Ok(to))
+	}
+
+	/// Copy the metadata and data of this file to an empty file.
+	async fn copy_data(&self, to: &File<'a, D>, dat: Data) -> Result<(), Error<D>> {
+		dir::copy_meta(&self.item, &to.item).await?;
+		match dat {
+			Data::Embed { offset, length, .. } => {
+				let mut buf = vec![0; length.into()];
+				self.dir().kv().read(offset, &mut buf).await?;
+				to.write_grow(0, &buf).await?.unwrap();
+			}
+			Data::Object { is_sym, id, length } => {
+				let obj = self.fs.storage.create().await?;
+				if !dir::charge(self.fs, to.key.dir, length, true).await? {
+					obj.dealloc().await?;
+					return Err(Error::QuotaExceeded);
 				}
-				&mut Data::Embed { length, capacity, offset, is_sym } => {
-					let _dir_lock = self.fs.lock_dir_mut(self.key.dir).await;
-					let mut buf = vec![0; length.into()];
-					kv.read(offset, &mut buf).await?;
-					kv.dealloc(offset, capacity.into()).await?;
-					let obj = self.fs.storage.create().await?;
-					obj.write(0, &buf).await?;
-					dat = Data::Object { is_sym, id: obj.id(), length: new_len };
+				// Point the entry to the object before copying,
+				// so removing the entry on failure frees the object and the charge.
+				let r = async {
+					let (kv, _) = to.data().await?;
+					to.set_data(kv, Data::Object { is_sym, id: obj.id(), length })
+						.await
+				};
+				if let Err(e) = r.await {
+					dir::discharge(self.fs, to.key.dir, length).await?;
+					obj.dealloc().await?;
+					return Err(e);
 				}
+				obj.copy_from(&self.fs.get(id), 0, 0, length).await?;
 			}
 		}
-		self.set_data(kv, dat).await?;
-		Ok(Ok(()))
+		Ok(())
 	}
 
 	pub async fn is_embed(&self) -> Result<bool, Error<D>> {
@@ -238,6 +508,14 @@ impl<'a, D: Dev> File<'a, D> {
 		Ok(matches!(ty[0] & 7, 4 | 5))
 	}
 
+	/// Get the ID of the object holding the data of this file, if any.
+	pub(crate) async fn object_id(&self) -> Result<Option<u64>, Error<D>> {
+		Ok(match self.data().await?.1 {
+			Data::Object { id, .. } => Some(id),
+			Data::Embed { .. } => None,
+		})
+	}
+
 	/// Create stub dir helper.
 	///
 	/// # Note
@@ -249,18 +527,26 @@ impl<'a, D: Dev> File<'a, D> {
 
 	/// Determine the embed factor.
 	fn embed_factor(&self) -> u64 {
-		let embed_lim = EMBED_FACTOR << self.fs.block_size().to_raw();
+		let embed_lim = u64::from(self.fs.embed_factor()) << self.fs.block_size().to_raw();
 		u64::from(u16::MAX).min(embed_lim)
 	}
 
 	async fn data(&self) -> Result<(Kv<'a, D>, Data), Error<D>> {
 		let mut kv = self.dir().kv();
+		self.check_live(&mut kv).await?;
 		let buf = &mut [0; 16];
 		kv.read_user_data(self.key.tag, 0, buf).await?;
-		Ok((kv, Data::from_raw(*buf)))
+		let mut data = Data::from_raw(*buf);
+		if let Data::Object { length, .. } = &mut data {
+			if let Some(&l) = self.fs.pending_lengths.borrow().get(&self.key) {
+				*length = l;
+			}
+		}
+		Ok((kv, data))
 	}
 
 	async fn set_data(&self, mut kv: Kv<'_, D>, data: Data) -> Result<(), Error<D>> {
+		self.fs.pending_lengths.borrow_mut().remove(&self.key);
 		kv.write_user_data(self.key.tag, 0, &data.into_raw()).await
 	}
 }
@@ -324,6 +610,12 @@ impl Data {
 			Self::Embed { length, .. } => u64::from(*length),
 		}
 	}
+
+	fn is_sym(&self) -> bool {
+		match self {
+			&Self::Object { is_sym, .. } | &Self::Embed { is_sym, .. } => is_sym,
+		}
+	}
 }
 
 /// Error returned if the length is larger than supported.
@@ -338,7 +630,36 @@ impl fmt::Display for LengthTooLong {
 
 impl core::error::Error for LengthTooLong {}
 
+/// An error that occured while trying to access the target of a symbolic link.
+#[derive(Clone, Debug)]
+pub enum LinkError {
+	/// The item is not a symbolic link.
+	NotSymlink,
+	/// The target is longer than supported.
+	LengthTooLong,
+}
+
+impl fmt::Display for LinkError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			Self::NotSymlink => "not a symlink",
+			Self::LengthTooLong => "length too long",
+		}
+		.fmt(f)
+	}
+}
+
+impl core::error::Error for LinkError {}
+
 fn calc_end(offset: u64, len: usize) -> Option<u64> {
 	let len = u64::try_from(len).ok()?;
 	offset.checked_add(len)
 }
+
+/// Whether an error indicates there is no room left for more data.
+fn is_full<D: Dev>(e: &Error<D>) -> bool {
+	matches!(
+		e,
+		Error::QuotaExceeded | Error::Nros(nros::Error::NotEnoughSpace)
+	)
+}
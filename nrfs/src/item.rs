@@ -1,5 +1,5 @@
 use {
-	crate::{dir::Kv, Dir, Error, Nrfs, HDR_ROOT_OFFT},
+	crate::{attr::AttrMap, dir::Kv, Dir, Error, Nrfs, HDR_ROOT_OFFT},
 	alloc::borrow::Cow,
 	core::fmt,
 	nrkv::{Key, Tag},
@@ -70,6 +70,7 @@ impl<'a, D: Dev> Item<'a, D> {
 
 	async fn read_attr(&self) -> Result<(Kv<'a, D>, (u64, u16), Vec<u8>), Error<D>> {
 		let mut kv = self.parent_kv();
+		self.check_live(&mut kv).await?;
 
 		let a = &mut [0; 8];
 		kv.read_user_data(self.key.tag, ATTR_OFFT, a).await?;
@@ -128,61 +129,83 @@ impl<'a, D: Dev> Item<'a, D> {
 		Ok(None)
 	}
 
+	/// Get multiple attributes at once.
+	///
+	/// Unlike calling [`Item::attr`] for each attribute,
+	/// the attribute list is only read once.
+	///
+	/// The values are returned in the same order as `keys`.
+	pub async fn attrs(&self, keys: &[&Key]) -> Result<Vec<Option<Vec<u8>>>, Error<D>> {
+		let mut values = vec![None; keys.len()];
+		if self.key.dir == u64::MAX {
+			return Ok(values);
+		}
+		let mut attr_map = self.fs.attr_map().await?;
+		let mut ids = Vec::with_capacity(keys.len());
+		for key in keys {
+			ids.push(attr_map.get_attr(key).await?);
+		}
+		let (_, _, attr) = self.read_attr().await?;
+		let mut attr = &*attr;
+		while let Some((i, val)) = attr_next(&mut attr) {
+			for (id, v) in ids.iter().zip(&mut values) {
+				if *id == Some(i) {
+					*v = Some(val.into());
+				}
+			}
+		}
+		Ok(values)
+	}
+
 	pub async fn set_attr(
 		&self,
 		key: &Key,
 		value: &[u8],
+	) -> Result<Result<(), SetAttrError>, Error<D>> {
+		self.set_attrs(&[(key, value)]).await
+	}
+
+	/// Set multiple attributes at once.
+	///
+	/// Unlike calling [`Item::set_attr`] for each attribute,
+	/// the attribute list is only reallocated once.
+	///
+	/// If an error is returned none of the attributes are set.
+	pub async fn set_attrs(
+		&self,
+		attrs: &[(&Key, &[u8])],
 	) -> Result<Result<(), SetAttrError>, Error<D>> {
 		if self.key.dir == u64::MAX {
 			return Ok(Err(SetAttrError::IsRoot));
 		}
 
 		let (kv, addr, mut attr) = self.read_attr().await?;
-		if attr.len() + (8 + 1 + value.len().min(8)) > usize::from(u16::MAX) {
+		let add_len = attrs
+			.iter()
+			.map(|(_, value)| 8 + 1 + value.len().min(8))
+			.sum::<usize>();
+		if attr.len() + add_len > usize::from(u16::MAX) {
 			return Ok(Err(SetAttrError::Full));
 		}
 
 		let mut attr_map = self.fs.attr_map().await?;
-		let id = 'new: {
-			if let Some(id) = attr_map.get_attr(key).await? {
-				let mut a = &*attr;
-				let mut start = 0;
-				while let Some((i, _)) = attr_next(&mut a) {
-					let end = attr.len() - a.len();
-					if i == id {
-						attr.drain(start..end);
-						break 'new id;
-					}
-					start = end;
-				}
-			}
-			attr_map.ref_attr(key).await?
-		};
-
-		let mut id = id.get();
-		while id > 0x7fff {
-			let b = (id as u16 | 0x8000).to_le_bytes();
-			attr.extend_from_slice(&b);
-			id >>= 15;
-		}
-		let b = (id as u16).to_le_bytes();
-		attr.extend_from_slice(&b);
-
-		if value.len() < 255 {
-			attr.push(value.len() as _);
-		} else {
-			let l = u32::try_from(value.len()).unwrap();
-			attr.push(255);
-			attr.extend_from_slice(&l.to_le_bytes());
+		for &(key, value) in attrs {
+			put_attr(&mut attr_map, &mut attr, key, value).await?;
 		}
-		attr.extend_from_slice(value);
 
 		self.write_attr(kv, addr, attr).await?;
 
 		Ok(Ok(()))
 	}
 
+	/// Remove an attribute.
+	///
+	/// Returns whether the attribute existed.
+	/// The root directory has no attributes, so nothing is removed from it.
 	pub async fn del_attr(&self, key: &Key) -> Result<bool, Error<D>> {
+		if self.key.dir == u64::MAX {
+			return Ok(false);
+		}
 		let mut attr_map = self.fs.attr_map().await?;
 		let Some(id) = attr_map.get_attr(key).await? else { return Ok(false) };
 
@@ -208,13 +231,30 @@ impl<'a, D: Dev> Item<'a, D> {
 		if self.key.dir == u64::MAX {
 			buf.copy_from_slice(&self.fs.storage.header_data()[..16]);
 		} else {
-			let mut kv = Dir::new(self.fs, ItemKey::INVAL, self.key.dir).kv();
+			let mut kv = self.parent_kv();
+			self.check_live(&mut kv).await?;
 			kv.read_user_data(self.key.tag, 0, buf).await?;
 		}
 		let len = u64::from_le_bytes(buf[8..].try_into().unwrap());
 		Ok(match ItemTy::from_raw(buf[0] & 7).unwrap() {
 			ItemTy::Dir | ItemTy::EmbedFile | ItemTy::EmbedSym => len & 0xffff_ffff,
-			ItemTy::File | ItemTy::Sym => len,
+			ItemTy::File | ItemTy::Sym => self.pending_len().unwrap_or(len),
+		})
+	}
+
+	/// Get the amount of bytes this item counts towards the quota of its ancestors.
+	pub(crate) async fn usage(&self) -> Result<u64, Error<D>> {
+		let buf = &mut [0; 16];
+		let mut kv = self.parent_kv();
+		self.check_live(&mut kv).await?;
+		kv.read_user_data(self.key.tag, 0, buf).await?;
+		let a = u64::from_le_bytes(buf[..8].try_into().unwrap());
+		let b = u64::from_le_bytes(buf[8..].try_into().unwrap());
+		Ok(match a & 7 {
+			1 => Dir::new(self.fs, self.key, a >> 5).usage().await?,
+			2 | 3 => self.pending_len().unwrap_or(b),
+			4 | 5 => b & 0xffff,
+			_ => return Err(Error::CorruptItem),
 		})
 	}
 
@@ -225,9 +265,9 @@ impl<'a, D: Dev> Item<'a, D> {
 				&self.fs.storage.header_data()[HDR_ROOT_OFFT..][MODIFIED_OFFT.into()..][..16],
 			);
 		} else {
-			self.parent_kv()
-				.read_user_data(self.key.tag, MODIFIED_OFFT, buf)
-				.await?;
+			let mut kv = self.parent_kv();
+			self.check_live(&mut kv).await?;
+			kv.read_user_data(self.key.tag, MODIFIED_OFFT, buf).await?;
 		}
 		Ok(Modified {
 			time: i64::from_le_bytes(buf[..8].try_into().unwrap()),
@@ -244,9 +284,9 @@ impl<'a, D: Dev> Item<'a, D> {
 				.copy_from_slice(buf);
 			Ok(())
 		} else {
-			self.parent_kv()
-				.write_user_data(self.key.tag, MODIFIED_OFFT, buf)
-				.await
+			let mut kv = self.parent_kv();
+			self.check_live(&mut kv).await?;
+			kv.write_user_data(self.key.tag, MODIFIED_OFFT, buf).await
 		}
 	}
 
@@ -256,8 +296,9 @@ impl<'a, D: Dev> Item<'a, D> {
 				.copy_from_slice(&time.to_le_bytes());
 			Ok(())
 		} else {
-			self.parent_kv()
-				.write_user_data(self.key.tag, MODIFIED_OFFT, &time.to_le_bytes())
+			let mut kv = self.parent_kv();
+			self.check_live(&mut kv).await?;
+			kv.write_user_data(self.key.tag, MODIFIED_OFFT, &time.to_le_bytes())
 				.await
 		}
 	}
@@ -268,20 +309,117 @@ impl<'a, D: Dev> Item<'a, D> {
 				.copy_from_slice(&gen.to_le_bytes());
 			Ok(())
 		} else {
-			self.parent_kv()
-				.write_user_data(self.key.tag, MODIFIED_OFFT + 8, &gen.to_le_bytes())
+			let mut kv = self.parent_kv();
+			self.check_live(&mut kv).await?;
+			kv.write_user_data(self.key.tag, MODIFIED_OFFT + 8, &gen.to_le_bytes())
 				.await
 		}
 	}
 
+	/// Get the time the metadata of this item was last changed,
+	/// in microseconds since the UNIX epoch.
+	///
+	/// Returns `None` if no change time is recorded.
+	pub async fn ctime(&self) -> Result<Option<i64>, Error<D>> {
+		self.time_attr(b"nrfs.ctime".into()).await
+	}
+
+	/// Set the time the metadata of this item was last changed,
+	/// in microseconds since the UNIX epoch.
+	pub async fn set_ctime(&self, time: i64) -> Result<Result<(), SetAttrError>, Error<D>> {
+		self.set_attr(b"nrfs.ctime".into(), &time.to_le_bytes())
+			.await
+	}
+
+	/// Get the time the data of this item was last accessed,
+	/// in microseconds since the UNIX epoch.
+	///
+	/// Returns `None` if no access time is recorded.
+	pub async fn atime(&self) -> Result<Option<i64>, Error<D>> {
+		self.time_attr(b"nrfs.atime".into()).await
+	}
+
+	/// Set the time the data of this item was last accessed,
+	/// in microseconds since the UNIX epoch.
+	pub async fn set_atime(&self, time: i64) -> Result<Result<(), SetAttrError>, Error<D>> {
+		self.set_attr(b"nrfs.atime".into(), &time.to_le_bytes())
+			.await
+	}
+
+	/// Read a timestamp stored as attribute.
+	///
+	/// Values with an unexpected length are treated as absent.
+	async fn time_attr(&self, key: &Key) -> Result<Option<i64>, Error<D>> {
+		let Some(val) = self.attr(key).await? else { return Ok(None) };
+		Ok(val.try_into().ok().map(i64::from_le_bytes))
+	}
+
 	pub fn key(&self) -> ItemKey {
 		self.key
 	}
 
+	/// Check whether both items refer to the same data,
+	/// e.g. if one is a hard link to the other.
+	pub async fn same_object(&self, other: &Item<'_, D>) -> Result<bool, Error<D>> {
+		if !core::ptr::eq(self.fs, other.fs) {
+			return Ok(false);
+		}
+		Ok(self.data_id().await? == other.data_id().await?)
+	}
+
+	/// Get an identifier for the data of this item.
+	///
+	/// This is the ID of the object for unembedded items.
+	/// Embedded data can't be shared, so the key of the item is used instead.
+	async fn data_id(&self) -> Result<(u64, u64), Error<D>> {
+		let a = &mut [0; 8];
+		if self.key.dir == u64::MAX {
+			a.copy_from_slice(&self.fs.storage.header_data()[..8]);
+		} else {
+			let mut kv = self.parent_kv();
+			self.check_live(&mut kv).await?;
+			kv.read_user_data(self.key.tag, 0, a).await?;
+		}
+		let a = u64::from_le_bytes(*a);
+		Ok(match a & 7 {
+			1 | 2 | 3 => (u64::MAX, a >> 5),
+			4 | 5 => (self.key.dir, self.key.tag.get()),
+			_ => return Err(Error::CorruptItem),
+		})
+	}
+
+	/// Get the length deferred by [`File::append`](crate::File::append), if any.
+	fn pending_len(&self) -> Option<u64> {
+		self.fs.pending_lengths.borrow().get(&self.key).copied()
+	}
+
 	fn parent_kv(&self) -> Kv<'a, D> {
 		Dir::new(self.fs, ItemKey::INVAL, self.key.dir).kv()
 	}
 
+	/// Check whether the item still exists, i.e. it has not been removed.
+	///
+	/// Keys of removed items are not reused by new items,
+	/// unless the tag generation of the directory wraps around.
+	pub async fn exists(&self) -> Result<bool, Error<D>> {
+		if self.key.dir == u64::MAX {
+			return Ok(true);
+		}
+		self.parent_kv().is_live(self.key.tag).await
+	}
+
+	/// Ensure the item has not been removed.
+	///
+	/// # Errors
+	///
+	/// [`Error::Stale`] if the item has been removed.
+	pub(crate) async fn check_live(&self, kv: &mut Kv<'a, D>) -> Result<(), Error<D>> {
+		if self.key.dir != u64::MAX && !kv.is_live(self.key.tag).await? {
+			return Err(Error::Stale);
+		}
+		Ok(())
+	}
+
 	pub(crate) async fn realloc(
 		&self,
 		to_dir: &Dir<'a, D>,
@@ -331,6 +469,7 @@ impl<'a, D: Dev> Item<'a, D> {
 				if ty == 1 && b != 0 {
 					return Ok(false);
 				}
+				self.fs.dir_headers.borrow_mut().remove(&(a >> 5));
 				self.fs.get(a >> 5).dealloc().await?;
 			}
 			4 | 5 => {
@@ -343,6 +482,7 @@ impl<'a, D: Dev> Item<'a, D> {
 		let attr = u64::from_le_bytes(buf[ATTR_OFFT.into()..].try_into().unwrap());
 		let (offt, len) = (attr >> 16, attr as u16);
 		kv.dealloc(offt, len.into()).await?;
+		self.fs.pending_lengths.borrow_mut().remove(&self.key);
 		Ok(true)
 	}
 }
@@ -370,6 +510,49 @@ pub struct Modified {
 	pub gen: i64,
 }
 
+/// Add or replace an attribute in an attribute list.
+async fn put_attr<D: Dev>(
+	attr_map: &mut AttrMap<'_, D>,
+	attr: &mut Vec<u8>,
+	key: &Key,
+	value: &[u8],
+) -> Result<(), Error<D>> {
+	let id = 'new: {
+		if let Some(id) = attr_map.get_attr(key).await? {
+			let mut a = &attr[..];
+			let mut start = 0;
+			while let Some((i, _)) = attr_next(&mut a) {
+				let end = attr.len() - a.len();
+				if i == id {
+					attr.drain(start..end);
+					break 'new id;
+				}
+				start = end;
+			}
+		}
+		attr_map.ref_attr(key).await?
+	};
+
+	let mut id = id.get();
+	while id > 0x7fff {
+		let b = (id as u16 | 0x8000).to_le_bytes();
+		attr.extend_from_slice(&b);
+		id >>= 15;
+	}
+	let b = (id as u16).to_le_bytes();
+	attr.extend_from_slice(&b);
+
+	if value.len() < 255 {
+		attr.push(value.len() as _);
+	} else {
+		let l = u32::try_from(value.len()).unwrap();
+		attr.push(255);
+		attr.extend_from_slice(&l.to_le_bytes());
+	}
+	attr.extend_from_slice(value);
+	Ok(())
+}
+
 fn attr_next<'a>(attr: &mut &'a [u8]) -> Option<(nrkv::Tag, &'a [u8])> {
 	if attr.is_empty() {
 		return None;
@@ -64,26 +64,37 @@ mod item;
 mod lock;
 #[cfg(test)]
 mod test;
+mod verify;
+mod walk;
 
 pub use {
 	config::{LoadConfig, NewConfig},
 	core::cell::RefCell,
-	dir::{CreateError, Dir, RemoveError, TransferError},
-	file::{File, LengthTooLong},
+	dir::{CreateError, Dir, Entry, RemoveError, TransferError},
+	file::{File, LengthTooLong, LinkError},
 	item::{Item, ItemInfo, ItemKey, ItemTy, Modified, SetAttrError},
-	nrkv::Key,
+	nrkv::{Hasher, Key},
 	nros::{
-		dev, BlockSize, CipherType, Compression, Dev, KeyDeriver, KeyPassword, MaxRecordSize,
-		Resource,
+		dev, BlockSize, CachePolicy, CipherType, Compression, Dev, KeyDeriver, KeyPassword,
+		MaxRecordSize, Resource,
 	},
+	verify::VerifyReport,
+	walk::Walk,
 };
 
 use core::{fmt, future::Future, pin::Pin};
 
+use alloc::collections::BTreeMap;
+
 use util::task::{lock::Lock, lock_set::LockSet};
 
 const HDR_ROOT_OFFT: usize = 0;
 const HDR_ATTR_OFFT: usize = 40;
+const HDR_CREATED_OFFT: usize = 48;
+const HDR_MAX_DEPTH_OFFT: usize = 56;
+const HDR_EMBED_FACTOR_OFFT: usize = 60;
+const HDR_VERSION_OFFT: usize = 61;
+const HDR_LABEL_OFFT: usize = 64;
 
 /// NRFS filesystem manager.
 #[derive(Debug)]
@@ -96,11 +107,70 @@ pub struct Nrfs<D: Dev> {
 	dir_locks: LockSet<u64>,
 	/// Attribute map lock.
 	attr_map_lock: Lock,
+	/// Lengths of files which have been appended to
+	/// but whose descriptor has not been updated yet.
+	pending_lengths: RefCell<BTreeMap<ItemKey, u64>>,
+	/// Cached headers of directories,
+	/// which avoids reading every ancestor when accounting usage.
+	dir_headers: RefCell<BTreeMap<u64, dir::DirHeader>>,
+	/// Lock serializing updates to the usage of directories.
+	usage_lock: Lock,
 }
 
 impl<D: Dev> Nrfs<D> {
 	const MAGIC: [u8; 4] = *b"NRFS";
 
+	/// The version of the on-disk format.
+	///
+	/// Filesystems with a newer version are rejected on load.
+	/// Filesystems with version 0 have no directory headers
+	/// and are loaded without quotas.
+	pub const FORMAT_VERSION: u8 = 1;
+
+	/// The maximum length of a volume label.
+	pub const MAX_LABEL_LEN: usize = 64;
+
+	/// The maximum length of the target of a symbolic link.
+	pub const MAX_LINK_LEN: usize = 1 << 16;
+
+	/// The maximum nesting depth of directories if none has been set.
+	pub const DEFAULT_MAX_DEPTH: u32 = 1024;
+
+	/// How many multiples of the block size a file may be before it is unembedded
+	/// if no factor has been set.
+	///
+	/// Waste calculation: `waste = 1 - blocks / (blocks + 1)`
+	///
+	/// Some factors for reference:
+	///
+	/// +--------+------------------------------+--------------------+-------------------+
+	/// | Factor | Maximum waste (Uncompressed) | Maximum size (512) | Maximum size (4K) |
+	/// +========+==============================+====================+===================+
+	/// |      1 |                          50% |                512 |                4K |
+	/// +--------+------------------------------+--------------------+-------------------+
+	/// |      2 |                          33% |                 1K |                8K |
+	/// +--------+------------------------------+--------------------+-------------------+
+	/// |      3 |                          25% |               1.5K |               12K |
+	/// +--------+------------------------------+--------------------+-------------------+
+	/// |      4 |                          20% |                 2K |               16K |
+	/// +--------+------------------------------+--------------------+-------------------+
+	/// |      5 |                        16.6% |               2.5K |               20K |
+	/// +--------+------------------------------+--------------------+-------------------+
+	///
+	/// * Maximum waste = how much data may be padding if stored as an object.
+	pub const DEFAULT_EMBED_FACTOR: u8 = 4;
+
+	/// The maximum embed factor.
+	///
+	/// Embedded data is limited to 64KiB,
+	/// which this factor reaches even with the smallest block size.
+	pub const MAX_EMBED_FACTOR: u8 = 128;
+
+	/// Create a new filesystem.
+	///
+	/// # Errors
+	///
+	/// [`Error::InvalidEmbedFactor`] if the embed factor exceeds [`Self::MAX_EMBED_FACTOR`].
 	pub async fn new(config: NewConfig<'_, D>) -> Result<Self, Error<D>> {
 		let NewConfig {
 			mirrors,
@@ -109,8 +179,12 @@ impl<D: Dev> Nrfs<D> {
 			block_size,
 			max_record_size,
 			compression,
+			embed_factor,
 			cache_size,
 		} = config;
+		if embed_factor > Self::MAX_EMBED_FACTOR {
+			return Err(Error::InvalidEmbedFactor { embed_factor });
+		}
 		let conf = nros::NewConfig {
 			mirrors,
 			key_deriver,
@@ -119,6 +193,8 @@ impl<D: Dev> Nrfs<D> {
 			max_record_size,
 			compression,
 			cache_size,
+			soft_cache_size: None,
+			hard_cache_size: None,
 			resource: nros::StdResource::new(),
 			magic: Self::MAGIC,
 		};
@@ -129,41 +205,83 @@ impl<D: Dev> Nrfs<D> {
 			read_only: false,
 			dir_locks: Default::default(),
 			attr_map_lock: Default::default(),
+			pending_lengths: Default::default(),
+			dir_headers: Default::default(),
+			usage_lock: Default::default(),
 		};
-		let id = Dir::init(&s).await?;
+		s.storage.header_data_mut()[HDR_VERSION_OFFT] = Self::FORMAT_VERSION;
+		let id = Dir::init(&s, u64::MAX, Hasher::default(), false).await?;
 		s.storage.header_data_mut()[HDR_ROOT_OFFT..][..8]
 			.copy_from_slice(&(id << 5 | 1).to_le_bytes());
 
 		let id = attr::AttrMap::init(&s).await?;
 		s.storage.header_data_mut()[HDR_ATTR_OFFT..][..8].copy_from_slice(&id.to_le_bytes());
 
+		let created = std::time::SystemTime::now()
+			.duration_since(std::time::UNIX_EPOCH)
+			.map_or(0, |t| i64::try_from(t.as_micros()).unwrap_or(i64::MAX));
+		s.storage.header_data_mut()[HDR_CREATED_OFFT..][..8]
+			.copy_from_slice(&created.to_le_bytes());
+		s.storage.header_data_mut()[HDR_EMBED_FACTOR_OFFT] = embed_factor;
+
 		Ok(s)
 	}
 
+	/// Load an existing filesystem.
+	///
+	/// # Errors
+	///
+	/// [`Error::UnsupportedVersion`] if the filesystem has a newer format version.
 	pub async fn load(config: LoadConfig<'_, D>) -> Result<Self, Error<D>> {
 		trace!("load");
-		let LoadConfig { devices, cache_size, allow_repair, retrieve_key } = config;
+		let LoadConfig { devices, cache_size, cache_policy, allow_repair, retrieve_key } = config;
 		let conf = nros::LoadConfig {
 			devices,
 			cache_size,
+			soft_cache_size: None,
+			hard_cache_size: None,
+			cache_policy,
 			allow_repair,
+			scrub_only: false,
+			nonce_safety_margin: 0,
+			lazy_object_bitmap: false,
 			retrieve_key,
 			resource: nros::StdResource::new(),
 			magic: Self::MAGIC,
 		};
 		let storage = nros::Nros::load(conf).await?;
+		let version = storage.header_data()[HDR_VERSION_OFFT];
+		if version > Self::FORMAT_VERSION {
+			return Err(Error::UnsupportedVersion { version });
+		}
 		Ok(Self {
 			storage,
 			read_only: !allow_repair,
 			dir_locks: Default::default(),
 			attr_map_lock: Default::default(),
+			pending_lengths: Default::default(),
+			dir_headers: Default::default(),
+			usage_lock: Default::default(),
 		})
 	}
 
+	/// Check whether a key or password matches the filesystem.
+	///
+	/// Only the headers are read, the filesystem is not mounted.
+	///
+	/// Returns `false` if no key or password is provided.
+	pub async fn verify_key(
+		devices: &[D],
+		retrieve_key: &mut dyn FnMut(bool) -> Option<KeyPassword>,
+	) -> Result<bool, Error<D>> {
+		trace!("verify_key");
+		Ok(nros::Nros::<D, nros::StdResource>::verify_key(devices, retrieve_key).await?)
+	}
+
 	/// Get a reference to the root directory.
 	pub fn root_dir(&self) -> Dir<'_, D> {
 		let data = self.storage.header_data();
-		let id = u64::from_le_bytes(data[..8].try_into().unwrap()) >> 8;
+		let id = u64::from_le_bytes(data[..8].try_into().unwrap()) >> 5;
 		let key = ItemKey { dir: u64::MAX, tag: nrkv::Tag::MAX };
 		Dir::new(self, key, id)
 	}
@@ -177,6 +295,8 @@ impl<D: Dev> Nrfs<D> {
 	}
 
 	pub async fn finish_transaction(&self) -> Result<(), Error<D>> {
+		self.flush_lengths().await?;
+		self.dir_headers.borrow_mut().clear();
 		self.storage.finish_transaction().await.map_err(Error::Nros)
 	}
 
@@ -184,18 +304,46 @@ impl<D: Dev> Nrfs<D> {
 	///
 	/// This performs one last transaction.
 	pub async fn unmount(self) -> Result<Vec<D>, Error<D>> {
+		self.run(self.flush_lengths()).await?;
 		self.storage.unmount().await.map_err(Error::Nros)
 	}
 
+	/// Write all lengths deferred by [`File::append`] to their descriptors.
+	async fn flush_lengths(&self) -> Result<(), Error<D>> {
+		let keys = self
+			.pending_lengths
+			.borrow()
+			.keys()
+			.copied()
+			.collect::<Vec<_>>();
+		for key in keys {
+			self.file(key).flush().await?;
+		}
+		Ok(())
+	}
+
 	pub fn block_size(&self) -> BlockSize {
 		self.storage.block_size()
 	}
 
+	/// The cipher used to encrypt the filesystem.
+	pub fn cipher(&self) -> CipherType {
+		self.storage.cipher()
+	}
+
 	/// Get statistics for this session.
 	pub fn statistics(&self) -> Statistics {
 		Statistics { object_store: self.storage.statistics() }
 	}
 
+	/// Get the amount of used and free inodes.
+	///
+	/// Every directory and every file that isn't embedded uses an object,
+	/// so the object count is used as inode count.
+	pub fn inode_stats(&self) -> (u64, u64) {
+		self.storage.inode_stats()
+	}
+
 	/// Get the key used to encrypt the header.
 	pub fn header_key(&self) -> [u8; 32] {
 		self.storage.header_key()
@@ -208,21 +356,114 @@ impl<D: Dev> Nrfs<D> {
 		self.storage.set_key_deriver(kdf)
 	}
 
+	/// The unique identifier for this filesystem.
+	pub fn uid(&self) -> u128 {
+		self.storage.uid()
+	}
+
+	/// Get the time the filesystem was created, in microseconds since the UNIX epoch.
+	///
+	/// This is `0` for filesystems created before the time was recorded.
+	pub fn created_at(&self) -> i64 {
+		let mut t = [0; 8];
+		t.copy_from_slice(&self.storage.header_data()[HDR_CREATED_OFFT..][..8]);
+		i64::from_le_bytes(t)
+	}
+
+	/// Get the volume label.
+	///
+	/// The label is empty if none has been set.
+	pub fn label(&self) -> Vec<u8> {
+		let data = self.storage.header_data();
+		let label = &data[HDR_LABEL_OFFT..][..Self::MAX_LABEL_LEN];
+		let len = label.iter().rposition(|&c| c != 0).map_or(0, |i| i + 1);
+		label[..len].into()
+	}
+
+	/// Set the volume label.
+	///
+	/// The label is only used to identify the filesystem and has no special meaning otherwise.
+	/// Trailing zeros are not preserved.
+	pub fn set_label(&self, label: &[u8]) -> Result<(), LengthTooLong> {
+		trace!("set_label {:?}", label);
+		assert!(!self.read_only, "read only");
+		if label.len() > Self::MAX_LABEL_LEN {
+			return Err(LengthTooLong);
+		}
+		let mut data = self.storage.header_data_mut();
+		let l = &mut data[HDR_LABEL_OFFT..][..Self::MAX_LABEL_LEN];
+		l.fill(0);
+		l[..label.len()].copy_from_slice(label);
+		Ok(())
+	}
+
+	/// Whether directories have a header with their parent, quota and usage.
+	///
+	/// Filesystems with format version 0 were made before directories had a header.
+	pub(crate) fn has_dir_header(&self) -> bool {
+		self.storage.header_data()[HDR_VERSION_OFFT] != 0
+	}
+
+	/// Get the maximum nesting depth of directories.
+	///
+	/// The root directory is at depth 0.
+	pub fn max_depth(&self) -> u32 {
+		let mut d = [0; 4];
+		d.copy_from_slice(&self.storage.header_data()[HDR_MAX_DEPTH_OFFT..][..4]);
+		match u32::from_le_bytes(d) {
+			0 => Self::DEFAULT_MAX_DEPTH,
+			d => d,
+		}
+	}
+
+	/// Get how many multiples of the block size a file may be before its data is moved
+	/// to a separate object.
+	pub fn embed_factor(&self) -> u8 {
+		match self.storage.header_data()[HDR_EMBED_FACTOR_OFFT] {
+			0 => Self::DEFAULT_EMBED_FACTOR,
+			f => f,
+		}
+	}
+
+	/// Set the maximum nesting depth of directories.
+	///
+	/// Existing directories that are nested deeper are not affected,
+	/// but no new directories can be created inside them.
+	///
+	/// # Panics
+	///
+	/// If `depth` is `0`.
+	pub fn set_max_depth(&self, depth: u32) {
+		trace!("set_max_depth {}", depth);
+		assert!(!self.read_only, "read only");
+		assert!(depth > 0, "depth must be at least 1");
+		self.storage.header_data_mut()[HDR_MAX_DEPTH_OFFT..][..4]
+			.copy_from_slice(&depth.to_le_bytes());
+	}
+
 	/// Get an object.
 	fn get(&self, id: u64) -> nros::Object<'_, D, nros::StdResource> {
 		self.storage.get(id)
 	}
 
+	/// Get a directory.
+	///
+	/// # Errors
+	///
+	/// [`Error::NotADirectory`] if the item is not a directory.
 	pub async fn dir(&self, key: ItemKey) -> Result<Dir<'_, D>, Error<D>> {
 		trace!("dir {:?}", key);
 		let mut a = [0; 8];
 		if key.dir != u64::MAX {
 			let mut kv = Dir::new(self, ItemKey::INVAL, key.dir).kv();
+			self.item(key).check_live(&mut kv).await?;
 			kv.read_user_data(key.tag, 0, &mut a).await?;
 		} else {
 			a.copy_from_slice(&self.storage.header_data()[..8]);
 		}
-		assert_eq!(a[0] & 7, 1, "ty not a dir ({})", a[0] & 7);
+		if a[0] & 7 != 1 {
+			return Err(Error::NotADirectory);
+		}
 		Ok(Dir::new(self, key, u64::from_le_bytes(a) >> 5))
 	}
 
@@ -234,6 +475,13 @@ impl<D: Dev> Nrfs<D> {
 		Item::new(self, key)
 	}
 
+	/// Move data towards the start of the filesystem and finish the transaction.
+	///
+	/// Returns the minimum amount of blocks the filesystem can be resized to.
+	pub async fn shrink_to_fit(&self) -> Result<u64, Error<D>> {
+		Ok(self.storage.shrink_to_fit().await?)
+	}
+
 	pub async fn set_block_count(&self, blocks: u64) -> Result<(), Error<D>> {
 		self.storage.set_block_count(blocks).await?;
 		Ok(())
@@ -255,6 +503,22 @@ where
 	Nros(nros::Error<D>),
 	Truncated,
 	CorruptExtension,
+	/// The quota of a directory would be exceeded.
+	QuotaExceeded,
+	/// The item has been removed.
+	Stale,
+	/// The item is not a directory.
+	NotADirectory,
+	/// The type of an item is invalid, i.e. its directory is corrupt.
+	CorruptItem,
+	/// The filesystem has a format version that is not supported by this build.
+	UnsupportedVersion {
+		version: u8,
+	},
+	/// The embed factor exceeds [`Nrfs::MAX_EMBED_FACTOR`].
+	InvalidEmbedFactor {
+		embed_factor: u8,
+	},
 }
 
 impl<D> fmt::Debug for Error<D>
@@ -267,6 +531,18 @@ where
 			Self::Nros(e) => f.debug_tuple("Nros").field(e).finish(),
 			Self::Truncated => f.debug_tuple("Truncated").finish(),
 			Self::CorruptExtension => f.debug_tuple("CorruptExtension").finish(),
+			Self::QuotaExceeded => f.debug_tuple("QuotaExceeded").finish(),
+			Self::Stale => f.debug_tuple("Stale").finish(),
+			Self::NotADirectory => f.debug_tuple("NotADirectory").finish(),
+			Self::CorruptItem => f.debug_tuple("CorruptItem").finish(),
+			Self::UnsupportedVersion { version } => f
+				.debug_struct("UnsupportedVersion")
+				.field("version", version)
+				.finish(),
+			Self::InvalidEmbedFactor { embed_factor } => f
+				.debug_struct("InvalidEmbedFactor")
+				.field("embed_factor", embed_factor)
+				.finish(),
 		}
 	}
 }
@@ -344,6 +620,8 @@ impl<'a, D: Dev> nrkv::Store for Store<'a, D> {
 		})
 	}
 
+	/// Objects are sparse and always span the maximum object length,
+	/// so the store is as large as any object can be.
 	fn len(&self) -> u64 {
 		self.fs.storage.obj_max_len()
 	}
@@ -61,6 +61,37 @@ fn del_attr() {
 	});
 }
 
+#[test]
+fn del_attr_middle() {
+	let fs = new();
+	run(&fs, async {
+		let f = mkfile(&fs.root_dir(), b"file").await;
+		for (k, v) in [(b"a", b"one"), (b"b", b"two"), (b"c", b"six")] {
+			f.set_attr(k.into(), v).await.unwrap().unwrap();
+		}
+		assert!(f.del_attr(b"b".into()).await.unwrap());
+		assert!(f.attr(b"b".into()).await.unwrap().is_none());
+		let v = f.attr(b"a".into()).await.unwrap().unwrap();
+		assert_eq!(b"one", &*v);
+		let v = f.attr(b"c".into()).await.unwrap().unwrap();
+		assert_eq!(b"six", &*v);
+		assert_eq!(f.attr_keys().await.unwrap().len(), 2);
+	});
+}
+
+#[test]
+fn del_attr_root() {
+	let fs = new();
+	run(&fs, async {
+		let f = mkfile(&fs.root_dir(), b"file").await;
+		f.set_attr(b"hello".into(), b"world")
+			.await
+			.unwrap()
+			.unwrap();
+		assert!(!fs.root_dir().del_attr(b"hello".into()).await.unwrap());
+	});
+}
+
 #[test]
 fn get_attr_multi() {
 	let fs = new();
@@ -145,3 +176,125 @@ fn many_attrs() {
 		}
 	});
 }
+
+#[test]
+fn stale_attr() {
+	let fs = new();
+	run(&fs, async {
+		let d = fs.root_dir();
+		let f = mkfile(&d, b"file").await;
+		f.set_attr(b"hello".into(), b"world")
+			.await
+			.unwrap()
+			.unwrap();
+		assert!(f.exists().await.unwrap());
+
+		d.remove(f.key()).await.unwrap().unwrap();
+		assert!(!f.exists().await.unwrap());
+		assert!(matches!(f.attr(b"hello".into()).await, Err(Error::Stale)));
+		assert!(matches!(f.modified().await, Err(Error::Stale)));
+		assert!(d.exists().await.unwrap());
+	});
+}
+
+#[test]
+fn ctime_atime() {
+	let fs = new();
+	run(&fs, async {
+		let f = mkfile(&fs.root_dir(), b"file").await;
+		assert_eq!(f.ctime().await.unwrap(), None);
+		assert_eq!(f.atime().await.unwrap(), None);
+		f.set_ctime(1234).await.unwrap().unwrap();
+		f.set_atime(-5678).await.unwrap().unwrap();
+		assert!(matches!(
+			fs.root_dir().set_ctime(1).await.unwrap(),
+			Err(SetAttrError::IsRoot)
+		));
+	});
+
+	let devices = block_on(fs.unmount()).unwrap();
+	let fs = block_on(Nrfs::load(LoadConfig {
+		devices,
+		cache_size: 1 << 12,
+		cache_policy: Default::default(),
+		allow_repair: true,
+		retrieve_key: &mut |_| unreachable!(),
+	}))
+	.unwrap();
+	run(&fs, async {
+		let f = fs.root_dir().search(b"file".into()).await.unwrap().unwrap();
+		let f = fs.item(f.key);
+		assert_eq!(f.ctime().await.unwrap(), Some(1234));
+		assert_eq!(f.atime().await.unwrap(), Some(-5678));
+		assert_eq!(fs.root_dir().ctime().await.unwrap(), None);
+	});
+}
+
+#[test]
+fn set_attrs() {
+	let fs = new();
+	run(&fs, async {
+		let attrs: &[(&Key, &[u8])] = &[
+			(b"hello".into(), b"world"),
+			(b"cheers".into(), b"mate"),
+			(b"hello".into(), b"earth"),
+			(b"long".into(), &[7; 300]),
+		];
+
+		let a = mkfile(&fs.root_dir(), b"a").await;
+		for &(k, v) in attrs {
+			a.set_attr(k, v).await.unwrap().unwrap();
+		}
+		let b = mkfile(&fs.root_dir(), b"b").await;
+		b.set_attrs(attrs).await.unwrap().unwrap();
+
+		let mut keys_a = a.attr_keys().await.unwrap();
+		let mut keys_b = b.attr_keys().await.unwrap();
+		keys_a.sort();
+		keys_b.sort();
+		assert_eq!(keys_a, keys_b);
+		assert_eq!(keys_b.len(), 3);
+		for k in keys_a {
+			assert_eq!(
+				a.attr(&k).await.unwrap().unwrap(),
+				b.attr(&k).await.unwrap().unwrap()
+			);
+		}
+		let v = b.attr(b"hello".into()).await.unwrap().unwrap();
+		assert_eq!(b"earth", &*v);
+
+		assert!(matches!(
+			fs.root_dir().set_attrs(attrs).await.unwrap(),
+			Err(SetAttrError::IsRoot)
+		));
+	});
+}
+
+#[test]
+fn get_attrs() {
+	let fs = new();
+	run(&fs, async {
+		let f = mkfile(&fs.root_dir(), b"file").await;
+		f.set_attrs(&[(b"hello".into(), b"world"), (b"cheers".into(), b"mate")])
+			.await
+			.unwrap()
+			.unwrap();
+		f.set_attr(b"unused".into(), b"").await.unwrap().unwrap();
+		f.del_attr(b"unused".into()).await.unwrap();
+
+		let keys: &[&Key] = &[
+			b"cheers".into(),
+			b"missing".into(),
+			b"hello".into(),
+			b"unused".into(),
+		];
+		let v = f.attrs(keys).await.unwrap();
+		assert_eq!(
+			v,
+			[Some(b"mate".to_vec()), None, Some(b"world".to_vec()), None]
+		);
+
+		let v = fs.root_dir().attrs(keys).await.unwrap();
+		assert_eq!(v, [None, None, None, None]);
+	});
+}
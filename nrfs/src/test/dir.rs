@@ -24,6 +24,62 @@ fn create_sym() {
 	});
 }
 
+#[test]
+fn create_sym_with_target_short() {
+	let fs = new();
+	run(&fs, async {
+		let f = fs
+			.root_dir()
+			.create_sym_with_target(b"sym".into(), b"target")
+			.await
+			.unwrap()
+			.unwrap()
+			.unwrap();
+		assert!(f.is_embed().await.unwrap());
+		assert_eq!(f.read_link().await.unwrap().unwrap(), b"target");
+		let sym = fs.root_dir().search(b"sym".into()).await.unwrap().unwrap();
+		assert_eq!(sym.ty, ItemTy::EmbedSym);
+
+		assert!(matches!(
+			fs.root_dir()
+				.create_sym_with_target(b"sym".into(), b"other")
+				.await
+				.unwrap(),
+			Ok(Err(CreateError::Duplicate))
+		));
+	});
+}
+
+#[test]
+fn create_sym_with_target_long() {
+	let fs = new();
+	run(&fs, async {
+		let target = (0..1 << 13).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+		let f = fs
+			.root_dir()
+			.create_sym_with_target(b"sym".into(), &target)
+			.await
+			.unwrap()
+			.unwrap()
+			.unwrap();
+		assert!(!f.is_embed().await.unwrap());
+		assert_eq!(f.read_link().await.unwrap().unwrap(), target);
+		let sym = fs.root_dir().search(b"sym".into()).await.unwrap().unwrap();
+		assert_eq!(sym.ty, ItemTy::Sym);
+	});
+}
+
+#[test]
+fn dir_not_a_directory() {
+	let fs = new();
+	run(&fs, async {
+		let f = mkfile(&fs.root_dir(), b"file").await;
+		assert!(matches!(fs.dir(f.key()).await, Err(Error::NotADirectory)));
+		let s = mksym(&fs.root_dir(), b"sym").await;
+		assert!(matches!(fs.dir(s.key()).await, Err(Error::NotADirectory)));
+	});
+}
+
 #[test]
 fn get_file() {
 	let fs = new();
@@ -54,6 +110,36 @@ fn get_sym() {
 	});
 }
 
+#[test]
+fn open() {
+	let fs = new();
+	run(&fs, async {
+		let d = mkdir(&fs.root_dir(), b"dir").await;
+		mkfile(&d, b"file").await;
+		mksym(&fs.root_dir(), b"sym").await;
+
+		let Some(Entry::Dir(d)) = fs.root_dir().open(b"dir".into()).await.unwrap()
+			else { panic!("expected dir") };
+		let Some(Entry::File(f)) = d.open(b"file".into()).await.unwrap()
+			else { panic!("expected file") };
+		f.write_grow(0, b"Hello!").await.unwrap().unwrap();
+		mkfile(&d, b"other").await;
+
+		let Some(Entry::File(f)) = d.open(b"file".into()).await.unwrap()
+			else { panic!("expected file") };
+		let buf = &mut [0; 6];
+		assert_eq!(f.read(0, buf).await.unwrap(), 6);
+		assert_eq!(buf, b"Hello!");
+		assert!(d.search(b"other".into()).await.unwrap().is_some());
+
+		assert!(matches!(
+			fs.root_dir().open(b"sym".into()).await.unwrap(),
+			Some(Entry::Sym(_))
+		));
+		assert!(fs.root_dir().open(b"none".into()).await.unwrap().is_none());
+	});
+}
+
 #[test]
 fn destroy_file() {
 	let fs = new();
@@ -104,6 +190,323 @@ fn destroy_sym() {
 	});
 }
 
+#[test]
+fn remove_tree() {
+	let fs = new();
+	run(&fs, async {
+		let (used, _) = fs.inode_stats();
+
+		let root = fs.root_dir();
+		let a = mkdir(&root, b"a").await;
+		let f = mkfile(&a, b"file").await;
+		f.write_grow(0, &[0; 1 << 16]).await.unwrap().unwrap();
+		mksym(&a, b"sym").await;
+		let b = mkdir(&a, b"b").await;
+		let c = mkdir(&b, b"c").await;
+		mkfile(&c, b"file").await;
+		mkdir(&b, b"d").await;
+		assert!(fs.inode_stats().0 > used);
+
+		c.remove_tree().await.unwrap().unwrap();
+		assert!(b.search(b"c".into()).await.unwrap().is_none());
+
+		a.remove_tree().await.unwrap().unwrap();
+		assert!(root.next_from(0).await.unwrap().is_none());
+		assert_eq!(fs.inode_stats().0, used);
+	});
+}
+
+#[test]
+fn walk() {
+	let fs = new();
+	run(&fs, async {
+		let root = fs.root_dir();
+		let a = mkdir(&root, b"a").await;
+		mkfile(&a, b"x").await;
+		let b = mkdir(&a, b"b").await;
+		mksym(&b, b"y").await;
+		mkfile(&root, b"f").await;
+
+		let fs = &fs;
+		let collect = |max_depth| async move {
+			let mut w = fs.walk(fs.root_dir().key(), max_depth).await.unwrap();
+			let mut paths = vec![];
+			while let Some((path, info)) = w.next().await.unwrap() {
+				assert_eq!(path.last().unwrap(), &info.name.into_owned());
+				let path = path.iter().map(|k| &***k).collect::<Vec<_>>().join(&b'/');
+				paths.push(path);
+			}
+			paths
+		};
+
+		let paths = collect(u32::MAX).await;
+		assert_eq!(paths, collect(u32::MAX).await);
+		// Parents are yielded before their children.
+		for (i, p) in paths.iter().enumerate() {
+			if let Some(n) = p.iter().rposition(|&c| c == b'/') {
+				assert!(paths[..i].contains(&p[..n].to_vec()), "{:?}", paths);
+			}
+		}
+		let mut paths = paths;
+		paths.sort();
+		let expect: [&[u8]; 5] = [b"a", b"a/b", b"a/b/y", b"a/x", b"f"];
+		assert_eq!(paths, expect);
+
+		let mut paths = collect(1).await;
+		paths.sort();
+		let expect: [&[u8]; 2] = [b"a", b"f"];
+		assert_eq!(paths, expect);
+
+		let mut paths = collect(2).await;
+		paths.sort();
+		let expect: [&[u8]; 4] = [b"a", b"a/b", b"a/x", b"f"];
+		assert_eq!(paths, expect);
+	});
+}
+
+#[test]
+fn case_insensitive() {
+	let fs = new();
+	run(&fs, async {
+		let root = fs.root_dir();
+		let d = mkdir(&root, b"default").await;
+		let ci = root
+			.create_dir_case_insensitive(b"ci".into(), Hasher::default())
+			.await
+			.unwrap()
+			.unwrap();
+		assert!(!d.is_case_insensitive().await.unwrap());
+		assert!(ci.is_case_insensitive().await.unwrap());
+
+		mkfile(&d, b"Foo").await;
+		mkfile(&ci, b"Foo").await;
+		assert!(d.search(b"foo".into()).await.unwrap().is_none());
+		assert!(ci.search(b"foo".into()).await.unwrap().is_some());
+		assert!(ci.search(b"FOO".into()).await.unwrap().is_some());
+		assert!(ci.search(b"fo".into()).await.unwrap().is_none());
+
+		mkfile(&d, b"foo").await;
+		assert!(matches!(
+			ci.create_file(b"FOO".into()).await.unwrap(),
+			Err(CreateError::Duplicate)
+		));
+
+		// The name is stored as given.
+		let (info, _) = ci.next_from(0).await.unwrap().unwrap();
+		assert_eq!(&**info.name, b"Foo");
+	});
+}
+
+#[test]
+fn case_insensitive_rename_case() {
+	let fs = new();
+	run(&fs, async {
+		let root = fs.root_dir();
+		let ci = root
+			.create_dir_case_insensitive(b"ci".into(), Hasher::default())
+			.await
+			.unwrap()
+			.unwrap();
+		mkfile(&ci, b"foo").await;
+		mkfile(&ci, b"bar").await;
+
+		let key = ci
+			.rename_within(b"foo".into(), b"FOO".into())
+			.await
+			.unwrap()
+			.unwrap()
+			.unwrap();
+		assert_eq!(ci.len().await.unwrap(), 2);
+		assert_eq!(ci.search(b"foo".into()).await.unwrap().unwrap().key, key);
+		let entries = ci.all_entries().await.unwrap();
+		let mut names = entries.iter().map(|e| e.name.to_vec()).collect::<Vec<_>>();
+		names.sort();
+		assert_eq!(names, [&b"FOO"[..], b"bar"]);
+
+		let key = ci.transfer(key, &ci, b"Foo".into()).await.unwrap().unwrap();
+		assert_eq!(ci.len().await.unwrap(), 2);
+		assert_eq!(ci.search(b"FOO".into()).await.unwrap().unwrap().key, key);
+
+		// Other items still conflict.
+		assert!(matches!(
+			ci.rename_within(b"foo".into(), b"BAR".into())
+				.await
+				.unwrap(),
+			Some(Err(TransferError::Duplicate))
+		));
+		assert!(matches!(
+			ci.transfer(key, &ci, b"Bar".into()).await.unwrap(),
+			Err(TransferError::Duplicate)
+		));
+	});
+}
+
+#[test]
+fn kv_store_len() {
+	let fs = new();
+	run(&fs, async {
+		let d = mkdir(&fs.root_dir(), b"dir").await;
+		let kv = d.kv();
+		assert_eq!(nrkv::Store::len(kv.inner().0), fs.storage.obj_max_len());
+		let kv = fs.root_dir().kv();
+		assert_eq!(nrkv::Store::len(kv.inner().0), fs.storage.obj_max_len());
+	});
+}
+
+#[test]
+fn len() {
+	let fs = new();
+	run(&fs, async {
+		let root = fs.root_dir();
+		let d = mkdir(&root, b"dir").await;
+		assert_eq!(d.len().await.unwrap(), 0);
+		let a = mkfile(&d, b"a").await;
+		mkdir(&d, b"b").await;
+		mksym(&d, b"c").await;
+		assert_eq!(d.len().await.unwrap(), 3);
+		assert_eq!(root.len().await.unwrap(), 1);
+
+		d.remove(a.key()).await.unwrap().unwrap();
+		assert_eq!(d.len().await.unwrap(), 2);
+		let c = d.search(b"c".into()).await.unwrap().unwrap();
+		d.transfer(c.key, &root, b"c".into())
+			.await
+			.unwrap()
+			.unwrap();
+		assert_eq!(d.len().await.unwrap(), 1);
+		assert_eq!(root.len().await.unwrap(), 2);
+	});
+
+	let devices = block_on(fs.unmount()).unwrap();
+	let fs = block_on(Nrfs::load(LoadConfig {
+		devices,
+		cache_size: 1 << 12,
+		cache_policy: Default::default(),
+		allow_repair: true,
+		retrieve_key: &mut |_| unreachable!(),
+	}))
+	.unwrap();
+	run(&fs, async {
+		let root = fs.root_dir();
+		assert_eq!(root.len().await.unwrap(), 2);
+		let Some(Entry::Dir(d)) = root.open(b"dir".into()).await.unwrap()
+			else { panic!("expected dir") };
+		assert_eq!(d.len().await.unwrap(), 1);
+	});
+}
+
+#[test]
+fn relocate() {
+	let fs = new();
+	run(&fs, async {
+		let d = mkdir(&fs.root_dir(), b"dir").await;
+		let f = mkfile(&d, b"file").await;
+		f.write_grow(0, b"hello").await.unwrap().unwrap();
+		let g = mkfile(&d, b"large").await;
+		g.write_grow(0, &[1; 1 << 14]).await.unwrap().unwrap();
+		let s = mkdir(&d, b"sub").await;
+		let sf = mkfile(&s, b"file").await;
+		let (old_id, f_tag, s_tag, sf_key) = (d.id, f.key().tag, s.key().tag, sf.key());
+
+		let d = d.relocate().await.unwrap();
+		assert_ne!(d.id, old_id);
+		let info = fs.root_dir().search(b"dir".into()).await.unwrap().unwrap();
+		assert_eq!(fs.dir(info.key).await.unwrap().id, d.id);
+
+		let f = d.search(b"file".into()).await.unwrap().unwrap();
+		assert_eq!(f.key.tag, f_tag);
+		let buf = &mut [0; 5];
+		fs.file(f.key).read(0, buf).await.unwrap();
+		assert_eq!(buf, b"hello");
+
+		// The usage of the subdirectory must propagate to the relocated directory.
+		let s = fs.dir(ItemKey { dir: d.id, tag: s_tag }).await.unwrap();
+		let usage = d.usage().await.unwrap();
+		fs.file(sf_key)
+			.write_grow(0, b"world")
+			.await
+			.unwrap()
+			.unwrap();
+		assert_eq!(s.usage().await.unwrap(), 5);
+		assert_eq!(d.usage().await.unwrap(), usage + 5);
+
+		let root = fs.root_dir().relocate().await.unwrap();
+		assert_eq!(fs.root_dir().id, root.id);
+		let info = root.search(b"dir".into()).await.unwrap().unwrap();
+		assert_eq!(fs.dir(info.key).await.unwrap().id, d.id);
+
+		fs.finish_transaction().await.unwrap();
+		let report = fs.verify(false).await.unwrap();
+		assert!(report.is_ok(), "{:?}", report);
+	});
+}
+
+#[test]
+fn compact() {
+	let fs = new();
+	run(&fs, async {
+		let d = mkdir(&fs.root_dir(), b"dir").await;
+		let keep = mkfile(&d, b"keep").await;
+		keep.write_grow(0, b"Hello!").await.unwrap().unwrap();
+		let mut removed = vec![];
+		for i in 0..100 {
+			let f = mkfile(&d, format!("{}", i).as_bytes()).await;
+			removed.push(f.key());
+			d.remove(f.key()).await.unwrap().unwrap();
+		}
+		assert!(d.compact().await.unwrap() > 0);
+		assert_eq!(d.compact().await.unwrap(), 0);
+
+		let f = d.search(b"keep".into()).await.unwrap().unwrap();
+		let buf = &mut [0; 6];
+		fs.file(f.key).read(0, buf).await.unwrap();
+		assert_eq!(buf, b"Hello!");
+		mkfile(&d, b"0").await;
+
+		// Keys of removed items remain stale.
+		for key in removed {
+			assert!(!fs.item(key).exists().await.unwrap());
+		}
+	});
+}
+
+/// The filesystem header stores the root directory item as `id << 5 | 1`.
+///
+/// The root directory is created first and has ID 0,
+/// so decoding it with the wrong shift only fails once it is relocated.
+#[test]
+fn relocate_root_remount() {
+	let fs = new();
+	let id = block_on(fs.run(async {
+		for i in 0..16u8 {
+			mkdir(&fs.root_dir(), &[i]).await;
+		}
+		let root = fs.root_dir().relocate().await?;
+		assert!(root.id >= 1 << 3, "ID {} is too small", root.id);
+		assert_eq!(fs.root_dir().id, root.id);
+		Ok::<_, Error<_>>(root.id)
+	}))
+	.unwrap();
+
+	let devices = block_on(fs.unmount()).unwrap();
+	let fs = block_on(Nrfs::load(LoadConfig {
+		devices,
+		cache_size: 1 << 12,
+		cache_policy: Default::default(),
+		allow_repair: true,
+		retrieve_key: &mut |_| unreachable!(),
+	}))
+	.unwrap();
+	assert_eq!(fs.root_dir().id, id);
+	run(&fs, async {
+		for i in 0..16u8 {
+			let name = [i];
+			let item = fs.root_dir().search((&name).into()).await.unwrap();
+			assert!(item.is_some(), "{} not found", i);
+		}
+	});
+}
+
 #[test]
 fn transfer_self() {
 	let fs = new();
@@ -130,6 +533,58 @@ fn transfer_self() {
 	});
 }
 
+#[test]
+fn rename_within() {
+	let fs = new();
+	run(&fs, async {
+		let d = mkdir(&fs.root_dir(), b"dir").await;
+		let f = mkfile(&d, b"a").await;
+		f.write_grow(0, b"Hello!").await.unwrap().unwrap();
+		f.set_attr(b"x".into(), b"y").await.unwrap().unwrap();
+		mkfile(&d, b"c").await;
+		let usage = d.usage().await.unwrap();
+
+		let key = d
+			.rename_within(b"a".into(), b"b".into())
+			.await
+			.unwrap()
+			.unwrap()
+			.unwrap();
+		assert!(d.search(b"a".into()).await.unwrap().is_none());
+		assert_eq!(d.search(b"b".into()).await.unwrap().unwrap().key, key);
+		let f = fs.file(key);
+		let buf = &mut [0; 6];
+		f.read(0, buf).await.unwrap();
+		assert_eq!(buf, b"Hello!");
+		assert_eq!(f.attr(b"x".into()).await.unwrap().unwrap(), b"y");
+		assert_eq!(d.len().await.unwrap(), 2);
+		assert_eq!(d.usage().await.unwrap(), usage);
+
+		// No-op
+		let same = d
+			.rename_within(b"b".into(), b"b".into())
+			.await
+			.unwrap()
+			.unwrap()
+			.unwrap();
+		assert_eq!(same, key);
+
+		// Duplicate
+		assert!(matches!(
+			d.rename_within(b"b".into(), b"c".into()).await.unwrap(),
+			Some(Err(TransferError::Duplicate))
+		));
+		assert_eq!(d.search(b"b".into()).await.unwrap().unwrap().key, key);
+
+		// Not found
+		assert!(d
+			.rename_within(b"a".into(), b"d".into())
+			.await
+			.unwrap()
+			.is_none());
+	});
+}
+
 #[test]
 fn transfer_other() {
 	let fs = new();
@@ -185,3 +640,234 @@ fn create_remove_many_minicache() {
 		}
 	});
 }
+
+#[test]
+fn create_file_at_restore() {
+	let fs = new();
+	run(&fs, async {
+		let f = mkfile(&fs.root_dir(), b"file").await;
+		let key = f.key();
+		fs.root_dir().remove(key).await.unwrap().unwrap();
+		let f = fs
+			.root_dir()
+			.create_file_at(b"file".into(), key)
+			.await
+			.unwrap()
+			.unwrap();
+		assert_eq!(f.key(), key);
+		let info = fs.root_dir().search(b"file".into()).await.unwrap().unwrap();
+		assert_eq!(info.key, key);
+		assert_eq!(fs.file(key).len().await.unwrap(), 0);
+	});
+}
+
+#[test]
+fn create_file_at_occupied() {
+	let fs = new();
+	run(&fs, async {
+		let f = mkfile(&fs.root_dir(), b"file").await;
+		let r = fs
+			.root_dir()
+			.create_file_at(b"other".into(), f.key())
+			.await
+			.unwrap();
+		assert!(matches!(r, Err(CreateError::Occupied)));
+	});
+}
+
+#[test]
+fn quota() {
+	let fs = new();
+	run(&fs, async {
+		let root = fs.root_dir();
+		let dir = mkdir(&root, b"dir").await;
+		let sub = mkdir(&dir, b"sub").await;
+		dir.set_quota(Some(1000)).await.unwrap();
+		assert_eq!(dir.quota().await.unwrap(), Some(1000));
+
+		let f = mkfile(&sub, b"file").await;
+		f.write_grow(0, &[1; 600]).await.unwrap().unwrap();
+		f.resize(1000).await.unwrap().unwrap();
+		assert_eq!(dir.usage().await.unwrap(), 1000);
+		assert_eq!(root.usage().await.unwrap(), 1000);
+
+		let g = mkfile(&sub, b"other").await;
+		assert!(matches!(
+			g.write_grow(0, &[2; 1]).await,
+			Err(Error::QuotaExceeded)
+		));
+		assert!(matches!(f.resize(1001).await, Err(Error::QuotaExceeded)));
+		assert_eq!(f.len().await.unwrap(), 1000);
+		assert_eq!(g.len().await.unwrap(), 0);
+
+		// Shrinking frees up space again.
+		f.resize(500).await.unwrap().unwrap();
+		g.write_grow(0, &[2; 500]).await.unwrap().unwrap();
+		sub.remove(f.key()).await.unwrap().unwrap();
+		assert_eq!(dir.usage().await.unwrap(), 500);
+		assert_eq!(root.usage().await.unwrap(), 500);
+	});
+}
+
+#[test]
+fn move_to_same_fs() {
+	let fs = new();
+	run(&fs, async {
+		let root = fs.root_dir();
+		let dir = mkdir(&root, b"dir").await;
+		let f = mkfile(&root, b"file").await;
+		f.write_grow(0, b"Hello!").await.unwrap().unwrap();
+
+		let key = root
+			.move_to(f.key(), &dir, b"file".into())
+			.await
+			.unwrap()
+			.unwrap();
+		assert!(root.search(b"file".into()).await.unwrap().is_none());
+		assert_eq!(dir.search(b"file".into()).await.unwrap().unwrap().key, key);
+		let buf = &mut [0; 6];
+		assert_eq!(fs.file(key).read(0, buf).await.unwrap(), 6);
+		assert_eq!(buf, b"Hello!");
+	});
+}
+
+#[test]
+fn move_to_other_fs() {
+	let (fs, fs2) = (new(), new());
+	run(&fs, async {
+		let fut = async {
+			let root = fs.root_dir();
+			let dir = mkdir(&root, b"dir").await;
+			let f = mkfile(&dir, b"file").await;
+			f.write_grow(0, b"Hello!").await.unwrap().unwrap();
+			f.set_attr(b"attr".into(), b"value").await.unwrap().unwrap();
+
+			let root2 = fs2.root_dir();
+			let key = root
+				.move_to(dir.key(), &root2, b"moved".into())
+				.await
+				.unwrap()
+				.unwrap();
+			assert!(root.search(b"dir".into()).await.unwrap().is_none());
+
+			let dir2 = fs2.dir(key).await.unwrap();
+			let info = dir2.search(b"file".into()).await.unwrap().unwrap();
+			let f2 = fs2.file(info.key);
+			let buf = &mut [0; 6];
+			assert_eq!(f2.read(0, buf).await.unwrap(), 6);
+			assert_eq!(buf, b"Hello!");
+			assert_eq!(
+				f2.attr(b"attr".into()).await.unwrap().as_deref(),
+				Some(&b"value"[..])
+			);
+			Ok::<_, Error<_>>(())
+		};
+		// Both filesystems need to be able to run background tasks.
+		fs2.run(fut).await.unwrap();
+	});
+}
+
+#[test]
+fn move_to_other_fs_fail() {
+	let (fs, fs2) = (new(), new());
+	run(&fs, async {
+		let fut = async {
+			let root = fs.root_dir();
+			let dir = mkdir(&root, b"dir").await;
+			mkfile(&dir, b"a").await.write_grow(0, &[1; 100]).await?.unwrap();
+			let sub = mkdir(&dir, b"sub").await;
+			mkfile(&sub, b"b").await.write_grow(0, &[2; 1000]).await?.unwrap();
+
+			let root2 = fs2.root_dir();
+			root2.set_quota(Some(500)).await?;
+			let r = root.move_to(dir.key(), &root2, b"moved".into()).await;
+			assert!(matches!(r, Err(Error::QuotaExceeded)));
+
+			// Nothing is left behind on the other filesystem
+			// and the original is untouched.
+			assert!(root2.search(b"moved".into()).await?.is_none());
+			assert_eq!(root2.usage().await?, 0);
+			assert_eq!(root2.len().await?, 0);
+			assert_eq!(dir.len().await?, 2);
+			assert_eq!(sub.len().await?, 1);
+			Ok::<_, Error<_>>(())
+		};
+		fs2.run(fut).await.unwrap();
+	});
+}
+
+#[test]
+fn create_dir_hasher() {
+	let fs = new();
+	run(&fs, async {
+		let root = fs.root_dir();
+		let a = root
+			.create_dir_with_hasher(b"a".into(), Hasher::SipHasher13)
+			.await
+			.unwrap()
+			.unwrap();
+		let b = root
+			.create_dir_with_hasher(b"b".into(), Hasher::Xxh3)
+			.await
+			.unwrap()
+			.unwrap();
+		assert_eq!(a.hasher().await.unwrap(), Hasher::SipHasher13);
+		assert_eq!(b.hasher().await.unwrap(), Hasher::Xxh3);
+		assert_eq!(root.hasher().await.unwrap(), Hasher::SipHasher13);
+
+		for d in [&a, &b] {
+			for i in 0..32u8 {
+				mkfile(d, &[b'f', i]).await;
+			}
+		}
+		for d in [&a, &b] {
+			for i in 0..32u8 {
+				let name = [b'f', i];
+				let item = d.search((&name).into()).await.unwrap().unwrap();
+				assert_eq!(item.ty, ItemTy::EmbedFile);
+			}
+			assert!(d.search(b"g".into()).await.unwrap().is_none());
+			let r = d.create_file((&[b'f', 0]).into()).await.unwrap();
+			assert!(matches!(r, Err(CreateError::Duplicate)));
+		}
+	});
+}
+
+#[test]
+fn max_depth() {
+	let fs = new();
+	assert_eq!(fs.max_depth(), Nrfs::<MemDev>::DEFAULT_MAX_DEPTH);
+	fs.set_max_depth(3);
+	assert_eq!(fs.max_depth(), 3);
+	run(&fs, async {
+		let mut d = fs.root_dir();
+		for _ in 0..3 {
+			d = mkdir(&d, b"d").await;
+		}
+		let r = d.create_dir(b"d".into()).await.unwrap();
+		assert!(matches!(r, Err(CreateError::TooDeep)));
+		// Other items can still be created.
+		mkfile(&d, b"f").await;
+	})
+}
+
+#[test]
+fn max_depth_transfer() {
+	let fs = new();
+	fs.set_max_depth(3);
+	run(&fs, async {
+		let root = fs.root_dir();
+		let a = mkdir(&root, b"a").await;
+		let b = mkdir(&a, b"b").await;
+		mkdir(&b, b"c").await;
+		let d = mkdir(&root, b"d").await;
+
+		// a/b/c would end up at depth 4.
+		let r = root.transfer(a.key(), &d, b"a".into()).await.unwrap();
+		assert!(matches!(r, Err(TransferError::TooDeep)));
+		assert!(root.search(b"a".into()).await.unwrap().is_some());
+
+		// b/c ends up at depth 3, which is fine.
+		a.transfer(b.key(), &d, b"b".into()).await.unwrap().unwrap();
+	})
+}
@@ -115,3 +115,333 @@ fn write_embed() {
 		assert_eq!(buf, [1; 231]);
 	})
 }
+
+#[test]
+fn link_target() {
+	let fs = new();
+	run(&fs, async {
+		let f = mksym(&fs.root_dir(), b"link").await;
+		f.set_link_target(b"some/long/path").await.unwrap().unwrap();
+		f.set_link_target(b"short").await.unwrap().unwrap();
+		assert_eq!(f.read_link().await.unwrap().unwrap(), b"short");
+	})
+}
+
+#[test]
+fn link_target_too_long() {
+	let fs = new();
+	run(&fs, async {
+		let f = mksym(&fs.root_dir(), b"link").await;
+		let target = &*vec![b'a'; Nrfs::<MemDev>::MAX_LINK_LEN + 1];
+		assert!(matches!(
+			f.set_link_target(target).await.unwrap(),
+			Err(LinkError::LengthTooLong)
+		));
+
+		// Bypass the check to simulate a corrupt length.
+		f.write_grow(0, target).await.unwrap().unwrap();
+		assert!(matches!(
+			f.read_link().await.unwrap(),
+			Err(LinkError::LengthTooLong)
+		));
+	})
+}
+
+#[test]
+fn read_link_file() {
+	let fs = new();
+	run(&fs, async {
+		let f = mkfile(&fs.root_dir(), b"file").await;
+		f.write_grow(0, b"not a link").await.unwrap().unwrap();
+		assert!(matches!(
+			f.read_link().await.unwrap(),
+			Err(LinkError::NotSymlink)
+		));
+		assert!(matches!(
+			f.set_link_target(b"target").await.unwrap(),
+			Err(LinkError::NotSymlink)
+		));
+	})
+}
+
+/// Read the length of a file directly from its descriptor.
+async fn descriptor_len(dir: &Dir<'_, MemDev>, file: &File<'_, MemDev>) -> u64 {
+	let buf = &mut [0; 8];
+	dir.kv()
+		.read_user_data(file.key().tag, 8, buf)
+		.await
+		.unwrap();
+	u64::from_le_bytes(*buf)
+}
+
+#[test]
+fn append_deferred_len() {
+	let fs = new();
+	run(&fs, async {
+		let root = fs.root_dir();
+		let f = mkfile(&root, b"log").await;
+		// Make sure the data is stored in a separate object.
+		f.resize(1 << 16).await.unwrap().unwrap();
+
+		for i in 0..100 {
+			f.append((1 << 16) + i * 10, b"0123456789")
+				.await
+				.unwrap()
+				.unwrap();
+		}
+		assert_eq!(f.len().await.unwrap(), (1 << 16) + 1000);
+		assert_eq!(descriptor_len(&root, &f).await, 1 << 16);
+
+		f.flush().await.unwrap();
+		assert_eq!(descriptor_len(&root, &f).await, (1 << 16) + 1000);
+		assert_eq!(f.len().await.unwrap(), (1 << 16) + 1000);
+
+		let buf = &mut [0; 10];
+		f.read((1 << 16) + 990, buf).await.unwrap();
+		assert_eq!(buf, b"0123456789");
+
+		// Pending lengths are written when the transaction finishes.
+		f.append((1 << 16) + 1000, b"abc").await.unwrap().unwrap();
+		assert_eq!(descriptor_len(&root, &f).await, (1 << 16) + 1000);
+		fs.finish_transaction().await.unwrap();
+		assert_eq!(descriptor_len(&root, &f).await, (1 << 16) + 1003);
+	})
+}
+
+/// The store running out of space is only detected when data is flushed,
+/// so the write itself succeeds in full.
+#[test]
+fn append_partial_no_space() {
+	let fs = new_cap(64, BlockSize::K1, MaxRecordSize::K1, 1 << 20);
+	run(&fs, async {
+		let f = mkfile(&fs.root_dir(), b"big").await;
+		let data = &[1; 128 << 10];
+		let n = f.append_partial(0, data).await.unwrap().unwrap();
+		assert_eq!(n, data.len());
+
+		let r = fs.finish_transaction().await;
+		assert!(
+			matches!(r, Err(Error::Nros(nros::Error::NotEnoughSpace))),
+			"{:?}",
+			r
+		);
+	})
+}
+
+#[test]
+fn append_partial_quota() {
+	let fs = new();
+	run(&fs, async {
+		let d = mkdir(&fs.root_dir(), b"dir").await;
+		d.set_quota(Some(2500)).await.unwrap();
+		let f = mkfile(&d, b"big").await;
+
+		// Data that fits is written at once.
+		let n = f.append_partial(0, &[1; 1000]).await.unwrap().unwrap();
+		assert_eq!(n, 1000);
+
+		// Only whole blocks that fit in the quota are written.
+		let data = &[2; 4000];
+		let n = f.append_partial(1000, data).await.unwrap().unwrap();
+		assert_eq!(n, 1048);
+		assert_eq!(f.len().await.unwrap(), 2048);
+		assert_eq!(d.usage().await.unwrap(), 2048);
+
+		let buf = &mut [0; 2048];
+		assert_eq!(f.read(0, buf).await.unwrap(), 2048);
+		assert!(buf[..1000].iter().all(|&b| b == 1));
+		assert!(buf[1000..].iter().all(|&b| b == 2));
+
+		// Nothing can be written anymore.
+		let r = f.append_partial(2048, &data[n..]).await;
+		assert!(matches!(r, Err(Error::QuotaExceeded)));
+	})
+}
+
+#[test]
+fn same_object() {
+	let fs = new();
+	run(&fs, async {
+		let root = fs.root_dir();
+		let a = mkfile(&root, b"a").await;
+		let b = mkfile(&root, b"b").await;
+		let c = mkfile(&root, b"c").await;
+		// Make sure the data is stored in a separate object.
+		a.resize(1 << 16).await.unwrap().unwrap();
+		b.resize(1 << 16).await.unwrap().unwrap();
+
+		assert!(a.same_object(&a).await.unwrap());
+		assert!(!a.same_object(&b).await.unwrap());
+		assert!(!b.same_object(&c).await.unwrap());
+		let d = mkfile(&root, b"d").await;
+		assert!(!c.same_object(&d).await.unwrap());
+
+		let a2 = root.search(b"a".into()).await.unwrap().unwrap();
+		assert!(a.same_object(&fs.item(a2.key)).await.unwrap());
+
+		// Link "b" to the data of "a".
+		let buf = &mut [0; 16];
+		root.kv().read_user_data(a.key.tag, 0, buf).await.unwrap();
+		root.kv().write_user_data(b.key.tag, 0, buf).await.unwrap();
+		assert!(a.same_object(&b).await.unwrap());
+
+		// An invalid type is reported as corruption.
+		root.kv().write_user_data(c.key.tag, 0, &[7]).await.unwrap();
+		assert!(matches!(a.same_object(&c).await, Err(Error::CorruptItem)));
+	})
+}
+
+#[test]
+fn create_file_embedded() {
+	let fs = new();
+	run(&fs, async {
+		let (file, embed) = fs
+			.root_dir()
+			.create_file(b"file".into())
+			.await
+			.unwrap()
+			.unwrap();
+		assert!(embed);
+		assert!(file.is_embed().await.unwrap());
+
+		file.write_grow(0, &[1; 1 << 16]).await.unwrap().unwrap();
+		assert!(!file.is_embed().await.unwrap());
+	});
+}
+
+/// Shrinking then growing a file must not expose the old data.
+#[test]
+fn shrink_grow_zero_tail() {
+	let fs = new();
+	run(&fs, async {
+		let root = fs.root_dir();
+		for (name, len) in [(b"embed", 256), (b"objct", 1 << 16)] {
+			let file = mkfile(&root, name).await;
+			file.write_grow(0, &vec![0xff; len]).await.unwrap().unwrap();
+			assert_eq!(file.is_embed().await.unwrap(), len == 256);
+			file.resize(64).await.unwrap().unwrap();
+
+			// Grow by resizing.
+			file.resize(256).await.unwrap().unwrap();
+			let buf = &mut [0xcc; 256];
+			assert_eq!(file.read(0, buf).await.unwrap(), 256);
+			assert!(buf[..64].iter().all(|&b| b == 0xff));
+			assert!(buf[64..].iter().all(|&b| b == 0), "{}", len);
+
+			// Grow by writing past the end.
+			file.resize(64).await.unwrap().unwrap();
+			file.write_grow(200, &[1]).await.unwrap().unwrap();
+			let buf = &mut [0xcc; 201];
+			assert_eq!(file.read(0, buf).await.unwrap(), 201);
+			assert!(buf[64..200].iter().all(|&b| b == 0), "{}", len);
+			assert_eq!(buf[200], 1);
+		}
+	});
+}
+
+#[test]
+fn copy_to_embed() {
+	let fs = new();
+	run(&fs, async {
+		let f = mkfile(&fs.root_dir(), b"file").await;
+		f.write_grow(0, b"Hello!").await.unwrap().unwrap();
+		f.set_attr(b"key".into(), b"value").await.unwrap().unwrap();
+		let d = mkdir(&fs.root_dir(), b"dir").await;
+
+		let g = f.copy_to(&d, b"copy".into()).await.unwrap().unwrap();
+		assert!(g.is_embed().await.unwrap());
+		let buf = &mut [0; 6];
+		assert_eq!(g.read(0, buf).await.unwrap(), 6);
+		assert_eq!(buf, b"Hello!");
+		let v = g.attr(b"key".into()).await.unwrap().unwrap();
+		assert_eq!(b"value", &*v);
+
+		g.write(0, b"Bye").await.unwrap();
+		f.read(0, buf).await.unwrap();
+		assert_eq!(buf, b"Hello!");
+
+		assert!(matches!(
+			f.copy_to(&d, b"copy".into()).await.unwrap(),
+			Err(CreateError::Duplicate)
+		));
+	});
+}
+
+#[test]
+fn copy_to_object() {
+	let fs = new();
+	run(&fs, async {
+		let data = (0..1 << 14).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+		let f = mkfile(&fs.root_dir(), b"file").await;
+		f.write_grow(0, &data).await.unwrap().unwrap();
+		assert!(!f.is_embed().await.unwrap());
+		let d = mkdir(&fs.root_dir(), b"dir").await;
+
+		let g = f.copy_to(&d, b"copy".into()).await.unwrap().unwrap();
+		assert!(!g.is_embed().await.unwrap());
+		assert_eq!(g.len().await.unwrap(), data.len() as u64);
+		assert_eq!(d.usage().await.unwrap(), data.len() as u64);
+		let buf = &mut vec![0; data.len()];
+		assert_eq!(g.read(0, buf).await.unwrap(), data.len());
+		assert_eq!(buf, &data);
+
+		g.write(0, &[0; 1 << 12]).await.unwrap();
+		f.read(0, buf).await.unwrap();
+		assert_eq!(buf, &data);
+	});
+}
+
+#[test]
+fn embed_factor() {
+	let fs = block_on(Nrfs::new(NewConfig {
+		key_deriver: KeyDeriver::None { key: &[0; 32] },
+		cipher: CipherType::NoneXxh3,
+		mirrors: vec![vec![MemDev::new(1 << 10, BlockSize::K1)]],
+		block_size: BlockSize::K1,
+		max_record_size: MaxRecordSize::K1,
+		compression: Compression::None,
+		embed_factor: 16,
+		cache_size: 4096,
+	}))
+	.unwrap();
+	assert_eq!(fs.embed_factor(), 16);
+	run(&fs, async {
+		let f = mkfile(&fs.root_dir(), b"file").await;
+		f.write_grow(0, &[1; 16 << 10]).await.unwrap().unwrap();
+		assert!(f.is_embed().await.unwrap());
+		let g = mkfile(&fs.root_dir(), b"large").await;
+		let data = &[1; (16 << 10) + 1];
+		g.write_grow(0, data).await.unwrap().unwrap();
+		assert!(!g.is_embed().await.unwrap());
+	});
+
+	let devices = block_on(fs.unmount()).unwrap();
+	let fs = block_on(Nrfs::load(LoadConfig {
+		devices,
+		cache_size: 1 << 12,
+		cache_policy: Default::default(),
+		allow_repair: true,
+		retrieve_key: &mut |_| unreachable!(),
+	}))
+	.unwrap();
+	assert_eq!(fs.embed_factor(), 16);
+	assert_eq!(new().embed_factor(), Nrfs::<MemDev>::DEFAULT_EMBED_FACTOR);
+}
+
+#[test]
+fn embed_factor_too_large() {
+	let r = block_on(Nrfs::new(NewConfig {
+		key_deriver: KeyDeriver::None { key: &[0; 32] },
+		cipher: CipherType::NoneXxh3,
+		mirrors: vec![vec![MemDev::new(1 << 10, BlockSize::K1)]],
+		block_size: BlockSize::K1,
+		max_record_size: MaxRecordSize::K1,
+		compression: Compression::None,
+		embed_factor: Nrfs::<MemDev>::MAX_EMBED_FACTOR + 1,
+		cache_size: 4096,
+	}));
+	assert!(matches!(
+		r,
+		Err(Error::InvalidEmbedFactor { embed_factor: 129 })
+	));
+}
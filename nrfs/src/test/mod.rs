@@ -45,6 +45,7 @@ fn new_cap(
 		block_size,
 		max_record_size,
 		compression: Compression::None,
+		embed_factor: 0,
 		cache_size,
 	}))
 	.unwrap()
@@ -62,6 +63,7 @@ async fn mkfile<'a>(dir: &Dir<'a, MemDev>, name: &[u8]) -> File<'a, MemDev> {
 		.await
 		.unwrap()
 		.unwrap()
+		.0
 }
 
 async fn mksym<'a>(dir: &Dir<'a, MemDev>, name: &[u8]) -> File<'a, MemDev> {
@@ -81,7 +83,7 @@ fn create_file() {
 	let fs = new();
 	run(&fs, async {
 		let d = fs.root_dir();
-		let f = d.create_file(b"test.txt".into()).await.unwrap().unwrap();
+		let (f, _) = d.create_file(b"test.txt".into()).await.unwrap().unwrap();
 		f.write_grow(0, b"Hello, world!").await.unwrap().unwrap();
 
 		assert!(d.search(b"I do not exist".into()).await.unwrap().is_none());
@@ -105,7 +107,7 @@ fn create_many_files() {
 			let contents = format!("This is file #{}", i);
 
 			let d = fs.root_dir();
-			let f = d
+			let (f, _) = d
 				.create_file(<&Key>::try_from(&*name).unwrap())
 				.await
 				.unwrap()
@@ -165,7 +167,7 @@ fn destroy_file() {
 	run(&fs, async {
 		let d = fs.root_dir();
 
-		let f = d.create_file(b"hello".into()).await.unwrap().unwrap();
+		let (f, _) = d.create_file(b"hello".into()).await.unwrap().unwrap();
 		d.create_file(b"world".into()).await.unwrap().unwrap();
 		d.create_file(b"exist".into()).await.unwrap().unwrap();
 
@@ -187,8 +189,192 @@ fn remount() {
 	block_on(Nrfs::load(LoadConfig {
 		devices,
 		cache_size: 1 << 12,
+		cache_policy: Default::default(),
 		allow_repair: true,
 		retrieve_key: &mut |_| unreachable!(),
 	}))
 	.unwrap();
 }
+
+#[test]
+fn unsupported_version() {
+	let fs = new();
+	fs.storage.header_data_mut()[HDR_VERSION_OFFT] = Nrfs::<MemDev>::FORMAT_VERSION + 1;
+	let devices = block_on(fs.unmount()).unwrap();
+	let r = block_on(Nrfs::load(LoadConfig {
+		devices,
+		cache_size: 1 << 12,
+		cache_policy: Default::default(),
+		allow_repair: true,
+		retrieve_key: &mut |_| unreachable!(),
+	}));
+	assert!(matches!(r, Err(Error::UnsupportedVersion { version: 2 })));
+}
+
+/// Filesystems made before directories had a header must still be usable,
+/// without quotas.
+#[test]
+fn version_0() {
+	let fs = new();
+	fs.storage.header_data_mut()[HDR_VERSION_OFFT] = 0;
+	run(&fs, async {
+		let id = Dir::init(&fs, u64::MAX, Hasher::default(), false)
+			.await
+			.unwrap();
+		fs.storage.header_data_mut()[HDR_ROOT_OFFT..][..8]
+			.copy_from_slice(&(id << 5 | 1).to_le_bytes());
+	});
+	let devices = block_on(fs.unmount()).unwrap();
+	let fs = block_on(Nrfs::load(LoadConfig {
+		devices,
+		cache_size: 1 << 12,
+		cache_policy: Default::default(),
+		allow_repair: true,
+		retrieve_key: &mut |_| unreachable!(),
+	}))
+	.unwrap();
+
+	run(&fs, async {
+		let root = fs.root_dir();
+		let d = mkdir(&root, b"dir").await;
+		let sub = mkdir(&d, b"sub").await;
+		mkfile(&sub, b"file")
+			.await
+			.write_grow(0, &[1; 5000])
+			.await
+			.unwrap()
+			.unwrap();
+		assert_eq!(d.usage().await.unwrap(), 0);
+		assert_eq!(d.quota().await.unwrap(), None);
+		assert!(matches!(
+			d.set_quota(Some(100)).await,
+			Err(Error::UnsupportedVersion { version: 0 })
+		));
+		assert!(matches!(
+			d.create_dir_case_insensitive(b"ci".into(), Hasher::default())
+				.await,
+			Err(Error::UnsupportedVersion { version: 0 })
+		));
+
+		sub.remove_tree().await.unwrap().unwrap();
+		assert_eq!(d.len().await.unwrap(), 0);
+	});
+}
+
+#[test]
+fn label() {
+	let fs = new();
+	assert_eq!(fs.label(), b"");
+	fs.set_label(b"backup 2").unwrap();
+	assert!(fs
+		.set_label(&[b'a'; Nrfs::<MemDev>::MAX_LABEL_LEN + 1])
+		.is_err());
+	assert_eq!(fs.label(), b"backup 2");
+
+	let devices = block_on(fs.unmount()).unwrap();
+	let fs = block_on(Nrfs::load(LoadConfig {
+		devices,
+		cache_size: 1 << 12,
+		cache_policy: Default::default(),
+		allow_repair: true,
+		retrieve_key: &mut |_| unreachable!(),
+	}))
+	.unwrap();
+	assert_eq!(fs.label(), b"backup 2");
+}
+
+#[test]
+fn created_at() {
+	let fs = new();
+	let created = fs.created_at();
+	assert!(created > 0);
+
+	let devices = block_on(fs.unmount()).unwrap();
+	let fs = block_on(Nrfs::load(LoadConfig {
+		devices,
+		cache_size: 1 << 12,
+		cache_policy: Default::default(),
+		allow_repair: true,
+		retrieve_key: &mut |_| unreachable!(),
+	}))
+	.unwrap();
+	assert_eq!(fs.created_at(), created);
+
+	run(&fs, async {
+		let f = mkfile(&fs.root_dir(), b"file").await;
+		let now = std::time::SystemTime::now()
+			.duration_since(std::time::UNIX_EPOCH)
+			.unwrap()
+			.as_micros();
+		f.set_modified_time(now.try_into().unwrap()).await.unwrap();
+		assert!(created <= f.modified().await.unwrap().time);
+	});
+}
+
+#[test]
+fn inode_stats() {
+	let fs = new();
+	run(&fs, async {
+		let (used, free) = fs.inode_stats();
+		mkdir(&fs.root_dir(), b"a").await;
+		mkdir(&fs.root_dir(), b"b").await;
+		assert_eq!(fs.inode_stats(), (used + 2, free - 2));
+	});
+}
+
+#[test]
+fn verify() {
+	let fs = new();
+	run(&fs, async {
+		let d = mkdir(&fs.root_dir(), b"dir").await;
+		let f = mkfile(&d, b"file").await;
+		f.write_grow(0, &[1; 1 << 14]).await.unwrap().unwrap();
+		assert!(!f.is_embed().await.unwrap());
+		mkfile(&fs.root_dir(), b"embed").await;
+		fs.finish_transaction().await.unwrap();
+
+		let report = fs.verify(false).await.unwrap();
+		assert!(report.is_ok(), "{:?}", report);
+
+		// Create an object that isn't referenced by any item.
+		let obj = fs.storage.create().await.unwrap();
+		obj.write(0, &[2; 3000]).await.unwrap();
+		let id = obj.id();
+
+		let report = fs.verify(false).await.unwrap();
+		assert_eq!(report.unreachable_objects, [id]);
+		assert!(!report.is_ok());
+
+		let report = fs.verify(true).await.unwrap();
+		assert_eq!(report.unreachable_objects, [id]);
+
+		let report = fs.verify(false).await.unwrap();
+		assert!(report.is_ok(), "{:?}", report);
+		assert_eq!(f.read(0, &mut [0; 16]).await.unwrap(), 16);
+	});
+}
+
+/// Repairing must not free objects of items in deep parts of a directory's hash map.
+#[test]
+fn verify_repair_many() {
+	let fs = new_cap(1 << 12, BlockSize::K1, MaxRecordSize::K1, 1 << 16);
+	run(&fs, async {
+		// With this many items in a root of 4096 entries there are bound to be collisions.
+		let d = mkdir(&fs.root_dir(), b"dir").await;
+		for i in 0..600u16 {
+			mkdir(&d, &i.to_le_bytes()).await;
+		}
+		fs.finish_transaction().await.unwrap();
+
+		let report = fs.verify(true).await.unwrap();
+		assert!(report.is_ok(), "{:?}", report);
+
+		for i in 0..600u16 {
+			let name = i.to_le_bytes();
+			let item = d.search((&name).into()).await.unwrap().unwrap();
+			fs.dir(item.key).await.unwrap().len().await.unwrap();
+		}
+		let report = fs.verify(false).await.unwrap();
+		assert!(report.is_ok(), "{:?}", report);
+	});
+}
@@ -0,0 +1,90 @@
+use {
+	crate::{Dev, Error, ItemKey, ItemTy, Nrfs, HDR_ATTR_OFFT},
+	std::collections::BTreeSet,
+};
+
+/// Inconsistencies found by [`Nrfs::verify`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct VerifyReport {
+	/// Inconsistencies found in the object store.
+	pub object_store: nros::VerifyReport,
+	/// Objects in use that are not referenced by any item.
+	pub unreachable_objects: Vec<u64>,
+	/// Items that refer to an object which is not in use.
+	pub dangling_items: Vec<ItemKey>,
+}
+
+impl VerifyReport {
+	/// Whether no inconsistencies were found.
+	pub fn is_ok(&self) -> bool {
+		self.object_store.is_ok()
+			&& self.unreachable_objects.is_empty()
+			&& self.dangling_items.is_empty()
+	}
+}
+
+impl<D: Dev> Nrfs<D> {
+	/// Check the consistency of the filesystem.
+	///
+	/// This checks whether the object store is consistent
+	/// and whether all objects in use are reachable from the root directory.
+	///
+	/// If `repair` is `true`, leaked and unreachable objects are freed.
+	/// Dangling items are only reported.
+	pub async fn verify(&self, repair: bool) -> Result<VerifyReport, Error<D>> {
+		trace!("verify {}", repair);
+		assert!(!repair || !self.read_only, "read only");
+
+		let object_store = self.storage.verify(repair).await?;
+		let used = self
+			.storage
+			.used_objects()
+			.into_iter()
+			.collect::<BTreeSet<_>>();
+
+		let root = self.root_dir();
+		let attr_map = u64::from_le_bytes(
+			self.storage.header_data()[HDR_ATTR_OFFT..][..8]
+				.try_into()
+				.unwrap(),
+		);
+		let mut reachable = BTreeSet::from([root.id, attr_map]);
+		let mut dangling_items = vec![];
+
+		// Any object that is missed here is considered unreachable and freed on repair,
+		// so use the non-lossy iterator and give up entirely if any directory fails.
+		let mut dirs = vec![root];
+		while let Some(dir) = dirs.pop() {
+			for info in dir.all_entries().await? {
+				let id = match info.ty {
+					ItemTy::Dir => {
+						let d = self.dir(info.key).await?;
+						let id = d.id;
+						if used.contains(&id) && !reachable.contains(&id) {
+							dirs.push(d);
+						}
+						Some(id)
+					}
+					ItemTy::File | ItemTy::Sym => self.file(info.key).object_id().await?,
+					ItemTy::EmbedFile | ItemTy::EmbedSym => None,
+				};
+				if let Some(id) = id {
+					if !used.contains(&id) {
+						dangling_items.push(info.key);
+					}
+					reachable.insert(id);
+				}
+			}
+		}
+
+		let unreachable_objects = used.difference(&reachable).copied().collect::<Vec<_>>();
+		if repair {
+			for &id in &unreachable_objects {
+				self.dir_headers.borrow_mut().remove(&id);
+				self.storage.get(id).dealloc().await?;
+			}
+		}
+
+		Ok(VerifyReport { object_store, unreachable_objects, dangling_items })
+	}
+}
@@ -0,0 +1,80 @@
+use {
+	crate::{Dev, Dir, Error, ItemInfo, ItemKey, ItemTy, Nrfs},
+	nrkv::Key,
+	std::collections::BTreeSet,
+};
+
+/// Pre-order traversal of a directory tree.
+///
+/// Created by [`Nrfs::walk`].
+#[derive(Debug)]
+pub struct Walk<'a, D: Dev> {
+	fs: &'a Nrfs<D>,
+	/// Directories being iterated with the state to continue iterating from.
+	stack: Vec<(Dir<'a, D>, u64)>,
+	/// Names of the directories on the stack, excluding the root.
+	path: Vec<Box<Key>>,
+	/// IDs of directories that have been entered.
+	visited: BTreeSet<u64>,
+	max_depth: usize,
+}
+
+impl<D: Dev> Nrfs<D> {
+	/// Walk over all descendants of a directory.
+	///
+	/// Every item is yielded before its descendants.
+	/// Items are ordered the same way as [`Dir::next_from`],
+	/// so walking the same tree twice yields items in the same order.
+	///
+	/// Only items up to `max_depth` levels deep are yielded,
+	/// i.e. a depth of `1` only yields the items in `root` itself.
+	///
+	/// # Errors
+	///
+	/// [`Error::NotADirectory`] if `root` is not a directory.
+	pub async fn walk(&self, root: ItemKey, max_depth: u32) -> Result<Walk<'_, D>, Error<D>> {
+		trace!("walk {:?} {}", root, max_depth);
+		let root = self.dir(root).await?;
+		Ok(Walk {
+			fs: self,
+			visited: BTreeSet::from([root.id]),
+			stack: vec![(root, 0)],
+			path: vec![],
+			max_depth: usize::try_from(max_depth).unwrap_or(usize::MAX),
+		})
+	}
+}
+
+impl<'a, D: Dev> Walk<'a, D> {
+	/// Get the next item along with its path relative to the root of the walk.
+	///
+	/// The last component of the path is the name of the item itself.
+	///
+	/// Directories that appear more than once in the tree,
+	/// which may happen if the filesystem is corrupt,
+	/// are yielded but not entered again.
+	pub async fn next(&mut self) -> Result<Option<(Vec<Box<Key>>, ItemInfo<'static>)>, Error<D>> {
+		loop {
+			let Some((dir, state)) = self.stack.last_mut() else { return Ok(None) };
+			let Some((info, next)) = dir.next_from(*state).await? else {
+				self.stack.pop();
+				self.path.pop();
+				continue;
+			};
+			*state = next;
+
+			let mut path = self.path.iter().map(|k| Box::from(&**k)).collect::<Vec<_>>();
+			path.push(info.name.clone().into_owned());
+
+			if info.ty == ItemTy::Dir && self.stack.len() < self.max_depth {
+				let d = self.fs.dir(info.key).await?;
+				if self.visited.insert(d.id) {
+					self.stack.push((d, 0));
+					self.path.push(info.name.clone().into_owned());
+				}
+			}
+
+			return Ok(Some((path, info)));
+		}
+	}
+}
@@ -3,6 +3,31 @@ use core::fmt;
 pub trait Conf {
 	fn header_offset(&self) -> u64;
 	fn item_offset(&self) -> u16;
+
+	/// Offset of the byte indicating the [`Hasher`](crate::Hasher) to use.
+	///
+	/// If `None`, [`Hasher::SipHasher13`](crate::Hasher::SipHasher13) is always used.
+	fn hasher_offset(&self) -> Option<u64> {
+		None
+	}
+
+	/// Offset of the byte indicating whether keys are compared case-insensitively.
+	///
+	/// Only ASCII letters are folded.
+	/// If `None`, keys are always compared exactly.
+	fn fold_case_offset(&self) -> Option<u64> {
+		None
+	}
+
+	/// Offset of the 2 bytes holding the current generation.
+	///
+	/// The generation is advanced whenever items are freed and is part of the tags of new items,
+	/// so tags of freed items are not mistaken for items that reuse the memory.
+	/// It wraps around after 2^15 increments.
+	/// If `None`, the generation is always 0.
+	fn generation_offset(&self) -> Option<u64> {
+		None
+	}
 }
 
 #[derive(Debug)]
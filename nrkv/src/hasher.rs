@@ -0,0 +1,48 @@
+use {
+	core::hash::Hasher as _,
+	siphasher::sip128::{Hasher128, SipHasher13},
+};
+
+/// Hash algorithm used to index keys.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Hasher {
+	/// Keyed cryptographic hash.
+	///
+	/// Resistant to hash flooding, i.e. suitable for untrusted keys.
+	#[default]
+	SipHasher13 = 0,
+	/// Fast non-cryptographic hash.
+	///
+	/// Only use this for trusted keys.
+	Xxh3 = 1,
+}
+
+impl Hasher {
+	pub fn from_raw(n: u8) -> Option<Self> {
+		Some(match n {
+			0 => Self::SipHasher13,
+			1 => Self::Xxh3,
+			_ => return None,
+		})
+	}
+
+	pub fn to_raw(self) -> u8 {
+		self as _
+	}
+
+	pub(crate) fn hash(self, key: &[u8; 16], data: &[u8]) -> u128 {
+		match self {
+			Self::SipHasher13 => {
+				let mut h = SipHasher13::new_with_key(key);
+				h.write(data);
+				h.finish128().as_u128()
+			}
+			Self::Xxh3 => {
+				let (a, b) = key.split_at(8);
+				let seed = u64::from_le_bytes(a.try_into().unwrap())
+					^ u64::from_le_bytes(b.try_into().unwrap());
+				xxhash_rust::xxh3::xxh3_128_with_seed(data, seed)
+			}
+		}
+	}
+}
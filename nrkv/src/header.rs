@@ -3,13 +3,14 @@ use {
 	core::fmt,
 };
 
-#[derive(Default)]
+#[derive(Clone, Copy, Default)]
 #[repr(C)]
 struct FreeRegion {
 	offset: [u8; 6],
 	length: [u8; 6],
 }
 
+#[derive(Clone, Copy)]
 #[repr(C)]
 pub(crate) struct Header {
 	pub hash_key: [u8; 16],
@@ -88,6 +89,49 @@ impl Header {
 		Some((offt, 0))
 	}
 
+	/// Allocate a specific range.
+	///
+	/// Returns the free ranges directly before and after the allocated range,
+	/// which may be empty.
+	/// Returns `None` if the range is not entirely free.
+	pub fn alloc_at(&mut self, offset: u64, amount: u64) -> Option<[(u64, u64); 2]> {
+		let end = offset + amount;
+		let head = u48_to_u64(self.free_head);
+		if head <= offset {
+			self.free_head = u64_to_u48(end)?;
+			self.used = apply_u48(self.used, |n| n + amount).unwrap();
+			if head < offset {
+				self.insert_free_region(head, offset - head);
+			}
+			return Some([(head, offset - head), (end, 0)]);
+		}
+		for i in 0..self.free_regions.len() {
+			let r = &mut self.free_regions[i];
+			let o = u48_to_u64(r.offset);
+			let e = o + u48_to_u64(r.length);
+			if o < e && o <= offset && end <= e {
+				if o == offset {
+					[r.offset, r.length] = [[0; 6]; 2];
+				} else {
+					r.length = u64_to_u48(offset - o).unwrap();
+				}
+				self.used = apply_u48(self.used, |n| n + amount).unwrap();
+				if end < e {
+					self.insert_free_region(end, e - end);
+				}
+				return Some([(o, offset - o), (end, e - end)]);
+			}
+		}
+		None
+	}
+
+	/// The end of the allocated region.
+	///
+	/// All data past this offset is zero.
+	pub fn end(&self) -> u64 {
+		u48_to_u64(self.free_head)
+	}
+
 	pub fn dealloc(&mut self, amount: u64) -> Option<()> {
 		self.used = apply_u48(self.used, |n| n - amount)?;
 		Some(())
@@ -6,6 +6,10 @@ use core::{
 	ops::{Deref, DerefMut},
 };
 
+/// A key of an item.
+///
+/// Keys are between 1 and [`Key::MAX_LEN`] bytes long,
+/// as the length is stored in a single byte.
 #[derive(PartialEq, Eq, PartialOrd, Ord, Hash)]
 #[repr(transparent)]
 pub struct Key([u8]);
@@ -14,6 +18,16 @@ pub struct Key([u8]);
 pub struct TooLong;
 
 impl Key {
+	/// The maximum length of a key.
+	pub const MAX_LEN: usize = 255;
+
+	/// Create a key from a slice.
+	///
+	/// Fails if the slice is empty or longer than [`Key::MAX_LEN`].
+	pub fn try_key(s: &[u8]) -> Result<&Self, TooLong> {
+		s.try_into()
+	}
+
 	pub fn len_u8(&self) -> u8 {
 		self.len_nonzero_u8().get()
 	}
@@ -30,7 +44,7 @@ impl<'a> TryFrom<&'a [u8]> for &'a Key {
 
 	fn try_from(s: &'a [u8]) -> Result<Self, Self::Error> {
 		// SAFETY: Key is repr(transparent)
-		(1..=255)
+		(1..=Key::MAX_LEN)
 			.contains(&s.len())
 			.then(|| unsafe { &*(s as *const _ as *const _) })
 			.ok_or(TooLong)
@@ -42,7 +56,7 @@ impl<'a> TryFrom<&'a mut [u8]> for &'a mut Key {
 
 	fn try_from(s: &'a mut [u8]) -> Result<Self, Self::Error> {
 		// SAFETY: Key is repr(transparent)
-		(1..=255)
+		(1..=Key::MAX_LEN)
 			.contains(&s.len())
 			.then(|| unsafe { &mut *(s as *mut _ as *mut _) })
 			.ok_or(TooLong)
@@ -63,7 +77,7 @@ impl TryFrom<Box<[u8]>> for Box<Key> {
 
 	fn try_from(s: Box<[u8]>) -> Result<Self, Self::Error> {
 		// SAFETY: Key is repr(transparent)
-		(1..=255)
+		(1..=Key::MAX_LEN)
 			.contains(&s.len())
 			.then(|| unsafe { Box::from_raw(Box::into_raw(s) as *mut Key) })
 			.ok_or(TooLong)
@@ -160,7 +174,7 @@ impl fmt::Display for Key {
 #[cfg(fuzzing)]
 impl<'a> arbitrary::Arbitrary<'a> for &'a Key {
 	fn arbitrary(u: &mut arbitrary::Unstructured<'a>) -> arbitrary::Result<Self> {
-		let len = u.int_in_range::<usize>(1..=255)?;
+		let len = u.int_in_range::<usize>(1..=Key::MAX_LEN)?;
 		u.bytes(len).map(|b| b.try_into().unwrap())
 	}
 
@@ -6,26 +6,25 @@
 extern crate alloc;
 
 mod conf;
+mod hasher;
 mod header;
 mod key;
 mod store;
 #[cfg(test)]
 mod test;
 
-pub use {conf::*, key::*, store::*};
+pub use {conf::*, hasher::*, key::*, store::*};
 
 use {
-	alloc::boxed::Box,
+	alloc::{vec, vec::Vec},
 	core::{
 		cell::{RefCell, RefMut},
 		fmt,
 		future::Future,
-		hash::Hasher,
-		pin::Pin,
+		ops::{Deref, DerefMut},
 	},
 	header::*,
 	rand_core::{CryptoRng, RngCore},
-	siphasher::sip128::{Hasher128, SipHasher13},
 };
 
 const HEADER_SIZE: u64 = 64;
@@ -33,12 +32,20 @@ const HAMT_ENTRY_SIZE: u64 = 6;
 const HAMT_ROOT_LEN: u64 = 4096;
 const HAMT_CHILD_LEN: u64 = 16;
 const HASH_KEY_OFFSET: u64 = 0;
+const TAG_OFFSET_MASK: u64 = (1 << 48) - 1;
+const GENERATION_MASK: u16 = 0x7fff;
 
+/// Reference to an item or allocation.
+///
+/// The lower 48 bits are the offset in the store.
+/// The upper bits hold the generation the memory was allocated in.
 pub type Tag = core::num::NonZeroU64;
 
 pub struct Nrkv<S, C> {
 	store: S,
 	conf: C,
+	/// In-memory copy of the header used by [`Nrkv::insert_many`].
+	batch_header: Option<Header>,
 }
 
 impl<S, C> Nrkv<S, C> {
@@ -68,71 +75,197 @@ impl<S: Store, C: Conf> Nrkv<S, C> {
 
 	#[must_use]
 	pub async fn init_with_key(store: S, conf: C, hash_key: [u8; 16]) -> Result<Self, S::Error> {
+		Self::init_with_key_and_hasher(store, conf, hash_key, Hasher::SipHasher13).await
+	}
+
+	/// Initialize a new store which uses the given hash algorithm.
+	///
+	/// # Panics
+	///
+	/// If `hasher` is not [`Hasher::SipHasher13`] and [`Conf::hasher_offset`] is `None`.
+	#[must_use]
+	pub async fn init_with_key_and_hasher(
+		store: S,
+		conf: C,
+		hash_key: [u8; 16],
+		hasher: Hasher,
+	) -> Result<Self, S::Error> {
 		let hdr = Header::new(hash_key, conf.header_offset());
-		let mut slf = Self { store, conf };
+		let hasher_offt = conf.hasher_offset();
+		let mut slf = Self { store, conf, batch_header: None };
 		slf.set_header(hdr).await?;
+		match hasher_offt {
+			Some(offt) => slf.write(offt, &[hasher.to_raw()]).await?,
+			None => assert_eq!(hasher, Hasher::SipHasher13, "no hasher offset"),
+		}
 		Ok(slf)
 	}
 
 	#[must_use]
 	pub fn wrap(store: S, conf: C) -> Self {
-		Self { store, conf }
+		Self { store, conf, batch_header: None }
 	}
 
 	async fn header(&mut self) -> Result<Header, S::Error> {
+		if let Some(hdr) = self.batch_header {
+			return Ok(hdr);
+		}
 		let hdr = &mut [0; HEADER_SIZE as _];
 		self.read(self.conf.header_offset(), hdr).await?;
 		Ok(Header::from_raw(hdr))
 	}
 
 	async fn set_header(&mut self, header: Header) -> Result<(), S::Error> {
+		if let Some(hdr) = &mut self.batch_header {
+			*hdr = header;
+			return Ok(());
+		}
 		self.write(self.conf.header_offset(), &header.to_raw())
 			.await
 	}
 
 	pub async fn insert(&mut self, key: &Key, data: &[u8]) -> Result<Result<Tag, Tag>, S::Error> {
-		let h = self.hash(key).await?;
+		Ok(self
+			.insert_inner(key, data, None)
+			.await?
+			.map_err(|e| match e {
+				InsertAtError::Duplicate(tag) => tag,
+				InsertAtError::Occupied => unreachable!(),
+			}))
+	}
+
+	/// Insert multiple items at once.
+	///
+	/// This is more efficient than calling [`Self::insert`] repeatedly,
+	/// as the header is only read and written once.
+	///
+	/// Returns the result of each insertion in the same order as `entries`.
+	///
+	/// If the future is dropped before completion the header is not written,
+	/// but subsequent operations will read and write the header directly again.
+	pub async fn insert_many(
+		&mut self,
+		entries: &[(&Key, &[u8])],
+	) -> Result<Vec<Result<Tag, Tag>>, S::Error> {
+		let hdr = self.header().await?;
+		let mut slf = BatchGuard::new(self, hdr);
+		let mut tags = Vec::with_capacity(entries.len());
+		let mut res = Ok(());
+		for &(key, data) in entries {
+			match slf.insert(key, data).await {
+				Ok(t) => tags.push(t),
+				Err(e) => {
+					res = Err(e);
+					break;
+				}
+			}
+		}
+		// Write the header even on failure so the allocations made so far are accounted for.
+		let hdr = slf.batch_header.take().unwrap();
+		slf.set_header(hdr).await?;
+		res.map(|()| tags)
+	}
+
+	/// Insert an item or replace the user data of the item with the same key.
+	///
+	/// When replacing, all user data is overwritten, i.e. the bytes after `data` are zeroed.
+	///
+	/// Returns the tag of the item and the previous user data if it already existed.
+	pub async fn insert_or_replace(
+		&mut self,
+		key: &Key,
+		data: &[u8],
+	) -> Result<(Tag, Option<Vec<u8>>), S::Error> {
+		let tag = match self.insert(key, data).await? {
+			Ok(tag) => return Ok((tag, None)),
+			Err(tag) => tag,
+		};
+		assert!(data.len() <= usize::from(self.conf.item_offset()));
+		let mut old = vec![0; self.conf.item_offset().into()];
+		let mut item = Item::new(self, tag);
+		item.read_user(0, &mut old).await?;
+		let mut new = vec![0; old.len()];
+		new[..data.len()].copy_from_slice(data);
+		item.write_user(0, &new).await?;
+		Ok((tag, Some(old)))
+	}
+
+	/// Insert an item at a specific tag.
+	///
+	/// This is useful to recreate items such that previously recorded tags remain valid.
+	///
+	/// Fails if an item with the same key already exists or if the tag is not available.
+	pub async fn insert_at(
+		&mut self,
+		key: &Key,
+		data: &[u8],
+		tag: Tag,
+	) -> Result<Result<(), InsertAtError>, S::Error> {
+		Ok(self.insert_inner(key, data, Some(tag)).await?.map(|_| ()))
+	}
+
+	async fn insert_inner(
+		&mut self,
+		key: &Key,
+		data: &[u8],
+		at: Option<Tag>,
+	) -> Result<Result<Tag, InsertAtError>, S::Error> {
+		debug_assert!(
+			(1..=Key::MAX_LEN).contains(&key.len()),
+			"key length out of range"
+		);
+		let fold = self.fold_case().await?;
+		let h = self.hash(key, fold).await?;
 		let next = |h, d| (h / u128::from(d), h % u128::from(d));
 		let (mut h, mut i) = next(h, HAMT_ROOT_LEN);
 		let (mut slot_offt, slot) = self.hamt_root_get(i as _).await?;
 		let Some(mut slot) = slot else {
-			let offt = self.replace_item(None, key, data).await?;
+			let Some(offt) = self.replace_item(None, key, data, at).await? else {
+				return Ok(Err(InsertAtError::Occupied));
+			};
 			self.hamt_set_entry(slot_offt, offt.get()).await?;
-			return Ok(Ok(offt));
+			return Ok(Ok(self.tag(offt).await?));
 		};
 
 		let mut replace = None;
 		loop {
 			let mut item = Item::new(self, slot);
-			match item.key_eq(key).await? {
+			match item.key_eq(key, fold).await? {
 				None => {
 					replace.get_or_insert((slot_offt, slot));
 				}
 				Some(false) => {}
-				Some(true) => return Ok(Err(slot)),
+				Some(true) => {
+					let tag = item.kv.tag(slot).await?;
+					return Ok(Err(InsertAtError::Duplicate(tag)));
+				}
 			}
 			(h, i) = next(h, HAMT_CHILD_LEN);
 			let (o, s) = item.hamt_get(i as _).await?;
 			let Some(s) = s else {
 				let (o, prev_slot) = replace.map_or((o, None), |(o, s)| (o, Some(s)));
-				let offt = item.kv.replace_item(prev_slot, key, data).await?;
+				let Some(offt) = item.kv.replace_item(prev_slot, key, data, at).await? else {
+					return Ok(Err(InsertAtError::Occupied));
+				};
 				self.hamt_set_entry(o, offt.get()).await?;
-				return Ok(Ok(offt));
+				return Ok(Ok(self.tag(offt).await?));
 			};
 			(slot_offt, slot) = (o, s);
 		}
 	}
 
 	pub async fn find(&mut self, key: &Key) -> Result<Option<Tag>, S::Error> {
-		let h = self.hash(key).await?;
+		let fold = self.fold_case().await?;
+		let h = self.hash(key, fold).await?;
 		let next = |h, d| (h / u128::from(d), h % u128::from(d));
 		let (mut h, mut i) = next(h, HAMT_ROOT_LEN);
 		let (_, mut slot) = self.hamt_root_get(i as _).await?;
 
 		while let Some(item) = slot {
 			let mut item = Item::new(self, item);
-			if item.key_eq(key).await? == Some(true) {
-				return Ok(Some(item.offset));
+			if item.key_eq(key, fold).await? == Some(true) {
+				let offset = item.offset;
+				return Ok(Some(self.tag(offset).await?));
 			}
 			(h, i) = next(h, HAMT_CHILD_LEN);
 			(_, slot) = item.hamt_get(i as _).await?;
@@ -144,6 +277,48 @@ impl<S: Store, C: Conf> Nrkv<S, C> {
 		Item::new(self, tag).erase_key().await
 	}
 
+	/// Check whether the item at the given tag is live,
+	/// i.e. it has not been removed.
+	///
+	/// Tags of freed items are not live even if the memory has been reused by another item,
+	/// unless the generation has wrapped around since.
+	/// See [`Conf::generation_offset`].
+	pub async fn is_live(&mut self, tag: Tag) -> Result<bool, S::Error> {
+		if self.tag(tag_offset(tag)).await? != tag {
+			return Ok(false);
+		}
+		Ok(Item::new(self, tag).read_key(&mut []).await? > 0)
+	}
+
+	/// Get the tag of the region at the given offset,
+	/// which includes the generation it was allocated in.
+	async fn tag(&mut self, offset: Tag) -> Result<Tag, S::Error> {
+		let b = &mut [0; 8];
+		self.read(offset.get() - 8, b).await?;
+		let gen = (u64::from_le_bytes(*b) as u16) >> 1;
+		Ok(Tag::new(offset.get() | u64::from(gen) << 48).unwrap())
+	}
+
+	/// Get the current generation.
+	///
+	/// New regions are marked with it,
+	/// so tags of freed regions can be told apart from tags of regions reusing the memory.
+	async fn generation(&mut self) -> Result<u16, S::Error> {
+		let Some(offt) = self.conf.generation_offset() else { return Ok(0) };
+		let b = &mut [0; 2];
+		self.read(offt, b).await?;
+		Ok(u16::from_le_bytes(*b) & GENERATION_MASK)
+	}
+
+	/// Advance the generation.
+	///
+	/// This must be done after freeing items and before allocating memory again.
+	async fn next_generation(&mut self) -> Result<(), S::Error> {
+		let Some(offt) = self.conf.generation_offset() else { return Ok(()) };
+		let gen = (self.generation().await? + 1) & GENERATION_MASK;
+		self.write(offt, &gen.to_le_bytes()).await
+	}
+
 	async fn hamt_root_get(&mut self, index: u16) -> Result<(Tag, Option<Tag>), S::Error> {
 		debug_assert!(u64::from(index) < HAMT_ROOT_LEN);
 		let mut buf = [0; 8];
@@ -158,14 +333,43 @@ impl<S: Store, C: Conf> Nrkv<S, C> {
 		self.write(offset.get(), &value.to_le_bytes()[..6]).await
 	}
 
+	/// Allocate a new item, copying the HAMT entries of the previous item if any.
+	///
+	/// If `at` is `Some`, the item is allocated at that tag.
+	/// Returns `None` if that tag is not available.
 	async fn replace_item(
 		&mut self,
 		prev_slot: Option<Tag>,
 		key: &Key,
 		data: &[u8],
-	) -> Result<Tag, S::Error> {
+		at: Option<Tag>,
+	) -> Result<Option<Tag>, S::Error> {
 		assert!(data.len() <= usize::from(self.conf.item_offset()));
-		let offt = self.alloc(self.item_len(key.len_u8())).await?;
+		let len = self.item_len(key.len_u8());
+		let offt = match at {
+			None => self.alloc(len).await?,
+			Some(at) if prev_slot == Some(tag_offset(at)) => {
+				// Reuse the removed item in-place if it is large enough.
+				let offt = tag_offset(at);
+				let b = &mut [0; 8];
+				self.read(offt.get() - 8, b).await?;
+				let l = u64::from_le_bytes(*b) >> 16;
+				if l - 16 != (len + 15) & !15 {
+					return Ok(None);
+				}
+				// Take over the generation of the tag.
+				let marker = &(l << 16 | u64::from(tag_generation(at)) << 1 | 1).to_le_bytes();
+				self.write(offt.get() - 8, marker).await?;
+				self.write(offt.get() - 16 + l, marker).await?;
+				self.write_zeros(offt.get(), self.conf.item_offset().into())
+					.await?;
+				Item::new(self, offt).write_user(0, data).await?;
+				Item::new(self, offt).write_key(key).await?;
+				return Ok(Some(offt));
+			}
+			Some(at) if self.alloc_at(at, len).await? => tag_offset(at),
+			Some(_) => return Ok(None),
+		};
 
 		Item::new(self, offt).write_user(0, data).await?;
 
@@ -183,9 +387,10 @@ impl<S: Store, C: Conf> Nrkv<S, C> {
 			let b = u64::from_le_bytes(*b);
 			assert!(b & 1 == 1, "not allocated");
 			self.dealloc(prev_slot.get(), (b >> 16) - 16).await?;
+			self.next_generation().await?;
 		}
 
-		Ok(offt)
+		Ok(Some(offt))
 	}
 
 	fn item_len(&self, key_len: u8) -> u64 {
@@ -194,11 +399,40 @@ impl<S: Store, C: Conf> Nrkv<S, C> {
 			+ (1 + u64::from(key_len))
 	}
 
-	async fn hash(&mut self, data: &[u8]) -> Result<u128, S::Error> {
+	/// Hash a key.
+	///
+	/// If `fold` is `true`, ASCII letters are hashed as lowercase.
+	async fn hash(&mut self, key: &Key, fold: bool) -> Result<u128, S::Error> {
 		let h = self.hash_key().await?;
-		let mut h = SipHasher13::new_with_key(&h);
-		h.write(data);
-		Ok(h.finish128().as_u128())
+		let hasher = self.hasher().await?;
+		if !fold {
+			return Ok(hasher.hash(&h, key));
+		}
+		// Keys are short, so fold them in a buffer on the stack.
+		let buf = &mut [0; Key::MAX_LEN][..key.len()];
+		buf.copy_from_slice(key);
+		buf.make_ascii_lowercase();
+		Ok(hasher.hash(&h, buf))
+	}
+
+	/// Get the hash algorithm used by this store.
+	///
+	/// # Panics
+	///
+	/// If the stored algorithm is unknown.
+	pub async fn hasher(&mut self) -> Result<Hasher, S::Error> {
+		let Some(offt) = self.conf.hasher_offset() else { return Ok(Hasher::SipHasher13) };
+		let b = &mut [0];
+		self.read(offt, b).await?;
+		Ok(Hasher::from_raw(b[0]).unwrap_or_else(|| panic!("unknown hasher {}", b[0])))
+	}
+
+	/// Whether keys are compared case-insensitively.
+	pub async fn fold_case(&mut self) -> Result<bool, S::Error> {
+		let Some(offt) = self.conf.fold_case_offset() else { return Ok(false) };
+		let b = &mut [0];
+		self.read(offt, b).await?;
+		Ok(b[0] != 0)
 	}
 
 	async fn hash_key(&mut self) -> Result<[u8; 16], S::Error> {
@@ -240,22 +474,56 @@ impl<S: Store, C: Conf> Nrkv<S, C> {
 		let len = 8 + len + 8;
 		assert!(len < 1 << 48);
 
+		let gen = self.generation().await?;
 		let mut hdr = self.header().await?;
 		let (offt, prev_region_len) = hdr.alloc(len).unwrap();
 		self.set_header(hdr).await?;
 
-		let marker = &(len << 16 | 1).to_le_bytes();
+		let marker = &(len << 16 | u64::from(gen) << 1 | 1).to_le_bytes();
 		self.write(offt.get(), marker).await?;
 		self.write(offt.get() + len - 8, marker).await?;
 
 		if prev_region_len > 0 && prev_region_len - len > 0 {
-			let marker = &(prev_region_len - len << 16 | 0).to_le_bytes();
+			let marker = &((prev_region_len - len) << 16).to_le_bytes();
 			self.write(offt.get() + len, marker).await?;
 			self.write(offt.get() + prev_region_len - 8, marker).await?;
 		}
 		Ok(offt.checked_add(8).unwrap())
 	}
 
+	/// Allocate memory at a specific tag.
+	///
+	/// The memory is marked with the generation of the tag.
+	///
+	/// Returns `false` if the memory is not available.
+	pub async fn alloc_at(&mut self, tag: Tag, len: u64) -> Result<bool, S::Error> {
+		assert!(len > 0, "zero-sized allocation");
+		let gen = tag_generation(tag);
+		let tag = tag_offset(tag);
+		if tag.get() % 16 != 0 {
+			return Ok(false);
+		}
+		let len = (len + 15) & !15;
+		let len = 8 + len + 8;
+		assert!(len < 1 << 48);
+		let start = tag.get() - 8;
+
+		let mut hdr = self.header().await?;
+		let Some(free) = hdr.alloc_at(start, len) else { return Ok(false) };
+		self.set_header(hdr).await?;
+
+		let marker = &(len << 16 | u64::from(gen) << 1 | 1).to_le_bytes();
+		self.write(start, marker).await?;
+		self.write(start + len - 8, marker).await?;
+
+		for (offt, l) in free.into_iter().filter(|&(_, l)| l > 0) {
+			let marker = &(l << 16).to_le_bytes();
+			self.write(offt, marker).await?;
+			self.write(offt + l - 8, marker).await?;
+		}
+		Ok(true)
+	}
+
 	pub async fn dealloc(&mut self, offset: u64, len: u64) -> Result<(), S::Error> {
 		if len == 0 {
 			return Ok(());
@@ -294,7 +562,7 @@ impl<S: Store, C: Conf> Nrkv<S, C> {
 		if hdr.insert_free_region(start, l) {
 			self.write_zeros(start, 8).await?;
 		} else {
-			let marker = &(l << 16 | 0).to_le_bytes();
+			let marker = &(l << 16).to_le_bytes();
 			self.write(start, marker).await?;
 			self.write(end - 8, marker).await?;
 		}
@@ -303,6 +571,90 @@ impl<S: Store, C: Conf> Nrkv<S, C> {
 		Ok(())
 	}
 
+	/// Free removed items and release trailing free space.
+	///
+	/// Removed items are freed if they have no children,
+	/// which may in turn leave their parents without children.
+	/// Live items are never moved.
+	///
+	/// The released space is zeroed,
+	/// which frees the underlying storage of sparse stores.
+	///
+	/// # Warning
+	///
+	/// The memory of removed items may be reused afterwards.
+	/// Their tags are only guaranteed to not refer to new items if the store has a generation,
+	/// see [`Conf::generation_offset`].
+	///
+	/// Returns the amount of bytes released from the end of the store.
+	pub async fn compact(&mut self) -> Result<u64, S::Error> {
+		let end = self.header().await?.end();
+		let mut freed = false;
+
+		// Walk the HAMT depth-first, visiting children before their parents.
+		let mut stack = Vec::new();
+		for i in 0..HAMT_ROOT_LEN {
+			if let (slot, Some(item)) = self.hamt_root_get(i as _).await? {
+				stack.push((slot, item, 0));
+			}
+			while let Some(&(slot, item, i)) = stack.last() {
+				if u64::from(i) < HAMT_CHILD_LEN {
+					stack.last_mut().unwrap().2 += 1;
+					if let (s, Some(child)) = Item::new(self, item).hamt_get(i).await? {
+						stack.push((s, child, 0));
+					}
+					continue;
+				}
+				stack.pop();
+
+				let mut it = Item::new(self, item);
+				if it.read_key(&mut []).await? > 0 {
+					continue;
+				}
+				let buf = &mut [0; _];
+				it.read_hamt(buf).await?;
+				if buf.iter().any(|&b| b != 0) {
+					continue;
+				}
+				if !freed {
+					self.next_generation().await?;
+					freed = true;
+				}
+				self.hamt_set_entry(slot, 0).await?;
+				let b = &mut [0; 8];
+				self.read(item.get() - 8, b).await?;
+				self.dealloc(item.get(), (u64::from_le_bytes(*b) >> 16) - 16)
+					.await?;
+			}
+		}
+
+		// Freeing a region at the end of the store lowers the end,
+		// so trailing free space is released by the deallocations above.
+		let new_end = self.header().await?.end();
+		self.write_zeros(new_end, end - new_end).await?;
+		Ok(end - new_end)
+	}
+
+	/// Copy all data to another store and continue using that store.
+	///
+	/// Tags only depend on data inside the store, so all tags remain valid.
+	///
+	/// `to` must be zeroed.
+	///
+	/// Returns the previous store.
+	pub async fn relocate(&mut self, mut to: S) -> Result<S, S::Error> {
+		let end = self.header().await?.end();
+		let buf = &mut [0; 1 << 10];
+		let mut offt = 0;
+		while offt < end {
+			let l = (end - offt).min(buf.len() as u64) as usize;
+			self.read(offt, &mut buf[..l]).await?;
+			to.write(offt, &buf[..l]).await?;
+			offt += l as u64;
+		}
+		Ok(core::mem::replace(&mut self.store, to))
+	}
+
 	pub async fn read(&mut self, offset: u64, buf: &mut [u8]) -> Result<(), S::Error> {
 		self.store.read(offset, buf).await
 	}
@@ -363,62 +715,155 @@ impl<'a, S: Store, C: Conf> ShareNrkv<'a, S, C> {
 		let (_, Some(root)) = self.borrow_mut().hamt_root_get(state.root()).await?
 			else { return Ok(true) };
 
-		// FIXME make non-recursive to avoid alloc
-		async fn rec<S: Store, C: Conf, F, Fut>(
-			slf: &ShareNrkv<'_, S, C>,
-			item: Tag,
-			depth: u8,
-			state: &mut IterState,
-			f: &mut F,
-		) -> Result<bool, S::Error>
-		where
-			F: FnMut(Tag) -> Fut,
-			Fut: Future<Output = Result<bool, S::Error>>,
-		{
-			if depth == state.depth() {
-				state.incr_depth();
-				if !f(item).await? {
-					return Ok(false);
+		// Items on the path from the root to the current item.
+		// The index of the child to visit next at each depth is kept in `state`.
+		let mut path = Vec::with_capacity(16);
+		path.push(root);
+		let mut enter = true;
+		while let Some(&item) = path.last() {
+			let depth = u8::try_from(path.len() - 1).unwrap();
+			if enter {
+				enter = false;
+				if depth == state.depth() {
+					state.incr_depth();
+					let tag = self.borrow_mut().tag(item).await?;
+					if !f(tag).await? {
+						return Ok(false);
+					}
 				}
 			}
-			for i in state.child(depth)..=15 {
-				let mut kv = slf.borrow_mut();
+
+			let child = loop {
+				let mut kv = self.borrow_mut();
 				let mut item = Item::new(&mut kv, item);
-				if let (_, Some(child)) = item.hamt_get(i).await? {
-					fn box_fut<'a, T>(
-						f: impl Future<Output = T> + 'a,
-					) -> Pin<Box<dyn Future<Output = T> + 'a>> {
-						Box::pin(f)
-					}
-					drop(kv);
-					let f = box_fut(rec(slf, child, depth + 1, state, f));
-					if !f.await? {
-						return Ok(false);
+				if let (_, Some(child)) = item.hamt_get(state.child(depth)).await? {
+					break Some(child);
+				}
+				if !state.step_child(depth) {
+					break None;
+				}
+			};
+			if let Some(child) = child {
+				path.push(child);
+				enter = true;
+				continue;
+			}
+
+			// Go up until we find an item with children left to visit.
+			loop {
+				state.decr_depth();
+				path.pop();
+				let Some(depth) = path.len().checked_sub(1) else { break };
+				if state.step_child(u8::try_from(depth).unwrap()) {
+					break;
+				}
+			}
+		}
+		Ok(true)
+	}
+
+	/// Visit every live item exactly once.
+	///
+	/// Unlike [`Self::next_batch`] this is not lossy, but it cannot be resumed.
+	/// Items are visited in HAMT order, parents before children.
+	///
+	/// Iteration stops early if `f` returns `false`.
+	pub async fn iter_all<F, Fut>(&self, mut f: F) -> Result<(), S::Error>
+	where
+		F: FnMut(Tag) -> Fut,
+		Fut: Future<Output = Result<bool, S::Error>>,
+	{
+		let mut stack = Vec::new();
+		for i in 0..HAMT_ROOT_LEN {
+			if let (_, Some(item)) = self.borrow_mut().hamt_root_get(i as _).await? {
+				stack.push(item);
+			}
+			while let Some(item) = stack.pop() {
+				let tag = self.borrow_mut().tag(item).await?;
+				let live = self.borrow_mut().is_live(tag).await?;
+				if live && !f(tag).await? {
+					return Ok(());
+				}
+				// Push in reverse so children are visited in order.
+				for k in (0..HAMT_CHILD_LEN as u8).rev() {
+					let mut kv = self.borrow_mut();
+					if let (_, Some(child)) = Item::new(&mut kv, item).hamt_get(k).await? {
+						stack.push(child);
 					}
 				}
-				state.step_child(depth);
 			}
-			state.decr_depth();
-			Ok(true)
 		}
+		Ok(())
+	}
+}
+
+/// Keeps the header in memory while a batch of operations is in progress.
+///
+/// Operations through the guard read and modify the header in memory
+/// instead of reading and writing it in the store.
+/// To persist the header, take it out of `batch_header` and write it with [`Nrkv::set_header`].
+///
+/// When dropped, the in-memory header is discarded without being written,
+/// so later operations access the header in the store again.
+/// This ensures a stale header is never used if [`Nrkv::insert_many`] is dropped mid-batch.
+struct BatchGuard<'a, S, C>(&'a mut Nrkv<S, C>);
+
+impl<'a, S, C> BatchGuard<'a, S, C> {
+	fn new(kv: &'a mut Nrkv<S, C>, header: Header) -> Self {
+		kv.batch_header = Some(header);
+		Self(kv)
+	}
+}
+
+impl<S, C> Deref for BatchGuard<'_, S, C> {
+	type Target = Nrkv<S, C>;
+
+	fn deref(&self) -> &Self::Target {
+		self.0
+	}
+}
+
+impl<S, C> DerefMut for BatchGuard<'_, S, C> {
+	fn deref_mut(&mut self) -> &mut Self::Target {
+		self.0
+	}
+}
 
-		rec(self, root, 0, state, f).await
+impl<S, C> Drop for BatchGuard<'_, S, C> {
+	fn drop(&mut self) {
+		self.0.batch_header = None;
 	}
 }
 
+/// Get the offset a tag points to.
+fn tag_offset(tag: Tag) -> Tag {
+	Tag::new(tag.get() & TAG_OFFSET_MASK).unwrap()
+}
+
+/// Get the generation the memory a tag points to was allocated in.
+fn tag_generation(tag: Tag) -> u16 {
+	(tag.get() >> 48) as u16
+}
+
 struct Item<'a, S, C> {
 	kv: &'a mut Nrkv<S, C>,
 	offset: Tag,
 }
 
 impl<'a, S, C> Item<'a, S, C> {
-	fn new(kv: &'a mut Nrkv<S, C>, offset: Tag) -> Self {
-		Self { kv, offset }
+	/// The generation of `tag` is ignored.
+	fn new(kv: &'a mut Nrkv<S, C>, tag: Tag) -> Self {
+		Self { kv, offset: tag_offset(tag) }
 	}
 }
 
 impl<'a, S: Store, C: Conf> Item<'a, S, C> {
-	async fn key_eq(&mut self, key: &Key) -> Result<Option<bool>, S::Error> {
+	/// Compare the key of this item.
+	///
+	/// If `fold` is `true`, ASCII letters are compared case-insensitively.
+	///
+	/// Returns `None` if the item has been removed.
+	async fn key_eq(&mut self, key: &Key, fold: bool) -> Result<Option<bool>, S::Error> {
 		let len = &mut [0];
 		self.read(self.key_offset(), len).await?;
 		if len[0] == 0 {
@@ -428,7 +873,11 @@ impl<'a, S: Store, C: Conf> Item<'a, S, C> {
 		}
 		let buf = &mut [0; 255][..key.len()];
 		self.read(self.key_offset() + 1, buf).await?;
-		Ok(Some(&buf[..key.len()] == &**key))
+		Ok(Some(if fold {
+			buf.eq_ignore_ascii_case(&**key)
+		} else {
+			&buf[..key.len()] == &**key
+		}))
 	}
 
 	async fn hamt_get(&mut self, index: u8) -> Result<(Tag, Option<Tag>), S::Error> {
@@ -496,6 +945,15 @@ impl<'a, S: Store, C: Conf> Item<'a, S, C> {
 	}
 }
 
+/// An error that occured while trying to insert an item at a specific tag.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum InsertAtError {
+	/// An item with the same key already exists.
+	Duplicate(Tag),
+	/// The tag is already in use.
+	Occupied,
+}
+
 /// "lossy" iterator, i.e. it may step over some entries in very rare cases.
 #[derive(Default)]
 pub struct IterState {
@@ -512,6 +970,24 @@ impl IterState {
 		(self.children as u64) << 16 | u64::from(self.root_depth)
 	}
 
+	/// Estimate the fraction of items that have been iterated over.
+	///
+	/// The returned value is between `0.0` and `1.0`.
+	/// It is only accurate if the items are evenly distributed, which is usually the case.
+	pub fn progress(&self) -> f32 {
+		if u64::from(self.root()) == HAMT_ROOT_LEN - 1 && self.depth() == 15 {
+			return 1.0;
+		}
+		let mut p = 0.0;
+		let mut scale = 1.0 / HAMT_ROOT_LEN as f64;
+		p += f64::from(self.root()) * scale;
+		for d in 0..self.depth() {
+			scale /= HAMT_CHILD_LEN as f64;
+			p += f64::from(self.child(d)) * scale;
+		}
+		p as f32
+	}
+
 	fn depth(&self) -> u8 {
 		u8::try_from(self.root_depth & 0xf).unwrap()
 	}
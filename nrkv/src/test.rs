@@ -1,12 +1,13 @@
 use crate::StaticConf;
 
 use {
-	crate::{Nrkv, ShareNrkv},
-	alloc::vec,
+	crate::{Nrkv, ShareNrkv, Store},
+	alloc::{boxed::Box, vec},
 	core::{
 		cell::{Cell, RefCell},
 		future::Future,
-		task::{Context, RawWaker, RawWakerVTable, Waker},
+		pin::{pin, Pin},
+		task::{Context, Poll, RawWaker, RawWakerVTable, Waker},
 	},
 };
 
@@ -156,6 +157,123 @@ fn next_batch() {
 	});
 }
 
+#[test]
+fn next_batch_progress() {
+	run(async {
+		let mut kv = mkkv().await;
+		for i in 0..64u8 {
+			kv.insert((&[i]).into(), &[]).await.unwrap().unwrap();
+		}
+		let kv = &ShareNrkv::new(&mut kv);
+		let mut state = crate::IterState::default();
+		let mut prev = state.progress();
+		assert_eq!(prev, 0.0);
+		loop {
+			let visited = &Cell::new(false);
+			kv.next_batch(&mut state, |_| async move {
+				visited.set(true);
+				Ok(false)
+			})
+			.await
+			.unwrap();
+			let p = state.progress();
+			assert!(p >= prev, "progress went backwards: {} -> {}", prev, p);
+			prev = p;
+			if !visited.get() {
+				break;
+			}
+		}
+		assert_eq!(prev, 1.0);
+	});
+}
+
+#[test]
+fn next_batch_4096() {
+	run(async {
+		let mut kv = Nrkv::init_with_key(vec![0; 1 << 20], StaticConf::<64, 32>, [0; 16])
+			.await
+			.unwrap();
+		for i in 0..4096u16 {
+			kv.insert((&i.to_le_bytes()).into(), &[])
+				.await
+				.unwrap()
+				.unwrap();
+		}
+		let kv = &ShareNrkv::new(&mut kv);
+
+		// In one go
+		let count = &Cell::new(0);
+		kv.next_batch(&mut Default::default(), |_| async move {
+			count.set(count.get() + 1);
+			Ok(true)
+		})
+		.await
+		.unwrap();
+		assert_eq!(count.get(), 4096);
+
+		// In batches of 7, resuming each time.
+		let visited = &RefCell::new(Vec::new());
+		let mut state = crate::IterState::default();
+		loop {
+			let n = &Cell::new(0);
+			kv.next_batch(&mut state, |tag| async move {
+				visited.borrow_mut().push(tag);
+				n.set(n.get() + 1);
+				Ok(n.get() < 7)
+			})
+			.await
+			.unwrap();
+			if n.get() == 0 {
+				break;
+			}
+		}
+		let mut visited = visited.take();
+		visited.sort();
+		visited.dedup();
+		assert_eq!(visited.len(), 4096);
+	});
+}
+
+#[test]
+fn iter_all() {
+	run(async {
+		let mut kv = mkkv().await;
+		let mut live = Vec::new();
+		for i in 0..200u16 {
+			let tag = kv
+				.insert((&i.to_le_bytes()).into(), &[])
+				.await
+				.unwrap()
+				.unwrap();
+			if i % 3 == 0 {
+				kv.remove(tag).await.unwrap();
+			} else {
+				live.push(tag);
+			}
+		}
+		live.sort();
+
+		let kv = &ShareNrkv::new(&mut kv);
+		let mut prev = None;
+		for _ in 0..3 {
+			let visited = &RefCell::new(Vec::new());
+			kv.iter_all(|tag| async move {
+				visited.borrow_mut().push(tag);
+				Ok(true)
+			})
+			.await
+			.unwrap();
+			let visited = visited.take();
+			let mut sorted = visited.clone();
+			sorted.sort();
+			assert_eq!(sorted, live, "visited set differs from live set");
+			if let Some(prev) = prev.replace(visited.clone()) {
+				assert_eq!(prev, visited, "order differs between runs");
+			}
+		}
+	});
+}
+
 #[test]
 fn user_data() {
 	run(async {
@@ -242,3 +360,299 @@ fn dealloc_merge_large() {
 		kv.dealloc(c.get(), 96).await.unwrap();
 	});
 }
+
+#[test]
+fn is_live_removed() {
+	run(async {
+		let mut kv = mkkv().await;
+		let t = kv.insert(b"hello".into(), &[]).await.unwrap().unwrap();
+		assert!(kv.is_live(t).await.unwrap());
+		kv.remove(t).await.unwrap();
+		assert!(!kv.is_live(t).await.unwrap());
+	});
+}
+
+#[test]
+fn insert_at_removed() {
+	run(async {
+		let mut kv = mkkv().await;
+		let t = kv.insert(b"hello".into(), &[1]).await.unwrap().unwrap();
+		kv.remove(t).await.unwrap();
+		kv.insert_at(b"hello".into(), &[2], t)
+			.await
+			.unwrap()
+			.unwrap();
+		assert_eq!(kv.find(b"hello".into()).await.unwrap(), Some(t));
+		let buf = &mut [0; 2];
+		kv.read_user_data(t, 0, buf).await.unwrap();
+		assert_eq!(buf, &[2, 0]);
+	});
+}
+
+#[test]
+fn insert_at_occupied() {
+	run(async {
+		let mut kv = mkkv().await;
+		let t = kv.insert(b"hello".into(), &[]).await.unwrap().unwrap();
+		let r = kv.insert_at(b"quack".into(), &[], t).await.unwrap();
+		assert_eq!(r, Err(crate::InsertAtError::Occupied));
+	});
+}
+
+#[test]
+fn relocate() {
+	run(async {
+		let mut kv = mkkv().await;
+		let a = kv.insert(b"hello".into(), &[1]).await.unwrap().unwrap();
+		let b = kv.insert(b"world".into(), &[2]).await.unwrap().unwrap();
+		kv.remove(a).await.unwrap();
+		let c = kv.insert(b"quack".into(), &[3]).await.unwrap().unwrap();
+
+		let old = kv.relocate(mkstore()).await.unwrap();
+		assert_eq!(kv.inner().0, &old);
+
+		assert_eq!(kv.find(b"hello".into()).await.unwrap(), None);
+		assert_eq!(kv.find(b"world".into()).await.unwrap(), Some(b));
+		assert_eq!(kv.find(b"quack".into()).await.unwrap(), Some(c));
+		let buf = &mut [0];
+		kv.read_user_data(c, 0, buf).await.unwrap();
+		assert_eq!(buf, &[3]);
+		kv.insert(b"hello".into(), &[]).await.unwrap().unwrap();
+	});
+}
+
+#[test]
+fn compact() {
+	run(async {
+		let mut kv = Nrkv::init_with_key(vec![0; 1 << 20], StaticConf::<64, 32>, [0; 16])
+			.await
+			.unwrap();
+		let end = kv.header().await.unwrap().end();
+		let mut tags = vec![];
+		for i in 0..1000 {
+			let key = format!("{}", i);
+			let tag = kv
+				.insert(key.as_bytes().try_into().unwrap(), &[])
+				.await
+				.unwrap()
+				.unwrap();
+			tags.push(tag);
+		}
+		for t in tags {
+			kv.remove(t).await.unwrap();
+		}
+
+		assert!(kv.compact().await.unwrap() > 0);
+		assert_eq!(kv.header().await.unwrap().end(), end);
+		assert!(kv.inner().0[end as usize..].iter().all(|&b| b == 0));
+		assert!(kv.find(b"42".into()).await.unwrap().is_none());
+		kv.insert(b"42".into(), &[]).await.unwrap().unwrap();
+		assert!(kv.find(b"42".into()).await.unwrap().is_some());
+	});
+}
+
+#[test]
+fn compact_generation() {
+	struct GenConf;
+
+	impl crate::Conf for GenConf {
+		fn header_offset(&self) -> u64 {
+			64
+		}
+		fn item_offset(&self) -> u16 {
+			32
+		}
+		fn generation_offset(&self) -> Option<u64> {
+			Some(0)
+		}
+	}
+
+	run(async {
+		let mut kv = Nrkv::init_with_key(mkstore(), GenConf, [0; 16])
+			.await
+			.unwrap();
+		let a = kv.insert(b"hello".into(), &[]).await.unwrap().unwrap();
+		kv.remove(a).await.unwrap();
+		kv.compact().await.unwrap();
+
+		// The memory is reused, but the tag is different.
+		let b = kv.insert(b"world".into(), &[]).await.unwrap().unwrap();
+		assert_eq!(a.get() & 0xffff_ffff_ffff, b.get() & 0xffff_ffff_ffff);
+		assert_ne!(a, b);
+		assert!(!kv.is_live(a).await.unwrap());
+		assert!(kv.is_live(b).await.unwrap());
+		assert_eq!(kv.find(b"world".into()).await.unwrap(), Some(b));
+
+		// The tag of an item can be used to recreate it.
+		kv.remove(b).await.unwrap();
+		kv.insert_at(b"world".into(), &[], b)
+			.await
+			.unwrap()
+			.unwrap();
+		assert!(kv.is_live(b).await.unwrap());
+		assert!(!kv.is_live(a).await.unwrap());
+	});
+}
+
+#[test]
+fn hasher_xxh3() {
+	#[derive(Debug)]
+	struct HasherConf;
+
+	impl crate::Conf for HasherConf {
+		fn header_offset(&self) -> u64 {
+			64
+		}
+		fn item_offset(&self) -> u16 {
+			32
+		}
+		fn hasher_offset(&self) -> Option<u64> {
+			Some(0)
+		}
+	}
+
+	run(async {
+		let mut kv =
+			Nrkv::init_with_key_and_hasher(mkstore(), HasherConf, [0; 16], crate::Hasher::Xxh3)
+				.await
+				.unwrap();
+		assert_eq!(kv.hasher().await.unwrap(), crate::Hasher::Xxh3);
+		let a = kv.insert(b"hello".into(), &[]).await.unwrap().unwrap();
+		let b = kv.insert(b"quack".into(), &[]).await.unwrap().unwrap();
+		assert_eq!(kv.find(b"hello".into()).await.unwrap(), Some(a));
+		assert_eq!(kv.find(b"quack".into()).await.unwrap(), Some(b));
+		assert!(kv.find(b"world".into()).await.unwrap().is_none());
+	});
+}
+
+#[test]
+fn insert_or_replace_new() {
+	run(async {
+		let mut kv = mkkv().await;
+		let (tag, old) = kv
+			.insert_or_replace(b"hello".into(), &[1, 2, 3])
+			.await
+			.unwrap();
+		assert!(old.is_none());
+		assert_eq!(kv.find(b"hello".into()).await.unwrap(), Some(tag));
+		let buf = &mut [0; 4];
+		kv.read_user_data(tag, 0, buf).await.unwrap();
+		assert_eq!(buf, &[1, 2, 3, 0]);
+	});
+}
+
+#[test]
+fn insert_or_replace_existing() {
+	run(async {
+		let mut kv = mkkv().await;
+		let t = kv
+			.insert(b"hello".into(), &[1, 2, 3])
+			.await
+			.unwrap()
+			.unwrap();
+		let (tag, old) = kv
+			.insert_or_replace(b"hello".into(), &[4, 5])
+			.await
+			.unwrap();
+		assert_eq!(tag, t);
+		let old = old.unwrap();
+		assert_eq!(old.len(), 32);
+		assert_eq!(old[..4], [1, 2, 3, 0]);
+		let buf = &mut [0; 4];
+		kv.read_user_data(tag, 0, buf).await.unwrap();
+		assert_eq!(buf, &[4, 5, 0, 0]);
+	});
+}
+
+#[test]
+fn key_too_long() {
+	assert!(crate::Key::try_key(&[1; 256]).is_err());
+	assert!(crate::Key::try_key(&[]).is_err());
+	assert_eq!(crate::Key::try_key(&[1; 255]).unwrap().len_u8(), 255);
+}
+
+#[test]
+fn insert_many() {
+	run(async {
+		let keys = (0..200u16).map(|i| i.to_le_bytes()).collect::<Vec<_>>();
+
+		let mut a = mkkv().await;
+		let mut expect = Vec::new();
+		for k in keys.iter() {
+			expect.push(a.insert(k.into(), &[k[0]]).await.unwrap());
+		}
+		expect.push(a.insert(b"\0\0".into(), &[]).await.unwrap());
+
+		let mut b = mkkv().await;
+		let mut entries = keys
+			.iter()
+			.map(|k| (<&crate::Key>::from(k), &k[..1]))
+			.collect::<Vec<_>>();
+		entries.push((b"\0\0".into(), &[]));
+		let tags = b.insert_many(&entries).await.unwrap();
+
+		assert_eq!(tags, expect);
+		assert!(tags.last().unwrap().is_err(), "duplicate not detected");
+		assert!(a.into_inner().0 == b.into_inner().0, "stores differ");
+	});
+}
+
+/// Store that is pending once before every write.
+struct YieldStore(Vec<u8>);
+
+impl Store for YieldStore {
+	type Error = !;
+
+	fn read<'a>(
+		&'a mut self,
+		offset: u64,
+		buf: &'a mut [u8],
+	) -> Pin<Box<dyn Future<Output = Result<(), !>> + 'a>> {
+		self.0.read(offset, buf)
+	}
+
+	fn write<'a>(
+		&'a mut self,
+		offset: u64,
+		data: &'a [u8],
+	) -> Pin<Box<dyn Future<Output = Result<(), !>> + 'a>> {
+		Box::pin(async move {
+			let mut pending = true;
+			core::future::poll_fn(|_| match core::mem::take(&mut pending) {
+				true => Poll::Pending,
+				false => Poll::Ready(()),
+			})
+			.await;
+			self.0.write(offset, data).await
+		})
+	}
+
+	fn write_zeros<'a>(
+		&'a mut self,
+		offset: u64,
+		len: u64,
+	) -> Pin<Box<dyn Future<Output = Result<(), !>> + 'a>> {
+		self.0.write_zeros(offset, len)
+	}
+
+	fn len(&self) -> u64 {
+		Store::len(&self.0)
+	}
+}
+
+#[test]
+fn insert_many_drop() {
+	run(async {
+		let mut kv = Nrkv::init_with_key(YieldStore(mkstore()), StaticConf::<64, 32>, [0; 16])
+			.await
+			.unwrap();
+		let entries = [(b"a".into(), &[][..]), (b"b".into(), &[][..])];
+		{
+			let mut fut = pin!(kv.insert_many(&entries));
+			let cx = &mut Context::from_waker(&NOOP_WAKER);
+			assert!(fut.as_mut().poll(cx).is_pending());
+			assert!(fut.as_mut().poll(cx).is_pending());
+		}
+		assert!(kv.batch_header.is_none(), "header still kept in memory");
+	});
+}
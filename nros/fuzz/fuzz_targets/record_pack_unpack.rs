@@ -0,0 +1,8 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+
+use nros::test::fuzz::record::Test;
+
+fuzz_target!(|test: Test| {
+    test.run();
+});
@@ -5,7 +5,7 @@ use {
 		waker_queue::{WakerQueue, WakerQueueTicket},
 	},
 	alloc::collections::BTreeMap,
-	core::task::Waker,
+	core::{ops::RangeInclusive, task::Waker},
 };
 
 #[derive(Default, Debug)]
@@ -34,6 +34,11 @@ impl BusyMap {
 		e.refcount == 1
 	}
 
+	/// Whether any entry in the range is referenced.
+	pub fn contains_range(&self, range: RangeInclusive<IdKey>) -> bool {
+		self.map.range(range).next().is_some()
+	}
+
 	/// Insert a waker to be woken when an entry becomes present.
 	pub fn wait(&mut self, key: IdKey, waker: Waker) -> WakerQueueTicket<()> {
 		trace!("busy_wait {:?}", key);
@@ -52,6 +52,8 @@ impl<B: Buf> Drop for EntryRef<'_, B> {
 				let entry = d.records.get_mut(&self.key).expect("no entry");
 				debug_assert_eq!(entry.lru_idx, IDX_NONE, "entry already in LRU");
 				entry.lru_idx = idx;
+			} else {
+				d.mem.soft_touch(lru_idx);
 			}
 		} else if self.in_lru {
 			d.mem.soft_del(lru_idx);
@@ -95,6 +97,14 @@ impl<'a, B: Buf> EntryRef<'a, B> {
 		self.dirty.insert(self.key);
 	}
 
+	/// Check whether the data at the given offset is equal to the given data.
+	pub(super) fn is_equal(&self, offset: usize, data: &[u8]) -> bool {
+		let d = self.entry.data.get();
+		let d = d.get(offset..).unwrap_or(&[]);
+		let l = d.len().min(data.len());
+		d[..l] == data[..l] && data[l..].iter().all(|&b| b == 0)
+	}
+
 	/// Write zeroes to the entry.
 	pub(super) fn write_zeros(&mut self, offset: usize, length: usize) {
 		trace!(
@@ -121,6 +131,13 @@ impl<'a, B: Buf> EntryRef<'a, B> {
 		self.dirty.insert(self.key);
 	}
 
+	/// Mark the entry as dirty without modifying it.
+	///
+	/// This forces the record to be written out again.
+	pub(super) fn mark_dirty(&mut self) {
+		self.dirty.insert(self.key);
+	}
+
 	/// Replace entry data.
 	pub(super) fn replace(&mut self, mut data: B) {
 		trace!("EntryRef::replace {:?} len {}", self.key, data.len());
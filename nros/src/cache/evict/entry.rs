@@ -30,7 +30,26 @@ impl<D: Dev, R: Resource> Cache<D, R> {
 		Some(util::box_fut(async move {
 			trace!("evict_entry::(background) {:?}", key);
 
-			let (record_ref, data) = self.store.write(data).await?;
+			// If writing fails, put the entry back so it stays dirty.
+			//
+			// Running out of space is not fatal here:
+			// the next flush tries to write the entry again and reports the error to the caller.
+			let (record_ref, data) = match self.store.write(data).await {
+				Ok(r) => r,
+				Err((e, data)) => {
+					self.data().busy.wake(key);
+					self.entry_insert(key, data);
+					let mut data = self.data();
+					data.evict_tasks_count -= 1;
+					if data.evict_tasks_count == 0 {
+						data.wake_after_evicts.take().map(|w| w.wake());
+					}
+					return match e {
+						Error::NotEnoughSpace => Ok(()),
+						e => Err(e),
+					};
+				}
+			};
 			drop(data);
 			self.mem().hard_del();
 
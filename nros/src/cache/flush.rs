@@ -1,8 +1,11 @@
 use {
 	super::{Cache, IdKey, Key, RootIndex, Tree, OBJECT_BITMAP_ID, OBJECT_LIST_ID},
 	crate::{data::record::Depth, Dev, Error, Resource},
-	core::{future, task::Poll},
-	futures_util::stream::{FuturesUnordered, TryStreamExt},
+	core::{
+		future::{self, Future},
+		task::Poll,
+	},
+	futures_util::stream::{FuturesUnordered, StreamExt},
 };
 
 impl<D: Dev, R: Resource> Cache<D, R> {
@@ -11,7 +14,7 @@ impl<D: Dev, R: Resource> Cache<D, R> {
 	/// This does not evict the entry.
 	///
 	/// Does nothing if the entry wasn't present or dirty.
-	async fn flush_entry(&self, key: IdKey) -> Result<(), Error<D>> {
+	pub(super) async fn flush_entry(&self, key: IdKey) -> Result<(), Error<D>> {
 		trace!("flush_entry {:?}", key);
 
 		// Wait for entry
@@ -30,7 +33,15 @@ impl<D: Dev, R: Resource> Cache<D, R> {
 		let (data, _) = self.entry_remove(key);
 
 		// Store entry.
-		let (rec, data) = self.store.write(data).await?;
+		// If it fails, put the entry back so it stays dirty and can be flushed again later.
+		let (rec, data) = match self.store.write(data).await {
+			Ok(r) => r,
+			Err((e, data)) => {
+				self.data().busy.wake(key);
+				self.entry_insert(key, data);
+				return Err(e);
+			}
+		};
 
 		// TODO check if tree can allocate enough reserved memory to operate
 		// If not, discard entry to avoid potential deadlock.
@@ -91,11 +102,7 @@ impl<D: Dev, R: Resource> Cache<D, R> {
 					.copied()
 					.collect::<Vec<_>>();
 				// Flush all entries at current level.
-				keys.into_iter()
-					.map(|key| self.flush_entry(key))
-					.collect::<FuturesUnordered<_>>()
-					.try_for_each(|()| async { Ok(()) })
-					.await?;
+				join_all(keys.into_iter().map(|key| self.flush_entry(key))).await?;
 			}
 			Ok(())
 		};
@@ -115,18 +122,14 @@ impl<D: Dev, R: Resource> Cache<D, R> {
 			.filter(|id| ![OBJECT_LIST_ID, OBJECT_BITMAP_ID].contains(id))
 			.flat_map(|id| (RootIndex::I0..=RootIndex::I3).map(move |r| (id, r)))
 			.map(|(id, root)| flush_object(id, root))
-			.collect::<FuturesUnordered<_>>();
-		queue.try_for_each(|()| async { Ok(()) }).await?;
+			.collect::<Vec<_>>();
+		join_all(queue).await?;
 
 		// Wait for evicts to finish.
 		self.wait_all_evict().await;
 
 		// Now flush the object list and bitmap.
-		[OBJECT_LIST_ID, OBJECT_BITMAP_ID]
-			.into_iter()
-			.map(|id| flush_object(id, RootIndex::I0))
-			.collect::<FuturesUnordered<_>>()
-			.try_for_each(|()| async { Ok(()) })
+		join_all([OBJECT_LIST_ID, OBJECT_BITMAP_ID].map(|id| flush_object(id, RootIndex::I0)))
 			.await?;
 
 		// Wait for evicts to finish.
@@ -152,3 +155,19 @@ impl<D: Dev, R: Resource> Cache<D, R> {
 		.await
 	}
 }
+
+/// Run all tasks to completion and return the first error, if any.
+///
+/// Unlike [`TryStreamExt::try_for_each`](futures_util::TryStreamExt::try_for_each),
+/// no task is dropped halfway if another task fails,
+/// so entries that are being flushed are not lost.
+async fn join_all<E, F>(tasks: impl IntoIterator<Item = F>) -> Result<(), E>
+where
+	F: Future<Output = Result<(), E>>,
+{
+	tasks
+		.into_iter()
+		.collect::<FuturesUnordered<_>>()
+		.fold(Ok(()), |res, r| future::ready(res.and(r)))
+		.await
+}
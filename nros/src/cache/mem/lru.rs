@@ -66,6 +66,27 @@ impl<V> LruList<V> {
 		val
 	}
 
+	/// Move a node to the front of the list.
+	///
+	/// # Panics
+	///
+	/// If the node at the index does not exist.
+	pub fn promote(&mut self, index: Idx) {
+		self.remove_list(index);
+		self.push_front(index);
+		#[cfg(test)]
+		self.assert_valid();
+	}
+
+	/// Get a mutable reference to the value of a node.
+	///
+	/// # Panics
+	///
+	/// If the node at the index does not exist.
+	pub fn get_mut(&mut self, index: Idx) -> &mut V {
+		&mut self.nodes[index].value
+	}
+
 	/// Get the value & handle last node from the list.
 	pub fn last(&self) -> Option<(Idx, &V)> {
 		(!self.nodes.is_empty()).then(|| {
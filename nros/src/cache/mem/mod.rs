@@ -1,11 +1,16 @@
 mod lru;
+mod policy;
 
-pub use lru::{Idx, IDX_NONE};
+pub use {
+	lru::{Idx, IDX_NONE},
+	policy::CachePolicy,
+};
 
 use {
 	super::IdKey,
 	crate::waker_queue::{self, WakerQueue, WakerQueueTicket},
 	core::task::Waker,
+	policy::Policy,
 };
 
 /// Memory management.
@@ -19,20 +24,20 @@ pub(super) struct Mem {
 	hard_wakers: WakerQueue<()>,
 	/// Amount of entries beyond which entries will be evicted.
 	soft_limit: usize,
-	/// LRU tracker for unreferenced entries.
-	lru: lru::LruList<IdKey>,
+	/// Eviction tracker for unreferenced entries.
+	policy: Box<dyn Policy>,
 	/// Tasks to wake if there are more entries to evict.
 	soft_wakers: WakerQueue<()>,
 }
 
 impl Mem {
-	pub fn new(soft_limit: usize, hard_limit: usize) -> Self {
+	pub fn new(soft_limit: usize, hard_limit: usize, policy: CachePolicy) -> Self {
 		Self {
 			hard_limit,
 			hard_count: 0,
 			hard_wakers: Default::default(),
 			soft_limit,
-			lru: Default::default(),
+			policy: policy.build(),
 			soft_wakers: Default::default(),
 		}
 	}
@@ -41,12 +46,17 @@ impl Mem {
 	pub fn soft_add(&mut self, key: IdKey) -> Idx {
 		trace!("soft_add {:?}", key);
 		self.soft_wakers.wake_all();
-		self.lru.insert(key)
+		self.policy.insert(key)
 	}
 
 	pub fn soft_del(&mut self, idx: Idx) -> IdKey {
 		trace!("soft_del {:?}", idx);
-		self.lru.remove(idx)
+		self.policy.remove(idx)
+	}
+
+	pub fn soft_touch(&mut self, idx: Idx) {
+		trace!("soft_touch {:?}", idx);
+		self.policy.touch(idx)
 	}
 
 	pub fn hard_del(&mut self) {
@@ -56,10 +66,9 @@ impl Mem {
 	}
 
 	pub fn evict_next(&mut self, waker: &Waker) -> Result<IdKey, WakerQueueTicket<()>> {
-		(self.lru.len() > self.soft_limit)
-			.then(|| self.lru.last())
+		(self.policy.len() > self.soft_limit)
+			.then(|| self.policy.next())
 			.flatten()
-			.map(|(_, k)| *k)
 			.ok_or_else(|| self.soft_wakers.push(waker.clone(), ()))
 	}
 
@@ -68,7 +77,11 @@ impl Mem {
 	}
 
 	pub fn soft_count(&self) -> usize {
-		self.lru.len()
+		self.policy.len()
+	}
+
+	pub fn hard_limit(&self) -> usize {
+		self.hard_limit
 	}
 
 	pub fn set_soft_limit(&mut self, value: usize) {
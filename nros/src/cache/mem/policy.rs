@@ -0,0 +1,150 @@
+use {
+	super::{
+		lru::{Idx, LruList},
+		IdKey,
+	},
+	core::fmt,
+};
+
+/// Policy to select cache entries for eviction with.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum CachePolicy {
+	/// Evict the least recently used entry first.
+	#[default]
+	Lru,
+	/// Evict the least recently added entry first.
+	///
+	/// Accesses do not affect the order of eviction.
+	Fifo,
+	/// Evict the least recently added entry first,
+	/// but give entries that have been accessed since a second chance.
+	///
+	/// Unlike [`CachePolicy::Lru`], entries that are only used once,
+	/// e.g. during a sequential scan,
+	/// do not push out entries that are used frequently.
+	Clock,
+}
+
+impl CachePolicy {
+	/// Create a tracker for this policy.
+	pub(super) fn build(self) -> Box<dyn Policy> {
+		match self {
+			Self::Lru => Box::new(Lru::default()),
+			Self::Fifo => Box::new(Fifo::default()),
+			Self::Clock => Box::new(Clock::default()),
+		}
+	}
+}
+
+/// Tracker for unreferenced entries which selects the next entry to evict.
+pub(super) trait Policy: fmt::Debug {
+	/// Begin tracking an entry.
+	fn insert(&mut self, key: IdKey) -> Idx;
+
+	/// Stop tracking an entry.
+	///
+	/// # Panics
+	///
+	/// If the entry at the index does not exist.
+	fn remove(&mut self, idx: Idx) -> IdKey;
+
+	/// Mark an entry as used.
+	///
+	/// # Panics
+	///
+	/// If the entry at the index does not exist.
+	fn touch(&mut self, idx: Idx);
+
+	/// Get the entry that should be evicted next, if any.
+	///
+	/// The entry is not removed.
+	fn next(&mut self) -> Option<IdKey>;
+
+	/// The amount of tracked entries.
+	fn len(&self) -> usize;
+}
+
+#[derive(Debug, Default)]
+struct Lru(LruList<IdKey>);
+
+impl Policy for Lru {
+	fn insert(&mut self, key: IdKey) -> Idx {
+		self.0.insert(key)
+	}
+
+	fn remove(&mut self, idx: Idx) -> IdKey {
+		self.0.remove(idx)
+	}
+
+	fn touch(&mut self, idx: Idx) {
+		self.0.promote(idx)
+	}
+
+	fn next(&mut self) -> Option<IdKey> {
+		self.0.last().map(|(_, k)| *k)
+	}
+
+	fn len(&self) -> usize {
+		self.0.len()
+	}
+}
+
+#[derive(Debug, Default)]
+struct Fifo(LruList<IdKey>);
+
+impl Policy for Fifo {
+	fn insert(&mut self, key: IdKey) -> Idx {
+		self.0.insert(key)
+	}
+
+	fn remove(&mut self, idx: Idx) -> IdKey {
+		self.0.remove(idx)
+	}
+
+	fn touch(&mut self, _: Idx) {}
+
+	fn next(&mut self) -> Option<IdKey> {
+		self.0.last().map(|(_, k)| *k)
+	}
+
+	fn len(&self) -> usize {
+		self.0.len()
+	}
+}
+
+/// Second-chance list.
+///
+/// Each entry has a reference bit which is set when it is accessed.
+/// Entries with the bit set are moved to the front instead of being evicted.
+#[derive(Debug, Default)]
+struct Clock(LruList<(IdKey, bool)>);
+
+impl Policy for Clock {
+	fn insert(&mut self, key: IdKey) -> Idx {
+		self.0.insert((key, false))
+	}
+
+	fn remove(&mut self, idx: Idx) -> IdKey {
+		self.0.remove(idx).0
+	}
+
+	fn touch(&mut self, idx: Idx) {
+		self.0.get_mut(idx).1 = true;
+	}
+
+	fn next(&mut self) -> Option<IdKey> {
+		// Terminates since every pass clears the reference bit of an entry.
+		loop {
+			let (idx, &(key, referenced)) = self.0.last()?;
+			if !referenced {
+				break Some(key);
+			}
+			self.0.get_mut(idx).1 = false;
+			self.0.promote(idx);
+		}
+	}
+
+	fn len(&self) -> usize {
+		self.0.len()
+	}
+}
@@ -4,14 +4,22 @@ mod evict;
 mod flush;
 mod mem;
 mod object;
+mod scrub;
+mod shrink;
 mod tree;
+mod verify;
 
-pub use object::Object;
+pub use {
+	mem::CachePolicy,
+	object::{Layout, LengthTooLong, Object, ObjectStatistics},
+	scrub::ScrubReport,
+	verify::VerifyReport,
+};
 
 use {
 	crate::{
-		data::record::Depth, resource::Buf, storage, util, Background, BlockSize, Dev, Error,
-		KeyDeriver, MaxRecordSize, Resource, Store,
+		data::record::Depth, resource::Buf, storage, util, Background, BlockSize, CipherType,
+		Compression, Dev, Error, KeyDeriver, MaxRecordSize, Resource, Store,
 	},
 	alloc::collections::{BTreeMap, BTreeSet},
 	core::{
@@ -51,6 +59,10 @@ struct CacheData<B: Buf> {
 	busy: busy::BusyMap,
 	/// Used object IDs.
 	used_objects_ids: RangeSet<u64>,
+	/// Object IDs below this bound have been scanned from the object bitmap.
+	///
+	/// [`u64::MAX`] if the object bitmap has been scanned entirely.
+	bitmap_scan_end: u64,
 	/// Evict tasks in progress.
 	evict_tasks_count: usize,
 	/// Task to wake if evict tasks reaches 0.
@@ -75,6 +87,7 @@ impl<B: Buf> fmt::Debug for CacheData<B> {
 			.field("dirty", &self.dirty)
 			.field("busy", &self.busy)
 			.field("used_objects_ids", &self.used_objects_ids)
+			.field("bitmap_scan_end", &self.bitmap_scan_end)
 			.field("evict_tasks_count", &self.evict_tasks_count)
 			.field("wake_after_evicts", &self.wake_after_evicts)
 			.field("mem", &self.mem)
@@ -109,6 +122,10 @@ pub(crate) struct Cache<D: Dev, R: Resource> {
 	///
 	/// Derived from the depth of the object list tree.
 	object_bitmap_depth: Cell<Depth>,
+	/// Whether to compare data with the cached record before writing it.
+	///
+	/// If equal, the record is not marked dirty.
+	compare_on_write: Cell<bool>,
 }
 
 impl<D: Dev + fmt::Debug, R: Resource + fmt::Debug> fmt::Debug for Cache<D, R> {
@@ -119,19 +136,34 @@ impl<D: Dev + fmt::Debug, R: Resource + fmt::Debug> fmt::Debug for Cache<D, R> {
 			.field("data", &self.data)
 			.field("root_max_size", &self.root_max_size)
 			.field("object_bitmap_depth", &self.object_bitmap_depth)
+			.field("compare_on_write", &self.compare_on_write)
 			.finish()
 	}
 }
 
 impl<D: Dev, R: Resource> Cache<D, R> {
 	/// Initialize a cache layer.
-	pub async fn new(store: Store<D, R>, cache_size: usize) -> Result<Self, Error<D>> {
-		trace!("new {}", cache_size);
-
-		// TODO don't hardcode, make user-configurable.
-		let soft_limit = cache_size >> store.max_rec_size().to_raw();
+	///
+	/// If `lazy_object_bitmap` is `false`, the object bitmap is scanned entirely.
+	/// Otherwise used object IDs are discovered on demand.
+	pub async fn new(
+		store: Store<D, R>,
+		soft_cache_size: usize,
+		hard_cache_size: Option<usize>,
+		policy: CachePolicy,
+		lazy_object_bitmap: bool,
+	) -> Result<Self, Error<D>> {
+		trace!("new {} {:?} {:?}", soft_cache_size, hard_cache_size, policy);
+
+		let soft_limit = soft_cache_size >> store.max_rec_size().to_raw();
 		let soft_limit = soft_limit.max(1);
-		let hard_limit = soft_limit * 2 + 1 + 1; // 1 to ensure operation + 1 for grow_object_list
+		// 1 to ensure operation + 1 for grow_object_list
+		let hard_limit = if let Some(hard_cache_size) = hard_cache_size {
+			let hard_limit = hard_cache_size >> store.max_rec_size().to_raw();
+			hard_limit.max(soft_limit + 1 + 1)
+		} else {
+			soft_limit * 2 + 1 + 1
+		};
 		trace!(info "soft/hard limit: {}/{}", soft_limit, hard_limit);
 
 		let mut root_max_size = [0; 4];
@@ -141,52 +173,80 @@ impl<D: Dev, R: Resource> Cache<D, R> {
 				root_max_size[i - 1] << store.max_rec_size().to_raw() - RECORDREF_SIZE_P2;
 		}
 
-		let mut s = Self {
+		let s = Self {
 			store,
 			data: RefCell::new(CacheData {
 				records: Default::default(),
 				dirty: Default::default(),
 				busy: Default::default(),
-				mem: Mem::new(soft_limit, hard_limit),
+				mem: Mem::new(soft_limit, hard_limit, policy),
 				used_objects_ids: Default::default(),
+				bitmap_scan_end: 0,
 				evict_tasks_count: 0,
 				wake_after_evicts: None,
 			}),
 			root_max_size,
 			object_bitmap_depth: Cell::new(Depth::D0),
+			compare_on_write: Cell::new(false),
 		};
 		s.object_bitmap_depth
 			.set(s.calc_bitmap_depth(s.store.object_list_depth()));
 
 		// Scan bitmap for empty slots.
-		//let bg = Background::default();
-		let mut used_objects_ids = RangeSet::new();
-		let bitmap = Tree::object_bitmap(&s);
-		let entries_per_leaf = 4 << s.max_rec_size().to_raw();
-
-		s.run(async {
-			for offset in 0..bitmap.max_offset() {
-				let entry = bitmap.get(Depth::D0, offset).await?;
-				let mut id = offset * entries_per_leaf;
-				for &byte in entry.as_slice() {
-					for k in 0..8 {
-						if (byte >> k) & 1 != 0 {
-							trace!(info "id {:#x} in use", id);
-							used_objects_ids.insert(id..id + 1);
-						}
-						id += 1;
+		if !lazy_object_bitmap {
+			s.run(s.scan_object_bitmap(u64::MAX)).await?;
+		}
+
+		Ok(s)
+	}
+
+	/// Discover used object IDs in the object bitmap until at least all IDs below `end`
+	/// have been scanned.
+	///
+	/// Bitmap records are scanned in order, so all IDs below the end are always known.
+	async fn scan_object_bitmap(&self, end: u64) -> Result<(), Error<D>> {
+		trace!("scan_object_bitmap {:#x}", end);
+		let bitmap = Tree::object_bitmap(self);
+		let rec_bits = self.max_rec_size().to_raw() + 3;
+		loop {
+			let start = self.data().bitmap_scan_end;
+			if start >= end {
+				return Ok(());
+			}
+			let offset = start >> rec_bits;
+			if offset >= bitmap.max_offset() {
+				// There are no used IDs past the end of the bitmap.
+				trace!(final "used object ids: {:#x?}", &self.data().used_objects_ids);
+				self.data().bitmap_scan_end = u64::MAX;
+				return Ok(());
+			}
+
+			// Other tasks may scan the same record concurrently.
+			// Only the first scan to finish is used,
+			// as IDs may have been allocated or freed since.
+			let entry = bitmap.get(Depth::D0, offset).await?;
+			let mut used = RangeSet::new();
+			let mut id = offset << rec_bits;
+			for &byte in entry.as_slice() {
+				for k in 0..8 {
+					if (byte >> k) & 1 != 0 {
+						trace!(info "id {:#x} in use", id);
+						used.insert(id..id + 1);
 					}
+					id += 1;
 				}
 			}
-			Ok(())
-		})
-		.await?;
-
-		trace!(final "used object ids: {:#x?}", &used_objects_ids);
-
-		s.data.get_mut().used_objects_ids = used_objects_ids;
+			drop(entry);
 
-		Ok(s)
+			let mut data = self.data();
+			if data.bitmap_scan_end > offset << rec_bits {
+				continue;
+			}
+			for r in used.iter() {
+				data.used_objects_ids.insert(r.clone());
+			}
+			data.bitmap_scan_end = data.bitmap_scan_end.max((offset + 1) << rec_bits);
+		}
 	}
 
 	/// Run task with a background runner.
@@ -215,23 +275,27 @@ impl<D: Dev, R: Resource> Cache<D, R> {
 	}
 
 	/// Allocate an object IDs.
-	fn alloc_id(&self) -> u64 {
-		let mut slf = self.data();
-		let id = slf
-			.used_objects_ids
-			.gaps(&(0..u64::MAX))
-			.next()
-			.expect("more than 2**64 objects allocated")
-			.start;
-		slf.used_objects_ids.insert(id..id + 1);
-		id
+	///
+	/// The object bitmap is scanned further until a free ID is found.
+	async fn alloc_id(&self) -> Result<u64, Error<D>> {
+		loop {
+			let mut slf = self.data();
+			let end = slf.bitmap_scan_end;
+			if let Some(r) = slf.used_objects_ids.gaps(&(0..end)).next() {
+				slf.used_objects_ids.insert(r.start..r.start + 1);
+				return Ok(r.start);
+			}
+			assert!(end != u64::MAX, "more than 2**64 objects allocated");
+			drop(slf);
+			self.scan_object_bitmap(end + 1).await?;
+		}
 	}
 
 	/// Create an object.
 	pub async fn create(&self) -> Result<Object<'_, D, R>, Error<D>> {
 		trace!("create");
 		// Allocate
-		let id = self.alloc_id();
+		let id = self.alloc_id().await?;
 		trace!(info "{:#x}", id);
 
 		// Resize if necessary
@@ -244,6 +308,40 @@ impl<D: Dev, R: Resource> Cache<D, R> {
 		Ok(Object::new(self, id))
 	}
 
+	/// Create multiple objects at once.
+	///
+	/// The object bitmap is updated in bulk.
+	pub async fn create_many(&self, n: usize) -> Result<Vec<Object<'_, D, R>>, Error<D>> {
+		trace!("create_many {}", n);
+		let mut ids = Vec::with_capacity(n);
+		let r = async {
+			// Allocate
+			for _ in 0..n {
+				ids.push(self.alloc_id().await?);
+			}
+			ids.sort_unstable();
+
+			// Resize if necessary
+			if let Some(&max) = ids.last() {
+				while max >= self.object_list_len() {
+					self.grow_object_list().await?;
+				}
+			}
+
+			self.objects_set_allocated(&ids, true).await
+		};
+		if let Err(e) = r.await {
+			// Release the reserved IDs so they can be allocated again.
+			let mut data = self.data();
+			for &id in &ids {
+				data.dealloc_id(id);
+			}
+			return Err(e);
+		}
+
+		Ok(ids.into_iter().map(|id| Object::new(self, id)).collect())
+	}
+
 	/// Get a reference to an object.
 	pub fn get(&self, id: u64) -> Object<'_, D, R> {
 		Object::new(self, id)
@@ -254,6 +352,12 @@ impl<D: Dev, R: Resource> Cache<D, R> {
 		// First flush cache
 		self.flush_all().await?;
 
+		// Remove unused levels from the object list.
+		// Only do so if anything was modified so unmodified stores aren't written to.
+		if self.store.is_dirty() {
+			self.maybe_shrink_object_list().await?;
+		}
+
 		// Flush store-specific data.
 		self.store.finish_transaction().await?;
 
@@ -270,17 +374,28 @@ impl<D: Dev, R: Resource> Cache<D, R> {
 		self.store.max_rec_size()
 	}
 
+	/// The cipher used by the underlying [`Store`].
+	pub fn cipher_type(&self) -> CipherType {
+		self.store.cipher_type()
+	}
+
 	/// Readjust cache size.
 	///
 	/// This may be useful to increase or decrease depending on total system memory usage.
 	///
-	/// # Panics
+	/// The size is clamped to at least one record,
+	/// so eviction doesn't thrash,
+	/// and at most the hard limit minus the two records reserved for operation,
+	/// so tasks can't wait on entries that are never evicted.
 	///
-	/// If `global_max < write_max`.
-	pub fn resize_cache(&self, global_max: usize) -> Result<(), Error<D>> {
-		let soft_limit = global_max >> self.max_rec_size().to_raw();
-		self.data().mem.set_soft_limit(soft_limit);
-		Ok(())
+	/// Returns the effective size.
+	pub fn resize_cache(&self, global_max: usize) -> Result<usize, Error<D>> {
+		let mut mem = self.mem();
+		// 1 to ensure operation + 1 for grow_object_list
+		let max = mem.hard_limit() - 1 - 1;
+		let soft_limit = (global_max >> self.max_rec_size().to_raw()).clamp(1, max);
+		mem.set_soft_limit(soft_limit);
+		Ok(soft_limit << self.max_rec_size().to_raw())
 	}
 
 	/// Unmount the cache.
@@ -292,25 +407,71 @@ impl<D: Dev, R: Resource> Cache<D, R> {
 		Ok(self.store)
 	}
 
+	/// Add a chain of devices as a new mirror.
+	///
+	/// The current transaction is finished first.
+	pub async fn add_mirror(&mut self, devices: Vec<D>) -> Result<(), Error<D>> {
+		trace!("add_mirror");
+		self.run(self.finish_transaction()).await?;
+		self.store.add_mirror(devices).await
+	}
+
+	/// Discard the cache without flushing and return the underlying [`Store`].
+	///
+	/// Any dirty entries are lost.
+	pub fn abort(self) -> Store<D, R> {
+		trace!("abort");
+		self.store
+	}
+
+	/// Check whether all blocks in a range are allocated.
+	pub fn is_allocated(&self, lba: u64, blocks: u64) -> bool {
+		self.store.is_allocated(lba, blocks)
+	}
+
+	/// Get the amount of bytes held by tasks, which cannot be evicted.
+	pub fn referenced_usage(&self) -> usize {
+		let mem = self.mem();
+		(mem.hard_count() - mem.soft_count()) << self.max_rec_size().to_raw()
+	}
+
 	/// Get statistics for this sesion.
 	pub fn statistics(&self) -> Statistics {
+		let referenced_usage = self.referenced_usage();
 		let d = self.data();
 		Statistics {
 			storage: self.store.statistics(),
 			soft_usage: d.mem.soft_count() << self.max_rec_size().to_raw(),
 			hard_usage: d.mem.hard_count() << self.max_rec_size().to_raw(),
+			referenced_usage,
 			used_objects: d
 				.used_objects_ids
 				.iter()
 				.fold(0, |x, r| r.end - r.start + x),
+			object_list_depth: self.store.object_list_depth().into(),
 		}
 	}
 
+	/// The maximum amount of objects that can be allocated.
+	///
+	/// This is bounded by the capacity of the object list at maximum depth.
+	pub fn max_objects(&self) -> u64 {
+		let p2 = u32::from(self.entries_per_parent_p2()) * 3
+			+ u32::from(self.max_rec_size().to_raw() - OBJECT_SIZE_P2);
+		1u64.checked_shl(p2)
+			.map_or(OBJECT_LIST_ID, |n| n.min(OBJECT_LIST_ID))
+	}
+
 	/// Get the key used to encrypt the header.
 	pub fn header_key(&self) -> [u8; 32] {
 		self.store.header_key()
 	}
 
+	/// The unique identifier for this filesystem.
+	pub fn uid(&self) -> u128 {
+		self.store.uid()
+	}
+
 	/// Get a reference to filesystem data in the header
 	pub fn header_data(&self) -> Ref<'_, [u8; 256]> {
 		self.store.header_data()
@@ -340,6 +501,21 @@ impl<D: Dev, R: Resource> Cache<D, R> {
 		self.max_rec_size().to_raw() - RECORDREF_SIZE_P2
 	}
 
+	/// Set the default compression to use for new records.
+	pub fn set_compression(&self, compression: Compression) {
+		self.store.set_compression(compression)
+	}
+
+	/// Set whether to skip writes of data that is equal to the stored data.
+	pub fn set_compare_on_write(&self, enable: bool) {
+		self.compare_on_write.set(enable)
+	}
+
+	/// Whether to skip writes of data that is equal to the stored data.
+	pub fn compare_on_write(&self) -> bool {
+		self.compare_on_write.get()
+	}
+
 	pub async fn set_block_count(&self, blocks: u64) -> Result<(), Error<D>> {
 		self.store.set_block_count(blocks).await?;
 		Ok(())
@@ -374,8 +550,17 @@ pub struct Statistics {
 	pub soft_usage: usize,
 	/// Amount of bytes counting towards the hard limit.
 	pub hard_usage: usize,
+	/// Amount of bytes held by tasks, which cannot be evicted.
+	///
+	/// This is the difference between the hard and soft usage.
+	pub referenced_usage: usize,
 	/// Total amount of objects allocated.
+	///
+	/// If the object bitmap is loaded lazily, only the objects discovered so far are counted,
+	/// i.e. this is a lower bound until the bitmap has been scanned entirely.
 	pub used_objects: u64,
+	/// Amount of levels of the object list above the leaves.
+	pub object_list_depth: u8,
 }
 
 #[cfg(feature = "trace")]
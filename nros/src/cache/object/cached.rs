@@ -0,0 +1,31 @@
+use {
+	super::{Dev, IdKey, Key, Object, Resource},
+	crate::data::record::Depth,
+	core::ops::Range,
+};
+
+impl<'a, D: Dev, R: Resource> Object<'a, D, R> {
+	/// Check whether all leaf records covering a byte range are present in the cache.
+	///
+	/// If so, [`Object::read`] will not need to fetch any records from the devices.
+	///
+	/// The range is clamped to the maximum length of the object.
+	pub fn is_fully_cached(&self, range: Range<u64>) -> bool {
+		trace!("is_fully_cached {:#x} {:?}", self.id, range);
+
+		let end = range.end.min(self.max_len());
+		if range.start >= end {
+			return true;
+		}
+
+		let rec_size_p2 = self.cache.max_rec_size().to_raw();
+		let data = self.cache.data();
+		(range.start >> rec_size_p2..=(end - 1) >> rec_size_p2).all(|offset| {
+			let (root, offt) = self
+				.offset_to_tree(offset)
+				.expect("offset is not addressable");
+			let key = IdKey { id: self.id, key: Key::new(root, Depth::D0, offt) };
+			data.records.contains_key(&key)
+		})
+	}
+}
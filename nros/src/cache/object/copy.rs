@@ -0,0 +1,161 @@
+use {
+	super::{Dev, Object, Resource},
+	crate::{resource::Buf, Error},
+};
+
+impl<'a, D: Dev, R: Resource> Object<'a, D, R> {
+	/// Copy a range of data from another object.
+	///
+	/// If both objects are in the same store and the offsets are at the same position
+	/// inside a record, the fully covered records are copied directly.
+	/// The unaligned edges and misaligned ranges are copied through a buffer.
+	///
+	/// Returns the actual amount of bytes copied.
+	///
+	/// # Panics
+	///
+	/// If `src` is the same object and the ranges overlap.
+	/// Use [`Object::copy_range`] instead.
+	pub async fn copy_from(
+		&self,
+		src: &Object<'_, D, R>,
+		src_offset: u64,
+		dst_offset: u64,
+		len: u64,
+	) -> Result<u64, Error<D>> {
+		trace!(
+			"copy_from id {:#x} <- {:#x}, offset {} <- {}, len {}",
+			self.id,
+			src.id,
+			dst_offset,
+			src_offset,
+			len
+		);
+
+		// Ensure the range fits in both objects.
+		let len = len
+			.min(src.max_len().saturating_sub(src_offset))
+			.min(self.max_len().saturating_sub(dst_offset));
+		if len == 0 {
+			return Ok(0);
+		}
+
+		let same_store = core::ptr::eq(self.cache, src.cache);
+		assert!(
+			!same_store
+				|| self.id != src.id
+				|| src_offset + len <= dst_offset
+				|| dst_offset + len <= src_offset,
+			"overlapping ranges"
+		);
+
+		let rec_size_p2 = self.cache.max_rec_size().to_raw();
+		let mask = (1 << rec_size_p2) - 1;
+
+		if !same_store || src_offset & mask != dst_offset & mask {
+			self.copy_buffered(src, src_offset, dst_offset, len).await?;
+			return Ok(len);
+		}
+
+		// Copy to first record |----xxxx|
+		let head = ((1 << rec_size_p2) - (dst_offset & mask)) & mask;
+		let head = head.min(len);
+		self.copy_buffered(src, src_offset, dst_offset, head)
+			.await?;
+
+		// Copy middle records |xxxxxxxx|
+		let (src_start, dst_start) = (src_offset + head, dst_offset + head);
+		let count = (len - head) >> rec_size_p2;
+		for i in 0..count {
+			let buf = {
+				let entry = src.get((src_start >> rec_size_p2) + i).await?;
+				let mut buf = self.cache.resource().alloc();
+				buf.extend_from_slice(entry.as_slice());
+				buf
+			};
+			self.set((dst_start >> rec_size_p2) + i, buf).await?;
+		}
+
+		// Copy end record |xxxx----|
+		let done = head + (count << rec_size_p2);
+		self.copy_buffered(src, src_offset + done, dst_offset + done, len - done)
+			.await?;
+
+		Ok(len)
+	}
+
+	/// Copy a range of data from another object, which may be this object.
+	///
+	/// Unlike [`Object::copy_from`], the ranges may overlap if `src` is this object.
+	/// The result is as if the source range was copied to a temporary buffer first.
+	///
+	/// Returns the actual amount of bytes copied.
+	pub async fn copy_range(
+		&self,
+		src: &Object<'_, D, R>,
+		src_offset: u64,
+		dst_offset: u64,
+		len: u64,
+	) -> Result<u64, Error<D>> {
+		trace!(
+			"copy_range id {:#x} <- {:#x}, offset {} <- {}, len {}",
+			self.id,
+			src.id,
+			dst_offset,
+			src_offset,
+			len
+		);
+
+		let same_object = core::ptr::eq(self.cache, src.cache) && self.id == src.id;
+		let (low, high) = (src_offset.min(dst_offset), src_offset.max(dst_offset));
+		if !same_object || low.saturating_add(len) <= high {
+			return self.copy_from(src, src_offset, dst_offset, len).await;
+		}
+
+		let len = len.min(self.max_len().saturating_sub(high));
+		if src_offset == dst_offset {
+			return Ok(len);
+		}
+
+		// Copy from the end of the source range which isn't overwritten first,
+		// like memmove.
+		let buf = &mut vec![0; 1 << self.cache.max_rec_size().to_raw()];
+		let mut done = 0;
+		while done < len {
+			let l = usize::try_from(len - done)
+				.unwrap_or(usize::MAX)
+				.min(buf.len());
+			let l64 = u64::try_from(l).unwrap();
+			let offt = if dst_offset > src_offset {
+				len - done - l64
+			} else {
+				done
+			};
+			self.read(src_offset + offt, &mut buf[..l]).await?;
+			self.write(dst_offset + offt, &buf[..l]).await?;
+			done += l64;
+		}
+		Ok(len)
+	}
+
+	/// Copy a range of data from another object through an intermediate buffer.
+	async fn copy_buffered(
+		&self,
+		src: &Object<'_, D, R>,
+		src_offset: u64,
+		dst_offset: u64,
+		len: u64,
+	) -> Result<(), Error<D>> {
+		let buf = &mut vec![0; 1 << self.cache.max_rec_size().to_raw()];
+		let mut done = 0;
+		while done < len {
+			let l = usize::try_from(len - done)
+				.unwrap_or(usize::MAX)
+				.min(buf.len());
+			src.read(src_offset + done, &mut buf[..l]).await?;
+			self.write(dst_offset + done, &buf[..l]).await?;
+			done += u64::try_from(l).unwrap();
+		}
+		Ok(())
+	}
+}
@@ -0,0 +1,66 @@
+use {
+	super::{Dev, IdKey, Key, Object, Resource, RootIndex},
+	crate::{data::record::Depth, Error},
+	core::ops::Range,
+};
+
+impl<'a, D: Dev, R: Resource> Object<'a, D, R> {
+	/// Get the byte offsets of the leaf records that have been modified
+	/// but not yet written to the devices.
+	///
+	/// The offsets are sorted in ascending order.
+	pub fn dirty_offsets(&self) -> Vec<u64> {
+		trace!("dirty_offsets {:#x}", self.id);
+		let rec_size_p2 = self.cache.max_rec_size().to_raw();
+		let data = self.cache.data();
+		let mut base = 0;
+		let mut offsets = Vec::new();
+		for (root, size) in (RootIndex::I0..=RootIndex::I3).zip(self.cache.root_max_size) {
+			let start = IdKey { id: self.id, key: Key::new(root, Depth::D0, 0) };
+			let end = IdKey { id: self.id, key: Key::new(root, Depth::D0, Key::MAX_OFFSET) };
+			offsets.extend(
+				data.dirty
+					.range(start..=end)
+					.map(|k| (base + k.key.offset()) << rec_size_p2),
+			);
+			base += size >> rec_size_p2;
+		}
+		offsets
+	}
+
+	/// Write the modified leaf records covering a byte range to the devices.
+	///
+	/// Other dirty records of this object are left in the cache.
+	///
+	/// The records are only referenced by the object store once the
+	/// parent records are written, i.e. when the transaction finishes.
+	///
+	/// The range is clamped to the maximum length of the object.
+	pub async fn flush_range(&self, range: Range<u64>) -> Result<(), Error<D>> {
+		trace!("flush_range {:#x} {:?}", self.id, range);
+		self.check_id().await?;
+
+		let end = range.end.min(self.max_len());
+		if range.start >= end {
+			return Ok(());
+		}
+
+		// Only look at the dirty records of this object
+		// so huge ranges don't iterate over every possible record.
+		let rec_size_p2 = self.cache.max_rec_size().to_raw();
+		let records = range.start >> rec_size_p2..=(end - 1) >> rec_size_p2;
+		let offsets = self
+			.dirty_offsets()
+			.into_iter()
+			.map(|offt| offt >> rec_size_p2)
+			.filter(|offt| records.contains(offt));
+		for offset in offsets {
+			let (root, offt) = self
+				.offset_to_tree(offset)
+				.expect("offset is not addressable");
+			let key = IdKey { id: self.id, key: Key::new(root, Depth::D0, offt) };
+			self.cache.flush_entry(key).await?;
+		}
+		Ok(())
+	}
+}
@@ -25,6 +25,7 @@ impl<'a, D: Dev, R: Resource> Object<'a, D, R> {
 	/// If `offset >= 2**55`
 	pub async fn get(&self, offset: u64) -> Result<EntryRef<'a, R::Buf>, Error<D>> {
 		trace!("get {:#x} {:?}", self.id, offset);
+		self.check_id().await?;
 
 		let (root, offt) = self
 			.offset_to_tree(offset)
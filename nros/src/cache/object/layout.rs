@@ -0,0 +1,55 @@
+use {
+	super::{super::Tree, Dev, Object, Resource, RootIndex},
+	crate::{data::record::RecordRef, Error},
+	core::ops::Range,
+};
+
+/// Physical structure of an object.
+///
+/// Intended for diagnostics.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Layout {
+	/// The byte range covered by each tree.
+	///
+	/// The tree at index `i` has depth `i`.
+	pub roots: [Range<u64>; 4],
+	/// The depth of the deepest tree that has any records written out.
+	pub depth: Option<u8>,
+	/// The maximum length of the object.
+	pub max_len: u64,
+}
+
+impl Layout {
+	/// Determine the index of the tree containing the given byte offset.
+	///
+	/// `None` if out of range.
+	pub fn root(&self, offset: u64) -> Option<usize> {
+		self.roots.iter().position(|r| r.contains(&offset))
+	}
+}
+
+impl<'a, D: Dev, R: Resource> Object<'a, D, R> {
+	/// Get the physical structure of this object.
+	pub async fn layout(&self) -> Result<Layout, Error<D>> {
+		trace!("layout {:#x}", self.id);
+		self.check_id().await?;
+
+		let mut roots = [0..0, 0..0, 0..0, 0..0];
+		let mut depth = None;
+		let mut start = 0;
+		for (root, size) in (RootIndex::I0..=RootIndex::I3).zip(self.cache.root_max_size) {
+			let end = start.saturating_add(size);
+			roots[root as usize] = start..end;
+			start = end;
+
+			let rec = Tree::object(self.cache, self.id, root)
+				.root_record()
+				.await?;
+			if rec != RecordRef::NONE {
+				depth = Some(root.depth() as u8);
+			}
+		}
+
+		Ok(Layout { roots, depth, max_len: self.max_len() })
+	}
+}
@@ -1,14 +1,25 @@
+mod cached;
+mod copy;
+mod flush;
 mod get;
 mod key;
+mod layout;
 mod read;
+mod read_stream;
 mod set;
+mod statistics;
+mod truncate_to_fit;
 mod write;
 mod write_zeros;
 
 pub(super) use key::{Key, RootIndex};
+pub use {layout::Layout, statistics::ObjectStatistics, write::LengthTooLong};
 
 use {
-	super::{Buf, Cache, Dev, Error, IdKey, Resource, Tree, OBJECT_BITMAP_ID, OBJECT_LIST_ID},
+	super::{
+		Buf, Cache, Dev, Error, IdKey, Resource, Tree, OBJECT_BITMAP_ID, OBJECT_LIST_ID,
+		OBJECT_SIZE_P2,
+	},
 	crate::{
 		data::record::{Depth, RecordRef},
 		util,
@@ -36,17 +47,52 @@ impl<'a, D: Dev, R: Resource> Object<'a, D, R> {
 		self.id
 	}
 
+	/// Ensure this object is allocated.
+	///
+	/// The special objects are never considered allocated.
+	async fn check_id(&self) -> Result<(), Error<D>> {
+		// The special objects and anything past them are out of range of the bitmap.
+		// Don't scan the entire bitmap only to find that out.
+		if self.id >= OBJECT_LIST_ID {
+			return Err(Error::InvalidObject { id: self.id });
+		}
+		self.cache.scan_object_bitmap(self.id + 1).await?;
+		if !self.cache.data().used_objects_ids.contains(&self.id) {
+			return Err(Error::InvalidObject { id: self.id });
+		}
+		Ok(())
+	}
+
 	/// Deallocate this object.
 	///
 	/// This zeros out all data in this object.
 	pub async fn dealloc(&self) -> Result<(), Error<D>> {
 		trace!("dealloc {:#x}", self.id);
+		self.check_id().await?;
 		self.write_zeros(0, u64::MAX).await?;
 		self.cache.object_set_allocated(self.id, false).await?;
 		self.cache.data().dealloc_id(self.id);
 		Ok(())
 	}
 
+	/// Deallocate this object and count the blocks used by it.
+	///
+	/// Counting requires walking all records of the object,
+	/// so use [`Self::dealloc`] if the amount of blocks is not needed.
+	///
+	/// Returns the amount of blocks that will be freed.
+	/// The blocks are released when the zeroed records are flushed.
+	pub async fn dealloc_blocks(&self) -> Result<u64, Error<D>> {
+		trace!("dealloc_blocks {:#x}", self.id);
+		self.check_id().await?;
+		let mut blocks = 0;
+		for root in RootIndex::I0..=RootIndex::I3 {
+			blocks += Tree::object(self.cache, self.id, root).blocks().await?;
+		}
+		self.dealloc().await?;
+		Ok(blocks)
+	}
+
 	/// Determine start & end offsets inside records.
 	fn calc_record_offsets(&self, offset: u64, length: usize) -> (usize, usize) {
 		let mask = (1 << self.cache.max_rec_size().to_raw()) - 1;
@@ -148,6 +194,128 @@ impl<D: Dev, R: Resource> Cache<D, R> {
 		Ok(())
 	}
 
+	/// Shrink the object list, i.e. remove levels, as long as all allocated objects still fit.
+	///
+	/// This should be called after all entries have been flushed.
+	/// If any entry of the object list or bitmap is in use by another task nothing is done
+	/// and shrinking is attempted again in the next transaction.
+	pub(super) async fn maybe_shrink_object_list(&self) -> Result<(), Error<D>> {
+		trace!("maybe_shrink_object_list");
+		// Steps:
+		// * check if all allocated objects fit in a list with one less level.
+		// * take the first child of the top-level record as new root.
+		// * evict entries that are out of range.
+		// * destroy the original root.
+		// Repeat for bitmap, if necessary.
+
+		// The highest used ID is only known once the bitmap has been scanned entirely.
+		if self.data().bitmap_scan_end != u64::MAX {
+			return Ok(());
+		}
+
+		loop {
+			let cur_list_depth = self.store.object_list_depth();
+			if cur_list_depth == Depth::D0 {
+				return Ok(());
+			}
+			let new_list_depth = cur_list_depth.prev();
+
+			let end = self
+				.data()
+				.used_objects_ids
+				.iter()
+				.last()
+				.map_or(0, |r| r.end);
+			let len = (1u64 << self.entries_per_parent_p2() * (new_list_depth as u8))
+				<< self.max_rec_size().to_raw() - OBJECT_SIZE_P2;
+			if end > len {
+				return Ok(());
+			}
+
+			// Check if depth of bitmap also needs to decrease.
+			let cur_bitmap_depth = self.object_bitmap_depth.get();
+			let new_bitmap_depth = self.calc_bitmap_depth(new_list_depth);
+
+			let list_root = self.store.object_list_root();
+			let bitmap_root = self.store.object_bitmap_root();
+			let Some(new_list_root) = self.first_child(list_root).await? else { return Ok(()) };
+			let new_bitmap_root = if cur_bitmap_depth != new_bitmap_depth {
+				let Some(rec) = self.first_child(bitmap_root).await? else { return Ok(()) };
+				rec
+			} else {
+				bitmap_root
+			};
+
+			// Other tasks may have modified the list or bitmap while reading the roots.
+			if self.store.object_list_root() != list_root
+				|| self.store.object_bitmap_root() != bitmap_root
+				|| self.is_busy_or_dirty(OBJECT_LIST_ID)
+				|| self.is_busy_or_dirty(OBJECT_BITMAP_ID)
+			{
+				trace!(info "in use");
+				return Ok(());
+			}
+
+			// Evict entries that are out of range.
+			let epp = self.entries_per_parent_p2();
+			let out_of_range = |key: &IdKey, depth: Depth| {
+				let d = key.key.depth();
+				d > depth || key.key.offset() >> epp * (depth as u8 - d as u8) != 0
+			};
+			let keys = self
+				.data()
+				.records
+				.keys()
+				.filter(|k| match k.id {
+					OBJECT_LIST_ID => out_of_range(k, new_list_depth),
+					OBJECT_BITMAP_ID => out_of_range(k, new_bitmap_depth),
+					_ => false,
+				})
+				.copied()
+				.collect::<Vec<_>>();
+			for key in keys {
+				let (_, is_dirty) = self.entry_remove(key);
+				debug_assert!(!is_dirty, "evicted dirty entry");
+				self.mem().hard_del();
+			}
+
+			// List
+			self.store.destroy(list_root);
+			self.store.set_object_list_root(new_list_root);
+			self.store.set_object_list_depth(new_list_depth);
+
+			// Bitmap
+			if cur_bitmap_depth != new_bitmap_depth {
+				self.store.destroy(bitmap_root);
+				self.store.set_object_bitmap_root(new_bitmap_root);
+				self.object_bitmap_depth.set(new_bitmap_depth);
+			}
+		}
+	}
+
+	/// Get the first child of a record.
+	///
+	/// `None` if any other child is not empty.
+	async fn first_child(&self, record_ref: RecordRef) -> Result<Option<RecordRef>, Error<D>> {
+		if record_ref == RecordRef::NONE {
+			return Ok(Some(RecordRef::NONE));
+		}
+		let buf = self.store.read(record_ref).await?;
+		let data = buf.get();
+		let mut child = RecordRef::default();
+		let n = data.len().min(child.as_ref().len());
+		child.as_mut()[..n].copy_from_slice(&data[..n]);
+		Ok(data[n..].iter().all(|&b| b == 0).then_some(child))
+	}
+
+	/// Whether any entry of an object is referenced by a task or dirty.
+	fn is_busy_or_dirty(&self, id: u64) -> bool {
+		let start = IdKey { id, key: Key::new(RootIndex::I0, Depth::D0, 0) };
+		let end = IdKey { id, key: Key::new(RootIndex::I3, Depth::D3, Key::MAX_OFFSET) };
+		let data = self.data();
+		data.busy.contains_range(start..=end) || data.dirty.range(start..=end).next().is_some()
+	}
+
 	/// Determine the depth of the bitmap for the given depth of the object list.
 	pub(super) fn calc_bitmap_depth(&self, obj_list_depth: Depth) -> Depth {
 		// Determine highest valid *byte* offset of object bitmap.
@@ -181,4 +349,41 @@ impl<D: Dev, R: Resource> Cache<D, R> {
 		entry.write(index, b);
 		Ok(())
 	}
+
+	/// Mark multiple objects as allocated or deallocated.
+	///
+	/// Each record of the bitmap is fetched only once.
+	///
+	/// `ids` must be sorted in ascending order.
+	pub(super) async fn objects_set_allocated(
+		&self,
+		mut ids: &[u64],
+		value: bool,
+	) -> Result<(), Error<D>> {
+		trace!("objects_set_allocated {} {}", ids.len(), value);
+		debug_assert!(ids.windows(2).all(|w| w[0] < w[1]), "ids not sorted");
+
+		let rec_bits = self.max_rec_size().to_raw() + 3;
+		while let Some(&first) = ids.first() {
+			let (offt, _) = util::divmod_p2(first, rec_bits);
+			let n = ids
+				.iter()
+				.take_while(|&&id| util::divmod_p2(id, rec_bits).0 == offt)
+				.count();
+			let cur;
+			(cur, ids) = ids.split_at(n);
+
+			let mut entry = Tree::object_bitmap(self).get(Depth::D0, offt).await?;
+			for &id in cur {
+				let (offt, bit) = util::divmod_p2(id, 3);
+				let (_, index) = util::divmod_p2(offt, self.max_rec_size().to_raw());
+				let b = &mut [0];
+				entry.read(index, b);
+				b[0] &= !(1 << bit);
+				b[0] |= u8::from(value) << bit;
+				entry.write(index, b);
+			}
+		}
+		Ok(())
+	}
 }
@@ -15,6 +15,7 @@ impl<'a, D: Dev, R: Resource> Object<'a, D, R> {
 			offset,
 			buf.len()
 		);
+		self.check_id().await?;
 
 		let max_len = self.max_len();
 
@@ -0,0 +1,52 @@
+use {
+	super::{Dev, Object, Resource},
+	crate::{resource::Buf, Error},
+	futures_util::{stream, Stream},
+};
+
+impl<'a, D: Dev, R: Resource> Object<'a, D, R> {
+	/// Read data from a range as a stream of buffers.
+	///
+	/// Every buffer but the last ends at a record boundary.
+	/// Records are only fetched when the next buffer is requested.
+	///
+	/// The range is clamped to the maximum length of the object.
+	pub fn read_stream(
+		&self,
+		offset: u64,
+		len: u64,
+	) -> impl Stream<Item = Result<R::Buf, Error<D>>> + 'a {
+		trace!(
+			"read_stream id {:#x}, offset {}, len {}",
+			self.id,
+			offset,
+			len
+		);
+		let end = offset.saturating_add(len).min(self.max_len());
+		let rec_size_p2 = self.cache.max_rec_size().to_raw();
+		let mask = (1 << rec_size_p2) - 1;
+		let (cache, id) = (self.cache, self.id);
+
+		stream::try_unfold(offset, move |offset| {
+			let obj = Object::new(cache, id);
+			async move {
+				if offset >= end {
+					return Ok(None);
+				}
+				let next = (offset | mask).saturating_add(1).min(end);
+				let start = usize::try_from(offset & mask).unwrap();
+				let len = usize::try_from(next - offset).unwrap();
+
+				let mut buf = obj.cache.resource().alloc();
+				{
+					let entry = obj.get(offset >> rec_size_p2).await?;
+					// Trailing zeros are not stored in the cache.
+					let data = entry.as_slice().get(start..).unwrap_or(&[]);
+					buf.extend_from_slice(&data[..data.len().min(len)]);
+				}
+				buf.resize(len, 0);
+				Ok(Some((buf, next)))
+			}
+		})
+	}
+}
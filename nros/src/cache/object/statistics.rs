@@ -0,0 +1,47 @@
+use {
+	super::{Dev, IdKey, Key, Object, Resource, RootIndex},
+	crate::{data::record::Depth, Error},
+};
+
+/// Statistics of a single object.
+///
+/// Intended for diagnostics.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ObjectStatistics {
+	/// The maximum length of the object.
+	pub max_len: u64,
+	/// The depth of the deepest tree that has any records written out.
+	pub depth: Option<u8>,
+	/// Amount of leaf records of this object in the cache.
+	pub resident_records: usize,
+	/// Amount of leaf records of this object that have been modified
+	/// but not yet written to the devices.
+	pub dirty_records: usize,
+}
+
+impl<'a, D: Dev, R: Resource> Object<'a, D, R> {
+	/// Get statistics for this object.
+	///
+	/// Objects do not track their length,
+	/// so only the maximum length is reported.
+	pub async fn statistics(&self) -> Result<ObjectStatistics, Error<D>> {
+		trace!("statistics {:#x}", self.id);
+		let layout = self.layout().await?;
+
+		let data = self.cache.data();
+		let (mut resident_records, mut dirty_records) = (0, 0);
+		for root in RootIndex::I0..=RootIndex::I3 {
+			let start = IdKey { id: self.id, key: Key::new(root, Depth::D0, 0) };
+			let end = IdKey { id: self.id, key: Key::new(root, Depth::D0, Key::MAX_OFFSET) };
+			resident_records += data.records.range(start..=end).count();
+			dirty_records += data.dirty.range(start..=end).count();
+		}
+
+		Ok(ObjectStatistics {
+			max_len: layout.max_len,
+			depth: layout.depth,
+			resident_records,
+			dirty_records,
+		})
+	}
+}
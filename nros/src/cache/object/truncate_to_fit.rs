@@ -0,0 +1,34 @@
+use {
+	super::{super::Tree, Dev, Object, Resource, RootIndex},
+	crate::Error,
+};
+
+impl<'a, D: Dev, R: Resource> Object<'a, D, R> {
+	/// Determine the length of this object up to and including the last non-zero byte.
+	///
+	/// Objects do not track their length,
+	/// so the new length is returned and should be stored by the caller.
+	/// Records past the last non-zero byte are already empty and take no space.
+	///
+	/// Zero records are skipped without reading them,
+	/// so this is cheap even for sparse objects.
+	pub async fn truncate_to_fit(&self) -> Result<u64, Error<D>> {
+		trace!("truncate_to_fit {:#x}", self.id);
+		self.check_id().await?;
+
+		let rec_size_p2 = self.cache.max_rec_size().to_raw();
+		let mut base = [0; 4];
+		for (i, size) in self.cache.root_max_size[..3].iter().enumerate() {
+			base[i + 1] = base[i].saturating_add(*size);
+		}
+
+		for root in (RootIndex::I0..=RootIndex::I3).rev() {
+			let tree = Tree::object(self.cache, self.id, root);
+			if let Some((offt, len)) = tree.last_nonzero().await? {
+				let len = u64::try_from(len).unwrap();
+				return Ok(base[root as usize] + (offt << rec_size_p2) + len);
+			}
+		}
+		Ok(0)
+	}
+}
@@ -1,5 +1,5 @@
 use {
-	super::{Dev, Object, Resource},
+	super::{super::EntryRef, Dev, Object, Resource},
 	crate::{resource::Buf, Error},
 };
 
@@ -8,6 +8,14 @@ impl<'a, D: Dev, R: Resource> Object<'a, D, R> {
 	///
 	/// Returns the actual amount of bytes written.
 	/// It may exit early if the necessary data is not cached (e.g. partial record write)
+	///
+	/// The data is split into pieces of at most one record each,
+	/// hence `data` may be larger than the maximum record size.
+	/// Data beyond the maximum length of the object is ignored.
+	/// Use [`Object::try_write`] to get an error instead.
+	///
+	/// If [`Nros::set_compare_before_write`](crate::Nros::set_compare_before_write) is enabled,
+	/// records whose data is unchanged are not marked dirty.
 	pub async fn write(&self, offset: u64, data: &[u8]) -> Result<usize, Error<D>> {
 		trace!(
 			"write id {:#x}, offset {}, len {}",
@@ -15,6 +23,7 @@ impl<'a, D: Dev, R: Resource> Object<'a, D, R> {
 			offset,
 			data.len()
 		);
+		self.check_id().await?;
 
 		let max_len = self.max_len();
 
@@ -36,7 +45,7 @@ impl<'a, D: Dev, R: Resource> Object<'a, D, R> {
 
 		if range.start() == range.end() {
 			// We need to slice one record twice
-			self.get(*range.start()).await?.write(first_offset, data);
+			self.write_entry(&mut self.get(*range.start()).await?, first_offset, data);
 		} else {
 			// We need to slice the first & last record once and operate on the others in full.
 			let mut data = data;
@@ -50,7 +59,7 @@ impl<'a, D: Dev, R: Resource> Object<'a, D, R> {
 			if let Some(first_key) = first_key {
 				let d;
 				(d, data) = data.split_at((1 << self.cache.max_rec_size().to_raw()) - first_offset);
-				self.get(first_key).await?.write(first_offset, d);
+				self.write_entry(&mut self.get(first_key).await?, first_offset, d);
 			}
 
 			// Copy middle records |xxxxxxxx|
@@ -62,17 +71,57 @@ impl<'a, D: Dev, R: Resource> Object<'a, D, R> {
 				let mut buf = self.cache.resource().alloc();
 				buf.extend_from_slice(&d[..end]);
 
-				self.set(offset, buf).await?;
+				if self.cache.compare_on_write() {
+					let mut entry = self.get(offset).await?;
+					if !entry.is_equal(0, d) {
+						entry.replace(buf);
+					}
+				} else {
+					self.set(offset, buf).await?;
+				}
 			}
 
 			// Copy end record |xxxx----|
 			// Don't bother if there is no data
 			if last_offset > 0 {
 				debug_assert_eq!(data.len(), last_offset);
-				self.get(last_key).await?.write(0, data);
+				self.write_entry(&mut self.get(last_key).await?, 0, data);
 			}
 		}
 
 		Ok(data.len())
 	}
+
+	/// Write data to a range.
+	///
+	/// Like [`Object::write`], but returns [`LengthTooLong`] if the range does not fit in the
+	/// object instead of writing only part of the data.
+	/// Nothing is written in that case.
+	///
+	/// As with [`Object::write`], `data` may be larger than the maximum record size.
+	pub async fn try_write(
+		&self,
+		offset: u64,
+		data: &[u8],
+	) -> Result<Result<usize, LengthTooLong>, Error<D>> {
+		self.check_id().await?;
+		let end = offset.checked_add(u64::try_from(data.len()).unwrap());
+		if end.map_or(true, |end| end > self.max_len()) {
+			return Ok(Err(LengthTooLong));
+		}
+		self.write(offset, data).await.map(Ok)
+	}
+
+	/// Write data to an entry.
+	///
+	/// If comparing is enabled and the data is unchanged the entry is left untouched.
+	fn write_entry(&self, entry: &mut EntryRef<'a, R::Buf>, offset: usize, data: &[u8]) {
+		if !self.cache.compare_on_write() || !entry.is_equal(offset, data) {
+			entry.write(offset, data);
+		}
+	}
 }
+
+/// The range to write to exceeds the maximum length of the object.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LengthTooLong;
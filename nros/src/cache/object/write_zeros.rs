@@ -19,7 +19,16 @@ impl<'a, D: Dev, R: Resource> Object<'a, D, R> {
 			offset,
 			len,
 		);
+		self.check_id().await?;
+		self.write_zeros_unchecked(offset, len).await
+	}
 
+	/// Zero out a range of data, even if the object is not allocated.
+	pub(in super::super) async fn write_zeros_unchecked(
+		&self,
+		offset: u64,
+		len: u64,
+	) -> Result<u64, Error<D>> {
 		let max_len = self.max_len();
 		let len = if offset >= max_len || len == 0 {
 			return Ok(0);
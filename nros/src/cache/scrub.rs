@@ -0,0 +1,112 @@
+use {
+	super::{Cache, Depth, RootIndex, Tree, OBJECT_SIZE_P2},
+	crate::{
+		data::{self, record::RecordRef},
+		resource::Buf,
+		Dev, Error, Resource,
+	},
+};
+
+/// Results of [`Nros::scrub`](crate::Nros::scrub).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ScrubReport {
+	/// Amount of records that have been read.
+	pub records_checked: u64,
+	/// Amount of records that were broken on at least one chain and have been repaired.
+	pub records_repaired: u64,
+	/// Amount of records that could not be read from any chain.
+	pub records_unrecoverable: u64,
+}
+
+impl<D: Dev, R: Resource> Cache<D, R> {
+	/// Read every record referenced by the object list, object bitmap and objects
+	/// to check their integrity.
+	///
+	/// The current transaction is finished first so the records on the devices are up to date.
+	/// Repairs are only performed if enabled when loading the object store.
+	pub async fn scrub(&self) -> Result<ScrubReport, Error<D>> {
+		trace!("scrub");
+		self.finish_transaction().await?;
+
+		let mut report = ScrubReport::default();
+		let list_root = self.store.object_list_root();
+		let list_depth = self.store.object_list_depth();
+		self.scrub_tree(&mut report, list_root, list_depth).await?;
+		let bitmap_root = self.store.object_bitmap_root();
+		let bitmap_depth = self.object_bitmap_depth.get();
+		self.scrub_tree(&mut report, bitmap_root, bitmap_depth)
+			.await?;
+
+		self.scan_object_bitmap(u64::MAX).await?;
+		let used = self.data().used_objects_ids.clone();
+		let list = Tree::object_list(self);
+		let entries_per_leaf = 1u64 << self.max_rec_size().to_raw() - OBJECT_SIZE_P2;
+		for offset in 0..list.max_offset() {
+			let mut roots = vec![];
+			let entry = list.get(Depth::D0, offset).await?;
+			for i in 0..entries_per_leaf {
+				let id = offset * entries_per_leaf + i;
+				if !used.contains(&id) {
+					continue;
+				}
+				let mut obj = data::object::Object::default();
+				entry.read(usize::try_from(i << OBJECT_SIZE_P2).unwrap(), obj.as_mut());
+				roots.extend((RootIndex::I0..=RootIndex::I3).zip(obj.root));
+			}
+			drop(entry);
+
+			for (root, rec) in roots {
+				self.scrub_tree(&mut report, rec, root.depth()).await?;
+			}
+		}
+
+		// Write out repairs.
+		self.finish_transaction().await?;
+
+		Ok(report)
+	}
+
+	/// Read all records in a tree.
+	///
+	/// The tree is walked depth-first so only the references to records that have yet to be
+	/// read are kept in memory, not the records themselves.
+	async fn scrub_tree(
+		&self,
+		report: &mut ScrubReport,
+		root: RecordRef,
+		depth: Depth,
+	) -> Result<(), Error<D>> {
+		let mut stack = vec![(root, depth)];
+		while let Some((rec, depth)) = stack.pop() {
+			if rec == RecordRef::NONE {
+				continue;
+			}
+			report.records_checked += 1;
+
+			let mismatches = self.store.statistics().chain_mismatches;
+			let buf = match self.store.read(rec).await {
+				Ok(buf) => buf,
+				Err(Error::RecordUnpack(_)) => {
+					trace!(info "unrecoverable record {:?}", rec);
+					report.records_unrecoverable += 1;
+					continue;
+				}
+				Err(e) => return Err(e),
+			};
+			if self.store.statistics().chain_mismatches != mismatches
+				&& self.store.repairs_enabled()
+			{
+				report.records_repaired += 1;
+			}
+
+			if depth != Depth::D0 {
+				let mut data = buf.get();
+				while let Some((child, rest)) = RecordRef::from_raw_slice(data) {
+					stack.push((child, depth.prev()));
+					data = rest;
+				}
+			}
+		}
+		Ok(())
+	}
+}
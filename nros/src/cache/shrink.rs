@@ -0,0 +1,50 @@
+use {
+	super::{Cache, RootIndex, Tree},
+	crate::{Dev, Error, Resource},
+};
+
+impl<D: Dev, R: Resource> Cache<D, R> {
+	/// Move records towards the start of the devices.
+	///
+	/// Returns the end of the last allocated block.
+	pub async fn shrink_to_fit(&self) -> Result<u64, Error<D>> {
+		trace!("shrink_to_fit");
+
+		// Blocks freed in the current transaction can't be reused until it is finished.
+		self.finish_transaction().await?;
+		self.scan_object_bitmap(u64::MAX).await?;
+
+		loop {
+			// If all records were packed tightly, every allocation would be below this block.
+			let (used, end) = self.store.allocation_extent();
+			trace!(info "used {}, end {}", used, end);
+			if end <= used {
+				return Ok(end);
+			}
+
+			// Relocate all records past the ideal end.
+			let ids = self
+				.data()
+				.used_objects_ids
+				.iter()
+				.cloned()
+				.collect::<Vec<_>>();
+			for id in ids.into_iter().flatten() {
+				for root in RootIndex::I0..=RootIndex::I3 {
+					Tree::object(self, id, root).relocate(used).await?;
+				}
+			}
+			Tree::object_list(self).relocate(used).await?;
+			Tree::object_bitmap(self).relocate(used).await?;
+
+			// Freed blocks may not be reused in the same transaction,
+			// so records may not have been moved as far as possible.
+			// Try again until no more progress is made.
+			self.finish_transaction().await?;
+			let (_, new_end) = self.store.allocation_extent();
+			if new_end >= end {
+				return Ok(new_end);
+			}
+		}
+	}
+}
@@ -0,0 +1,54 @@
+use {
+	super::{super::Depth, RootLocation, Tree},
+	crate::{data::record::RecordRef, Dev, Error, Resource},
+};
+
+impl<'a, D: Dev, R: Resource> Tree<'a, D, R> {
+	/// Count the amount of blocks used by all records in this tree.
+	///
+	/// Only records that have been written out are counted.
+	pub(in super::super) async fn blocks(&self) -> Result<u64, Error<D>> {
+		trace!("blocks ({:#x}:{:?})", self.id(), self.root());
+
+		let mut total = u64::from(self.root_record().await?.blocks());
+		if self.depth() == Depth::D0 {
+			return Ok(total);
+		}
+
+		// Walk all parent records with non-zero or dirty children.
+		let mut stack = vec![(self.depth(), 0)];
+		while let Some((depth, offt)) = stack.pop() {
+			let entry = self.get(depth, offt).await?;
+			for i in 0..1 << self.cache.entries_per_parent_p2() {
+				let mut rec = RecordRef::NONE;
+				entry.read(i * 8, rec.as_mut());
+				total += u64::from(rec.blocks());
+
+				let d = depth.prev();
+				let co = offt << self.cache.entries_per_parent_p2() | u64::try_from(i).unwrap();
+				if d > Depth::D0
+					&& (rec != RecordRef::NONE || self.has_dirty(&entry, self.id_key(d, co).key))
+				{
+					stack.push((d, co));
+				}
+			}
+		}
+
+		Ok(total)
+	}
+
+	/// Get the record reference of the root of this tree.
+	pub(in super::super) async fn root_record(&self) -> Result<RecordRef, Error<D>> {
+		match &self.root {
+			RootLocation::Object { .. } => {
+				let (o_d, o_offt, index) = self.object_key_index();
+				let entry = Tree::object_list(self.cache).get(o_d, o_offt).await?;
+				let mut rec = RecordRef::NONE;
+				entry.read(index, rec.as_mut());
+				Ok(rec)
+			}
+			RootLocation::ObjectList => Ok(self.cache.store.object_list_root()),
+			RootLocation::ObjectBitmap => Ok(self.cache.store.object_bitmap_root()),
+		}
+	}
+}
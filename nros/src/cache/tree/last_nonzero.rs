@@ -0,0 +1,41 @@
+use {
+	super::{super::Depth, Tree},
+	crate::{data::record::RecordRef, Dev, Error, Resource},
+};
+
+impl<'a, D: Dev, R: Resource> Tree<'a, D, R> {
+	/// Find the last leaf record with non-zero data.
+	///
+	/// Zero children are skipped without fetching them, like [`Tree::set_zeros`].
+	///
+	/// Returns the offset of the record and the length of the data in it.
+	pub async fn last_nonzero(&self) -> Result<Option<(u64, usize)>, Error<D>> {
+		trace!("last_nonzero ({:#x}:{:?})", self.id(), self.root());
+
+		let epp_p2 = self.cache.entries_per_parent_p2();
+
+		// Children are pushed in ascending order so the last one is visited first.
+		let mut stack = vec![(self.depth(), 0)];
+		while let Some((depth, offt)) = stack.pop() {
+			let entry = self.get(depth, offt).await?;
+			if depth == Depth::D0 {
+				if entry.len() > 0 {
+					return Ok(Some((offt, entry.len())));
+				}
+				continue;
+			}
+
+			let d = depth.prev();
+			for i in 0..1 << epp_p2 {
+				let mut rec = RecordRef::NONE;
+				entry.read(i * 8, rec.as_mut());
+				let co = offt << epp_p2 | u64::try_from(i).unwrap();
+				if rec != RecordRef::NONE || self.has_dirty(&entry, self.id_key(d, co).key) {
+					stack.push((d, co));
+				}
+			}
+		}
+
+		Ok(None)
+	}
+}
@@ -1,5 +1,8 @@
+mod blocks;
 mod fetch;
 mod get;
+mod last_nonzero;
+mod relocate;
 mod set;
 mod set_zeros;
 mod update_record;
@@ -0,0 +1,56 @@
+use {
+	super::{super::Depth, Tree},
+	crate::{data::record::RecordRef, Dev, Error, Resource},
+};
+
+impl<'a, D: Dev, R: Resource> Tree<'a, D, R> {
+	/// Mark all records that extend past the given block as dirty.
+	///
+	/// When flushed, these records will be written to the first available free blocks.
+	pub(in super::super) async fn relocate(&self, above: u64) -> Result<(), Error<D>> {
+		trace!("relocate ({:#x}:{:?}) {}", self.id(), self.root(), above);
+
+		let is_above =
+			|rec: RecordRef| rec != RecordRef::NONE && rec.lba() + u64::from(rec.blocks()) > above;
+
+		let root = self.root_record().await?;
+		if root == RecordRef::NONE {
+			return Ok(());
+		}
+		if is_above(root) {
+			self.get(self.depth(), 0).await?.mark_dirty();
+		}
+		if self.depth() == Depth::D0 {
+			return Ok(());
+		}
+
+		// Walk all parent records with non-zero or dirty children.
+		let mut stack = vec![(self.depth(), 0)];
+		let mut move_children = vec![];
+		while let Some((depth, offt)) = stack.pop() {
+			let d = depth.prev();
+			let entry = self.get(depth, offt).await?;
+			for i in 0..1 << self.cache.entries_per_parent_p2() {
+				let mut rec = RecordRef::NONE;
+				entry.read(i * 8, rec.as_mut());
+
+				let co = offt << self.cache.entries_per_parent_p2() | u64::try_from(i).unwrap();
+				if is_above(rec) {
+					move_children.push(co);
+				}
+				if d > Depth::D0
+					&& (rec != RecordRef::NONE || self.has_dirty(&entry, self.id_key(d, co).key))
+				{
+					stack.push((d, co));
+				}
+			}
+			drop(entry);
+
+			for co in move_children.drain(..) {
+				self.get(d, co).await?.mark_dirty();
+			}
+		}
+
+		Ok(())
+	}
+}
@@ -97,7 +97,7 @@ impl<'a, D: Dev, R: Resource> Tree<'a, D, R> {
 	}
 
 	/// Check if the entry *with the given key* is or has any descendants that are dirty.
-	fn has_dirty(&self, entry: &EntryRef<'a, R::Buf>, key: Key) -> bool {
+	pub(super) fn has_dirty(&self, entry: &EntryRef<'a, R::Buf>, key: Key) -> bool {
 		trace!("has_dirty {:?}", key);
 		let mut offt = key.offset();
 		let mut end_offt = offt + 1;
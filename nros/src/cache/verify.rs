@@ -0,0 +1,108 @@
+use {
+	super::{Cache, Depth, Object, Tree, OBJECT_SIZE_P2},
+	crate::{
+		data::{self, record::RecordRef},
+		Dev, Error, Resource,
+	},
+};
+
+/// Inconsistencies found by [`Nros::verify`](crate::Nros::verify).
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct VerifyReport {
+	/// Objects that are free according to the bitmap but still refer to records.
+	pub leaked_objects: Vec<u64>,
+	/// Objects that refer to records which are not allocated.
+	pub dangling_objects: Vec<u64>,
+	/// Objects of which a root record could not be read.
+	pub corrupt_objects: Vec<u64>,
+}
+
+impl VerifyReport {
+	/// Whether no inconsistencies were found.
+	pub fn is_ok(&self) -> bool {
+		self.leaked_objects.is_empty()
+			&& self.dangling_objects.is_empty()
+			&& self.corrupt_objects.is_empty()
+	}
+}
+
+impl<D: Dev, R: Resource> Cache<D, R> {
+	/// Check whether the object list, object bitmap and allocation map agree with each other.
+	///
+	/// The root records of all objects are read to check their integrity.
+	///
+	/// If `repair` is `true`, leaked objects are cleared.
+	/// Other inconsistencies can't be repaired.
+	pub async fn verify(&self, repair: bool) -> Result<VerifyReport, Error<D>> {
+		trace!("verify {}", repair);
+		let mut report = VerifyReport::default();
+
+		// The bitmap is loaded in used_objects_ids.
+		self.scan_object_bitmap(u64::MAX).await?;
+		let used = self.data().used_objects_ids.clone();
+		let list = Tree::object_list(self);
+		let entries_per_leaf = 1u64 << self.max_rec_size().to_raw() - OBJECT_SIZE_P2;
+		for offset in 0..list.max_offset() {
+			let mut roots = vec![];
+			let entry = list.get(Depth::D0, offset).await?;
+			for i in 0..entries_per_leaf {
+				let id = offset * entries_per_leaf + i;
+				let mut obj = data::object::Object::default();
+				entry.read(usize::try_from(i << OBJECT_SIZE_P2).unwrap(), obj.as_mut());
+				if used.contains(&id) {
+					roots.extend(
+						obj.root
+							.into_iter()
+							.filter(|r| *r != RecordRef::NONE)
+							.map(|r| (id, r)),
+					);
+				} else if obj.root.iter().any(|r| *r != RecordRef::NONE) {
+					report.leaked_objects.push(id);
+				}
+			}
+			drop(entry);
+
+			for (id, rec) in roots {
+				if !self.store.is_allocated(rec.lba(), rec.blocks().into()) {
+					trace!(info "{:#x} has dangling root {:?}", id, rec);
+					if report.dangling_objects.last() != Some(&id) {
+						report.dangling_objects.push(id);
+					}
+					continue;
+				}
+				match self.store.read(rec).await {
+					Ok(_) => {}
+					Err(Error::RecordUnpack(_)) => {
+						trace!(info "{:#x} has corrupt root {:?}", id, rec);
+						if report.corrupt_objects.last() != Some(&id) {
+							report.corrupt_objects.push(id);
+						}
+					}
+					Err(e) => return Err(e),
+				}
+			}
+		}
+
+		if repair {
+			for &id in &report.leaked_objects {
+				Object::new(self, id)
+					.write_zeros_unchecked(0, u64::MAX)
+					.await?;
+			}
+		}
+
+		Ok(report)
+	}
+
+	/// Get the IDs of all objects in use.
+	///
+	/// If the object bitmap is loaded lazily, only the IDs discovered so far are included.
+	pub fn used_objects(&self) -> Vec<u64> {
+		self.data()
+			.used_objects_ids
+			.iter()
+			.cloned()
+			.flatten()
+			.collect()
+	}
+}
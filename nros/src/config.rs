@@ -1,6 +1,10 @@
 use {
-	crate::{BlockSize, CipherType, Compression, Dev, MaxRecordSize, Resource},
+	crate::{
+		key_derivation, BlockSize, CachePolicy, CipherType, Compression, Dev, MaxRecordSize,
+		Resource,
+	},
 	core::num::{NonZeroU32, NonZeroU8},
+	std::time::{Duration, Instant},
 };
 
 /// Algorithm to derive key with.
@@ -12,6 +16,19 @@ pub enum KeyDeriver<'a> {
 	Argon2id { password: &'a [u8], m: NonZeroU32, t: NonZeroU32, p: NonZeroU8 },
 }
 
+impl KeyDeriver<'_> {
+	/// Measure how long deriving a key takes on this host.
+	///
+	/// Useful to pick parameters for [`KeyDeriver::Argon2id`].
+	pub fn benchmark(&self) -> Duration {
+		let start = Instant::now();
+		if let &Self::Argon2id { password, m, t, p } = self {
+			key_derivation::argon2id(password, &[0; 16], m, t, p);
+		}
+		start.elapsed()
+	}
+}
+
 /// Configuration to create a new object store.
 pub struct NewConfig<'a, D: Dev, R: Resource> {
 	/// Memory & computation resources.
@@ -38,7 +55,22 @@ pub struct NewConfig<'a, D: Dev, R: Resource> {
 	///
 	/// This is a soft limit.
 	/// Real usage may exceed this.
+	///
+	/// The hard limit is derived from this unless `hard_cache_size` is set.
 	pub cache_size: usize,
+	/// Soft limit of the cache, overriding `cache_size`.
+	///
+	/// Unreferenced entries are evicted beyond this limit.
+	pub soft_cache_size: Option<usize>,
+	/// Hard limit of the cache.
+	///
+	/// Tasks wait for entries to be evicted when this limit is reached.
+	/// At least two records are reserved beyond the soft limit so operations can complete.
+	/// Values below that are raised accordingly.
+	///
+	/// Creating the object store fails with [`crate::Error::HardCacheSizeTooSmall`]
+	/// if this is smaller than the soft limit.
+	pub hard_cache_size: Option<usize>,
 }
 
 /// Key or password to decrypt the header.
@@ -59,11 +91,52 @@ pub struct LoadConfig<'a, D: Dev, R: Resource> {
 	///
 	/// This is a soft limit.
 	/// Real usage may exceed this.
+	///
+	/// The hard limit is derived from this unless `hard_cache_size` is set.
 	pub cache_size: usize,
+	/// Soft limit of the cache, overriding `cache_size`.
+	///
+	/// Unreferenced entries are evicted beyond this limit.
+	pub soft_cache_size: Option<usize>,
+	/// Hard limit of the cache.
+	///
+	/// Tasks wait for entries to be evicted when this limit is reached.
+	/// At least two records are reserved beyond the soft limit so operations can complete.
+	/// Values below that are raised accordingly.
+	///
+	/// Loading fails with [`crate::Error::HardCacheSizeTooSmall`]
+	/// if this is smaller than the soft limit.
+	pub hard_cache_size: Option<usize>,
+	/// Policy to select cache entries for eviction with.
+	pub cache_policy: CachePolicy,
 	/// Whether to allow repair of this filesystem.
 	///
 	/// If `false`, errors will not be corrected.
 	pub allow_repair: bool,
+	/// Whether to only detect errors in records without correcting them.
+	///
+	/// Records that could only be read from some chains are counted in the
+	/// `chain_mismatches` statistic but are not written back to the other chains.
+	pub scrub_only: bool,
+	/// Amount to advance the header nonce by when loading.
+	///
+	/// The header is encrypted with a nonce derived from a counter that is incremented
+	/// every time the header is saved.
+	/// If the same image is mounted twice, e.g. because the devices were copied or
+	/// a previous mount crashed after the headers were read by another mount,
+	/// both mounts will reuse the same nonces with different data.
+	/// With XChaCha this reveals the contents of both headers.
+	///
+	/// Advancing the counter by a margin larger than the amount of headers a
+	/// concurrent or lost mount may have saved avoids this.
+	/// This has no effect on unencrypted object stores.
+	pub nonce_safety_margin: u64,
+	/// Discover used object IDs in the object bitmap on demand instead of when loading.
+	///
+	/// This makes loading object stores with many objects faster.
+	/// Until the bitmap has been scanned entirely,
+	/// statistics only count the objects that have been discovered so far.
+	pub lazy_object_bitmap: bool,
 	/// Method to retrieve either a key directly or get a password.
 	///
 	/// If the passed parameter is `true` a password can be provided.
@@ -2,7 +2,10 @@
 use xxhash_rust::const_xxh3::xxh3_128;
 #[cfg(not(miri))]
 use xxhash_rust::xxh3::xxh3_128;
+#[cfg(feature = "encryption")]
 use {
+	aes::Aes256,
+	aes_gcm::{aead::consts::U24, AesGcm, Nonce},
 	chacha20::{
 		cipher::{KeyIvInit as _, StreamCipher as _},
 		XChaCha12,
@@ -11,15 +14,38 @@ use {
 		aead::{AeadInPlace, NewAead},
 		Tag, XChaCha12Poly1305, XNonce,
 	},
+	ctr::Ctr128BE,
 };
 
+/// AES-256-GCM using the full 24 byte nonce.
+///
+/// Nonces that aren't 12 bytes long are hashed with GHASH to derive the initial counter block.
+#[cfg(feature = "encryption")]
+type Aes256Gcm = AesGcm<aes_gcm::aes::Aes256, U24>;
+
 n2e! {
 	[CipherType]
 	0 NoneXxh3
 	1 XChaCha12Poly1305
+	2 Aes256Gcm
+}
+
+impl CipherType {
+	/// Whether this build can encrypt and decrypt with this cipher.
+	///
+	/// Only [`CipherType::NoneXxh3`] is available without the `encryption` feature.
+	pub fn is_supported(self) -> bool {
+		match self {
+			Self::NoneXxh3 => true,
+			Self::XChaCha12Poly1305 | Self::Aes256Gcm => cfg!(feature = "encryption"),
+		}
+	}
 }
 
 /// Generic cipher.
+///
+/// [`CipherType::Aes256Gcm`] uses the full nonce for data
+/// but only the first 16 bytes for metadata.
 pub(crate) struct Cipher {
 	pub key1: [u8; 32],
 	pub key2: [u8; 32],
@@ -28,16 +54,25 @@ pub(crate) struct Cipher {
 
 impl Cipher {
 	/// Apply keystream for metadata, such as record headers.
+	#[cfg_attr(not(feature = "encryption"), allow(unused_variables))]
 	pub fn apply_meta(&self, nonce: &[u8; 24], data: &mut [u8]) {
 		match self.ty {
 			CipherType::NoneXxh3 => {}
+			#[cfg(feature = "encryption")]
 			CipherType::XChaCha12Poly1305 => XChaCha12::new_from_slices(&self.key2, nonce)
 				.unwrap()
 				.apply_keystream(data),
+			#[cfg(feature = "encryption")]
+			CipherType::Aes256Gcm => Ctr128BE::<Aes256>::new_from_slices(&self.key2, &nonce[..16])
+				.unwrap()
+				.apply_keystream(data),
+			#[cfg(not(feature = "encryption"))]
+			CipherType::XChaCha12Poly1305 | CipherType::Aes256Gcm => unsupported(),
 		}
 	}
 
 	/// Decrypt data in-place.
+	#[cfg_attr(not(feature = "encryption"), allow(unused_variables))]
 	pub fn decrypt(
 		&self,
 		nonce: &[u8; 24],
@@ -48,6 +83,7 @@ impl Cipher {
 			CipherType::NoneXxh3 => (&xxh3_128(data).to_le_bytes() == hash)
 				.then_some(())
 				.ok_or(DecryptError),
+			#[cfg(feature = "encryption")]
 			CipherType::XChaCha12Poly1305 => XChaCha12Poly1305::new_from_slice(&self.key1)
 				.unwrap()
 				.decrypt_in_place_detached(
@@ -57,24 +93,53 @@ impl Cipher {
 					Tag::from_slice(hash),
 				)
 				.map_err(|_| DecryptError),
+			#[cfg(feature = "encryption")]
+			CipherType::Aes256Gcm => Aes256Gcm::new_from_slice(&self.key1)
+				.unwrap()
+				.decrypt_in_place_detached(
+					Nonce::from_slice(nonce),
+					&[],
+					data,
+					Tag::from_slice(hash),
+				)
+				.map_err(|_| DecryptError),
+			#[cfg(not(feature = "encryption"))]
+			CipherType::XChaCha12Poly1305 | CipherType::Aes256Gcm => unsupported(),
 		}
 	}
 
 	/// Encrypt data in-place.
 	///
 	/// Returns the hash.
+	#[cfg_attr(not(feature = "encryption"), allow(unused_variables))]
 	pub fn encrypt(&self, nonce: &[u8; 24], data: &mut [u8]) -> [u8; 16] {
 		match self.ty {
 			CipherType::NoneXxh3 => xxh3_128(data).to_le_bytes(),
+			#[cfg(feature = "encryption")]
 			CipherType::XChaCha12Poly1305 => XChaCha12Poly1305::new_from_slice(&self.key1)
 				.unwrap()
 				.encrypt_in_place_detached(XNonce::from_slice(nonce), &[], data)
 				.unwrap()
 				.into(),
+			#[cfg(feature = "encryption")]
+			CipherType::Aes256Gcm => Aes256Gcm::new_from_slice(&self.key1)
+				.unwrap()
+				.encrypt_in_place_detached(Nonce::from_slice(nonce), &[], data)
+				.unwrap()
+				.into(),
+			#[cfg(not(feature = "encryption"))]
+			CipherType::XChaCha12Poly1305 | CipherType::Aes256Gcm => unsupported(),
 		}
 	}
 }
 
+/// Unsupported ciphers are rejected when creating or loading a store,
+/// so a [`Cipher`] with one should never exist.
+#[cfg(not(feature = "encryption"))]
+fn unsupported() -> ! {
+	unreachable!("encryption is not enabled")
+}
+
 /// Error returned when something went wrong while decrypting.
 #[derive(Clone, Debug)]
 pub struct DecryptError;
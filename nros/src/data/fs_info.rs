@@ -3,7 +3,10 @@ use {
 		cipher::{Cipher, DecryptError},
 		record::{Depth, RecordRef},
 	},
-	crate::{BlockSize, CipherType, Compression, KeyDerivation, MaxRecordSize},
+	crate::{
+		key_derivation, BlockSize, CipherType, Compression, Dev, Error, KeyDerivation, KeyPassword,
+		MaxRecordSize,
+	},
 	core::fmt,
 	endian::u64le,
 };
@@ -102,6 +105,61 @@ impl FsHeader {
 		cipher.decrypt(&nonce(self.nonce, &self.uid), &self.hash, data)?;
 		Ok(FsInfo::from_raw_slice(data).expect("data too small"))
 	}
+
+	/// Check whether the given key can decrypt the filesystem info.
+	///
+	/// `data` is left untouched.
+	pub fn verify_key(&self, key: &[u8; 32], data: &[u8; 512 - 64]) -> bool {
+		self.decrypt(key, &mut data.clone()).is_ok()
+	}
+
+	/// Get or derive the key to decrypt the filesystem info with.
+	pub fn derive_key(
+		&self,
+		retrieve_key: &mut dyn FnMut(bool) -> Option<KeyPassword>,
+	) -> Result<[u8; 32], DeriveKeyError> {
+		if matches!(self.cipher(), Ok(CipherType::NoneXxh3)) {
+			return Ok([0; 32]);
+		}
+		let kdf = self
+			.key_derivation()
+			.map_err(DeriveKeyError::UnsupportedKeyDerivation)?;
+		Ok(match kdf {
+			KeyDerivation::None => match retrieve_key(false).ok_or(DeriveKeyError::NoKey)? {
+				KeyPassword::Key(k) => k,
+				KeyPassword::Password(_) => return Err(DeriveKeyError::ExpectedKey),
+			},
+			KeyDerivation::Argon2id { p, t, m } => {
+				match retrieve_key(true).ok_or(DeriveKeyError::NoKey)? {
+					KeyPassword::Key(k) => k,
+					KeyPassword::Password(pwd) => {
+						key_derivation::argon2id(&pwd, &self.uid, m, t, p)
+					}
+				}
+			}
+		})
+	}
+}
+
+/// Error returned when a key could not be retrieved or derived.
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum DeriveKeyError {
+	/// No key or password was provided.
+	NoKey,
+	/// A password was provided but the filesystem does not use a key derivation algorithm.
+	ExpectedKey,
+	/// The key derivation algorithm is unknown.
+	UnsupportedKeyDerivation(u8),
+}
+
+impl DeriveKeyError {
+	pub fn into_error<D: Dev>(self) -> Error<D> {
+		match self {
+			Self::NoKey => Error::NoKey,
+			Self::ExpectedKey => Error::ExpectedKey,
+			Self::UnsupportedKeyDerivation(kdf) => Error::UnsupportedKeyDerivation { kdf },
+		}
+	}
 }
 
 impl Configuration {
@@ -166,6 +224,14 @@ impl Configuration {
 	pub fn set_compression_algorithm(&mut self, value: Compression) {
 		self.0[2] = value.to_raw()
 	}
+
+	pub fn allocation_log_version(&self) -> u8 {
+		self.0[3]
+	}
+
+	pub fn set_allocation_log_version(&mut self, value: u8) {
+		self.0[3] = value
+	}
 }
 
 impl fmt::Debug for FsHeader {
@@ -1,5 +1,7 @@
 mod lz4;
 mod none;
+#[cfg(feature = "zstd")]
+mod zstd;
 
 use {
 	crate::{resource::Buf, BlockSize},
@@ -10,9 +12,22 @@ n2e! {
 	[Compression]
 	0 None
 	1 Lz4
+	2 Zstd
 }
 
 impl Compression {
+	/// Whether this build can compress and decompress with this algorithm.
+	///
+	/// [`Compression::Zstd`] requires the `zstd` feature.
+	/// Without it, stores using it can neither be created nor loaded
+	/// and records are stored uncompressed if it is set as default compression.
+	pub fn is_supported(self) -> bool {
+		match self {
+			Self::None | Self::Lz4 => true,
+			Self::Zstd => cfg!(feature = "zstd"),
+		}
+	}
+
 	/// # Note
 	///
 	/// While [`Self::compress`] will always ensure that the compressed data is no larger than
@@ -22,6 +37,10 @@ impl Compression {
 		match self {
 			Compression::None => none::max_output_size(len),
 			Compression::Lz4 => lz4::max_output_size(len),
+			#[cfg(feature = "zstd")]
+			Compression::Zstd => zstd::max_output_size(len),
+			#[cfg(not(feature = "zstd"))]
+			Compression::Zstd => none::max_output_size(len),
 		}
 	}
 
@@ -43,6 +62,10 @@ impl Compression {
 		let res = match self {
 			Self::None => return (self, none::compress(data, buf)),
 			Self::Lz4 => lz4::compress(data, buf),
+			#[cfg(feature = "zstd")]
+			Self::Zstd => zstd::compress(data, buf),
+			#[cfg(not(feature = "zstd"))]
+			Self::Zstd => None,
 		};
 		match res {
 			Some(n) if header_size + n < data.len() => (self, n as _),
@@ -55,6 +78,10 @@ impl Compression {
 		match self {
 			Compression::None => none::decompress::<B>(data, buf, len),
 			Compression::Lz4 => lz4::decompress::<B>(data, buf, len),
+			#[cfg(feature = "zstd")]
+			Compression::Zstd => zstd::decompress::<B>(data, buf, len),
+			#[cfg(not(feature = "zstd"))]
+			Compression::Zstd => false,
 		}
 	}
 }
@@ -70,6 +97,7 @@ impl fmt::Display for Compression {
 		match self {
 			Self::None => "none",
 			Self::Lz4 => "lz4",
+			Self::Zstd => "zstd",
 		}
 		.fmt(f)
 	}
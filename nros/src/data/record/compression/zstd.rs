@@ -0,0 +1,19 @@
+use crate::resource::Buf;
+
+pub fn max_output_size(len: usize) -> usize {
+	::zstd::zstd_safe::compress_bound(len)
+}
+
+pub fn compress(data: &[u8], buf: &mut [u8]) -> Option<usize> {
+	// Level 0 selects the default level of the library.
+	::zstd::bulk::compress_to_buffer(data, buf, 0).ok()
+}
+
+pub fn decompress<B: Buf>(data: &[u8], buf: &mut B, len: usize) -> bool {
+	buf.resize(len, 0);
+	if let Ok(l) = ::zstd::bulk::decompress_to_buffer(data, buf.get_mut()) {
+		len == l
+	} else {
+		false
+	}
+}
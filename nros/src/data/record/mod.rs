@@ -12,7 +12,7 @@ pub use compression::Compression;
 /// Reference to a record.
 #[derive(Clone, Copy, Default, PartialEq)]
 #[repr(C)]
-pub(crate) struct RecordRef(u64le);
+pub struct RecordRef(u64le);
 
 pub const HEADER_LEN: u8 = 64;
 
@@ -32,10 +32,12 @@ impl RecordRef {
 		Self((lba << 16 | u64::from(blocks)).into())
 	}
 
+	/// The address of the first block of the record.
 	pub fn lba(&self) -> u64 {
 		u64::from(self.0) >> 16
 	}
 
+	/// The amount of blocks the record occupies.
 	pub fn blocks(&self) -> u16 {
 		u64::from(self.0) as u16
 	}
@@ -142,11 +142,14 @@ mod waker_queue;
 pub use resource::StdResource;
 pub use {
 	block_size::BlockSize,
-	cache::{Object, Statistics},
+	cache::{
+		CachePolicy, Layout, LengthTooLong, Object, ObjectStatistics, ScrubReport, Statistics,
+		VerifyReport,
+	},
 	config::{KeyDeriver, KeyPassword, LoadConfig, NewConfig},
 	data::{
 		cipher::CipherType,
-		record::{Compression, MaxRecordSize},
+		record::{Compression, MaxRecordSize, RecordRef},
 	},
 	resource::Resource,
 	storage::{dev, Dev},
@@ -174,27 +177,75 @@ pub struct Nros<D: Dev, R: Resource> {
 impl<D: Dev, R: Resource> Nros<D, R> {
 	/// Create a new object store.
 	pub async fn new(config: NewConfig<'_, D, R>) -> Result<Self, Error<D>> {
-		let cache_size = config.cache_size;
+		let soft_cache_size = config.soft_cache_size.unwrap_or(config.cache_size);
+		let hard_cache_size = config.hard_cache_size;
+		check_cache_size(soft_cache_size, hard_cache_size)?;
 		let devs = DevSet::new(config).await?;
-		Self::load_inner(devs, cache_size, true).await
+		let policy = CachePolicy::default();
+		Self::load_inner(
+			devs,
+			soft_cache_size,
+			hard_cache_size,
+			policy,
+			true,
+			false,
+			false,
+		)
+		.await
 	}
 
 	/// Load an existing object store.
 	pub async fn load(config: LoadConfig<'_, D, R>) -> Result<Self, Error<D>> {
-		let cache_size = config.cache_size;
-		let allow_repair = config.allow_repair;
+		let soft_cache_size = config.soft_cache_size.unwrap_or(config.cache_size);
+		let hard_cache_size = config.hard_cache_size;
+		check_cache_size(soft_cache_size, hard_cache_size)?;
+		let cache_policy = config.cache_policy;
+		let (allow_repair, scrub_only) = (config.allow_repair, config.scrub_only);
+		let lazy_object_bitmap = config.lazy_object_bitmap;
 		let devs = DevSet::load(config).await?;
-		Self::load_inner(devs, cache_size, allow_repair).await
+		Self::load_inner(
+			devs,
+			soft_cache_size,
+			hard_cache_size,
+			cache_policy,
+			allow_repair,
+			scrub_only,
+			lazy_object_bitmap,
+		)
+		.await
+	}
+
+	/// Check whether a key or password matches the object store.
+	///
+	/// Only the headers are read, no other data is loaded.
+	///
+	/// Returns `false` if no key or password is provided.
+	pub async fn verify_key(
+		devices: &[D],
+		retrieve_key: &mut dyn FnMut(bool) -> Option<KeyPassword>,
+	) -> Result<bool, Error<D>> {
+		DevSet::<D, R>::verify_key(devices, retrieve_key).await
 	}
 
 	/// Load an object store.
 	async fn load_inner(
 		devices: DevSet<D, R>,
-		cache_size: usize,
+		soft_cache_size: usize,
+		hard_cache_size: Option<usize>,
+		cache_policy: CachePolicy,
 		allow_repair: bool,
+		scrub_only: bool,
+		lazy_object_bitmap: bool,
 	) -> Result<Self, Error<D>> {
-		let store = Store::new(devices, allow_repair).await?;
-		let store = Cache::new(store, cache_size).await?;
+		let store = Store::new(devices, allow_repair, scrub_only).await?;
+		let store = Cache::new(
+			store,
+			soft_cache_size,
+			hard_cache_size,
+			cache_policy,
+			lazy_object_bitmap,
+		)
+		.await?;
 		Ok(Self { store })
 	}
 
@@ -214,6 +265,18 @@ impl<D: Dev, R: Resource> Nros<D, R> {
 		self.store.create().await
 	}
 
+	/// Create multiple objects at once.
+	///
+	/// This is more efficient than calling [`Self::create`] repeatedly.
+	pub async fn create_many(&self, n: usize) -> Result<Vec<Object<'_, D, R>>, Error<D>> {
+		self.store.create_many(n).await
+	}
+
+	/// Write all modified data to the devices and update the headers.
+	///
+	/// All records are fenced before the headers referencing them are written,
+	/// so an interrupted transaction never leaves a header pointing to records
+	/// that have not been flushed.
 	pub async fn finish_transaction<'a>(&'a self) -> Result<(), Error<D>> {
 		self.store.finish_transaction().await
 	}
@@ -222,7 +285,15 @@ impl<D: Dev, R: Resource> Nros<D, R> {
 		self.store.block_size()
 	}
 
+	/// The cipher used to encrypt the header and records.
+	pub fn cipher(&self) -> CipherType {
+		self.store.cipher_type()
+	}
+
 	/// Return an owned reference to an object.
+	///
+	/// Operations on the object fail with [`Error::InvalidObject`]
+	/// if the object is not allocated.
 	pub fn get(&self, id: u64) -> Object<'_, D, R> {
 		self.store.get(id)
 	}
@@ -231,27 +302,95 @@ impl<D: Dev, R: Resource> Nros<D, R> {
 	///
 	/// This may be useful to increase or decrease depending on total system memory usage.
 	///
-	/// # Panics
+	/// The size is at least one record and at most the hard limit minus two records.
+	/// Sizes outside this range are clamped.
 	///
-	/// If `global_max < write_max`.
-	pub fn resize_cache(&self, soft_limit: usize) -> Result<(), Error<D>> {
+	/// Returns the effective size.
+	pub fn resize_cache(&self, soft_limit: usize) -> Result<usize, Error<D>> {
 		self.store.resize_cache(soft_limit)
 	}
 
+	/// Check whether all blocks of a record are marked as allocated.
+	///
+	/// Unlike the checks performed on reads this is also available in release builds,
+	/// which makes it useful to detect dangling references.
+	pub fn validate_allocation(&self, record: RecordRef) -> bool {
+		self.store
+			.is_allocated(record.lba(), record.blocks().into())
+	}
+
 	/// Get statistics for current session.
 	pub fn statistics(&self) -> Statistics {
 		self.store.statistics()
 	}
 
+	/// Check the consistency of the object store.
+	///
+	/// If `repair` is `true`, objects that are free but still refer to records are cleared.
+	pub async fn verify(&self, repair: bool) -> Result<VerifyReport, Error<D>> {
+		self.store.verify(repair).await
+	}
+
+	/// Read every record in use to detect corruption.
+	///
+	/// Broken records are repaired if `allow_repair` was set when loading.
+	/// This finishes the current transaction.
+	pub async fn scrub(&self) -> Result<ScrubReport, Error<D>> {
+		self.store.scrub().await
+	}
+
+	/// Get the IDs of all objects in use.
+	pub fn used_objects(&self) -> Vec<u64> {
+		self.store.used_objects()
+	}
+
+	/// Get the amount of used and free objects.
+	///
+	/// Suitable for reporting inode counts.
+	///
+	/// If the object bitmap is loaded lazily, the amount of used objects is a lower bound.
+	/// See [`Statistics::used_objects`].
+	pub fn inode_stats(&self) -> (u64, u64) {
+		let used = self.store.statistics().used_objects;
+		(used, self.store.max_objects() - used)
+	}
+
 	/// Unmount the object store.
 	///
 	/// This performs one last transaction.
+	/// If the object store was loaded without `allow_repair` and nothing was modified,
+	/// nothing is written to the devices.
 	pub async fn unmount(self) -> Result<Vec<D>, Error<D>> {
 		let store = self.store.unmount().await?;
 		let devset = store.unmount().await?;
 		Ok(devset.into_devices())
 	}
 
+	/// Add a chain of devices as a new mirror.
+	///
+	/// The current transaction is finished and all data is copied to the new mirror
+	/// before returning.
+	///
+	/// This takes `&mut self` so no other task can allocate or write records during the copy.
+	/// Otherwise a block that is freed and reused could be overwritten with stale data,
+	/// and headers could refer to a mirror that doesn't have all data yet.
+	///
+	/// At most 4 mirrors are supported.
+	pub async fn add_mirror(&mut self, devices: Vec<D>) -> Result<(), Error<D>> {
+		self.store.add_mirror(devices).await
+	}
+
+	/// Discard all changes since the last transaction and return the devices.
+	///
+	/// Nothing is written to the devices, hence the on-disk state is that of the last
+	/// committed transaction.
+	///
+	/// **All changes made since the last transaction are lost.**
+	/// Use [`Nros::unmount`] to keep them.
+	pub fn abort(self) -> Vec<D> {
+		self.store.abort().abort().into_devices()
+	}
+
 	/// Get the key used to encrypt the header.
 	pub fn header_key(&self) -> [u8; 32] {
 		self.store.header_key()
@@ -278,8 +417,7 @@ impl<D: Dev, R: Resource> Nros<D, R> {
 
 	/// The unique identifier for this filesystem.
 	pub fn uid(&self) -> u128 {
-		todo!()
-		//self.store
+		self.store.uid()
 	}
 
 	/// Determine the record and offset from a byte offset.
@@ -293,6 +431,35 @@ impl<D: Dev, R: Resource> Nros<D, R> {
 		self.store.obj_max_len()
 	}
 
+	/// Set the default compression algorithm.
+	///
+	/// Only records written after this call are affected.
+	/// Existing records keep the algorithm they were compressed with.
+	///
+	/// The new default is persisted in the header at the end of the transaction.
+	pub fn set_default_compression(&self, compression: Compression) {
+		self.store.set_compression(compression)
+	}
+
+	/// Compare data with the stored data before writing it.
+	///
+	/// If the data is unchanged the record is not marked dirty,
+	/// which avoids packing and writing it out again.
+	///
+	/// This is disabled by default as writing entire records normally avoids a fetch,
+	/// whereas comparing requires the record to be read.
+	pub fn set_compare_before_write(&self, enable: bool) {
+		self.store.set_compare_on_write(enable)
+	}
+
+	/// Move records towards the start of the devices and finish the transaction.
+	///
+	/// Returns the minimum amount of blocks needed to hold all data,
+	/// which can be passed to [`Nros::set_block_count`] to shrink the filesystem.
+	pub async fn shrink_to_fit(&self) -> Result<u64, Error<D>> {
+		self.store.shrink_to_fit().await
+	}
+
 	pub async fn set_block_count(&self, blocks: u64) -> Result<(), Error<D>> {
 		self.store.set_block_count(blocks).await?;
 		Ok(())
@@ -303,6 +470,16 @@ impl<D: Dev, R: Resource> Nros<D, R> {
 	}
 }
 
+/// Ensure the hard limit of the cache isn't below the soft limit.
+///
+/// This is checked before any device is touched.
+fn check_cache_size<D: Dev>(soft: usize, hard: Option<usize>) -> Result<(), Error<D>> {
+	match hard {
+		Some(hard) if hard < soft => Err(Error::HardCacheSizeTooSmall { soft, hard }),
+		_ => Ok(()),
+	}
+}
+
 pub enum NewError<D: Dev> {
 	BlockTooSmall,
 	Dev(D::Error),
@@ -320,6 +497,69 @@ pub enum Error<D: Dev> {
 	Dev(D::Error),
 	RecordUnpack(data::record::UnpackError),
 	NotEnoughSpace,
+	/// A device has fewer blocks than recorded in its header,
+	/// e.g. because the image was truncated.
+	///
+	/// Block counts are in terms of the block size of the object store
+	/// and include the headers.
+	GeometryMismatch {
+		device: usize,
+		expected: u64,
+		actual: u64,
+	},
+	/// The object is one of the special objects or is not allocated.
+	InvalidObject {
+		id: u64,
+	},
+	/// The store uses a compression algorithm that is unknown
+	/// or not enabled in this build.
+	UnsupportedCompression {
+		compression: u8,
+	},
+	/// The store uses a cipher that is unknown
+	/// or not enabled in this build.
+	UnsupportedCipher {
+		cipher: u8,
+	},
+	/// The store uses a key derivation algorithm that is unknown.
+	UnsupportedKeyDerivation {
+		kdf: u8,
+	},
+	/// No key or password was provided for an encrypted store.
+	NoKey,
+	/// A password was provided but the store expects a key.
+	ExpectedKey,
+	/// The allocation log has a format version that is not supported by this build.
+	UnsupportedAllocationLog {
+		version: u8,
+	},
+	/// The block size, maximum record size or compression of a device does not match
+	/// that of the other devices,
+	/// e.g. because devices of different object stores were mixed up.
+	InconsistentConfig {
+		device: usize,
+	},
+	/// The block size of a device is larger than the block size of the object store.
+	BlockSizeTooLarge {
+		device: usize,
+		device_block_size: BlockSize,
+		block_size: BlockSize,
+	},
+	/// The hard limit of the cache is smaller than the soft limit.
+	HardCacheSizeTooSmall {
+		soft: usize,
+		hard: usize,
+	},
+	/// The maximum amount of mirrors has been reached.
+	TooManyMirrors,
+	/// A device is too small to hold the headers and a record of the maximum size.
+	///
+	/// `blocks` and `required` are in terms of the device's own block size.
+	DeviceTooSmall {
+		device: usize,
+		blocks: u64,
+		required: u64,
+	},
 }
 
 impl<D: Dev> fmt::Debug for NewError<D>
@@ -345,6 +585,56 @@ where
 			Self::Dev(e) => f.debug_tuple("Dev").field(&e).finish(),
 			Self::RecordUnpack(e) => f.debug_tuple("RecordUnpack").field(&e).finish(),
 			Self::NotEnoughSpace => f.debug_tuple("NotEnoughSpace").finish(),
+			Self::GeometryMismatch { device, expected, actual } => f
+				.debug_struct("GeometryMismatch")
+				.field("device", device)
+				.field("expected", expected)
+				.field("actual", actual)
+				.finish(),
+			Self::InvalidObject { id } => f
+				.debug_struct("InvalidObject")
+				.field("id", &format_args!("{:#x}", id))
+				.finish(),
+			Self::UnsupportedCompression { compression } => f
+				.debug_struct("UnsupportedCompression")
+				.field("compression", compression)
+				.finish(),
+			Self::UnsupportedCipher { cipher } => f
+				.debug_struct("UnsupportedCipher")
+				.field("cipher", cipher)
+				.finish(),
+			Self::UnsupportedKeyDerivation { kdf } => f
+				.debug_struct("UnsupportedKeyDerivation")
+				.field("kdf", kdf)
+				.finish(),
+			Self::NoKey => f.debug_tuple("NoKey").finish(),
+			Self::ExpectedKey => f.debug_tuple("ExpectedKey").finish(),
+			Self::UnsupportedAllocationLog { version } => f
+				.debug_struct("UnsupportedAllocationLog")
+				.field("version", version)
+				.finish(),
+			Self::InconsistentConfig { device } => f
+				.debug_struct("InconsistentConfig")
+				.field("device", device)
+				.finish(),
+			Self::BlockSizeTooLarge { device, device_block_size, block_size } => f
+				.debug_struct("BlockSizeTooLarge")
+				.field("device", device)
+				.field("device_block_size", device_block_size)
+				.field("block_size", block_size)
+				.finish(),
+			Self::HardCacheSizeTooSmall { soft, hard } => f
+				.debug_struct("HardCacheSizeTooSmall")
+				.field("soft", soft)
+				.field("hard", hard)
+				.finish(),
+			Self::TooManyMirrors => f.debug_tuple("TooManyMirrors").finish(),
+			Self::DeviceTooSmall { device, blocks, required } => f
+				.debug_struct("DeviceTooSmall")
+				.field("device", device)
+				.field("blocks", blocks)
+				.field("required", required)
+				.finish(),
 		}
 	}
 }
@@ -5,12 +5,17 @@ use {
 		resource::Buf,
 		util, Error, Resource,
 	},
-	core::mem,
+	core::{mem, ops::Range},
 	endian::u64le,
 	futures_util::stream::{FuturesUnordered, TryStreamExt},
 	rangemap::RangeSet,
 };
 
+/// The version of the format of the allocation log.
+///
+/// Logs with any other version are rejected on load.
+pub(super) const LOG_VERSION: u8 = 0;
+
 #[derive(Clone, Copy, Debug, Default)]
 #[repr(C)]
 struct Entry {
@@ -68,6 +73,14 @@ pub struct Statistics {
 	pub allocated_blocks: u64,
 	/// Total amount of blocks deallocated in this session.
 	pub deallocated_blocks: u64,
+	/// Amount of separate runs of free blocks.
+	///
+	/// Blocks freed in the current transaction are not counted.
+	pub free_region_count: u64,
+	/// Amount of blocks in the largest run of free blocks.
+	///
+	/// Records larger than this cannot be allocated.
+	pub largest_free_run: u64,
 }
 
 impl Default for Allocator {
@@ -226,6 +239,34 @@ impl Allocator {
 		self.statistics.used_blocks -= blocks;
 	}
 
+	/// Check whether all blocks in a range are allocated and not freed.
+	pub fn is_allocated(&self, start: u64, blocks: u64) -> bool {
+		if blocks == 0 {
+			return true;
+		}
+		let range = start..start.saturating_add(blocks);
+		self.alloc_map.gaps(&range).next().is_none() && !self.free_map.overlaps(&range)
+	}
+
+	/// Iterate over all allocated ranges of blocks.
+	pub fn allocated(&self) -> impl Iterator<Item = Range<u64>> + '_ {
+		self.alloc_map.iter().cloned()
+	}
+
+	/// The amount of runs of free blocks and the size of the largest run.
+	pub fn free_regions(&self, block_count: u64) -> (u64, u64) {
+		self.alloc_map
+			.gaps(&(0..block_count))
+			.fold((0, 0), |(n, m), r| (n + 1, m.max(r.end - r.start)))
+	}
+
+	/// The amount of allocated blocks and the end of the last allocated range.
+	pub fn extent(&self) -> (u64, u64) {
+		self.alloc_map
+			.iter()
+			.fold((0, 0), |(n, _), r| (n + r.end - r.start, r.end))
+	}
+
 	/// Ensure all blocks in a range are allocated.
 	///
 	/// Used to detect use-after-frees.
@@ -273,12 +314,12 @@ impl Allocator {
 		// TODO avoid writing the entire log every time.
 
 		// Deallocate all stack records of current log.
-		for record in self.stack.drain(..) {
+		// The old stack is kept around in case saving fails.
+		let old_stack = mem::take(&mut self.stack);
+		for record in old_stack.iter() {
 			let lba = record.lba();
 			let blocks = record.blocks();
 			alloc_map.remove(lba..lba + u64::try_from(blocks).unwrap());
-			#[cfg(feature = "debug-trace-alloc")]
-			self.debug_alloc_traces.remove(&lba);
 		}
 
 		let mut iter = alloc_map.iter().peekable();
@@ -331,9 +372,10 @@ impl Allocator {
 			b.shrink(usize::from(blocks) << store.block_size().to_raw());
 
 			// Store record
-			let lba = self
-				.alloc(blocks.into(), store.devices.block_count())
-				.ok_or(Error::NotEnoughSpace)?;
+			let Some(lba) = self.alloc(blocks.into(), store.devices.block_count()) else {
+				self.rollback_save(old_stack);
+				return Err(Error::NotEnoughSpace);
+			};
 			writes.push(store.devices.write(lba, b, Set256::set_all()));
 
 			prev = RecordRef::new(lba, blocks);
@@ -341,9 +383,17 @@ impl Allocator {
 		}
 
 		// Finish writes
-		writes.try_collect().await?;
+		if let Err(e) = writes.try_collect::<()>().await {
+			self.rollback_save(old_stack);
+			return Err(e);
+		}
 		store.devices.allocation_log_head.set(prev);
 
+		#[cfg(feature = "debug-trace-alloc")]
+		for record in old_stack {
+			self.debug_alloc_traces.remove(&record.lba());
+		}
+
 		// Update alloc_map with *implicitly* recorded allocations for stack records.
 		for record in self.stack.iter() {
 			let lba = record.lba();
@@ -360,4 +410,21 @@ impl Allocator {
 
 		Ok(free_map)
 	}
+
+	/// Undo the allocations made by a failed [`Allocator::save`] and restore the previous log.
+	///
+	/// The allocator is left as it was before saving,
+	/// so saving can be retried after freeing space.
+	fn rollback_save(&mut self, old_stack: Vec<RecordRef>) {
+		for record in mem::replace(&mut self.stack, old_stack) {
+			let (lba, blocks) = (record.lba(), u64::from(record.blocks()));
+			self.alloc_map.remove(lba..lba + blocks);
+			self.dirty_map.remove(lba..lba + blocks);
+			self.statistics.allocations -= 1;
+			self.statistics.allocated_blocks -= blocks;
+			self.statistics.used_blocks -= blocks;
+			#[cfg(feature = "debug-trace-alloc")]
+			self.debug_alloc_traces.remove(&lba);
+		}
+	}
 }
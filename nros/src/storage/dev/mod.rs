@@ -19,7 +19,7 @@ pub use {
 	mem::{MemAllocator, MemDev, MemDevError},
 };
 
-pub(crate) use set::{DevSet, Set256};
+pub(crate) use set::{DevSet, Set256, SetBuf};
 
 use {crate::BlockSize, core::future::Future};
 
@@ -41,6 +41,7 @@ pub trait Dev: 'static {
 	/// Task that represents a pending write operation.
 	///
 	/// This task may finish before the data has been flushed.
+	/// Use [`Dev::fence`] to ensure it is.
 	type WriteTask<'a>: Future<Output = Result<(), Self::Error>>
 	where
 		Self: 'a;
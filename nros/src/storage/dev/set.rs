@@ -3,11 +3,13 @@ use {
 	crate::{
 		data::{
 			cipher::Cipher,
-			fs_info::{Configuration, FsHeader, FsInfo, MirrorCount, MirrorIndex},
+			fs_info::{Configuration, DeriveKeyError, FsHeader, FsInfo, MirrorCount, MirrorIndex},
 			record::{Depth, RecordRef},
 		},
-		key_derivation, BlockSize, CipherType, Compression, Error, KeyDerivation, KeyDeriver,
-		KeyPassword, LoadConfig, MaxRecordSize, NewConfig, Resource,
+		key_derivation,
+		storage::allocator,
+		BlockSize, CipherType, Compression, Error, KeyDerivation, KeyDeriver, KeyPassword,
+		LoadConfig, MaxRecordSize, NewConfig, Resource,
 	},
 	alloc::sync::Arc,
 	core::{
@@ -39,7 +41,7 @@ pub(crate) struct DevSet<D: Dev, R: Resource> {
 	/// The maximum size of a single record.
 	max_record_size: MaxRecordSize,
 	/// The default compression to use for records.
-	compression: Compression,
+	compression: Cell<Compression>,
 	/// The total amount of blocks covered in each chain.
 	block_count: Cell<u64>,
 	/// The unique identifier of this filesystem.
@@ -84,7 +86,44 @@ impl<D: Dev, R: Resource> DevSet<D, R> {
 	/// If any chain is empty.
 	///
 	/// If any device has no blocks.
+	///
+	/// # Errors
+	///
+	/// [`Error::UnsupportedCipher`] if the cipher is not supported by this build.
+	///
+	/// [`Error::UnsupportedCompression`] if the compression is not supported by this build.
 	pub async fn new(config: NewConfig<'_, D, R>) -> Result<Self, Error<D>> {
+		if !config.cipher.is_supported() {
+			return Err(Error::UnsupportedCipher { cipher: config.cipher.to_raw() });
+		}
+		if !config.compression.is_supported() {
+			return Err(Error::UnsupportedCompression { compression: config.compression.to_raw() });
+		}
+
+		let max_record_blocks =
+			1 << config.max_record_size.to_raw() - config.block_size.to_raw();
+		for (i, dev) in config.mirrors.iter().flatten().enumerate() {
+			// Records must be addressable in terms of device blocks.
+			if dev.block_size() > config.block_size {
+				return Err(Error::BlockSizeTooLarge {
+					device: i,
+					device_block_size: dev.block_size(),
+					block_size: config.block_size,
+				});
+			}
+			// Require that devices can contain both headers and a full-sized record
+			// to simplify read & write operations as well as ensure some sanity in general.
+			let shift = config.block_size.to_raw() - dev.block_size().to_raw();
+			let required = (2 + max_record_blocks) << shift;
+			if dev.block_count() < required {
+				return Err(Error::DeviceTooSmall {
+					device: i,
+					blocks: dev.block_count(),
+					required,
+				});
+			}
+		}
+
 		let calc_blocks = |dev: &D| {
 			let shift = config.block_size.to_raw() - dev.block_size().to_raw();
 			(dev.block_count() >> shift) - 2
@@ -115,27 +154,7 @@ impl<D: Dev, R: Resource> DevSet<D, R> {
 			})
 			.collect::<Box<_>>();
 
-		let block_size = config.block_size.to_raw();
-		let max_record_size = config.max_record_size.to_raw();
-
 		assert!(devices.iter().all(|c| !c.is_empty()), "empty chain");
-		// Require that devices can contain a full-sized record to simplify
-		// read & write operations as well as ensure some sanity in general.
-		assert!(
-			devices
-				.iter()
-				.flat_map(|c| c.iter())
-				.all(|d| calc_blocks(&d.dev) >= (1 << (max_record_size - block_size))),
-			"device cannot contain maximum size record & headers"
-		);
-
-		assert!(
-			devices
-				.iter()
-				.flat_map(|c| c.iter())
-				.all(|d| d.dev.block_size().to_raw() <= block_size),
-			"todo: support larger block sizes"
-		);
 
 		// Assign block offsets to devices in chains and write headers.
 		for chain in devices.iter_mut() {
@@ -165,7 +184,7 @@ impl<D: Dev, R: Resource> DevSet<D, R> {
 			devices,
 			block_size: config.block_size,
 			max_record_size: config.max_record_size,
-			compression: config.compression,
+			compression: config.compression.into(),
 			block_count: block_count.into(),
 
 			magic: config.magic,
@@ -205,6 +224,8 @@ impl<D: Dev, R: Resource> DevSet<D, R> {
 		// For per-device info we need any valid header per device.
 
 		let mut header_key = None;
+		let mut unsupported_cipher = None;
+		let mut key_error = None;
 
 		// Collect only start headers, as end header location is unknown.
 		let headers = config
@@ -219,21 +240,25 @@ impl<D: Dev, R: Resource> DevSet<D, R> {
 				let info: &mut [u8; 512 - 64] = info.try_into().unwrap();
 				let header = FsHeader::from_raw((&*hdr).try_into().unwrap());
 
+				match header.cipher() {
+					Ok(c) if c.is_supported() => {}
+					c => {
+						unsupported_cipher = Some(c.map_or_else(|c| c, |c| c.to_raw()));
+						return None;
+					}
+				}
+
+				// Don't ask for the key again if it failed before.
+				if key_error.is_some() {
+					return None;
+				}
 				let key = match header_key.as_ref() {
 					Some(h) => *h,
-					None if matches!(header.cipher(), Ok(CipherType::NoneXxh3)) => [0; 32],
-					None => match header.key_derivation().unwrap() {
-						KeyDerivation::None => match (config.retrieve_key)(false).unwrap() {
-							KeyPassword::Key(k) => k,
-							KeyPassword::Password(_) => panic!("expected key"),
-						},
-						KeyDerivation::Argon2id { p, t, m } => {
-							match (config.retrieve_key)(true).unwrap() {
-								KeyPassword::Key(k) => k,
-								KeyPassword::Password(pwd) => {
-									key_derivation::argon2id(&pwd, &header.uid, m, t, p)
-								}
-							}
+					None => match header.derive_key(config.retrieve_key) {
+						Ok(k) => k,
+						Err(e) => {
+							key_error = Some(e);
+							return None;
 						}
 					},
 				};
@@ -244,13 +269,21 @@ impl<D: Dev, R: Resource> DevSet<D, R> {
 			.collect::<Vec<_>>()
 			.await;
 
+		if let Some(cipher) = unsupported_cipher {
+			return Err(Error::UnsupportedCipher { cipher });
+		}
+		if let Some(e) = key_error {
+			return Err(e.into_error());
+		}
+
 		let has_broken_headers = headers.iter().any(|b| b.is_none());
-		let mut header = None;
+		let mut header: Option<(FsHeader, FsInfo, &[u8])> = None;
 
 		// FIXME check UIDs
 
 		// Build mirrors
 		let mut mirrors = vec![vec![]; 4];
+		let mut nonce = 0;
 		for (i, buf) in headers.iter().enumerate() {
 			let buf = buf.as_ref().expect("no valid header");
 
@@ -261,12 +294,43 @@ impl<D: Dev, R: Resource> DevSet<D, R> {
 
 			let data = &buf.get()[256..512];
 
+			// Ensure the device is large enough to hold all blocks & both headers.
+			// Extra blocks, e.g. from an extended image, are ignored.
+			let dev = &config.devices[i];
+			if dev.block_size() > hdr.block_size() {
+				return Err(Error::BlockSizeTooLarge {
+					device: i,
+					device_block_size: dev.block_size(),
+					block_size: hdr.block_size(),
+				});
+			}
+			let shift = hdr.block_size().to_raw() - dev.block_size().to_raw();
+			let expected = u64::from(info.block_count) + 2;
+			let actual = dev.block_count() >> shift;
+			if actual < expected {
+				return Err(Error::GeometryMismatch { device: i, expected, actual });
+			}
+
+			// Ensure all devices belong to the same object store configuration.
+			if let Some((first_hdr, first_info, _)) = &header {
+				let (a, b) = (&first_info.configuration, &info.configuration);
+				if first_hdr.block_size() != hdr.block_size()
+					|| a.max_record_size() != b.max_record_size()
+					|| a.compression_algorithm() != b.compression_algorithm()
+				{
+					return Err(Error::InconsistentConfig { device: i });
+				}
+			}
+
 			// Add to mirror.
 			mirrors
 				.get_mut(usize::from(info.configuration.mirror_index().to_raw()))
 				.expect("todo: invalid mirror index")
 				.push((i, u64::from(info.lba_offset), u64::from(info.block_count)));
 
+			// Use the highest nonce in case some headers weren't saved.
+			nonce = nonce.max(u64::from(hdr.nonce));
+
 			header.get_or_insert((hdr, info, data));
 		}
 		let (header, info, data) = header.expect("no header");
@@ -318,12 +382,23 @@ impl<D: Dev, R: Resource> DevSet<D, R> {
 
 		let hc = &info.configuration;
 
+		let compression = match hc.compression_algorithm() {
+			Ok(c) if c.is_supported() => c,
+			Ok(c) => return Err(Error::UnsupportedCompression { compression: c.to_raw() }),
+			Err(compression) => return Err(Error::UnsupportedCompression { compression }),
+		};
+
+		let version = hc.allocation_log_version();
+		if version != allocator::LOG_VERSION {
+			return Err(Error::UnsupportedAllocationLog { version });
+		}
+
 		let s = Self {
 			devices,
 
 			block_size: header.block_size(),
 			max_record_size: hc.max_record_size(),
-			compression: hc.compression_algorithm().unwrap(),
+			compression: compression.into(),
 			uid: header.uid,
 			block_count: Cell::new(info.total_block_count.into()),
 
@@ -341,7 +416,7 @@ impl<D: Dev, R: Resource> DevSet<D, R> {
 			object_list_depth: hc.object_list_depth().into(),
 
 			magic: header.magic,
-			nonce: Cell::new(header.nonce.into()),
+			nonce: Cell::new(nonce.saturating_add(config.nonce_safety_margin)),
 
 			resource: config.resource.into(),
 
@@ -349,13 +424,74 @@ impl<D: Dev, R: Resource> DevSet<D, R> {
 		};
 
 		// If any headers are broken, fix them now.
-		if config.allow_repair && has_broken_headers {
+		if config.allow_repair && !config.scrub_only && has_broken_headers {
 			s.save_headers().await?;
 		}
 
 		Ok(s)
 	}
 
+	/// Check whether the key or password matches the filesystem header.
+	///
+	/// The start headers of all devices are tried,
+	/// so a stale header on one mirror does not cause a valid key to be rejected.
+	/// No other data is loaded.
+	///
+	/// Returns `false` if no key or password is provided.
+	pub async fn verify_key(
+		devices: &[D],
+		retrieve_key: &mut dyn FnMut(bool) -> Option<KeyPassword>,
+	) -> Result<bool, Error<D>> {
+		let mut last_err = None;
+		let mut checked_any = false;
+		// Only ask for the key again if the key derivation differs between headers.
+		let mut derived = None;
+		for d in devices {
+			let buf = match d.read(0, 1 << d.block_size().to_raw()).await {
+				Ok(buf) => buf,
+				Err(e) => {
+					last_err = Some(Error::Dev(e));
+					continue;
+				}
+			};
+			let (hdr, info) = buf.get()[..512].split_at(64);
+			let info: &[u8; 512 - 64] = info.try_into().unwrap();
+			let header = FsHeader::from_raw(hdr.try_into().unwrap());
+
+			match header.cipher() {
+				Ok(c) if c.is_supported() => {}
+				c => {
+					let cipher = c.map_or_else(|c| c, |c| c.to_raw());
+					last_err = Some(Error::UnsupportedCipher { cipher });
+					continue;
+				}
+			}
+
+			let params = (header.cipher, header.kdf, header.kdf_parameters);
+			let key = match derived {
+				Some((p, k)) if p == params => k,
+				_ => match header.derive_key(retrieve_key) {
+					Ok(k) => k,
+					Err(DeriveKeyError::NoKey) => return Ok(false),
+					Err(e) => {
+						last_err = Some(e.into_error());
+						continue;
+					}
+				},
+			};
+			derived = Some((params, key));
+			checked_any = true;
+
+			if header.verify_key(&key, info) {
+				return Ok(true);
+			}
+		}
+		match last_err {
+			Some(e) if !checked_any => Err(e),
+			_ => Ok(false),
+		}
+	}
+
 	/// Save headers to the start and end of all devices.
 	///
 	/// This performs a fence before writing to the start
@@ -644,6 +780,7 @@ impl<D: Dev, R: Resource> DevSet<D, R> {
 		conf.set_object_list_depth(self.object_list_depth.get());
 		conf.set_compression_level(0);
 		conf.set_compression_algorithm(self.compression());
+		conf.set_allocation_log_version(allocator::LOG_VERSION);
 
 		let info = FsInfo {
 			configuration: conf,
@@ -716,7 +853,12 @@ impl<D: Dev, R: Resource> DevSet<D, R> {
 
 	/// The default compression to use for records.
 	pub fn compression(&self) -> Compression {
-		self.compression
+		self.compression.get()
+	}
+
+	/// Set the default compression to use for records.
+	pub fn set_compression(&self, compression: Compression) {
+		self.compression.set(compression)
 	}
 
 	/// The total amount of blocks addressable by this device set.
@@ -724,6 +866,75 @@ impl<D: Dev, R: Resource> DevSet<D, R> {
 		self.block_count.get()
 	}
 
+	/// The amount of mirrors, i.e. chains.
+	pub fn mirror_count(&self) -> usize {
+		self.devices.len()
+	}
+
+	/// Add a chain of devices as a new mirror.
+	///
+	/// No data is copied and no headers are written.
+	///
+	/// # Panics
+	///
+	/// If `devices` is empty.
+	pub fn add_chain(&mut self, devices: Vec<D>) -> Result<(), Error<D>> {
+		assert!(!devices.is_empty(), "empty chain");
+		if self.devices.len() >= 4 {
+			return Err(Error::TooManyMirrors);
+		}
+		for (i, dev) in devices.iter().enumerate() {
+			if dev.block_size() > self.block_size {
+				return Err(Error::BlockSizeTooLarge {
+					device: i,
+					device_block_size: dev.block_size(),
+					block_size: self.block_size,
+				});
+			}
+		}
+
+		let calc_blocks = |dev: &D| {
+			let shift = self.block_size.to_raw() - dev.block_size().to_raw();
+			(dev.block_count() >> shift).saturating_sub(2)
+		};
+		let block_count = self.block_count.get();
+		if devices.iter().map(calc_blocks).sum::<u64>() < block_count {
+			return Err(Error::NotEnoughSpace);
+		}
+
+		// Don't exceed the block count of the other chains.
+		let mut block_offset = 0;
+		let chain = devices
+			.into_iter()
+			.map(|dev| {
+				let count = (block_count - block_offset).min(calc_blocks(&dev));
+				let node =
+					Node { block_count: count.into(), dev, block_offset: block_offset.into() };
+				block_offset += count;
+				node
+			})
+			.collect::<Box<_>>();
+
+		let mut devices = mem::take(&mut self.devices).into_vec();
+		devices.push(chain);
+		self.devices = devices.into();
+		Ok(())
+	}
+
+	/// Remove the most recently added chain.
+	///
+	/// This undoes [`Self::add_chain`] as long as no headers have been saved since.
+	///
+	/// # Panics
+	///
+	/// If there is only one chain.
+	pub fn remove_last_chain(&mut self) {
+		assert!(self.devices.len() > 1, "can't remove the only chain");
+		let mut devices = mem::take(&mut self.devices).into_vec();
+		devices.pop();
+		self.devices = devices.into();
+	}
+
 	/// Set the total amount of addressable blocks.
 	///
 	/// Care must be taken not to discard used blocks.
@@ -738,6 +949,11 @@ impl<D: Dev, R: Resource> DevSet<D, R> {
 		Ok(())
 	}
 
+	/// The type of cipher used to encrypt the header and records.
+	pub fn cipher_type(&self) -> CipherType {
+		self.cipher
+	}
+
 	/// Get a cipher instance.
 	pub fn cipher(&self) -> Cipher {
 		Cipher { key1: self.key1, key2: self.key2, ty: self.cipher }
@@ -755,6 +971,11 @@ impl<D: Dev, R: Resource> DevSet<D, R> {
 		self.header_key.get()
 	}
 
+	/// The unique identifier for this filesystem.
+	pub fn uid(&self) -> [u8; 16] {
+		self.uid
+	}
+
 	/// Set a new key derivation function.
 	///
 	/// This replaces the header key.
@@ -823,7 +1044,14 @@ impl<D: Dev> SetBuf<D> {
 	}
 }
 
-#[derive(Clone, Copy, Debug, Default)]
+impl<D: Dev> fmt::Debug for SetBuf<D> {
+	#[no_coverage]
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		format_args!("[{} bytes]", self.get().len()).fmt(f)
+	}
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
 pub struct Set256(u128, u128);
 
 impl Set256 {
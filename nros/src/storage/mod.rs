@@ -5,17 +5,24 @@ use {
 	crate::{
 		data::record::{self, Depth, RecordRef},
 		resource::Buf,
-		BlockSize, Compression, Error, KeyDeriver, MaxRecordSize, Resource,
+		BlockSize, CipherType, Compression, Error, KeyDeriver, MaxRecordSize, Resource,
 	},
+	alloc::collections::BTreeMap,
 	allocator::Allocator,
-	core::cell::{Cell, Ref, RefCell, RefMut},
-	dev::Set256,
+	core::{
+		cell::{Cell, Ref, RefCell, RefMut},
+		mem,
+	},
+	dev::{Set256, SetBuf},
 };
 
 pub(crate) use dev::DevSet;
 
 pub use dev::Dev;
 
+/// The maximum size of a single write when flushing repaired records.
+const MAX_REPAIR_WRITE_SIZE: usize = 1 << 20;
+
 /// A single store of records.
 ///
 /// It manages allocations on the devices and ensures records are mirrored properly.
@@ -43,9 +50,22 @@ pub(crate) struct Store<D: Dev, R: Resource> {
 	device_read_failures: Cell<u64>,
 	/// Amount of record unpack failures.
 	record_unpack_failures: Cell<u64>,
+	/// Amount of records that failed to be read from one or more chains
+	/// but were read successfully from another chain.
+	chain_mismatches: Cell<u64>,
+	/// Histogram of the size of written records in blocks.
+	record_blocks: Cell<[u64; 16]>,
 
 	/// Whether to repair broken records or not.
 	allow_repair: bool,
+	/// Whether to only detect broken records.
+	///
+	/// If set, no repairs are written even if `allow_repair` is set.
+	scrub_only: bool,
+	/// Records to write to the chains on which reading them failed, keyed by LBA.
+	///
+	/// Repairs are written at the end of the transaction so they can be batched.
+	repairs: RefCell<BTreeMap<u64, (SetBuf<D>, Set256)>>,
 	/// Whether any data has been modified.
 	///
 	/// Used to avoid redundant transactions.
@@ -53,7 +73,11 @@ pub(crate) struct Store<D: Dev, R: Resource> {
 }
 
 impl<D: Dev, R: Resource> Store<D, R> {
-	pub async fn new(devices: DevSet<D, R>, allow_repair: bool) -> Result<Self, Error<D>> {
+	pub async fn new(
+		devices: DevSet<D, R>,
+		allow_repair: bool,
+		scrub_only: bool,
+	) -> Result<Self, Error<D>> {
 		let mut slf = Self {
 			allocator: Default::default(),
 			devices,
@@ -64,8 +88,12 @@ impl<D: Dev, R: Resource> Store<D, R> {
 			unpacked_bytes_written: Default::default(),
 			device_read_failures: Default::default(),
 			record_unpack_failures: Default::default(),
+			chain_mismatches: Default::default(),
+			record_blocks: Default::default(),
 			allow_repair,
-			dirty: allow_repair.into(),
+			scrub_only,
+			repairs: Default::default(),
+			dirty: (allow_repair && !scrub_only).into(),
 		};
 		slf.allocator = Allocator::load(&slf).await?.into();
 		Ok(slf)
@@ -127,10 +155,13 @@ impl<D: Dev, R: Resource> Store<D, R> {
 				}
 			}
 		};
-		if self.allow_repair {
-			// Write to all devices where failure was encountered.
-			self.devices.write(lba, data, blacklist).await?;
-			self.dirty.update(|x| x | blacklist.any());
+		if blacklist.any() {
+			self.chain_mismatches.update(|x| x + 1);
+		}
+		if self.repairs_enabled() && blacklist.any() {
+			// Write to all devices where failure was encountered at the end of the transaction.
+			self.repairs.borrow_mut().insert(lba, (data, blacklist));
+			self.dirty.set(true);
 		}
 
 		self.packed_bytes_read
@@ -142,7 +173,11 @@ impl<D: Dev, R: Resource> Store<D, R> {
 	}
 
 	/// Write a record.
-	pub async fn write(&self, data: R::Buf) -> Result<(RecordRef, R::Buf), Error<D>> {
+	///
+	/// On failure the data is returned along with the error,
+	/// so the caller can retry once the cause has been resolved,
+	/// e.g. after freeing space.
+	pub async fn write(&self, data: R::Buf) -> Result<(RecordRef, R::Buf), (Error<D>, R::Buf)> {
 		assert!(
 			data.len() <= 1 << self.max_rec_size().to_raw(),
 			"data len is greater than max record size"
@@ -157,10 +192,14 @@ impl<D: Dev, R: Resource> Store<D, R> {
 		let block_count = self.devices.block_count();
 
 		// Allocate and pack record.
-		let mut buf = self
+		let mut buf = match self
 			.devices
 			.alloc(max_blks << self.block_size().to_raw())
-			.await?;
+			.await
+		{
+			Ok(buf) => buf,
+			Err(e) => return Err((e, data)),
+		};
 		let compression = self.compression();
 		let block_size = self.block_size();
 		let data_len = data.len();
@@ -192,22 +231,30 @@ impl<D: Dev, R: Resource> Store<D, R> {
 		buf.shrink(usize::from(blks) << self.block_size().to_raw());
 
 		// Allocate storage space.
-		let lba = self
-			.allocator
-			.borrow_mut()
-			.alloc(blks.into(), block_count)
-			.ok_or(Error::NotEnoughSpace)?;
+		let Some(lba) = self.allocator.borrow_mut().alloc(blks.into(), block_count) else {
+			return Err((Error::NotEnoughSpace, data));
+		};
+		self.cancel_repairs(lba, blks.into());
 
 		// Write buffer.
 		let rec = RecordRef::new(lba, blks);
-		self.devices
+		if let Err(e) = self
+			.devices
 			.write(lba.try_into().unwrap(), buf, Set256::set_all())
-			.await?;
+			.await
+		{
+			self.allocator.borrow_mut().free(lba, blks.into());
+			return Err((e, data));
+		}
 
 		self.packed_bytes_written
 			.update(|x| x + (u64::from(rec.blocks()) << self.block_size().to_raw()));
 		self.unpacked_bytes_written
 			.update(|x| x + u64::try_from(data_len).unwrap());
+		self.record_blocks.update(|mut h| {
+			h[usize::try_from(rec.blocks().ilog2()).unwrap()] += 1;
+			h
+		});
 
 		self.dirty.set(true);
 
@@ -215,6 +262,18 @@ impl<D: Dev, R: Resource> Store<D, R> {
 		Ok((rec, data))
 	}
 
+	/// Check whether all blocks in a range are allocated.
+	pub fn is_allocated(&self, lba: u64, blocks: u64) -> bool {
+		self.allocator.borrow().is_allocated(lba, blocks)
+	}
+
+	/// Get the amount of allocated blocks and the end of the last allocated range.
+	///
+	/// Blocks freed in the current transaction are still counted.
+	pub fn allocation_extent(&self) -> (u64, u64) {
+		self.allocator.borrow().extent()
+	}
+
 	/// Destroy a record.
 	pub fn destroy(&self, record_ref: RecordRef) {
 		trace!("destroy {:?}", record_ref);
@@ -229,10 +288,18 @@ impl<D: Dev, R: Resource> Store<D, R> {
 	///
 	/// This saves the allocation log, ensures all writes are committed and makes blocks
 	/// freed in this transaction available for the next transaction.
+	///
+	/// Freed blocks are discarded on all chains once the headers have been saved.
+	///
+	/// If nothing was modified since the last transaction, nothing is written or fenced.
 	pub async fn finish_transaction(&self) -> Result<(), Error<D>> {
-		if self.dirty.take() {
+		// Only clear the dirty flag once the allocator has been saved,
+		// so the transaction can be retried if it fails.
+		if self.dirty.get() {
+			self.flush_repairs().await?;
 			let discard_blocks = self.allocator.borrow_mut().save(self).await?;
 			self.devices.save_headers().await?;
+			self.dirty.set(false);
 			for r in discard_blocks {
 				self.devices.discard(r.start, r.end - r.start).await?;
 			}
@@ -240,6 +307,69 @@ impl<D: Dev, R: Resource> Store<D, R> {
 		Ok(())
 	}
 
+	/// Whether anything was modified since the last transaction.
+	pub fn is_dirty(&self) -> bool {
+		self.dirty.get()
+	}
+
+	/// Write all queued repairs.
+	///
+	/// Adjacent records that need to be written to the same chains are merged in a single write
+	/// of at most [`MAX_REPAIR_WRITE_SIZE`] bytes.
+	async fn flush_repairs(&self) -> Result<(), Error<D>> {
+		let repairs = mem::take(&mut *self.repairs.borrow_mut());
+		trace!("flush_repairs {}", repairs.len());
+		let blocks =
+			|buf: &SetBuf<D>| u64::try_from(buf.get().len() >> self.block_size().to_raw()).unwrap();
+
+		// Skip records that have been freed in the meantime.
+		let mut repairs = repairs
+			.into_iter()
+			.filter(|(lba, (buf, _))| self.is_allocated(*lba, blocks(buf)))
+			.peekable();
+
+		while let Some((lba, (buf, chains))) = repairs.next() {
+			let mut end = lba + blocks(&buf);
+			let mut len = buf.get().len();
+			let mut group = vec![buf];
+			while let Some((l, (b, c))) = repairs.peek() {
+				if *l != end || *c != chains || len + b.get().len() > MAX_REPAIR_WRITE_SIZE {
+					break;
+				}
+				end += blocks(b);
+				len += b.get().len();
+				let (_, (b, _)) = repairs.next().unwrap();
+				group.push(b);
+			}
+
+			let buf = if group.len() == 1 {
+				group.pop().unwrap()
+			} else {
+				let mut buf = self.devices.alloc(len).await?;
+				let mut offt = 0;
+				for g in group {
+					let l = g.get().len();
+					buf.get_mut()[offt..offt + l].copy_from_slice(g.get());
+					offt += l;
+				}
+				buf
+			};
+			self.devices.write(lba, buf, chains).await?;
+		}
+		Ok(())
+	}
+
+	/// Drop queued repairs that overlap with a range of blocks.
+	///
+	/// Necessary if the blocks got reallocated, as the repair would overwrite the new data.
+	fn cancel_repairs(&self, lba: u64, blocks: u64) {
+		let bs = self.block_size().to_raw();
+		self.repairs.borrow_mut().retain(|&l, (buf, _)| {
+			let end = l + u64::try_from(buf.get().len() >> bs).unwrap();
+			end <= lba || lba + blocks <= l
+		});
+	}
+
 	/// Unmount the object store.
 	///
 	/// The current transaction is finished before returning the [`DevSet`].
@@ -248,6 +378,75 @@ impl<D: Dev, R: Resource> Store<D, R> {
 		Ok(self.devices)
 	}
 
+	/// Add a chain of devices as a new mirror.
+	///
+	/// All allocated blocks are copied to the new chain,
+	/// after which the headers are saved on all chains.
+	///
+	/// If copying or saving the headers fails, the chain is removed again.
+	///
+	/// The current transaction must be finished first.
+	pub async fn add_mirror(&mut self, devices: Vec<D>) -> Result<(), Error<D>> {
+		trace!("add_mirror {}", devices.len());
+		self.devices.add_chain(devices)?;
+		let mut r = self.copy_to_last_chain().await;
+		// Only refer to the new chain in the headers once it has all data.
+		if r.is_ok() {
+			r = self.devices.save_headers().await;
+		}
+		if r.is_err() {
+			self.devices.remove_last_chain();
+		}
+		r
+	}
+
+	/// Copy all allocated blocks to the most recently added chain.
+	async fn copy_to_last_chain(&self) -> Result<(), Error<D>> {
+		let chain = u8::try_from(self.devices.mirror_count() - 1).unwrap();
+		let mut whitelist = Set256::default();
+		whitelist.set(chain, true);
+
+		let ranges = self.allocator.borrow().allocated().collect::<Vec<_>>();
+		let max_blocks =
+			u64::try_from(MAX_REPAIR_WRITE_SIZE >> self.block_size().to_raw()).unwrap();
+		for range in ranges {
+			let mut lba = range.start;
+			while lba < range.end {
+				let blocks = (range.end - lba).min(max_blocks);
+				let len = usize::try_from(blocks << self.block_size().to_raw()).unwrap();
+
+				// If one of the chains fail, try another until we run out.
+				let mut blacklist = whitelist;
+				let mut last_err = None;
+				let buf = loop {
+					match self.devices.read(lba, len, &blacklist).await {
+						Ok(Some((buf, _))) => break buf,
+						Ok(None) => return Err(last_err.expect("no chains were tried")),
+						Err((e, chain)) => {
+							blacklist.set(chain, true);
+							last_err = Some(e);
+						}
+					}
+				};
+				self.devices.write(lba, buf, whitelist).await?;
+				lba += blocks;
+			}
+		}
+		Ok(())
+	}
+
+	/// Whether broken records are repaired when read.
+	pub fn repairs_enabled(&self) -> bool {
+		self.allow_repair && !self.scrub_only
+	}
+
+	/// Return the [`DevSet`] without finishing the current transaction.
+	///
+	/// Any changes made since the last transaction are lost.
+	pub fn abort(self) -> DevSet<D, R> {
+		self.devices
+	}
+
 	pub fn block_size(&self) -> BlockSize {
 		self.devices.block_size()
 	}
@@ -260,6 +459,10 @@ impl<D: Dev, R: Resource> Store<D, R> {
 		self.devices.compression()
 	}
 
+	pub fn cipher_type(&self) -> CipherType {
+		self.devices.cipher_type()
+	}
+
 	/// Get the root record of the object list.
 	pub fn object_list_root(&self) -> RecordRef {
 		self.devices.object_list_root.get()
@@ -311,8 +514,16 @@ impl<D: Dev, R: Resource> Store<D, R> {
 			unpacked_bytes_written
 			device_read_failures
 			record_unpack_failures
+			chain_mismatches
+			record_blocks
 		};
 		s.allocation.total_blocks = self.devices.block_count();
+		let (regions, largest) = self
+			.allocator
+			.borrow()
+			.free_regions(self.devices.block_count());
+		s.allocation.free_region_count = regions;
+		s.allocation.largest_free_run = largest;
 		s
 	}
 
@@ -321,6 +532,11 @@ impl<D: Dev, R: Resource> Store<D, R> {
 		self.devices.header_key()
 	}
 
+	/// The unique identifier for this filesystem.
+	pub fn uid(&self) -> u128 {
+		u128::from_le_bytes(self.devices.uid())
+	}
+
 	/// Get a reference to filesystem data in the header
 	pub fn header_data(&self) -> Ref<'_, [u8; 256]> {
 		self.devices.data.borrow()
@@ -341,6 +557,14 @@ impl<D: Dev, R: Resource> Store<D, R> {
 		self.devices.set_key_deriver(kdf)
 	}
 
+	/// Set the default compression to use for new records.
+	///
+	/// The header is updated at the end of the transaction.
+	pub fn set_compression(&self, compression: Compression) {
+		self.devices.set_compression(compression);
+		self.dirty.set(true);
+	}
+
 	pub async fn set_block_count(&self, blocks: u64) -> Result<(), Error<D>> {
 		self.devices.set_block_count(blocks).unwrap();
 		self.dirty.set(true);
@@ -385,4 +609,63 @@ pub struct Statistics {
 	pub device_read_failures: u64,
 	/// Amount of record unpack failures.
 	pub record_unpack_failures: u64,
+	/// Amount of records that failed to be read from one or more chains
+	/// but were read successfully from another chain.
+	pub chain_mismatches: u64,
+	/// Histogram of the size of written records in blocks.
+	///
+	/// Bucket `i` counts the records that are between `2**i` and `2**(i + 1) - 1` blocks large.
+	pub record_blocks: [u64; 16],
+}
+
+impl Statistics {
+	/// The ratio between unpacked and packed bytes written.
+	///
+	/// Higher is better.
+	/// Returns `1.0` if nothing has been written yet.
+	pub fn compression_ratio(&self) -> f64 {
+		if self.packed_bytes_written == 0 {
+			return 1.0;
+		}
+		self.unpacked_bytes_written as f64 / self.packed_bytes_written as f64
+	}
+
+	/// The ratio between packed bytes and unpacked bytes transferred,
+	/// including both reads and writes.
+	///
+	/// This includes the overhead of record headers and padding to the block size.
+	/// Returns `0.0` if nothing has been read or written yet.
+	pub fn write_amplification(&self) -> f64 {
+		let unpacked = self.unpacked_bytes_read + self.unpacked_bytes_written;
+		if unpacked == 0 {
+			return 0.0;
+		}
+		(self.packed_bytes_read + self.packed_bytes_written) as f64 / unpacked as f64
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn compression_ratio() {
+		let mut s = Statistics::default();
+		assert_eq!(s.compression_ratio(), 1.0);
+		s.packed_bytes_written = 1024;
+		s.unpacked_bytes_written = 4096;
+		assert_eq!(s.compression_ratio(), 4.0);
+	}
+
+	#[test]
+	fn write_amplification() {
+		let mut s = Statistics::default();
+		assert_eq!(s.write_amplification(), 0.0);
+		s.packed_bytes_written = 3072;
+		s.unpacked_bytes_written = 1024;
+		assert_eq!(s.write_amplification(), 3.0);
+		s.packed_bytes_read = 1024;
+		s.unpacked_bytes_read = 1024;
+		assert_eq!(s.write_amplification(), 2.0);
+	}
 }
@@ -1,4 +1,7 @@
-use {super::*, crate::dev};
+use {
+	super::*,
+	crate::{data::fs_info::FsHeader, dev},
+};
 
 async fn load(devices: Vec<dev::MemDev>) -> Nros<dev::MemDev, StdResource> {
 	Nros::load(LoadConfig {
@@ -6,8 +9,14 @@ async fn load(devices: Vec<dev::MemDev>) -> Nros<dev::MemDev, StdResource> {
 		resource: StdResource::new(),
 		devices,
 		cache_size: 1 << 12,
+		soft_cache_size: None,
+		hard_cache_size: None,
+		cache_policy: Default::default(),
 		retrieve_key: &mut |_| unreachable!(),
 		allow_repair: true,
+		scrub_only: false,
+		nonce_safety_margin: 0,
+		lazy_object_bitmap: false,
 	})
 	.await
 	.unwrap()
@@ -78,3 +87,149 @@ fn write_remount_write_read() {
 	}))
 	.unwrap();
 }
+
+#[test]
+fn validate_allocation() {
+	let s = new(MaxRecordSize::K1);
+	run(&s, async {
+		let obj = s.create().await.unwrap();
+		obj.write(0, &[1; 1000]).await.unwrap();
+		s.finish_transaction().await.unwrap();
+
+		let total = s.statistics().storage.allocation.total_blocks;
+		let used = (0..total)
+			.filter(|&lba| s.validate_allocation(RecordRef::new(lba, 1)))
+			.collect::<Vec<_>>();
+		assert!(!used.is_empty());
+		assert!(!s.validate_allocation(RecordRef::new(0, total.try_into().unwrap())));
+
+		obj.dealloc().await.unwrap();
+		s.finish_transaction().await.unwrap();
+
+		let freed = used
+			.iter()
+			.filter(|&&lba| !s.validate_allocation(RecordRef::new(lba, 1)));
+		assert!(freed.count() > 0);
+
+		Ok(())
+	});
+}
+
+#[test]
+fn shrink_to_fit() {
+	let s = new_cap(MaxRecordSize::K1, 256, 1 << 16);
+	let (a, c) = block_on(s.run(async {
+		let total = s.statistics().storage.allocation.total_blocks;
+		let end = || {
+			(0..total)
+				.rev()
+				.find(|&lba| s.validate_allocation(RecordRef::new(lba, 1)))
+				.map_or(0, |lba| lba + 1)
+		};
+
+		let a = s.create().await.unwrap();
+		let b = s.create().await.unwrap();
+		let c = s.create().await.unwrap();
+		a.write(0, &[1; 16 << 10]).await.unwrap();
+		b.write(0, &[2; 64 << 10]).await.unwrap();
+		s.finish_transaction().await.unwrap();
+		c.write(0, &[3; 16 << 10]).await.unwrap();
+		s.finish_transaction().await.unwrap();
+
+		// Free the middle.
+		b.dealloc().await.unwrap();
+		s.finish_transaction().await.unwrap();
+		// a and b alone took up 16 + 64 blocks, so c is past that.
+		let old_end = end();
+		assert!(old_end > 16 + 64);
+
+		let new_end = s.shrink_to_fit().await.unwrap();
+		assert_eq!(new_end, end());
+		assert!(new_end < 16 + 64, "{} >= {}", new_end, old_end);
+
+		let buf = &mut [0; 16 << 10];
+		a.read(0, buf).await.unwrap();
+		assert!(buf.iter().all(|&x| x == 1));
+		c.read(0, buf).await.unwrap();
+		assert!(buf.iter().all(|&x| x == 3));
+		Ok::<_, Error<_>>((a.id(), c.id()))
+	}))
+	.unwrap();
+
+	let s = block_on(async {
+		let devs = s.unmount().await.unwrap();
+		load(devs).await
+	});
+	run(&s, async {
+		let buf = &mut [0; 16 << 10];
+		s.get(a).read(0, buf).await.unwrap();
+		assert!(buf.iter().all(|&x| x == 1));
+		s.get(c).read(0, buf).await.unwrap();
+		assert!(buf.iter().all(|&x| x == 3));
+		Ok(())
+	});
+}
+
+#[test]
+fn free_regions() {
+	let s = new_cap(MaxRecordSize::K1, 256, 1 << 16);
+	run(&s, async {
+		let objs = s.create_many(16).await.unwrap();
+		for obj in objs.iter() {
+			obj.write(0, &[1; 1024]).await.unwrap();
+		}
+		s.finish_transaction().await.unwrap();
+
+		for obj in objs.iter().skip(1).step_by(2) {
+			obj.dealloc().await.unwrap();
+		}
+		s.finish_transaction().await.unwrap();
+
+		// Count the runs of free blocks manually.
+		let stat = s.statistics().storage.allocation;
+		let (mut regions, mut largest, mut run) = (0, 0, 0);
+		for lba in 0..stat.total_blocks {
+			if s.validate_allocation(RecordRef::new(lba, 1)) {
+				run = 0;
+			} else {
+				regions += u64::from(run == 0);
+				run += 1;
+				largest = largest.max(run);
+			}
+		}
+		assert_eq!(stat.free_region_count, regions);
+		assert_eq!(stat.largest_free_run, largest);
+		assert!(regions > 1, "free space is not fragmented");
+		assert!(largest < stat.total_blocks - stat.used_blocks);
+
+		Ok(())
+	});
+}
+
+#[test]
+fn unsupported_log_version() {
+	let s = new(MaxRecordSize::K1);
+	let dev = block_on(s.unmount()).unwrap().pop().unwrap();
+
+	// Bump the version of the allocation log and fix up the hash of the header.
+	block_on(async {
+		let data = dev.read(0, 1 << 10).await.unwrap();
+		let mut buf = dev.allocator().alloc(1 << 10).await.unwrap();
+		buf.get_mut().copy_from_slice(data.get());
+		let (hdr, info) = buf.get_mut()[..512].split_at_mut(64);
+		let info: &mut [u8; 512 - 64] = info.try_into().unwrap();
+		let mut header = FsHeader::from_raw((&*hdr).try_into().unwrap());
+		// The version is stored in the 4th byte of the configuration.
+		info[3] += 1;
+		header.encrypt(&[0; 32], info);
+		hdr.copy_from_slice(header.as_ref());
+		dev.write(0, buf).await.unwrap();
+	});
+
+	let Err(e) = load_dev(dev) else { panic!("loaded unsupported allocation log") };
+	assert!(
+		matches!(e, Error::UnsupportedAllocationLog { version: 1 }),
+		"{:?}",
+		e
+	);
+}
@@ -351,3 +351,214 @@ fn create_many() {
 	});
 	block_on(s.unmount()).unwrap();
 }
+
+#[test]
+fn is_fully_cached() {
+	let s = new_cap(MaxRecordSize::K1, 32, 1 << 16);
+	let id = block_on(s.run(async {
+		let obj = s.create().await.unwrap();
+		obj.write(0, &[1; 3000]).await.unwrap();
+		assert!(obj.is_fully_cached(0..3000));
+		s.finish_transaction().await.unwrap();
+		Ok::<_, Error<_>>(obj.id())
+	}))
+	.unwrap();
+
+	let s = block_on(async {
+		let devices = s.unmount().await.unwrap();
+		Nros::load(LoadConfig {
+			magic: *b"TEST",
+			resource: StdResource::new(),
+			devices,
+			cache_size: 1 << 16,
+			soft_cache_size: None,
+			hard_cache_size: None,
+			cache_policy: Default::default(),
+			retrieve_key: &mut |_| unreachable!(),
+			allow_repair: true,
+			scrub_only: false,
+			nonce_safety_margin: 0,
+			lazy_object_bitmap: false,
+		})
+		.await
+		.unwrap()
+	});
+
+	run(&s, async {
+		let obj = s.get(id);
+		assert!(!obj.is_fully_cached(0..3000));
+		assert!(obj.is_fully_cached(10..10));
+
+		obj.read(1024, &mut [0; 1024]).await.unwrap();
+		assert!(obj.is_fully_cached(1024..2048));
+		assert!(!obj.is_fully_cached(1024..2049));
+		assert!(!obj.is_fully_cached(0..3000));
+		Ok(())
+	});
+}
+
+#[test]
+fn compare_before_write() {
+	let s = new_cap(MaxRecordSize::K1, 64, 1 << 16);
+	s.set_compare_before_write(true);
+	run(&s, async {
+		let data = (0..3 << 10)
+			.map(|i| (i % 251) as u8 + 1)
+			.collect::<Vec<_>>();
+		let obj = s.create().await.unwrap();
+		obj.write(512, &data).await.unwrap();
+		s.finish_transaction().await.unwrap();
+
+		// Partial first & last record and one full record in the middle.
+		let written = s.statistics().storage.packed_bytes_written;
+		obj.write(512, &data).await.unwrap();
+		obj.write(1000, &data[488..2000]).await.unwrap();
+		s.finish_transaction().await.unwrap();
+		assert_eq!(s.statistics().storage.packed_bytes_written, written);
+
+		// Changed data must still be written.
+		obj.write(2000, &[0]).await.unwrap();
+		s.finish_transaction().await.unwrap();
+		assert!(s.statistics().storage.packed_bytes_written > written);
+
+		let buf = &mut vec![0; 3 << 10];
+		obj.read(512, buf).await.unwrap();
+		assert_eq!(&buf[..1488], &data[..1488]);
+		assert_eq!(buf[1488], 0);
+		assert_eq!(&buf[1489..], &data[1489..]);
+		Ok(())
+	});
+}
+
+/// Use a small working set twice, scan a larger object once
+/// and count how many records of the working set are still cached.
+fn scan_residency(policy: CachePolicy) -> usize {
+	let s = new_cap(MaxRecordSize::K1, 128, 1 << 16);
+	let (hot, scan) = block_on(s.run(async {
+		let hot = s.create().await.unwrap();
+		hot.write(1 << 10, &[1; 4 << 10]).await.unwrap();
+		let scan = s.create().await.unwrap();
+		scan.write(1 << 10, &[2; 16 << 10]).await.unwrap();
+		Ok::<_, Error<_>>((hot.id(), scan.id()))
+	}))
+	.unwrap();
+
+	let s = block_on(async {
+		let devices = s.unmount().await.unwrap();
+		Nros::load(LoadConfig {
+			magic: *b"TEST",
+			resource: StdResource::new(),
+			devices,
+			cache_size: 16 << 10,
+			soft_cache_size: None,
+			hard_cache_size: None,
+			cache_policy: policy,
+			retrieve_key: &mut |_| unreachable!(),
+			allow_repair: true,
+			scrub_only: false,
+			nonce_safety_margin: 0,
+			lazy_object_bitmap: false,
+		})
+		.await
+		.unwrap()
+	});
+
+	run(&s, async {
+		let hot = s.get(hot);
+		for _ in 0..2 {
+			for i in 1..5 {
+				hot.read(i << 10, &mut [0; 1 << 10]).await.unwrap();
+			}
+		}
+		Ok(())
+	});
+	run(&s, async {
+		let scan = s.get(scan);
+		for i in 1..17 {
+			scan.read(i << 10, &mut [0; 1 << 10]).await.unwrap();
+		}
+		Ok(())
+	});
+
+	let hot = s.get(hot);
+	(1..5)
+		.filter(|&i| hot.is_fully_cached(i << 10..(i + 1) << 10))
+		.count()
+}
+
+#[test]
+fn scan_resistant_policy() {
+	let lru = scan_residency(CachePolicy::Lru);
+	let clock = scan_residency(CachePolicy::Clock);
+	assert!(lru < clock, "lru: {}, clock: {}", lru, clock);
+	assert_eq!(clock, 4);
+}
+
+#[test]
+fn flush_range() {
+	let s = new_cap(MaxRecordSize::K1, 32, 1 << 16);
+	run(&s, async {
+		let obj = s.create().await.unwrap();
+		obj.write(0, &[1; 1024]).await.unwrap();
+		obj.write(8192, &[2; 1024]).await.unwrap();
+		assert_eq!(obj.dirty_offsets(), [0, 8192]);
+
+		obj.flush_range(100..200).await.unwrap();
+		assert_eq!(obj.dirty_offsets(), [8192]);
+
+		// Must not iterate over every addressable record.
+		obj.flush_range(4096..u64::MAX).await.unwrap();
+		assert!(obj.dirty_offsets().is_empty());
+
+		let buf = &mut [0; 1024];
+		obj.read(0, buf).await.unwrap();
+		assert_eq!(buf, &[1; 1024]);
+		Ok(())
+	});
+}
+
+#[test]
+fn resize_cache_clamp() {
+	let s = new_cap(MaxRecordSize::K1, 1 << 10, 4096);
+	run(&s, async {
+		assert_eq!(s.resize_cache(0).unwrap(), 1 << 10);
+		let data = (0..1 << 14).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+		let obj = s.create().await.unwrap();
+		obj.write(0, &data).await.unwrap();
+		let buf = &mut vec![0; data.len()];
+		obj.read(0, buf).await.unwrap();
+		assert_eq!(buf, &data);
+
+		// 4 records soft, 4 * 2 + 2 hard.
+		assert_eq!(s.resize_cache(usize::MAX).unwrap(), 8 << 10);
+		Ok(())
+	});
+}
+
+#[test]
+fn object_statistics() {
+	let s = new_cap(MaxRecordSize::K1, 1 << 10, 1 << 16);
+	run(&s, async {
+		let obj = s.create().await.unwrap();
+		let stat = obj.statistics().await.unwrap();
+		assert_eq!(stat.max_len, s.obj_max_len());
+		assert_eq!(stat.depth, None);
+		assert_eq!(stat.resident_records, 0);
+		assert_eq!(stat.dirty_records, 0);
+
+		// One leaf in the first, second and third tree.
+		for offset in [0, 5 << 10, 200 << 10] {
+			obj.write(offset, &[1]).await.unwrap();
+		}
+		let stat = obj.statistics().await.unwrap();
+		assert_eq!(stat.resident_records, 3);
+		assert_eq!(stat.dirty_records, 3);
+
+		s.finish_transaction().await.unwrap();
+		let stat = obj.statistics().await.unwrap();
+		assert_eq!(stat.depth, Some(2));
+		assert_eq!(stat.resident_records, 3);
+		assert_eq!(stat.dirty_records, 0);
+		Ok(())
+	});
+}
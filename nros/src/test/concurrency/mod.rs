@@ -13,6 +13,8 @@ async fn new(delay: usize) -> Nros<SlowDev, StdResource> {
 		max_record_size: MaxRecordSize::K1,
 		compression: Compression::None,
 		cache_size: 4096,
+		soft_cache_size: None,
+		hard_cache_size: None,
 		key_deriver: KeyDeriver::None { key: &[0; 32] },
 		cipher: CipherType::NoneXxh3,
 		magic: *b"TEST",
@@ -168,3 +170,48 @@ fn write() {
 		Ok(())
 	});
 }
+
+/// Records that are being fetched cannot be evicted and count as referenced.
+#[test]
+fn referenced_usage() {
+	let s = block_on(new(5));
+	let mut id = 0;
+	run(&s, async {
+		let obj = s.create().await.unwrap();
+		obj.write(0, &[1; 1024]).await.unwrap();
+		id = obj.id();
+		Ok(())
+	});
+	let devices = block_on(s.unmount()).unwrap();
+
+	let s = block_on(Nros::load(LoadConfig {
+		magic: *b"TEST",
+		resource: StdResource::new(),
+		devices,
+		cache_size: 4096,
+		soft_cache_size: None,
+		hard_cache_size: None,
+		cache_policy: Default::default(),
+		retrieve_key: &mut |_| unreachable!(),
+		allow_repair: true,
+		scrub_only: false,
+		nonce_safety_margin: 0,
+		lazy_object_bitmap: false,
+	}))
+	.unwrap();
+	run(&s, async {
+		let obj = s.get(id);
+		let buf = &mut [0; 1024];
+		let mut peak = 0;
+		{
+			let mut read = core::pin::pin!(obj.read(0, buf));
+			while let Poll::Pending = futures_util::poll!(read.as_mut()) {
+				peak = peak.max(s.statistics().referenced_usage);
+			}
+		}
+		assert!(peak >= 1 << 10, "no referenced records while reading");
+		assert_eq!(s.statistics().referenced_usage, 0);
+		assert_eq!(*buf, [1; 1024]);
+		Ok(())
+	});
+}
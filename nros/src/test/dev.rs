@@ -1,9 +1,15 @@
 use {
-	super::{block_on, Set256},
+	super::{block_on, new_cfg, Set256},
 	crate::{
 		dev,
-		storage::dev::{Allocator, Buf, Dev, DevSet, MemDev},
-		BlockSize, CipherType, Compression, KeyDeriver, MaxRecordSize, NewConfig, StdResource,
+		storage::dev::{Allocator, Buf, Dev, DevSet, MemDev, MemDevError},
+		BlockSize, CipherType, Compression, KeyDeriver, LoadConfig, MaxRecordSize, NewConfig, Nros,
+		StdResource,
+	},
+	alloc::rc::Rc,
+	core::{
+		cell::{Cell, RefCell},
+		future,
 	},
 };
 
@@ -18,6 +24,8 @@ async fn new(mirrors: Vec<Vec<dev::MemDev>>) -> DevSet<dev::MemDev, StdResource>
 		cipher: CipherType::NoneXxh3,
 		key_deriver: KeyDeriver::None { key: &[0; 32] },
 		cache_size: 1 << 14,
+		soft_cache_size: None,
+		hard_cache_size: None,
 	})
 	.await
 	.unwrap()
@@ -96,3 +104,203 @@ fn devset_1_read_write() {
 		assert_eq!(&rd1k.get()[512..], [0x22; 512 * 4]);
 	})
 }
+
+/// Operations recorded by a [`SpyDev`].
+///
+/// This is shared so it can be inspected while the device is owned by an object store.
+#[derive(Debug, Default)]
+pub(super) struct Spy {
+	/// Amount of writes.
+	pub writes: Cell<usize>,
+	/// Amount of headers written, i.e. writes to the first or last block.
+	pub headers: Cell<usize>,
+	/// Amount of fences.
+	pub fences: Cell<usize>,
+	/// All discarded ranges.
+	pub discarded: RefCell<Vec<(u64, u64)>>,
+	/// Whether data has been written since the last fence.
+	unfenced: Cell<bool>,
+}
+
+/// [`MemDev`] which records all operations.
+#[derive(Debug)]
+pub(super) struct SpyDev {
+	pub dev: MemDev,
+	pub spy: Rc<Spy>,
+	/// Whether writes fail.
+	pub broken: bool,
+	/// Whether to panic if a header is written before all data has been fenced.
+	pub check_fence: bool,
+}
+
+impl SpyDev {
+	pub fn new(blocks: usize) -> Self {
+		Self {
+			dev: MemDev::new(blocks, BlockSize::K1),
+			spy: Default::default(),
+			broken: false,
+			check_fence: false,
+		}
+	}
+}
+
+impl Dev for SpyDev {
+	type Allocator = <MemDev as Dev>::Allocator;
+	type Error = <MemDev as Dev>::Error;
+	type ReadTask<'a> = <MemDev as Dev>::ReadTask<'a>;
+	type WriteTask<'a> = <MemDev as Dev>::WriteTask<'a>;
+	type FenceTask<'a> = <MemDev as Dev>::FenceTask<'a>;
+	type DiscardTask<'a> = <MemDev as Dev>::DiscardTask<'a>;
+
+	fn block_count(&self) -> u64 {
+		self.dev.block_count()
+	}
+
+	fn block_size(&self) -> BlockSize {
+		self.dev.block_size()
+	}
+
+	fn read(&self, lba: u64, len: usize) -> Self::ReadTask<'_> {
+		self.dev.read(lba, len)
+	}
+
+	fn write(&self, lba: u64, buf: <Self::Allocator as Allocator>::Buf) -> Self::WriteTask<'_> {
+		self.spy.writes.update(|x| x + 1);
+		// Headers are at the start and end of the device.
+		if lba == 0 || lba == self.dev.block_count() - 1 {
+			assert!(
+				!self.check_fence || !self.spy.unfenced.get(),
+				"header written before data was fenced"
+			);
+			self.spy.headers.update(|x| x + 1);
+		} else {
+			self.spy.unfenced.set(true);
+		}
+		if self.broken {
+			return future::ready(Err(MemDevError::OutOfRange));
+		}
+		self.dev.write(lba, buf)
+	}
+
+	fn discard(&self, lba: u64, blocks: u64) -> Self::DiscardTask<'_> {
+		self.spy.discarded.borrow_mut().push((lba, blocks));
+		self.dev.discard(lba, blocks)
+	}
+
+	fn fence(&self) -> Self::FenceTask<'_> {
+		self.spy.fences.update(|x| x + 1);
+		self.spy.unfenced.set(false);
+		self.dev.fence()
+	}
+
+	fn allocator(&self) -> &Self::Allocator {
+		self.dev.allocator()
+	}
+}
+
+/// Ensure records are fenced before the headers referencing them are written.
+#[test]
+fn fence_before_header() {
+	let dev = SpyDev { check_fence: true, ..SpyDev::new(256) };
+	let spy = dev.spy.clone();
+	let s = block_on(Nros::new(new_cfg(vec![vec![dev]]))).unwrap();
+
+	block_on(s.run(async {
+		let obj = s.create().await?;
+		obj.write(0, &[1; 1 << 13]).await?;
+		s.finish_transaction().await?;
+		obj.write(1 << 13, &[2; 1 << 13]).await?;
+		s.finish_transaction().await
+	}))
+	.unwrap();
+
+	block_on(s.unmount()).unwrap();
+	// 2 headers per transaction.
+	assert!(
+		spy.headers.get() >= 4,
+		"{} headers written",
+		spy.headers.get()
+	);
+}
+
+/// Ensure records freed in a transaction are discarded when it finishes.
+#[test]
+fn discard_on_dealloc() {
+	let devs = [SpyDev::new(256), SpyDev::new(256)];
+	let spies = devs.each_ref().map(|d| d.spy.clone());
+	let [a, b] = devs;
+	let s = block_on(Nros::new(new_cfg(vec![vec![a], vec![b]]))).unwrap();
+
+	let discarded = || {
+		spies
+			.iter()
+			.map(|s| s.discarded.borrow().iter().map(|&(_, b)| b).sum::<u64>())
+			.collect::<Vec<_>>()
+	};
+
+	let obj = block_on(s.run(async {
+		let obj = s.create().await?;
+		obj.write(0, &[1; 1 << 13]).await?;
+		s.finish_transaction().await?;
+		Ok::<_, crate::Error<_>>(obj.id())
+	}))
+	.unwrap();
+	let before = discarded();
+
+	block_on(s.run(async {
+		s.get(obj).dealloc().await?;
+		s.finish_transaction().await
+	}))
+	.unwrap();
+	let after = discarded();
+
+	// 8 records of 1 block each, discarded on both mirrors.
+	for (b, a) in before.iter().zip(&after) {
+		assert!(a - b >= 8, "{} blocks discarded", a - b);
+	}
+}
+
+/// Ensure nothing is written or fenced when unmounting a read-only object store.
+#[test]
+fn unmount_read_only() {
+	let dev = SpyDev::new(256);
+	let spy = dev.spy.clone();
+	let s = block_on(Nros::new(new_cfg(vec![vec![dev]]))).unwrap();
+	let id = block_on(s.run(async {
+		let obj = s.create().await?;
+		obj.write(0, &[1; 1 << 13]).await?;
+		Ok::<_, crate::Error<_>>(obj.id())
+	}))
+	.unwrap();
+	let devs = block_on(s.unmount()).unwrap();
+	assert!(spy.writes.get() > 0 && spy.fences.get() > 0);
+	spy.writes.set(0);
+	spy.fences.set(0);
+
+	let s = block_on(Nros::load(LoadConfig {
+		magic: *b"TEST",
+		resource: StdResource::new(),
+		devices: devs,
+		cache_size: 1 << 14,
+		soft_cache_size: None,
+		hard_cache_size: None,
+		cache_policy: Default::default(),
+		allow_repair: false,
+		scrub_only: false,
+		nonce_safety_margin: 0,
+		lazy_object_bitmap: false,
+		retrieve_key: &mut |_| unreachable!(),
+	}))
+	.unwrap();
+	block_on(s.run(async {
+		let buf = &mut [0; 1 << 13];
+		s.get(id).read(0, buf).await?;
+		assert_eq!(buf, &[1; 1 << 13]);
+		s.finish_transaction().await
+	}))
+	.unwrap();
+	block_on(s.unmount()).unwrap();
+
+	assert_eq!(spy.writes.get(), 0, "read-only object store was written to");
+	assert_eq!(spy.fences.get(), 0, "read-only object store was fenced");
+}
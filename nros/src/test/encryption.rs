@@ -1,5 +1,6 @@
 use {
 	super::*,
+	crate::data::cipher::Cipher,
 	core::num::{NonZeroU32, NonZeroU8},
 };
 
@@ -15,6 +16,8 @@ fn write_read_nocache() {
 		max_record_size: MaxRecordSize::K1,
 		compression: Compression::None,
 		cache_size: 0,
+		soft_cache_size: None,
+		hard_cache_size: None,
 	}))
 	.unwrap();
 
@@ -31,6 +34,82 @@ fn write_read_nocache() {
 	});
 }
 
+#[test]
+fn write_read_remount_aes256gcm() {
+	let s = block_on(Nros::new(NewConfig {
+		resource: StdResource::new(),
+		mirrors: vec![vec![MemDev::new(1 << 12, BlockSize::K1)]],
+		magic: *b"CRYP",
+		key_deriver: KeyDeriver::None { key: &[0xcc; 32] },
+		cipher: CipherType::Aes256Gcm,
+		block_size: BlockSize::K1,
+		max_record_size: MaxRecordSize::K1,
+		compression: Compression::None,
+		cache_size: 0,
+		soft_cache_size: None,
+		hard_cache_size: None,
+	}))
+	.unwrap();
+
+	let id = block_on(s.run(async {
+		let obj = s.create().await?;
+		obj.write(0, &[1; 1 << 13]).await?;
+		Ok::<_, Error<_>>(obj.id())
+	}))
+	.unwrap();
+	let devices = block_on(s.unmount()).unwrap();
+
+	// The header can't be decrypted with the wrong key.
+	let verify = |key| {
+		block_on(Nros::<_, StdResource>::verify_key(&devices, &mut |_| {
+			Some(KeyPassword::Key(key))
+		}))
+		.unwrap()
+	};
+	assert!(!verify([0; 32]));
+	assert!(verify([0xcc; 32]));
+
+	let s = block_on(Nros::load(LoadConfig {
+		resource: StdResource::new(),
+		devices,
+		magic: *b"CRYP",
+		cache_size: 0,
+		soft_cache_size: None,
+		hard_cache_size: None,
+		cache_policy: Default::default(),
+		allow_repair: false,
+		scrub_only: false,
+		nonce_safety_margin: 0,
+		lazy_object_bitmap: false,
+		retrieve_key: &mut |_| Some(KeyPassword::Key([0xcc; 32])),
+	}))
+	.unwrap();
+
+	run(&s, async {
+		let buf = &mut [2; 1 << 13];
+		s.get(id).read(0, buf).await.unwrap();
+		assert_eq!(*buf, [1; 1 << 13]);
+		Ok(())
+	});
+}
+
+/// AES-256-GCM must use the entire nonce.
+///
+/// The header nonce ends with the UID of the filesystem.
+#[test]
+fn aes256gcm_full_nonce() {
+	let cipher = Cipher { key1: [0xcc; 32], key2: [0xdd; 32], ty: CipherType::Aes256Gcm };
+	let (a, b) = (&mut [1; 64], &mut [1; 64]);
+	let mut nonce = [0; 24];
+	let hash_a = cipher.encrypt(&nonce, a);
+	nonce[23] = 1;
+	let hash_b = cipher.encrypt(&nonce, b);
+	assert_ne!(a, b);
+	assert_ne!(hash_a, hash_b);
+	cipher.decrypt(&nonce, &hash_b, b).unwrap();
+	assert_eq!(*b, [1; 64]);
+}
+
 #[test]
 fn mkfs_argon2id() {
 	let s = block_on(Nros::new(NewConfig {
@@ -48,6 +127,8 @@ fn mkfs_argon2id() {
 		max_record_size: MaxRecordSize::K1,
 		compression: Compression::None,
 		cache_size: 0,
+		soft_cache_size: None,
+		hard_cache_size: None,
 	}))
 	.unwrap();
 	let devices = block_on(s.unmount()).unwrap();
@@ -56,8 +137,149 @@ fn mkfs_argon2id() {
 		devices,
 		magic: *b"CRYP",
 		cache_size: 0,
+		soft_cache_size: None,
+		hard_cache_size: None,
+		cache_policy: Default::default(),
 		allow_repair: false,
+		scrub_only: false,
+		nonce_safety_margin: 0,
+		lazy_object_bitmap: false,
 		retrieve_key: &mut |_| Some(KeyPassword::Password((*b"test").into())),
 	}))
 	.unwrap();
 }
+
+#[test]
+fn verify_key_argon2id() {
+	let s = block_on(Nros::new(NewConfig {
+		resource: StdResource::new(),
+		mirrors: vec![vec![MemDev::new(1 << 12, BlockSize::K1)]],
+		magic: *b"CRYP",
+		key_deriver: KeyDeriver::Argon2id {
+			password: b"test",
+			m: NonZeroU32::new(1024).unwrap(),
+			p: NonZeroU8::MIN,
+			t: NonZeroU32::MIN,
+		},
+		cipher: CipherType::XChaCha12Poly1305,
+		block_size: BlockSize::K1,
+		max_record_size: MaxRecordSize::K1,
+		compression: Compression::None,
+		cache_size: 0,
+		soft_cache_size: None,
+		hard_cache_size: None,
+	}))
+	.unwrap();
+	let key = s.header_key();
+	let devices = block_on(s.unmount()).unwrap();
+
+	let verify = |kp: KeyPassword| {
+		let mut kp = Some(kp);
+		block_on(Nros::<_, StdResource>::verify_key(&devices, &mut |_| {
+			kp.take()
+		}))
+		.unwrap()
+	};
+	assert!(verify(KeyPassword::Password((*b"test").into())));
+	assert!(!verify(KeyPassword::Password((*b"tset").into())));
+	assert!(verify(KeyPassword::Key(key)));
+	assert!(!verify(KeyPassword::Key([0; 32])));
+}
+
+/// A broken header on one mirror must not cause a valid key to be rejected.
+#[test]
+fn verify_key_broken_mirror() {
+	let s = block_on(Nros::new(NewConfig {
+		resource: StdResource::new(),
+		mirrors: vec![
+			vec![MemDev::new(1 << 12, BlockSize::K1)],
+			vec![MemDev::new(1 << 12, BlockSize::K1)],
+		],
+		magic: *b"CRYP",
+		key_deriver: KeyDeriver::None { key: &[0xcc; 32] },
+		cipher: CipherType::XChaCha12Poly1305,
+		block_size: BlockSize::K1,
+		max_record_size: MaxRecordSize::K1,
+		compression: Compression::None,
+		cache_size: 0,
+		soft_cache_size: None,
+		hard_cache_size: None,
+	}))
+	.unwrap();
+	let devices = block_on(s.unmount()).unwrap();
+
+	// Corrupt the start header of the first mirror.
+	block_on(async {
+		let data = devices[0].read(0, 1 << 10).await.unwrap();
+		let mut buf = devices[0].allocator().alloc(1 << 10).await.unwrap();
+		buf.get_mut().copy_from_slice(data.get());
+		buf.get_mut()[100] ^= 1;
+		devices[0].write(0, buf).await.unwrap();
+	});
+
+	let verify = |key| {
+		block_on(Nros::<_, StdResource>::verify_key(&devices, &mut |_| {
+			Some(KeyPassword::Key(key))
+		}))
+		.unwrap()
+	};
+	assert!(verify([0xcc; 32]));
+	assert!(!verify([0; 32]));
+}
+
+/// Loading must fail gracefully if no key or a password instead of a key is provided.
+#[test]
+fn load_key_errors() {
+	let s = block_on(Nros::new(NewConfig {
+		resource: StdResource::new(),
+		mirrors: vec![vec![MemDev::new(1 << 12, BlockSize::K1)]],
+		magic: *b"CRYP",
+		key_deriver: KeyDeriver::None { key: &[0xcc; 32] },
+		cipher: CipherType::XChaCha12Poly1305,
+		block_size: BlockSize::K1,
+		max_record_size: MaxRecordSize::K1,
+		compression: Compression::None,
+		cache_size: 0,
+		soft_cache_size: None,
+		hard_cache_size: None,
+	}))
+	.unwrap();
+	let dev = block_on(s.unmount()).unwrap().pop().unwrap();
+
+	let load = |kp: Option<KeyPassword>| {
+		let mut kp = Some(kp);
+		block_on(Nros::<_, StdResource>::load(LoadConfig {
+			resource: StdResource::new(),
+			devices: vec![resize_dev(&dev, 1 << 12)],
+			magic: *b"CRYP",
+			cache_size: 0,
+			soft_cache_size: None,
+			hard_cache_size: None,
+			cache_policy: Default::default(),
+			allow_repair: false,
+			scrub_only: false,
+			nonce_safety_margin: 0,
+			lazy_object_bitmap: false,
+			retrieve_key: &mut |_| kp.take().flatten(),
+		}))
+	};
+	assert!(matches!(load(None), Err(Error::NoKey)));
+	assert!(matches!(
+		load(Some(KeyPassword::Password((*b"test").into()))),
+		Err(Error::ExpectedKey)
+	));
+	assert!(load(Some(KeyPassword::Key([0xcc; 32]))).is_ok());
+}
+
+#[test]
+fn benchmark_argon2id() {
+	let kdf = |t| KeyDeriver::Argon2id {
+		password: b"test",
+		m: NonZeroU32::new(256).unwrap(),
+		p: NonZeroU8::MIN,
+		t: NonZeroU32::new(t).unwrap(),
+	};
+	let short = kdf(1).benchmark();
+	let long = kdf(32).benchmark();
+	assert!(long > short, "t=1: {:?}, t=32: {:?}", short, long);
+}
@@ -4,4 +4,5 @@
 
 use super::*;
 
+pub mod record;
 pub mod v1;
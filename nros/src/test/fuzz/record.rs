@@ -0,0 +1,91 @@
+use {
+	super::*,
+	crate::data::{cipher::Cipher, record},
+	arbitrary::{Arbitrary, Unstructured},
+};
+
+/// Pack & unpack a single record.
+#[derive(Debug)]
+pub struct Test {
+	data: Vec<u8>,
+	compression: Compression,
+	block_size: BlockSize,
+	max_record_size: MaxRecordSize,
+}
+
+impl<'a> Arbitrary<'a> for Test {
+	fn arbitrary(u: &mut Unstructured<'a>) -> arbitrary::Result<Self> {
+		let compression = Compression::from_raw(u.int_in_range(0..=1)?).unwrap();
+		let block_size = BlockSize::from_raw(u.int_in_range(9..=19)?).unwrap();
+		let max_record_size = MaxRecordSize::from_raw(u.int_in_range(9..=24)?).unwrap();
+		let mut data = u.arbitrary::<Vec<u8>>()?;
+		data.truncate(1 << max_record_size.to_raw());
+		Ok(Self { data, compression, block_size, max_record_size })
+	}
+}
+
+impl Test {
+	pub fn new(
+		data: impl Into<Vec<u8>>,
+		compression: Compression,
+		block_size: BlockSize,
+		max_record_size: MaxRecordSize,
+	) -> Self {
+		Self { data: data.into(), compression, block_size, max_record_size }
+	}
+
+	pub fn run(self) {
+		// Empty records are never packed.
+		if self.data.is_empty() {
+			return;
+		}
+		assert!(
+			self.data.len() <= 1 << self.max_record_size.to_raw(),
+			"data exceeds record size"
+		);
+
+		let cipher = || Cipher { key1: [0xcc; 32], key2: [0xdd; 32], ty: CipherType::NoneXxh3 };
+
+		// Same buffer size calculation as Store::write
+		let len = self.compression.max_output_size(self.data.len());
+		let max_blks = self
+			.block_size
+			.min_blocks(usize::from(record::HEADER_LEN) + len);
+		let buf = &mut vec![0; max_blks << self.block_size.to_raw()];
+
+		let blks = record::pack(
+			&self.data,
+			buf,
+			self.compression,
+			self.block_size,
+			cipher(),
+			&[0; 24],
+		);
+		let blks = usize::from(blks);
+
+		// Compression should never make records larger than an uncompressed record.
+		let max_rec_blks = self
+			.block_size
+			.min_blocks(usize::from(record::HEADER_LEN) + (1 << self.max_record_size.to_raw()));
+		assert!(blks > 0, "record is empty");
+		assert!(blks <= max_blks, "record exceeds buffer");
+		assert!(blks <= max_rec_blks, "record exceeds max record size");
+
+		let buf = &mut buf[..blks << self.block_size.to_raw()];
+		let res = record::unpack(buf, Vec::new(), self.max_record_size, cipher()).unwrap();
+		assert!(res == self.data, "data mismatch");
+	}
+}
+
+#[test]
+fn lz4_incompressible_max_record_size() {
+	let data = (0..1u32 << 10)
+		.map(|i| (i.wrapping_mul(0x9e3779b9) >> 24) as u8)
+		.collect::<Vec<_>>();
+	Test::new(data, Compression::Lz4, BlockSize::B512, MaxRecordSize::K1).run()
+}
+
+#[test]
+fn lz4_single_byte_large_block() {
+	Test::new([1], Compression::Lz4, BlockSize::K64, MaxRecordSize::B512).run()
+}
@@ -221,7 +221,13 @@ impl Test {
 					resource: StdResource::new(),
 					devices,
 					cache_size: new_cache_size,
+					soft_cache_size: None,
+					hard_cache_size: None,
+					cache_policy: Default::default(),
 					allow_repair: true,
+					scrub_only: false,
+					nonce_safety_margin: 0,
+					lazy_object_bitmap: false,
 					magic: *b"TEST",
 					retrieve_key: &mut |_| unreachable!(),
 				})
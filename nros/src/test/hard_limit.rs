@@ -54,3 +54,55 @@ fn create_many() {
 	});
 	block_on(s.unmount()).unwrap();
 }
+
+fn new_limits(soft: usize, hard: usize) -> Nros<MemDev, StdResource> {
+	let s = Nros::new(NewConfig {
+		magic: *b"TEST",
+		resource: StdResource::new(),
+		mirrors: vec![vec![MemDev::new(1 << 16, BlockSize::K1)]],
+		block_size: BlockSize::K1,
+		max_record_size: MaxRecordSize::K1,
+		compression: Compression::None,
+		cipher: CipherType::NoneXxh3,
+		key_deriver: KeyDeriver::None { key: &[0; 32] },
+		cache_size: 0,
+		soft_cache_size: Some(soft),
+		hard_cache_size: Some(hard),
+	});
+	block_on(s).unwrap()
+}
+
+#[test]
+fn hard_limit_one_record() {
+	let s = new_limits(0, (1 << 10) + 1);
+	run(&s, async {
+		let data = (0..1 << 14).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+		let obj = s.create().await.unwrap();
+		obj.write(0x1234_5678, &data).await.unwrap();
+		for _ in 0..256 {
+			s.create().await.unwrap();
+		}
+		s.finish_transaction().await.unwrap();
+
+		let buf = &mut vec![0; data.len()];
+		obj.read(0x1234_5678, buf).await.unwrap();
+		assert_eq!(buf, &data);
+		Ok(())
+	});
+	block_on(s.unmount()).unwrap();
+}
+
+#[test]
+fn hard_limit_below_soft_limit() {
+	let s = Nros::new(NewConfig {
+		soft_cache_size: Some(1 << 12),
+		hard_cache_size: Some(1 << 11),
+		..new_cfg(vec![vec![MemDev::new(1 << 16, BlockSize::K1)]])
+	});
+	match block_on(s) {
+		Err(Error::HardCacheSizeTooSmall { soft, hard }) => {
+			assert_eq!((soft, hard), (1 << 12, 1 << 11))
+		}
+		r => panic!("{:?}", r.map(|_| ())),
+	}
+}
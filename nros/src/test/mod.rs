@@ -4,17 +4,19 @@ mod allocator;
 mod cache;
 mod concurrency;
 mod dev;
+#[cfg(feature = "encryption")]
 mod encryption;
 mod hard_limit;
 mod raid;
 mod record;
 
 use {
-	crate::{dev::*, *},
+	crate::{dev::*, resource::Buf as _, *},
 	core::{
 		future::Future,
 		task::{Context, Poll},
 	},
+	futures_util::{StreamExt, TryStreamExt},
 };
 
 fn block_on<R>(fut: impl Future<Output = R>) -> R {
@@ -30,23 +32,31 @@ fn block_on<R>(fut: impl Future<Output = R>) -> R {
 	panic!("probably deadlocked?");
 }
 
-fn new_cap(
-	max_record_size: MaxRecordSize,
-	blocks: usize,
-	cache_size: usize,
-) -> Nros<MemDev, StdResource> {
-	let s = MemDev::new(blocks, BlockSize::K1);
-	let s = Nros::new(NewConfig {
+/// Configuration for a new object store with 1KiB blocks and records,
+/// without compression or encryption.
+fn new_cfg<D: Dev>(mirrors: Vec<Vec<D>>) -> NewConfig<'static, D, StdResource> {
+	NewConfig {
 		magic: *b"TEST",
 		resource: StdResource::new(),
-		mirrors: vec![vec![s]],
+		mirrors,
 		block_size: BlockSize::K1,
-		max_record_size,
+		max_record_size: MaxRecordSize::K1,
 		compression: Compression::None,
 		cipher: CipherType::NoneXxh3,
 		key_deriver: KeyDeriver::None { key: &[0; 32] },
-		cache_size,
-	});
+		cache_size: 1 << 14,
+		soft_cache_size: None,
+		hard_cache_size: None,
+	}
+}
+
+fn new_cap(
+	max_record_size: MaxRecordSize,
+	blocks: usize,
+	cache_size: usize,
+) -> Nros<MemDev, StdResource> {
+	let s = MemDev::new(blocks, BlockSize::K1);
+	let s = Nros::new(NewConfig { max_record_size, cache_size, ..new_cfg(vec![vec![s]]) });
 	block_on(s).unwrap()
 }
 
@@ -92,6 +102,354 @@ fn create_destroy_twice() {
 	});
 }
 
+#[test]
+fn uid_remount() {
+	let s = new(MaxRecordSize::K1);
+	// StdResource doesn't generate random data in tests, so the UID itself isn't checked.
+	let uid = s.uid();
+	let devices = block_on(s.unmount()).unwrap();
+	let s = block_on(Nros::load(LoadConfig {
+		magic: *b"TEST",
+		resource: StdResource::new(),
+		devices,
+		cache_size: 1 << 12,
+		soft_cache_size: None,
+		hard_cache_size: None,
+		cache_policy: Default::default(),
+		retrieve_key: &mut |_| unreachable!(),
+		allow_repair: true,
+		scrub_only: false,
+		nonce_safety_margin: 0,
+		lazy_object_bitmap: false,
+	}))
+	.unwrap();
+	assert_eq!(s.uid(), uid);
+}
+
+/// The header nonce must be advanced by the safety margin when loading.
+#[test]
+fn nonce_safety_margin() {
+	let header_nonce = |dev: &MemDev| {
+		let buf = block_on(dev.read(0, 1 << 10)).unwrap();
+		let hdr = crate::data::fs_info::FsHeader::from_raw(buf.get()[..64].try_into().unwrap());
+		u64::from(hdr.nonce)
+	};
+
+	let s = new(MaxRecordSize::K1);
+	let devices = block_on(s.unmount()).unwrap();
+	let nonce = header_nonce(&devices[0]);
+
+	let s = block_on(Nros::load(LoadConfig {
+		magic: *b"TEST",
+		resource: StdResource::new(),
+		devices,
+		cache_size: 1 << 12,
+		soft_cache_size: None,
+		hard_cache_size: None,
+		cache_policy: Default::default(),
+		retrieve_key: &mut |_| unreachable!(),
+		allow_repair: true,
+		scrub_only: false,
+		nonce_safety_margin: 1000,
+		lazy_object_bitmap: false,
+	}))
+	.unwrap();
+	run(&s, async {
+		let obj = s.create().await.unwrap();
+		obj.write(0, b"Hello").await.unwrap();
+		s.finish_transaction().await.unwrap();
+		Ok(())
+	});
+	let devices = block_on(s.unmount()).unwrap();
+
+	// Nonces between the old nonce and the margin must never be used.
+	let new_nonce = header_nonce(&devices[0]);
+	assert!(
+		new_nonce > nonce + 1000,
+		"{} <= {} + 1000",
+		new_nonce,
+		nonce
+	);
+}
+
+#[test]
+fn invalid_object() {
+	let s = new(MaxRecordSize::K1);
+	run(&s, async {
+		fn is_invalid<T>(r: Result<T, Error<MemDev>>) -> bool {
+			matches!(r, Err(Error::InvalidObject { .. }))
+		}
+
+		// Object list & object bitmap and IDs past those
+		for id in [1 << 58, 1 << 58 | 1, u64::MAX] {
+			let obj = s.get(id);
+			assert!(is_invalid(obj.read(0, &mut [0]).await));
+			assert!(is_invalid(obj.write(0, &[1]).await));
+			assert!(is_invalid(obj.write_zeros(0, 1).await));
+			assert!(is_invalid(obj.dealloc().await));
+			assert!(is_invalid(obj.dealloc_blocks().await));
+		}
+
+		// Unallocated
+		let obj = s.create().await.unwrap();
+		obj.write(0, &[1]).await.unwrap();
+		obj.dealloc().await.unwrap();
+		assert!(is_invalid(obj.read(0, &mut [0]).await));
+		assert!(is_invalid(s.get(obj.id() + 1).write(0, &[1]).await));
+
+		Ok(())
+	});
+}
+
+#[test]
+fn dealloc_freed_blocks() {
+	let s = new_cap(MaxRecordSize::K1, 64, 4096);
+	run(&s, async {
+		let obj = s.create().await.unwrap();
+		obj.write(0, &[0xcc; 4 << 10]).await.unwrap();
+		s.finish_transaction().await.unwrap();
+
+		let destroyed = s.statistics().storage.packed_bytes_destroyed;
+		// 4 leaves of 2 blocks each (data + header) and 1 parent record in the second tree.
+		assert_eq!(obj.dealloc_blocks().await.unwrap(), 4 * 2 + 1);
+		s.finish_transaction().await.unwrap();
+
+		// Records of the object list & bitmap may have been replaced too.
+		let destroyed = s.statistics().storage.packed_bytes_destroyed - destroyed;
+		assert!(
+			destroyed >= (4 * 2 + 1) << 10,
+			"{} bytes destroyed",
+			destroyed
+		);
+
+		Ok(())
+	});
+}
+
+#[test]
+fn create_many() {
+	let dev = dev::SpyDev::new(32);
+	let spy = dev.spy.clone();
+	let s = block_on(Nros::new(NewConfig {
+		cache_size: 4096,
+		..new_cfg(vec![vec![dev]])
+	}))
+	.unwrap();
+	run(&s, async {
+		s.finish_transaction().await.unwrap();
+		spy.writes.set(0);
+		spy.headers.set(0);
+
+		let objs = s.create_many(1000).await.unwrap();
+		let mut ids = objs.iter().map(|o| o.id()).collect::<Vec<_>>();
+		ids.dedup();
+		assert_eq!(ids.len(), 1000);
+		assert_eq!(s.statistics().used_objects, 1000);
+
+		s.finish_transaction().await.unwrap();
+		// The bitmap of 1000 objects fits in a single record,
+		// so only it and the allocation log should have been written besides the headers.
+		let records = spy.writes.get() - spy.headers.get();
+		assert!(records <= 2, "{} records written", records);
+
+		Ok(())
+	});
+}
+
+/// Streaming a range must give the same data as reading it.
+#[test]
+fn read_stream() {
+	let s = new_cap(MaxRecordSize::K1, 64, 1 << 16);
+	run(&s, async {
+		let data = (0..3000).map(|i| (i % 251) as u8 + 1).collect::<Vec<_>>();
+		let obj = s.create().await.unwrap();
+		obj.write(100, &data).await.unwrap();
+
+		let chunks = obj
+			.read_stream(50, 4000)
+			.try_collect::<Vec<_>>()
+			.await
+			.unwrap();
+		let lens = chunks.iter().map(|c| c.len()).collect::<Vec<_>>();
+		assert_eq!(lens, [1024 - 50, 1024, 1024, 4050 - 3072]);
+
+		let buf = &mut vec![0; 4000];
+		obj.read(50, buf).await.unwrap();
+		let stream = chunks
+			.iter()
+			.flat_map(|c| c.get())
+			.copied()
+			.collect::<Vec<_>>();
+		assert_eq!(&stream, buf);
+
+		// The range is clamped to the end of the object.
+		let max = s.obj_max_len();
+		let chunks = obj
+			.read_stream(max - 10, 100)
+			.try_collect::<Vec<_>>()
+			.await
+			.unwrap();
+		assert_eq!(chunks.len(), 1);
+		assert_eq!(chunks[0].get(), &[0; 10]);
+		assert_eq!(obj.read_stream(max, 100).count().await, 0);
+
+		Ok(())
+	});
+}
+
+#[test]
+fn truncate_to_fit() {
+	let s = new(MaxRecordSize::K1);
+	run(&s, async {
+		let obj = s.create().await.unwrap();
+		assert_eq!(obj.truncate_to_fit().await.unwrap(), 0);
+
+		obj.write(0, b"Hello").await.unwrap();
+		obj.write(1 << 20, b"world").await.unwrap();
+		assert_eq!(obj.truncate_to_fit().await.unwrap(), (1 << 20) + 5);
+		s.finish_transaction().await.unwrap();
+		assert_eq!(obj.truncate_to_fit().await.unwrap(), (1 << 20) + 5);
+
+		obj.write_zeros(1 << 20, 5).await.unwrap();
+		assert_eq!(obj.truncate_to_fit().await.unwrap(), 5);
+		s.finish_transaction().await.unwrap();
+		assert_eq!(obj.truncate_to_fit().await.unwrap(), 5);
+		Ok(())
+	});
+}
+
+/// The object list must shrink when the objects that required the extra levels are freed.
+#[test]
+fn shrink_object_list() {
+	let s = new(MaxRecordSize::K1);
+	run(&s, async {
+		// 32 objects fit in a single record of the object list.
+		let objs = s.create_many(64).await.unwrap();
+		s.finish_transaction().await.unwrap();
+		assert_eq!(s.statistics().object_list_depth, 1);
+
+		for obj in &objs[1..] {
+			obj.dealloc().await.unwrap();
+		}
+		s.finish_transaction().await.unwrap();
+		assert_eq!(s.statistics().object_list_depth, 0);
+
+		// The object list must still be usable.
+		objs[0].write(0, b"Hello").await.unwrap();
+		let obj = s.create().await.unwrap();
+		obj.write(0, b"world").await.unwrap();
+		s.finish_transaction().await.unwrap();
+		let buf = &mut [0; 5];
+		objs[0].read(0, buf).await.unwrap();
+		assert_eq!(buf, b"Hello");
+		obj.read(0, buf).await.unwrap();
+		assert_eq!(buf, b"world");
+
+		Ok(())
+	});
+}
+
+#[test]
+fn record_blocks_histogram() {
+	let s = new_cap(MaxRecordSize::K4, 256, 1 << 16);
+	run(&s, async {
+		let before = s.statistics().storage.record_blocks;
+		// 1 block, 2 blocks and 4 records of 5 blocks (including header)
+		s.create().await?.write(0, &[1; 100]).await?;
+		s.create().await?.write(0, &[1; 1500]).await?;
+		s.create().await?.write(0, &[1; 16 << 10]).await?;
+		s.finish_transaction().await?;
+
+		let h = s.statistics().storage.record_blocks;
+		let d = |i: usize| h[i] - before[i];
+		assert!(d(0) >= 1, "{:?}", h);
+		assert!(d(1) >= 1, "{:?}", h);
+		assert!(d(2) >= 4, "{:?}", h);
+		assert!(h[3..].iter().all(|&n| n == 0), "{:?}", h);
+		Ok(())
+	});
+}
+
+#[test]
+fn set_default_compression() {
+	let s = new_cap(MaxRecordSize::K4, 256, 1 << 16);
+	let (a, b) = block_on(s.run(async {
+		let a = s.create().await.unwrap();
+		a.write(0, &[1; 32 << 10]).await.unwrap();
+		s.finish_transaction().await.unwrap();
+		let written = s.statistics().storage.packed_bytes_written;
+		assert!(written >= 32 << 10, "{} bytes written", written);
+
+		s.set_default_compression(Compression::Lz4);
+		let b = s.create().await.unwrap();
+		b.write(0, &[2; 32 << 10]).await.unwrap();
+		s.finish_transaction().await.unwrap();
+		let written = s.statistics().storage.packed_bytes_written - written;
+		assert!(written < 16 << 10, "{} bytes written", written);
+
+		Ok::<_, Error<_>>((a.id(), b.id()))
+	}))
+	.unwrap();
+
+	let s = block_on(async {
+		let devices = s.unmount().await.unwrap();
+		Nros::load(LoadConfig {
+			magic: *b"TEST",
+			resource: StdResource::new(),
+			devices,
+			cache_size: 1 << 16,
+			soft_cache_size: None,
+			hard_cache_size: None,
+			cache_policy: Default::default(),
+			retrieve_key: &mut |_| unreachable!(),
+			allow_repair: true,
+			scrub_only: false,
+			nonce_safety_margin: 0,
+			lazy_object_bitmap: false,
+		})
+		.await
+		.unwrap()
+	});
+	assert_eq!(s.statistics().storage.compression, Compression::Lz4);
+
+	run(&s, async {
+		let buf = &mut [0; 32 << 10];
+		s.get(a).read(0, buf).await.unwrap();
+		assert!(buf.iter().all(|&x| x == 1));
+		s.get(b).read(0, buf).await.unwrap();
+		assert!(buf.iter().all(|&x| x == 2));
+		Ok(())
+	});
+}
+
+#[test]
+fn layout() {
+	let s = new(MaxRecordSize::K1);
+	run(&s, async {
+		let obj = s.create().await.unwrap();
+		let l = obj.layout().await.unwrap();
+		assert_eq!(l.depth, None);
+
+		// 1K records with 128 references per parent record.
+		let mut start = 0;
+		for (r, size) in l.roots.iter().zip([1 << 10, 1 << 17, 1 << 24, 1 << 31]) {
+			assert_eq!(*r, start..start + size);
+			start += size;
+		}
+		assert_eq!(l.max_len, start);
+		assert_eq!(l.root(0), Some(0));
+		assert_eq!(l.root((1 << 10) - 1), Some(0));
+		assert_eq!(l.root(1 << 10), Some(1));
+		assert_eq!(l.root(start), None);
+
+		obj.write(2 << 10, &[1]).await.unwrap();
+		s.finish_transaction().await.unwrap();
+		assert_eq!(obj.layout().await.unwrap().depth, Some(1));
+
+		Ok(())
+	});
+}
+
 #[test]
 fn write() {
 	let s = new(MaxRecordSize::K1);
@@ -103,6 +461,38 @@ fn write() {
 	});
 }
 
+#[test]
+fn try_write_record_plus_one() {
+	let s = new_cap(MaxRecordSize::K1, 64, 1 << 16);
+	run(&s, async {
+		let data = (0..(1 << 10) + 1)
+			.map(|i| (i % 251) as u8 + 1)
+			.collect::<Vec<_>>();
+		let obj = s.create().await.unwrap();
+		assert_eq!(obj.try_write(0, &data).await.unwrap(), Ok(data.len()));
+		assert_eq!(obj.try_write(1000, &data).await.unwrap(), Ok(data.len()));
+
+		let buf = &mut vec![0; 1000 + data.len()];
+		obj.read(0, buf).await.unwrap();
+		assert_eq!(&buf[..1000], &data[..1000]);
+		assert_eq!(&buf[1000..], &data[..]);
+
+		// Writes past the end of the object fail without writing anything.
+		let max = s.obj_max_len();
+		assert_eq!(
+			obj.try_write(max - 1, &data).await.unwrap(),
+			Err(LengthTooLong)
+		);
+		assert_eq!(
+			obj.try_write(u64::MAX, &[1]).await.unwrap(),
+			Err(LengthTooLong)
+		);
+		assert_eq!(obj.try_write(max - 1, &[1]).await.unwrap(), Ok(1));
+
+		Ok(())
+	});
+}
+
 #[test]
 fn finish_transaction() {
 	let s = new(MaxRecordSize::K1);
@@ -245,7 +635,13 @@ fn header_data() {
 		devices,
 		magic: *b"TEST",
 		cache_size: 0,
+		soft_cache_size: None,
+		hard_cache_size: None,
+		cache_policy: Default::default(),
 		allow_repair: false,
+		scrub_only: false,
+		nonce_safety_margin: 0,
+		lazy_object_bitmap: false,
 		retrieve_key: &mut |_| todo!(),
 	}))
 	.unwrap();
@@ -265,6 +661,8 @@ fn smaller_blocksize() {
 		cipher: CipherType::NoneXxh3,
 		key_deriver: KeyDeriver::None { key: &[0; 32] },
 		cache_size: 1 << 10,
+		soft_cache_size: None,
+		hard_cache_size: None,
 	}))
 	.unwrap();
 	let devices = block_on(s.unmount()).unwrap();
@@ -273,10 +671,324 @@ fn smaller_blocksize() {
 		resource: StdResource::new(),
 		devices,
 		cache_size: 1 << 10,
+		soft_cache_size: None,
+		hard_cache_size: None,
+		cache_policy: Default::default(),
+		allow_repair: false,
+		scrub_only: false,
+		nonce_safety_margin: 0,
+		lazy_object_bitmap: false,
+		retrieve_key: &mut |_| unreachable!(),
+	}))
+	.unwrap();
+}
+
+#[test]
+fn copy_from() {
+	let s = new_cap(MaxRecordSize::K1, 64, 1 << 16);
+	run(&s, async {
+		let data = (0..8 << 10)
+			.map(|i| (i % 251) as u8 + 1)
+			.collect::<Vec<_>>();
+		let a = s.create().await.unwrap();
+		a.write(0, &data).await.unwrap();
+
+		// Same offset inside records, so the middle records are copied directly.
+		let b = s.create().await.unwrap();
+		assert_eq!(b.copy_from(&a, 1000, 3048, 5000).await.unwrap(), 5000);
+		let buf = &mut vec![0; 9 << 10];
+		b.read(0, buf).await.unwrap();
+		assert!(buf[..3048].iter().all(|&b| b == 0));
+		assert_eq!(&buf[3048..8048], &data[1000..6000]);
+		assert!(buf[8048..].iter().all(|&b| b == 0));
+
+		// Different offsets inside records, so everything is copied through a buffer.
+		let c = s.create().await.unwrap();
+		assert_eq!(c.copy_from(&a, 100, 1, 5000).await.unwrap(), 5000);
+		let buf = &mut vec![0; 5002];
+		c.read(0, buf).await.unwrap();
+		assert_eq!(buf[0], 0);
+		assert_eq!(&buf[1..5001], &data[100..5100]);
+		assert_eq!(buf[5001], 0);
+
+		Ok(())
+	});
+}
+
+#[test]
+fn copy_range() {
+	let s = new_cap(MaxRecordSize::K1, 64, 1 << 16);
+	run(&s, async {
+		let data = (0..8 << 10)
+			.map(|i| (i % 251) as u8 + 1)
+			.collect::<Vec<_>>();
+		let a = s.create().await.unwrap();
+		a.write(0, &data).await.unwrap();
+
+		// Aligned and unaligned copies to another object.
+		let b = s.create().await.unwrap();
+		assert_eq!(b.copy_range(&a, 1024, 2048, 4096).await.unwrap(), 4096);
+		assert_eq!(b.copy_range(&a, 7, 9000, 3000).await.unwrap(), 3000);
+		let buf = &mut vec![0; 12 << 10];
+		b.read(0, buf).await.unwrap();
+		assert!(buf[..2048].iter().all(|&b| b == 0));
+		assert_eq!(&buf[2048..6144], &data[1024..5120]);
+		assert!(buf[6144..9000].iter().all(|&b| b == 0));
+		assert_eq!(&buf[9000..12000], &data[7..3007]);
+
+		// Overlapping copy to a higher offset.
+		let c = s.create().await.unwrap();
+		c.write(0, &data).await.unwrap();
+		assert_eq!(c.copy_range(&c, 100, 1500, 5000).await.unwrap(), 5000);
+		let buf = &mut vec![0; 8 << 10];
+		c.read(0, buf).await.unwrap();
+		assert_eq!(&buf[..1500], &data[..1500]);
+		assert_eq!(&buf[1500..6500], &data[100..5100]);
+		assert_eq!(&buf[6500..], &data[6500..]);
+
+		// Overlapping copy to a lower offset.
+		let d = s.create().await.unwrap();
+		d.write(0, &data).await.unwrap();
+		assert_eq!(d.copy_range(&d, 1500, 100, 5000).await.unwrap(), 5000);
+		let buf = &mut vec![0; 8 << 10];
+		d.read(0, buf).await.unwrap();
+		assert_eq!(&buf[..100], &data[..100]);
+		assert_eq!(&buf[100..5100], &data[1500..6500]);
+		assert_eq!(&buf[5100..], &data[5100..]);
+
+		Ok(())
+	});
+}
+
+/// Copy a device to a new device with a different amount of blocks.
+fn resize_dev(dev: &MemDev, blocks: usize) -> MemDev {
+	block_on(async {
+		let bs = dev.block_size();
+		let new = MemDev::new(blocks, bs);
+		let len = usize::try_from(dev.block_count()).unwrap().min(blocks) << bs.to_raw();
+		let data = dev.read(0, len).await.unwrap();
+		let mut buf = new.allocator().alloc(len).await.unwrap();
+		buf.get_mut().copy_from_slice(data.get());
+		new.write(0, buf).await.unwrap();
+		new
+	})
+}
+
+fn load_dev(dev: MemDev) -> Result<Nros<MemDev, StdResource>, Error<MemDev>> {
+	block_on(Nros::load(LoadConfig {
+		magic: *b"TEST",
+		resource: StdResource::new(),
+		devices: vec![dev],
+		cache_size: 1 << 12,
+		soft_cache_size: None,
+		hard_cache_size: None,
+		cache_policy: Default::default(),
 		allow_repair: false,
+		scrub_only: false,
+		nonce_safety_margin: 0,
+		lazy_object_bitmap: false,
 		retrieve_key: &mut |_| unreachable!(),
 	}))
+}
+
+/// Objects must be allocated in the same order whether the object bitmap is loaded lazily or not.
+#[test]
+fn lazy_object_bitmap() {
+	let s = new_cap(MaxRecordSize::K1, 64, 1 << 16);
+	run(&s, async {
+		let objs = s.create_many(64).await.unwrap();
+		for obj in objs.iter().filter(|o| o.id() % 3 != 0) {
+			obj.dealloc().await.unwrap();
+		}
+		Ok(())
+	});
+	let dev = block_on(s.unmount()).unwrap().pop().unwrap();
+
+	let alloc_ids = |dev: MemDev, lazy_object_bitmap: bool| {
+		let s = block_on(Nros::load(LoadConfig {
+			magic: *b"TEST",
+			resource: StdResource::new(),
+			devices: vec![dev],
+			cache_size: 1 << 16,
+			soft_cache_size: None,
+			hard_cache_size: None,
+			cache_policy: Default::default(),
+			allow_repair: false,
+			scrub_only: false,
+			nonce_safety_margin: 0,
+			lazy_object_bitmap,
+			retrieve_key: &mut |_| unreachable!(),
+		}))
+		.unwrap();
+		let mut ids = vec![];
+		run(&s, async {
+			for _ in 0..50 {
+				ids.push(s.create().await.unwrap().id());
+			}
+			assert!(s.get(63).read(0, &mut [0]).await.is_ok());
+			assert!(matches!(
+				s.get(200).read(0, &mut [0]).await,
+				Err(Error::InvalidObject { id: 200 })
+			));
+			Ok(())
+		});
+		ids
+	};
+	let eager = alloc_ids(resize_dev(&dev, 64), false);
+	let lazy = alloc_ids(dev, true);
+	assert_eq!(eager, lazy);
+	assert_eq!(eager[..4], [1, 2, 4, 5]);
+}
+
+#[test]
+fn load_shrunk_device() {
+	let s = new(MaxRecordSize::K1);
+	let dev = block_on(s.unmount()).unwrap().pop().unwrap();
+	let Err(e) = load_dev(resize_dev(&dev, 31)) else { panic!("shrunk device loaded") };
+	assert!(
+		matches!(
+			e,
+			Error::GeometryMismatch { device: 0, expected: 32, actual: 31 }
+		),
+		"{:?}",
+		e
+	);
+}
+
+#[test]
+fn new_block_size_too_large() {
+	let res = block_on(Nros::new(NewConfig {
+		magic: *b"TEST",
+		resource: StdResource::new(),
+		mirrors: vec![vec![
+			MemDev::new(32, BlockSize::K1),
+			MemDev::new(32, BlockSize::K4),
+		]],
+		block_size: BlockSize::K1,
+		max_record_size: MaxRecordSize::K1,
+		compression: Compression::None,
+		cipher: CipherType::NoneXxh3,
+		key_deriver: KeyDeriver::None { key: &[0; 32] },
+		cache_size: 1 << 12,
+		soft_cache_size: None,
+		hard_cache_size: None,
+	}));
+	let Err(e) = res else { panic!("device with larger block size accepted") };
+	assert!(
+		matches!(
+			e,
+			Error::BlockSizeTooLarge {
+				device: 1,
+				device_block_size: BlockSize::K4,
+				block_size: BlockSize::K1,
+			}
+		),
+		"{:?}",
+		e
+	);
+}
+
+#[test]
+fn new_device_too_small() {
+	let res = block_on(Nros::new(NewConfig {
+		magic: *b"TEST",
+		resource: StdResource::new(),
+		mirrors: vec![vec![MemDev::new(1, BlockSize::K1)]],
+		block_size: BlockSize::K4,
+		max_record_size: MaxRecordSize::K4,
+		compression: Compression::None,
+		cipher: CipherType::NoneXxh3,
+		key_deriver: KeyDeriver::None { key: &[0; 32] },
+		cache_size: 1 << 14,
+		soft_cache_size: None,
+		hard_cache_size: None,
+	}));
+	let Err(e) = res else { panic!("too small device accepted") };
+	// Two headers and one record of 4 KiB, in 1 KiB blocks.
+	assert!(
+		matches!(
+			e,
+			Error::DeviceTooSmall { device: 0, blocks: 1, required: 12 }
+		),
+		"{:?}",
+		e
+	);
+}
+
+#[test]
+fn load_block_size_too_large() {
+	let s = new(MaxRecordSize::K1);
+	let dev = block_on(s.unmount()).unwrap().pop().unwrap();
+
+	// Copy the image to a device with 4K blocks.
+	let new = MemDev::new(8, BlockSize::K4);
+	block_on(async {
+		let data = dev.read(0, 32 << 10).await.unwrap();
+		let mut buf = new.allocator().alloc(32 << 10).await.unwrap();
+		buf.get_mut().copy_from_slice(data.get());
+		new.write(0, buf).await.unwrap();
+	});
+
+	let Err(e) = load_dev(new) else { panic!("device with larger block size loaded") };
+	assert!(
+		matches!(
+			e,
+			Error::BlockSizeTooLarge {
+				device: 0,
+				device_block_size: BlockSize::K4,
+				block_size: BlockSize::K1,
+			}
+		),
+		"{:?}",
+		e
+	);
+}
+
+#[test]
+fn load_grown_device() {
+	let s = new(MaxRecordSize::K1);
+	let id = block_on(s.run(async {
+		let obj = s.create().await.unwrap();
+		obj.write(0, &[1; 3000]).await.unwrap();
+		Ok::<_, Error<_>>(obj.id())
+	}))
 	.unwrap();
+	let dev = block_on(s.unmount()).unwrap().pop().unwrap();
+
+	// Extra blocks are ignored.
+	let s = load_dev(resize_dev(&dev, 64)).unwrap();
+	assert_eq!(s.statistics().storage.allocation.total_blocks, 30);
+	run(&s, async {
+		let buf = &mut [0; 3000];
+		s.get(id).read(0, buf).await.unwrap();
+		assert_eq!(buf, &[1; 3000]);
+		Ok(())
+	});
+}
+
+#[test]
+fn abort_discards_uncommitted() {
+	let s = new(MaxRecordSize::K1);
+	let id = block_on(s.run(async {
+		let obj = s.create().await.unwrap();
+		obj.write(0, &[1; 3000]).await.unwrap();
+		s.finish_transaction().await.unwrap();
+		obj.write(1000, &[2; 3000]).await.unwrap();
+		s.create().await.unwrap();
+		Ok::<_, Error<_>>(obj.id())
+	}))
+	.unwrap();
+	let dev = s.abort().pop().unwrap();
+
+	let s = load_dev(dev).unwrap();
+	assert_eq!(s.inode_stats().0, 1);
+	run(&s, async {
+		let buf = &mut [0; 4000];
+		s.get(id).read(0, buf).await.unwrap();
+		assert_eq!(&buf[..3000], &[1; 3000]);
+		assert_eq!(&buf[3000..], &[0; 1000]);
+		Ok(())
+	});
 }
 
 #[test]
@@ -296,3 +1008,142 @@ fn write_many() {
 		Ok(())
 	});
 }
+
+#[test]
+fn inode_stats() {
+	let s = new(MaxRecordSize::K1);
+	run(&s, async {
+		let (used, free) = s.inode_stats();
+		assert_eq!(used, 0);
+		// 2**(7*3) records with 2**5 objects per record.
+		assert_eq!(free, 1 << 26);
+
+		let objs = s.create_many(10).await.unwrap();
+		assert_eq!(s.inode_stats(), (used + 10, free - 10));
+
+		objs[0].dealloc().await.unwrap();
+		assert_eq!(s.inode_stats(), (used + 9, free - 9));
+		Ok(())
+	});
+}
+
+#[cfg(not(feature = "zstd"))]
+#[test]
+fn new_unsupported_compression() {
+	let s = Nros::new(NewConfig {
+		magic: *b"TEST",
+		resource: StdResource::new(),
+		mirrors: vec![vec![MemDev::new(32, BlockSize::K1)]],
+		block_size: BlockSize::K1,
+		max_record_size: MaxRecordSize::K1,
+		compression: Compression::Zstd,
+		cipher: CipherType::NoneXxh3,
+		key_deriver: KeyDeriver::None { key: &[0; 32] },
+		cache_size: 4096,
+		soft_cache_size: None,
+		hard_cache_size: None,
+	});
+	let Err(e) = block_on(s) else { panic!("created without zstd support") };
+	assert!(
+		matches!(e, Error::UnsupportedCompression { compression: 2 }),
+		"{:?}",
+		e
+	);
+}
+
+#[cfg(not(feature = "zstd"))]
+#[test]
+fn load_unsupported_compression() {
+	let s = new(MaxRecordSize::K1);
+	s.set_default_compression(Compression::Zstd);
+	let dev = block_on(s.unmount()).unwrap().pop().unwrap();
+	let Err(e) = load_dev(dev) else { panic!("loaded without zstd support") };
+	assert!(
+		matches!(e, Error::UnsupportedCompression { compression: 2 }),
+		"{:?}",
+		e
+	);
+}
+
+#[cfg(not(feature = "encryption"))]
+#[test]
+fn new_unsupported_cipher() {
+	let s = Nros::new(NewConfig {
+		magic: *b"TEST",
+		resource: StdResource::new(),
+		mirrors: vec![vec![MemDev::new(32, BlockSize::K1)]],
+		block_size: BlockSize::K1,
+		max_record_size: MaxRecordSize::K1,
+		compression: Compression::None,
+		cipher: CipherType::XChaCha12Poly1305,
+		key_deriver: KeyDeriver::None { key: &[0; 32] },
+		cache_size: 4096,
+		soft_cache_size: None,
+		hard_cache_size: None,
+	});
+	let Err(e) = block_on(s) else { panic!("created without encryption support") };
+	assert!(
+		matches!(e, Error::UnsupportedCipher { cipher: 1 }),
+		"{:?}",
+		e
+	);
+}
+
+#[cfg(not(feature = "encryption"))]
+#[test]
+fn load_unsupported_cipher() {
+	let s = new(MaxRecordSize::K1);
+	let dev = block_on(s.unmount()).unwrap().pop().unwrap();
+	let s = load_dev(dev).unwrap();
+	let dev = block_on(s.unmount()).unwrap().pop().unwrap();
+
+	// Pretend the store is encrypted with XChaCha12Poly1305.
+	block_on(async {
+		let data = dev.read(0, 1 << 10).await.unwrap();
+		let mut b = dev.allocator().alloc(1 << 10).await.unwrap();
+		b.get_mut().copy_from_slice(data.get());
+		b.get_mut()[6] = CipherType::XChaCha12Poly1305.to_raw();
+		dev.write(0, b).await.unwrap();
+	});
+
+	let Err(e) = load_dev(dev) else { panic!("loaded without encryption support") };
+	assert!(
+		matches!(e, Error::UnsupportedCipher { cipher: 1 }),
+		"{:?}",
+		e
+	);
+}
+
+/// Running out of space while finishing a transaction must not lose any dirty entries,
+/// so the transaction can be finished after freeing space.
+#[test]
+fn not_enough_space_retry() {
+	let s = new_cap(MaxRecordSize::K1, 48, 1 << 16);
+	let data = (0..1 << 14).map(|i| (i % 251) as u8 | 1).collect::<Vec<_>>();
+	let id = block_on(s.run(async {
+		let a = s.create().await.unwrap();
+		let b = s.create().await.unwrap();
+		a.write(0, &data).await.unwrap();
+		b.write(0, &data).await.unwrap();
+		let e = s.finish_transaction().await.unwrap_err();
+		assert!(matches!(e, Error::NotEnoughSpace), "{:?}", e);
+
+		a.dealloc().await.unwrap();
+		s.finish_transaction().await.unwrap();
+
+		let buf = &mut vec![0; data.len()];
+		b.read(0, buf).await.unwrap();
+		assert!(buf == &data, "data mismatch");
+		Ok::<_, Error<_>>(b.id())
+	}))
+	.unwrap();
+
+	let dev = block_on(s.unmount()).unwrap().pop().unwrap();
+	let s = load_dev(dev).unwrap();
+	run(&s, async {
+		let buf = &mut vec![0; data.len()];
+		s.get(id).read(0, buf).await.unwrap();
+		assert!(buf == &data, "data mismatch");
+		Ok(())
+	});
+}
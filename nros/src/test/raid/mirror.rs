@@ -1,4 +1,7 @@
-use super::*;
+use super::{
+	super::{dev::SpyDev, new_cfg},
+	*,
+};
 
 /// Just create and save a filesystem with two devices.
 #[test]
@@ -69,6 +72,355 @@ fn write_corrupt_read_2() {
 	});
 }
 
+/// Add a mirror to an object store with one device,
+/// then check if data can be read from it with the original device wiped.
+#[test]
+fn add_mirror_1() {
+	block_on(async {
+		let mut s = new(vec![vec![dev::MemDev::new(1 << 5, BlockSize::K1)]]).await;
+
+		let obj = s.create().await.unwrap();
+		obj.write(0, &[1; 1 << 12]).await.unwrap();
+		drop(obj);
+
+		s.add_mirror(vec![dev::MemDev::new(1 << 5, BlockSize::K1)])
+			.await
+			.unwrap();
+		let devs = s.unmount().await.unwrap();
+		assert_eq!(devs.len(), 2);
+
+		// Wipe the original device except headers.
+		let mut buf = devs[0]
+			.allocator()
+			.alloc(((1 << 5) - 2) << 10)
+			.await
+			.unwrap();
+		buf.get_mut().fill(9);
+		devs[0].write(1, buf).await.unwrap();
+
+		let s = load(devs).await;
+		let buf = &mut [0; 1 << 12];
+		s.get(0).read(0, buf).await.unwrap();
+		assert_eq!(buf, &mut [1; 1 << 12]);
+	});
+}
+
+#[test]
+fn add_mirror_too_many() {
+	block_on(async {
+		let dev = || vec![dev::MemDev::new(1 << 5, BlockSize::K1)];
+		let mut s = new(vec![dev(), dev(), dev()]).await;
+		s.add_mirror(dev()).await.unwrap();
+		let e = s.add_mirror(dev()).await.unwrap_err();
+		assert!(matches!(e, Error::TooManyMirrors), "{:?}", e);
+	});
+}
+
+/// A mirror that fails to receive all data must not be added.
+#[test]
+fn add_mirror_fail() {
+	block_on(async {
+		let mut s = Nros::new(new_cfg(vec![vec![SpyDev::new(1 << 5)]]))
+			.await
+			.unwrap();
+
+		let obj = s.create().await.unwrap();
+		obj.write(0, &[1; 1 << 12]).await.unwrap();
+		drop(obj);
+
+		let broken = SpyDev { broken: true, ..SpyDev::new(1 << 5) };
+		let e = s.add_mirror(vec![broken]).await.unwrap_err();
+		assert!(matches!(e, Error::Dev(_)), "{:?}", e);
+
+		// The original chain is still usable on its own.
+		let obj = s.create().await.unwrap();
+		obj.write(0, &[2; 1 << 12]).await.unwrap();
+		drop(obj);
+		let devs = s.unmount().await.unwrap();
+		assert_eq!(devs.len(), 1);
+	});
+}
+
+/// Check if repairs are deferred to the end of the transaction and merged in fewer writes.
+#[test]
+fn repair_batch_2() {
+	block_on(async {
+		let mut s = Nros::new(NewConfig {
+			cache_size: 1 << 18,
+			..new_cfg(vec![vec![SpyDev::new(1 << 9)], vec![SpyDev::new(1 << 9)]])
+		})
+		.await
+		.unwrap();
+
+		// 64 records
+		let data = (0..1 << 16)
+			.map(|i| (i >> 10) as u8 | 1)
+			.collect::<Vec<_>>();
+		let obj = s.create().await.unwrap();
+		obj.write(0, &data).await.unwrap();
+		drop(obj);
+
+		for i in 0..2 {
+			let devs = s.unmount().await.unwrap();
+
+			// Wipe a device except headers.
+			let mut buf = devs[i]
+				.allocator()
+				.alloc(((1 << 9) - 2) << 10)
+				.await
+				.unwrap();
+			buf.get_mut().fill(9);
+			devs[i].write(1, buf).await.unwrap();
+
+			let spy = devs[i].spy.clone();
+			s = Nros::load(LoadConfig {
+				magic: *b"TEST",
+				resource: StdResource::new(),
+				devices: devs,
+				cache_size: 1 << 18,
+				soft_cache_size: None,
+				hard_cache_size: None,
+				cache_policy: Default::default(),
+				retrieve_key: &mut |_| unreachable!(),
+				allow_repair: true,
+				scrub_only: false,
+				nonce_safety_margin: 0,
+				lazy_object_bitmap: false,
+			})
+			.await
+			.unwrap();
+
+			spy.writes.set(0);
+
+			let obj = s.get(0);
+			let buf = &mut vec![0; 1 << 16];
+			obj.read(0, buf).await.unwrap();
+			assert_eq!(buf, &data);
+			drop(obj);
+
+			// Nothing should have been written yet.
+			assert_eq!(spy.writes.get(), 0);
+
+			s.finish_transaction().await.unwrap();
+
+			// Include some margin for the headers and allocation log.
+			assert!(spy.writes.get() < 16, "{} writes", spy.writes.get());
+		}
+	});
+}
+
+/// Check if broken records are counted but not repaired in scrub mode.
+#[test]
+fn scrub_only_2() {
+	block_on(async {
+		let s = Nros::new(NewConfig {
+			cache_size: 1 << 18,
+			..new_cfg(vec![vec![SpyDev::new(1 << 9)], vec![SpyDev::new(1 << 9)]])
+		})
+		.await
+		.unwrap();
+
+		// 64 records
+		let data = (0..1 << 16)
+			.map(|i| (i >> 10) as u8 | 1)
+			.collect::<Vec<_>>();
+		let obj = s.create().await.unwrap();
+		obj.write(0, &data).await.unwrap();
+		drop(obj);
+
+		let devs = s.unmount().await.unwrap();
+
+		// Wipe a device except headers.
+		let mut buf = devs[0]
+			.allocator()
+			.alloc(((1 << 9) - 2) << 10)
+			.await
+			.unwrap();
+		buf.get_mut().fill(9);
+		devs[0].write(1, buf).await.unwrap();
+
+		let spy = devs[0].spy.clone();
+		let s = Nros::load(LoadConfig {
+			magic: *b"TEST",
+			resource: StdResource::new(),
+			devices: devs,
+			cache_size: 1 << 18,
+			soft_cache_size: None,
+			hard_cache_size: None,
+			cache_policy: Default::default(),
+			retrieve_key: &mut |_| unreachable!(),
+			allow_repair: true,
+			scrub_only: true,
+			nonce_safety_margin: 0,
+			lazy_object_bitmap: false,
+		})
+		.await
+		.unwrap();
+
+		spy.writes.set(0);
+
+		let obj = s.get(0);
+		let buf = &mut vec![0; 1 << 16];
+		obj.read(0, buf).await.unwrap();
+		assert_eq!(buf, &data);
+		drop(obj);
+
+		s.finish_transaction().await.unwrap();
+
+		assert!(s.statistics().storage.chain_mismatches > 0);
+		assert_eq!(spy.writes.get(), 0, "broken mirror was modified");
+	});
+}
+
+/// Check if scrubbing finds and repairs broken records.
+#[test]
+fn scrub_2() {
+	block_on(async {
+		let s = Nros::new(NewConfig {
+			cache_size: 1 << 18,
+			..new_cfg(vec![vec![SpyDev::new(1 << 9)], vec![SpyDev::new(1 << 9)]])
+		})
+		.await
+		.unwrap();
+
+		// 64 records
+		let data = (0..1 << 16)
+			.map(|i| (i >> 10) as u8 | 1)
+			.collect::<Vec<_>>();
+		let obj = s.create().await.unwrap();
+		obj.write(0, &data).await.unwrap();
+		drop(obj);
+
+		let report = s.scrub().await.unwrap();
+		assert!(report.records_checked >= 64, "{:?}", report);
+		assert_eq!(report.records_repaired, 0);
+		assert_eq!(report.records_unrecoverable, 0);
+		let checked = report.records_checked;
+
+		let devs = s.unmount().await.unwrap();
+
+		// Wipe a device except headers.
+		let mut buf = devs[0]
+			.allocator()
+			.alloc(((1 << 9) - 2) << 10)
+			.await
+			.unwrap();
+		buf.get_mut().fill(9);
+		devs[0].write(1, buf).await.unwrap();
+
+		// Broken records are found but not repaired.
+		let s = Nros::load(LoadConfig {
+			magic: *b"TEST",
+			resource: StdResource::new(),
+			devices: devs,
+			cache_size: 1 << 18,
+			soft_cache_size: None,
+			hard_cache_size: None,
+			cache_policy: Default::default(),
+			retrieve_key: &mut |_| unreachable!(),
+			allow_repair: false,
+			scrub_only: false,
+			nonce_safety_margin: 0,
+			lazy_object_bitmap: false,
+		})
+		.await
+		.unwrap();
+		let report = s.scrub().await.unwrap();
+		assert_eq!(report.records_checked, checked);
+		assert_eq!(report.records_repaired, 0);
+		assert_eq!(report.records_unrecoverable, 0);
+		assert!(s.statistics().storage.chain_mismatches >= checked);
+		let devs = s.unmount().await.unwrap();
+
+		// Records read while loading are repaired before scrubbing starts,
+		// but all the object's records must be repaired by the scrub.
+		let s = Nros::load(LoadConfig {
+			magic: *b"TEST",
+			resource: StdResource::new(),
+			devices: devs,
+			cache_size: 1 << 18,
+			soft_cache_size: None,
+			hard_cache_size: None,
+			cache_policy: Default::default(),
+			retrieve_key: &mut |_| unreachable!(),
+			allow_repair: true,
+			scrub_only: false,
+			nonce_safety_margin: 0,
+			lazy_object_bitmap: false,
+		})
+		.await
+		.unwrap();
+		let report = s.scrub().await.unwrap();
+		assert_eq!(report.records_checked, checked);
+		assert!(report.records_repaired >= 64, "{:?}", report);
+		assert_eq!(report.records_unrecoverable, 0);
+
+		// Everything has been repaired.
+		let report = s.scrub().await.unwrap();
+		assert_eq!(report.records_checked, checked);
+		assert_eq!(report.records_repaired, 0);
+		assert_eq!(report.records_unrecoverable, 0);
+
+		let obj = s.get(0);
+		let buf = &mut vec![0; 1 << 16];
+		obj.read(0, buf).await.unwrap();
+		assert_eq!(buf, &data);
+	});
+}
+
+/// Check if devices of object stores with different record sizes are rejected.
+#[test]
+fn inconsistent_config_2() {
+	block_on(async {
+		let mut devs = vec![];
+		for max_record_size in [MaxRecordSize::K1, MaxRecordSize::K4] {
+			let s = Nros::new(NewConfig {
+				magic: *b"TEST",
+				resource: StdResource::new(),
+				mirrors: vec![
+					vec![dev::MemDev::new(1 << 5, BlockSize::K1)],
+					vec![dev::MemDev::new(1 << 5, BlockSize::K1)],
+				],
+				block_size: BlockSize::K1,
+				max_record_size,
+				compression: Compression::None,
+				cipher: CipherType::NoneXxh3,
+				key_deriver: KeyDeriver::None { key: &[0; 32] },
+				cache_size: 1 << 14,
+				soft_cache_size: None,
+				hard_cache_size: None,
+			})
+			.await
+			.unwrap();
+			devs.push(s.unmount().await.unwrap());
+		}
+
+		// Take the first mirror of one store and the second of the other.
+		let devices = vec![devs[0].remove(0), devs[1].remove(1)];
+		let res = Nros::load(LoadConfig {
+			magic: *b"TEST",
+			resource: StdResource::new(),
+			devices,
+			cache_size: 1 << 14,
+			soft_cache_size: None,
+			hard_cache_size: None,
+			cache_policy: Default::default(),
+			retrieve_key: &mut |_| unreachable!(),
+			allow_repair: true,
+			scrub_only: false,
+			nonce_safety_margin: 0,
+			lazy_object_bitmap: false,
+		})
+		.await;
+		let Err(e) = res else { panic!("loaded inconsistent devices") };
+		assert!(
+			matches!(e, Error::InconsistentConfig { device: 1 }),
+			"{:?}",
+			e
+		);
+	});
+}
+
 /* FIXME scan for tail header
 /// Corrupt the start headers only.
 #[test]
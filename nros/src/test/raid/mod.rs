@@ -20,6 +20,8 @@ async fn new(mirrors: Vec<Vec<dev::MemDev>>) -> Nros<dev::MemDev, StdResource> {
 		cipher: CipherType::NoneXxh3,
 		key_deriver: KeyDeriver::None { key: &[0; 32] },
 		cache_size: 1 << 14,
+		soft_cache_size: None,
+		hard_cache_size: None,
 	})
 	.await
 	.unwrap()
@@ -31,8 +33,14 @@ async fn load(devices: Vec<dev::MemDev>) -> Nros<dev::MemDev, StdResource> {
 		resource: StdResource::new(),
 		devices,
 		cache_size: 1 << 14,
+		soft_cache_size: None,
+		hard_cache_size: None,
+		cache_policy: Default::default(),
 		retrieve_key: &mut |_| unreachable!(),
 		allow_repair: true,
+		scrub_only: false,
+		nonce_safety_margin: 0,
+		lazy_object_bitmap: false,
 	})
 	.await
 	.unwrap()
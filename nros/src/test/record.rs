@@ -4,11 +4,11 @@ use {
 };
 
 macro_rules! t {
-	($mod:ident $comp:ident $($cipher_mod:ident $cipher:ident)*) => {
+	($mod:ident $comp:ident $($(#[$attr:meta])* $cipher_mod:ident $cipher:ident)*) => {
 		mod $mod {
 			use super::*;
 
-			$(mod $cipher_mod {
+			$($(#[$attr])* mod $cipher_mod {
 				use super::*;
 
 				fn cipher() -> Cipher {
@@ -50,5 +50,82 @@ macro_rules! t {
 	};
 }
 
-t!(none None none_xxh3 NoneXxh3 xchacha12_poly1305 XChaCha12Poly1305);
-t!(lz4 Lz4 none_xxh3 NoneXxh3 xchacha12_poly1305 XChaCha12Poly1305);
+t!(
+	none None
+	none_xxh3 NoneXxh3
+	#[cfg(feature = "encryption")]
+	xchacha12_poly1305 XChaCha12Poly1305
+	#[cfg(feature = "encryption")]
+	aes256_gcm Aes256Gcm
+);
+t!(
+	lz4 Lz4
+	none_xxh3 NoneXxh3
+	#[cfg(feature = "encryption")]
+	xchacha12_poly1305 XChaCha12Poly1305
+	#[cfg(feature = "encryption")]
+	aes256_gcm Aes256Gcm
+);
+#[cfg(feature = "zstd")]
+t!(
+	zstd Zstd
+	none_xxh3 NoneXxh3
+	#[cfg(feature = "encryption")]
+	xchacha12_poly1305 XChaCha12Poly1305
+	#[cfg(feature = "encryption")]
+	aes256_gcm Aes256Gcm
+);
+
+#[cfg(feature = "zstd")]
+mod zstd_round_trip {
+	use super::*;
+
+	/// Returns the size of the packed record in bytes.
+	fn round_trip(data: &[u8], max_record_size: MaxRecordSize) -> usize {
+		let cipher = Cipher { key1: [0xcc; 32], key2: [0xdd; 32], ty: CipherType::NoneXxh3 };
+		let b = &mut vec![0; data.len() * 2 + 512];
+		let blks = record::pack(
+			data,
+			b,
+			Compression::Zstd,
+			BlockSize::B512,
+			cipher,
+			&[0; 24],
+		);
+		let b = &mut b[..usize::from(blks) << BlockSize::B512.to_raw()];
+
+		let res = StdResource::new();
+		let d = record::unpack(b, res.alloc(), max_record_size, cipher).unwrap();
+		assert_eq!(data, &*d);
+		b.len()
+	}
+
+	fn random(len: usize) -> Vec<u8> {
+		let mut x = 0x2545_f491_4f6c_dd1du64;
+		(0..len)
+			.map(|_| {
+				x ^= x << 13;
+				x ^= x >> 7;
+				x ^= x << 17;
+				x as u8
+			})
+			.collect()
+	}
+
+	#[test]
+	fn compressible() {
+		for mrs in [MaxRecordSize::K1, MaxRecordSize::K4, MaxRecordSize::K64] {
+			let len = 1 << mrs.to_raw();
+			let data = (0..len).map(|i| (i / 100) as u8).collect::<Vec<_>>();
+			assert!(round_trip(&data, mrs) < len, "{:?} not compressed", mrs);
+		}
+	}
+
+	#[test]
+	fn incompressible() {
+		for mrs in [MaxRecordSize::K1, MaxRecordSize::K4, MaxRecordSize::K64] {
+			let len = 1 << mrs.to_raw();
+			assert!(round_trip(&random(len), mrs) > len, "{:?}", mrs);
+		}
+	}
+}
@@ -0,0 +1,330 @@
+use {
+	crate::{
+		make::{prompt_new_password, KeyDerivationFunction},
+		Compression, Encryption,
+	},
+	nrfs::{dev::FileDev, Dev, Dir, Item, ItemTy, Nrfs},
+	std::{
+		error::Error,
+		fmt,
+		fs::{self, File, OpenOptions},
+		future::Future,
+		pin::Pin,
+	},
+};
+
+/// Copy a filesystem to new images with different settings.
+///
+/// All data is decompressed when read from the source
+/// and recompressed with the settings of the destination.
+///
+/// The destination uses the same cipher and key or password as the source
+/// unless overridden.
+#[derive(clap::Args)]
+pub struct Clone {
+	/// Paths to the source filesystem's images.
+	#[clap(short, long, required = true)]
+	source: Vec<String>,
+	/// The paths to the images to write the new filesystem to.
+	///
+	/// To define a chain of images, specify paths with a comma inbetween them.
+	#[clap(value_parser = parse_mirrors)]
+	paths: Vec<Vec<Box<str>>>,
+	/// The record size to use.
+	#[clap(short, long, value_parser = 9..=24, default_value_t = 17)]
+	record_size_p2: i64,
+	/// The block size to use.
+	#[clap(short, long, value_parser = 9..=24, default_value_t = 12)]
+	block_size_p2: i64,
+	/// The compression to use.
+	#[clap(short, long, value_enum, default_value = "lz4")]
+	compression: Compression,
+	/// Soft limit on the cache size of each filesystem.
+	#[clap(long, default_value_t = 1 << 27)]
+	cache_size: usize,
+	/// File containing the key to decrypt the source filesystem header with.
+	#[arg(short = 'K', long)]
+	key_file: Option<String>,
+	/// Encryption to use on the new filesystem.
+	///
+	/// If not specified, the cipher of the source filesystem is used.
+	#[clap(short, long, value_enum)]
+	encryption: Option<Encryption>,
+	/// Write an unencrypted copy of an encrypted filesystem.
+	#[clap(long, conflicts_with = "encryption")]
+	decrypt: bool,
+	/// Which algorithm to use to derive the key for encryption.
+	///
+	/// If not specified, the key or password of the source filesystem is reused.
+	///
+	/// Possible values: none, argon2id[,m,t,p].
+	#[arg(long)]
+	key_derivation_function: Option<KeyDerivationFunction>,
+}
+
+fn parse_mirrors(s: &str) -> Result<Vec<Box<str>>, &'static str> {
+	Ok(s.split(',').map(From::from).collect())
+}
+
+pub async fn clone(args: Clone) -> Result<(), Box<dyn Error>> {
+	let key = args
+		.key_file
+		.map(|key_file| {
+			eprintln!("Loading key from {:?}", &key_file);
+			let key = fs::read(&key_file)?;
+			let key: [u8; 32] = key.try_into().map_err(|_| "key is not 32 bytes long")?;
+			Ok::<_, Box<dyn Error>>(key)
+		})
+		.transpose()?;
+
+	// Remember the key or password of the source so it can be reused for the destination.
+	let mut source_key = None;
+	let retrieve_key = &mut |use_password| {
+		let kp = if let Some(key) = key {
+			nrfs::KeyPassword::Key(key)
+		} else if use_password {
+			let pwd = rpassword::prompt_password("Password: ").expect("failed to ask password");
+			nrfs::KeyPassword::Password(pwd.into_bytes())
+		} else {
+			return None;
+		};
+		source_key = Some(match &kp {
+			nrfs::KeyPassword::Key(k) => nrfs::KeyPassword::Key(*k),
+			nrfs::KeyPassword::Password(p) => nrfs::KeyPassword::Password(p.clone()),
+		});
+		Some(kp)
+	};
+
+	let devices = args
+		.source
+		.into_iter()
+		.map(|p| File::open(p).map(FileDev::new))
+		.try_collect()?;
+
+	let conf = nrfs::LoadConfig {
+		retrieve_key,
+		devices,
+		cache_size: args.cache_size,
+		cache_policy: Default::default(),
+		allow_repair: false,
+	};
+	let from = Nrfs::load(conf).await?;
+
+	let block_size = nrfs::BlockSize::from_raw(args.block_size_p2.try_into().unwrap()).unwrap();
+	let max_record_size =
+		nrfs::MaxRecordSize::from_raw(args.record_size_p2.try_into().unwrap()).unwrap();
+
+	let mirrors = args
+		.paths
+		.into_iter()
+		.map(|chain| {
+			chain
+				.into_iter()
+				.map(|path| {
+					OpenOptions::new()
+						.truncate(false)
+						.read(true)
+						.write(true)
+						.open(&*path)
+						.map(FileDev::new)
+				})
+				.try_collect()
+		})
+		.try_collect()?;
+
+	// Never drop encryption unless explicitly asked to.
+	let cipher = match (args.encryption, args.decrypt) {
+		(Some(enc), _) => enc.into(),
+		(None, true) => nrfs::CipherType::NoneXxh3,
+		(None, false) => from.cipher(),
+	};
+
+	let keybuf;
+	let key_deriver = if cipher == nrfs::CipherType::NoneXxh3 {
+		nrfs::KeyDeriver::None { key: &[0; 32] }
+	} else {
+		match (args.key_derivation_function, &source_key) {
+			(Some(KeyDerivationFunction::None) | None, Some(nrfs::KeyPassword::Key(key))) => {
+				nrfs::KeyDeriver::None { key }
+			}
+			(Some(KeyDerivationFunction::None), _) => {
+				return Err("key derivation function 'none' requires a source key file".into())
+			}
+			(Some(KeyDerivationFunction::Argon2id { m, t, p }), _) => {
+				keybuf = prompt_new_password();
+				nrfs::KeyDeriver::Argon2id { password: &keybuf, m, t, p }
+			}
+			(None, source_key) => {
+				let KeyDerivationFunction::Argon2id { m, t, p } = KeyDerivationFunction::default()
+				else { unreachable!() };
+				let password = if let Some(nrfs::KeyPassword::Password(pwd)) = source_key {
+					pwd
+				} else {
+					keybuf = prompt_new_password();
+					&keybuf
+				};
+				nrfs::KeyDeriver::Argon2id { password, m, t, p }
+			}
+		}
+	};
+
+	let config = nrfs::NewConfig {
+		cipher,
+		key_deriver,
+		mirrors,
+		block_size,
+		max_record_size,
+		compression: args.compression.into(),
+		embed_factor: from.embed_factor(),
+		cache_size: args.cache_size,
+	};
+
+	eprintln!("Creating filesystem");
+	let to = Nrfs::new(config).await?;
+	to.set_label(&from.label())?;
+
+	from.run(to.run(async {
+		let buf = &mut vec![0; 1 << max_record_size.to_raw()];
+		let (from_root, to_root) = (from.root_dir(), to.root_dir());
+		to_root.set_modified(from_root.modified().await?).await?;
+		copy_dir(&from, from_root, to_root, buf).await?;
+		to.finish_transaction().await?;
+		Ok::<_, Box<dyn Error>>(())
+	}))
+	.await?;
+
+	to.unmount().await?;
+	Ok(())
+}
+
+async fn copy_dir<D: Dev>(
+	fs: &Nrfs<D>,
+	from: Dir<'_, D>,
+	to: Dir<'_, D>,
+	buf: &mut [u8],
+) -> Result<(), Box<dyn Error>>
+where
+	D::Error: fmt::Debug,
+{
+	let mut i = 0;
+	while let Some((data, next_i)) = from.next_from(i).await? {
+		let item = fs.item(data.key);
+		match data.ty {
+			ItemTy::Dir => {
+				let from_d = fs.dir(data.key).await?;
+				let hasher = from_d.hasher().await?;
+				let d = if from_d.is_case_insensitive().await? {
+					to.create_dir_case_insensitive(&data.name, hasher).await?
+				} else {
+					to.create_dir_with_hasher(&data.name, hasher).await?
+				};
+				let d = d.unwrap();
+				copy_attrs(&item, &d).await?;
+				let fut: Pin<Box<dyn Future<Output = _>>> = Box::pin(copy_dir(fs, from_d, d, buf));
+				fut.await?;
+			}
+			ItemTy::File | ItemTy::EmbedFile | ItemTy::Sym | ItemTy::EmbedSym => {
+				let f = if matches!(data.ty, ItemTy::File | ItemTy::EmbedFile) {
+					to.create_file(&data.name).await?.unwrap().0
+				} else {
+					to.create_sym(&data.name).await?.unwrap()
+				};
+				copy_attrs(&item, &f).await?;
+				let src = fs.file(data.key);
+				let len = src.len().await?;
+				let mut offt = 0;
+				while offt < len {
+					let l = src.read(offt, buf).await?;
+					if l == 0 {
+						break;
+					}
+					f.write_grow(offt, &buf[..l]).await??;
+					offt += u64::try_from(l).unwrap();
+				}
+			}
+		}
+		i = next_i;
+	}
+	// Set the quota last so it can't be exceeded while copying.
+	to.set_quota(from.quota().await?).await?;
+	Ok(())
+}
+
+async fn copy_attrs<D: Dev>(from: &Item<'_, D>, to: &Item<'_, D>) -> Result<(), Box<dyn Error>>
+where
+	D::Error: fmt::Debug,
+{
+	to.set_modified(from.modified().await?).await?;
+	for k in from.attr_keys().await? {
+		let v = from.attr(&k).await?.unwrap();
+		to.set_attr(&k, &v).await??;
+	}
+	Ok(())
+}
+
+#[cfg(test)]
+mod test {
+	use {super::*, nrfs::dev::MemDev};
+
+	fn new(compression: nrfs::Compression) -> Nrfs<MemDev> {
+		futures_executor::block_on(Nrfs::new(nrfs::NewConfig {
+			key_deriver: nrfs::KeyDeriver::None { key: &[0; 32] },
+			cipher: nrfs::CipherType::NoneXxh3,
+			mirrors: vec![vec![MemDev::new(1 << 10, nrfs::BlockSize::K1)]],
+			block_size: nrfs::BlockSize::K1,
+			max_record_size: nrfs::MaxRecordSize::K4,
+			compression,
+			embed_factor: 0,
+			cache_size: 1 << 16,
+		}))
+		.unwrap()
+	}
+
+	/// Data must be recompressed with the settings of the destination
+	/// and contents and attributes must be preserved.
+	#[test]
+	fn lz4_to_none() {
+		let from = new(nrfs::Compression::Lz4);
+		let to = new(nrfs::Compression::None);
+		let data = &[1; 1 << 16];
+
+		futures_executor::block_on(from.run(to.run(async {
+			let root = from.root_dir();
+			let d = root.create_dir(b"dir".into()).await?.unwrap();
+			d.set_attr(b"user.x".into(), b"y").await?.unwrap();
+			let f = d.create_file(b"file".into()).await?.unwrap().0;
+			f.write_grow(0, data).await?.unwrap();
+			f.set_attr(b"user.a".into(), b"b").await?.unwrap();
+			let sym = root.create_sym(b"sym".into()).await?.unwrap();
+			sym.set_link_target(b"dir/file").await?.unwrap();
+			from.finish_transaction().await?;
+
+			let buf = &mut vec![0; 1 << 12];
+			copy_dir(&from, from.root_dir(), to.root_dir(), buf).await?;
+			to.finish_transaction().await?;
+
+			let root = to.root_dir();
+			let info = root.search(b"dir".into()).await?.unwrap();
+			assert!(matches!(info.ty, ItemTy::Dir));
+			let d = to.dir(info.key).await?;
+			assert_eq!(d.attr(b"user.x".into()).await?.as_deref(), Some(&b"y"[..]));
+			let info = d.search(b"file".into()).await?.unwrap();
+			assert!(matches!(info.ty, ItemTy::File));
+			let f = to.file(info.key);
+			assert_eq!(f.attr(b"user.a".into()).await?.as_deref(), Some(&b"b"[..]));
+			assert_eq!(f.len().await?, data.len() as u64);
+			let buf = &mut vec![0; data.len()];
+			assert_eq!(f.read(0, buf).await?, data.len());
+			assert_eq!(&buf[..], &data[..]);
+			let info = root.search(b"sym".into()).await?.unwrap();
+			let target = to.file(info.key).read_link().await?.unwrap();
+			assert_eq!(target, b"dir/file");
+			Ok::<_, Box<dyn Error>>(())
+		})))
+		.unwrap();
+
+		let packed = |fs: &Nrfs<MemDev>| fs.statistics().object_store.storage.packed_bytes_written;
+		// Each 4 KiB record of the source compresses to a single block.
+		assert!(packed(&from) * 2 < packed(&to));
+	}
+}
@@ -0,0 +1,213 @@
+use {
+	nrfs::{dev::FileDev, Dev, ItemTy, Nrfs},
+	std::{
+		error::Error,
+		fs::{self, OpenOptions},
+	},
+};
+
+/// Compact directories and move data towards the start of the filesystem.
+///
+/// Every phase is committed separately,
+/// so interrupting the defragmentation leaves a consistent filesystem.
+#[derive(clap::Args)]
+pub struct Defrag {
+	/// Paths to the filesystem's images.
+	paths: Vec<String>,
+	/// Soft limit on the global cache size.
+	#[clap(long, default_value_t = 1 << 27)]
+	cache_size: usize,
+	/// File containing the key to decrypt the filesystem header with.
+	#[arg(short = 'K', long)]
+	key_file: Option<String>,
+}
+
+/// Results of defragmenting a filesystem.
+#[derive(Debug, Default)]
+struct Report {
+	/// Amount of bytes released by compacting directories.
+	dir_bytes: u64,
+	/// Amount of blocks in use before defragmenting.
+	used_blocks_before: u64,
+	/// Amount of blocks in use after defragmenting.
+	used_blocks_after: u64,
+	/// Amount of separate runs of free blocks before defragmenting.
+	free_regions_before: u64,
+	/// Amount of separate runs of free blocks after defragmenting.
+	free_regions_after: u64,
+	/// The minimum amount of blocks the filesystem can be resized to.
+	min_blocks: u64,
+}
+
+pub async fn defrag(args: Defrag) -> Result<(), Box<dyn Error>> {
+	let key = args
+		.key_file
+		.map(|key_file| {
+			eprintln!("Loading key from {:?}", &key_file);
+			let key = fs::read(&key_file)?;
+			let key: [u8; 32] = key.try_into().map_err(|_| "key is not 32 bytes long")?;
+			Ok::<_, Box<dyn Error>>(key)
+		})
+		.transpose()?;
+
+	let retrieve_key = &mut |use_password| {
+		if let Some(key) = key {
+			Some(nrfs::KeyPassword::Key(key))
+		} else if use_password {
+			let pwd = rpassword::prompt_password("Password: ").expect("failed to ask password");
+			Some(nrfs::KeyPassword::Password(pwd.into_bytes()))
+		} else {
+			None
+		}
+	};
+
+	let devices = args
+		.paths
+		.into_iter()
+		.map(|p| {
+			OpenOptions::new()
+				.create(false)
+				.truncate(false)
+				.read(true)
+				.write(true)
+				.open(&*p)
+				.map(FileDev::new)
+		})
+		.try_collect()?;
+
+	let conf = nrfs::LoadConfig {
+		retrieve_key,
+		devices,
+		cache_size: args.cache_size,
+		cache_policy: Default::default(),
+		allow_repair: true,
+	};
+	let fs = Nrfs::load(conf).await?;
+
+	let report = fs.run(defrag_fs(&fs)).await?;
+	fs.unmount().await?;
+
+	let e = |name: &str, val: &dyn std::fmt::Display| {
+		println!("{}: {:>indent$}", name, val, indent = 34 - name.len())
+	};
+	e("directory bytes released", &report.dir_bytes);
+	e("used blocks before", &report.used_blocks_before);
+	e("used blocks after", &report.used_blocks_after);
+	e("free regions before", &report.free_regions_before);
+	e("free regions after", &report.free_regions_after);
+	e("minimum blocks", &report.min_blocks);
+
+	Ok(())
+}
+
+/// Defragment a filesystem.
+///
+/// 1. Removed items are freed from all directories.
+/// 2. Records are moved towards the start of the filesystem.
+async fn defrag_fs<D: Dev>(fs: &Nrfs<D>) -> Result<Report, nrfs::Error<D>> {
+	let mut report = Report::default();
+	let alloc = fs.statistics().object_store.storage.allocation;
+	report.used_blocks_before = alloc.used_blocks;
+	report.free_regions_before = alloc.free_region_count;
+
+	eprintln!("Compacting directories");
+	let mut dirs = vec![fs.root_dir().key()];
+	while let Some(key) = dirs.pop() {
+		let d = fs.dir(key).await?;
+		report.dir_bytes += d.compact().await?;
+		let mut i = 0;
+		while let Some((data, next_i)) = d.next_from(i).await? {
+			if data.ty == ItemTy::Dir {
+				dirs.push(data.key);
+			}
+			i = next_i;
+		}
+	}
+	fs.finish_transaction().await?;
+
+	eprintln!("Moving data to the start of the filesystem");
+	report.min_blocks = fs.shrink_to_fit().await?;
+
+	let alloc = fs.statistics().object_store.storage.allocation;
+	report.used_blocks_after = alloc.used_blocks;
+	report.free_regions_after = alloc.free_region_count;
+	Ok(report)
+}
+
+#[cfg(test)]
+mod test {
+	use {super::*, nrfs::dev::MemDev};
+
+	#[test]
+	fn defrag() {
+		futures_executor::block_on(async {
+			let fs = Nrfs::new(nrfs::NewConfig {
+				key_deriver: nrfs::KeyDeriver::None { key: &[0; 32] },
+				cipher: nrfs::CipherType::NoneXxh3,
+				mirrors: vec![vec![MemDev::new(1 << 12, nrfs::BlockSize::K1)]],
+				block_size: nrfs::BlockSize::K1,
+				max_record_size: nrfs::MaxRecordSize::K1,
+				compression: nrfs::Compression::None,
+				embed_factor: 0,
+				cache_size: 1 << 12,
+			})
+			.await
+			.unwrap();
+
+			let data = (0..1 << 12).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+			fs.run(async {
+				// Interleave files to keep and files to remove.
+				let root = fs.root_dir();
+				let d = root.create_dir(b"dir".into()).await?.unwrap();
+				let mut remove = vec![];
+				for i in 0..64 {
+					let name = format!("{}", i);
+					let dir = [&root, &d][i % 2];
+					let (f, _) = dir
+						.create_file(name.as_bytes().try_into().unwrap())
+						.await?
+						.unwrap();
+					f.write_grow(0, &data).await?.unwrap();
+					if i % 3 != 0 {
+						remove.push((i % 2, f.key()));
+					}
+				}
+				fs.finish_transaction().await?;
+				for (dir, key) in remove {
+					[&root, &d][dir].remove(key).await?.unwrap();
+				}
+				fs.finish_transaction().await?;
+
+				let report = defrag_fs(&fs).await?;
+				assert!(report.dir_bytes > 0, "{:?}", report);
+				assert!(
+					report.used_blocks_after <= report.used_blocks_before,
+					"{:?}",
+					report
+				);
+				assert!(
+					report.free_regions_after < report.free_regions_before,
+					"{:?}",
+					report
+				);
+				assert!(report.min_blocks < 1 << 12, "{:?}", report);
+
+				assert!(fs.verify(false).await?.is_ok());
+				for i in (0..64).step_by(3) {
+					let name = format!("{}", i);
+					let dir = [&root, &d][i % 2];
+					let f = dir
+						.search(name.as_bytes().try_into().unwrap())
+						.await?
+						.unwrap();
+					let buf = &mut vec![0; data.len()];
+					fs.file(f.key).read(0, buf).await?;
+					assert_eq!(buf, &data);
+				}
+				Ok::<_, nrfs::Error<_>>(())
+			})
+			.await
+			.unwrap();
+		});
+	}
+}
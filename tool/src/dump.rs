@@ -1,9 +1,10 @@
 use crate::unix;
 
 use {
-	nrfs::{ItemTy, Nrfs},
+	nrfs::{Dev, ItemKey, ItemTy, Key, Nrfs},
 	std::{
 		error::Error,
+		fmt,
 		fs::{self, File},
 		future::Future,
 		pin::Pin,
@@ -21,8 +22,17 @@ pub struct Dump {
 	/// File containing the key to decrypt the filesystem header with.
 	#[arg(short = 'K', long)]
 	key_file: Option<String>,
+	/// Print the full target of symbolic links.
+	#[clap(long)]
+	full_links: bool,
+	/// Resolve symbolic links within the filesystem and print what they point to.
+	#[clap(long)]
+	resolve_links: bool,
 }
 
+/// Maximum amount of symbolic links to follow while resolving a single link.
+const MAX_LINK_HOPS: usize = 40;
+
 /// Additional statistics collected while iterating.
 #[derive(Debug, Default)]
 struct Statistics {
@@ -43,6 +53,7 @@ struct Statistics {
 pub async fn dump(args: Dump) -> Result<(), Box<dyn Error>> {
 	let key = args
 		.key_file
+		.as_ref()
 		.map(|key_file| {
 			eprintln!("Loading key from {:?}", &key_file);
 			let key = fs::read(&key_file)?;
@@ -64,7 +75,7 @@ pub async fn dump(args: Dump) -> Result<(), Box<dyn Error>> {
 
 	let devices = args
 		.paths
-		.into_iter()
+		.iter()
 		.map(|p| File::open(p).map(nrfs::dev::FileDev::new))
 		.try_collect()?;
 
@@ -72,6 +83,7 @@ pub async fn dump(args: Dump) -> Result<(), Box<dyn Error>> {
 		retrieve_key,
 		devices,
 		cache_size: args.cache_size,
+		cache_policy: Default::default(),
 		allow_repair: false,
 	};
 	let nrfs = nrfs::Nrfs::load(conf).await?;
@@ -79,7 +91,8 @@ pub async fn dump(args: Dump) -> Result<(), Box<dyn Error>> {
 	let mut stat = Statistics::default();
 
 	nrfs.run(async {
-		list_files(&nrfs, nrfs.root_dir(), &mut stat, 0).await?;
+		let path = &mut vec![(nrfs.root_dir().key(), vec![])];
+		list_files(&nrfs, &args, nrfs.root_dir(), path, &mut stat, 0).await?;
 		Ok::<_, Box<dyn Error>>(())
 	})
 	.await?;
@@ -102,22 +115,36 @@ pub async fn dump(args: Dump) -> Result<(), Box<dyn Error>> {
 	let obj = &stat.object_store;
 	let sto = &obj.storage;
 	let alloc = &sto.allocation;
+	e("label", &String::from_utf8_lossy(&nrfs.label()));
+	e("created", &nrfs.created_at());
 	e("block size", &format!("2**{}", sto.block_size.to_raw()));
 	e(
 		"max record size",
 		&format!("2**{}", sto.max_record_size.to_raw()),
 	);
 	e("compression", &sto.compression);
+	e("embed factor", &nrfs.embed_factor());
 	e("used objects", &obj.used_objects);
+	e("object list depth", &obj.object_list_depth);
 	e("used blocks", &alloc.used_blocks);
 	e("total blocks", &alloc.total_blocks);
+	e("free regions", &alloc.free_region_count);
+	e("largest free run", &alloc.largest_free_run);
+	for (i, &n) in sto.record_blocks.iter().enumerate().filter(|(_, &n)| n > 0) {
+		e(&format!("records of 2**{} blocks", i), &n);
+	}
 
 	Ok(())
 }
 
+/// List all items in a directory and its descendants.
+///
+/// `path` are the keys and names of the directories leading to and including `root`.
 async fn list_files(
 	fs: &Nrfs<nrfs::dev::FileDev>,
+	args: &Dump,
 	root: nrfs::Dir<'_, nrfs::dev::FileDev>,
+	path: &mut Vec<(ItemKey, Vec<u8>)>,
 	stats: &mut Statistics,
 	indent: usize,
 ) -> Result<(), Box<dyn Error>> {
@@ -176,9 +203,17 @@ async fn list_files(
 					name,
 					indent = indent + 4 + 8
 				);
+				// Only a corrupt filesystem can have directories nested this deeply.
+				if path.len() > usize::try_from(fs.max_depth()).unwrap() {
+					eprintln!("{}: nested too deeply, skipping", name);
+					i = next_i;
+					continue;
+				}
+				path.push((data.key, data.name.to_vec()));
 				let fut: Pin<Box<dyn Future<Output = _>>> =
-					Box::pin(list_files(fs, d, stats, indent + 2));
+					Box::pin(list_files(fs, args, d, path, stats, indent + 2));
 				fut.await?;
+				path.pop();
 			}
 			ItemTy::File | ItemTy::EmbedFile => {
 				let f = fs.file(data.key);
@@ -205,12 +240,23 @@ async fn list_files(
 				} else {
 					stats.symlinks += 1;
 				}
-				let len = f.len().await?;
-				let (len, trim_len) = if len > 64 { (61, true) } else { (len, false) };
-				let mut buf = vec![0; len as _];
-				f.read(0, &mut buf).await?;
-				let mut link = String::from_utf8_lossy(&buf);
-				trim_len.then(|| link += "...");
+				let link = if args.full_links || args.resolve_links {
+					let target = f.read_link().await??;
+					let mut link = String::from_utf8_lossy(&target).into_owned();
+					if args.resolve_links {
+						link += " => ";
+						link += &resolve_link(fs, path, &target).await?.to_string();
+					}
+					link
+				} else {
+					let len = f.len().await?;
+					let (len, trim_len) = if len > 64 { (61, true) } else { (len, false) };
+					let mut buf = vec![0; len as _];
+					f.read(0, &mut buf).await?;
+					let mut link = String::from_utf8_lossy(&buf).into_owned();
+					trim_len.then(|| link += "...");
+					link
+				};
 				println!(
 					"{:>12}  {:>indent$} {}s {} -> {}",
 					"",
@@ -228,8 +274,165 @@ async fn list_files(
 	Ok(())
 }
 
+/// The result of resolving a symbolic link.
+#[derive(Debug, PartialEq, Eq)]
+enum Resolved {
+	/// The absolute path of the item the link points to.
+	Found(Vec<u8>),
+	/// The link points to an item that does not exist.
+	NotFound,
+	/// Too many links had to be followed, which likely means there is a loop.
+	Loop,
+	/// A link that had to be followed is not actually a symbolic link,
+	/// which means the filesystem is corrupt.
+	Corrupt,
+}
+
+impl fmt::Display for Resolved {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			Self::Found(p) => bstr::BStr::new(p).fmt(f),
+			Self::NotFound => "(not found)".fmt(f),
+			Self::Loop => "(loop)".fmt(f),
+			Self::Corrupt => "(corrupt link)".fmt(f),
+		}
+	}
+}
+
+/// Resolve the target of a symbolic link within the filesystem.
+///
+/// `path` are the keys and names of the directories leading to the link,
+/// starting with the root directory.
+async fn resolve_link<D: Dev>(
+	fs: &Nrfs<D>,
+	path: &[(ItemKey, Vec<u8>)],
+	target: &[u8],
+) -> Result<Resolved, nrfs::Error<D>> {
+	let mut cur = path.to_vec();
+	// Components that still need to be resolved, in reverse order.
+	let mut todo = Vec::new();
+	let push_target = |cur: &mut Vec<_>, todo: &mut Vec<Vec<u8>>, target: &[u8]| {
+		if target.starts_with(b"/") {
+			cur.truncate(1);
+		}
+		todo.extend(target.split(|&c| c == b'/').rev().map(|c| c.to_vec()));
+	};
+	push_target(&mut cur, &mut todo, target);
+
+	let mut hops = 0;
+	let mut file = None;
+	while let Some(c) = todo.pop() {
+		if file.is_some() {
+			// Only directories can have children.
+			return Ok(Resolved::NotFound);
+		}
+		match &*c {
+			b"" | b"." => continue,
+			b".." => {
+				if cur.len() > 1 {
+					cur.pop();
+				}
+				continue;
+			}
+			_ => {}
+		}
+		let Ok(name) = <&Key>::try_from(&*c) else { return Ok(Resolved::NotFound) };
+		let dir = fs.dir(cur.last().unwrap().0).await?;
+		let Some(info) = dir.search(name).await? else { return Ok(Resolved::NotFound) };
+		match info.ty {
+			ItemTy::Dir => cur.push((info.key, c)),
+			ItemTy::File | ItemTy::EmbedFile => file = Some(c),
+			ItemTy::Sym | ItemTy::EmbedSym => {
+				hops += 1;
+				if hops > MAX_LINK_HOPS {
+					return Ok(Resolved::Loop);
+				}
+				let Ok(target) = fs.file(info.key).read_link().await?
+					else { return Ok(Resolved::Corrupt) };
+				push_target(&mut cur, &mut todo, &target);
+			}
+		}
+	}
+
+	let mut p = Vec::new();
+	for name in cur.iter().skip(1).map(|(_, n)| n).chain(file.as_ref()) {
+		p.push(b'/');
+		p.extend_from_slice(name);
+	}
+	if p.is_empty() {
+		p.push(b'/');
+	}
+	Ok(Resolved::Found(p))
+}
+
 fn decode_u(b: &[u8]) -> u128 {
 	let mut c = [0; 16];
 	c[..b.len()].copy_from_slice(b);
 	u128::from_le_bytes(c)
 }
+
+#[cfg(test)]
+mod test {
+	use {super::*, nrfs::dev::MemDev};
+
+	async fn mksym(
+		dir: &nrfs::Dir<'_, MemDev>,
+		name: &[u8],
+		target: &[u8],
+	) -> Result<(), nrfs::Error<MemDev>> {
+		let sym = dir.create_sym(name.try_into().unwrap()).await?.unwrap();
+		sym.set_link_target(target).await?.unwrap();
+		Ok(())
+	}
+
+	#[test]
+	fn resolve_links() {
+		futures_executor::block_on(async {
+			let fs = Nrfs::new(nrfs::NewConfig {
+				key_deriver: nrfs::KeyDeriver::None { key: &[0; 32] },
+				cipher: nrfs::CipherType::NoneXxh3,
+				mirrors: vec![vec![MemDev::new(1 << 10, nrfs::BlockSize::K1)]],
+				block_size: nrfs::BlockSize::K1,
+				max_record_size: nrfs::MaxRecordSize::K1,
+				compression: nrfs::Compression::None,
+				embed_factor: 0,
+				cache_size: 1 << 12,
+			})
+			.await
+			.unwrap();
+
+			fs.run(async {
+				let root = fs.root_dir();
+				let a = root.create_dir(b"a".into()).await?.unwrap();
+				a.create_file(b"file".into()).await?.unwrap();
+				mksym(&root, b"abs", b"/a/file").await?;
+				mksym(&a, b"up", b"../a/file").await?;
+				mksym(&root, b"loop", b"loop").await?;
+
+				let root_path = &[(root.key(), vec![])][..];
+				let a_path = &[(root.key(), vec![]), (a.key(), b"a".to_vec())][..];
+				let found = |p: &[u8]| Resolved::Found(p.to_vec());
+
+				let r = resolve_link(&fs, root_path, b"a").await?;
+				assert_eq!(r, found(b"/a"));
+				let r = resolve_link(&fs, root_path, b"abs").await?;
+				assert_eq!(r, found(b"/a/file"));
+				let r = resolve_link(&fs, a_path, b"up").await?;
+				assert_eq!(r, found(b"/a/file"));
+				let r = resolve_link(&fs, a_path, b"./../abs").await?;
+				assert_eq!(r, found(b"/a/file"));
+				let r = resolve_link(&fs, a_path, b"/").await?;
+				assert_eq!(r, found(b"/"));
+				let r = resolve_link(&fs, root_path, b"a/nothing").await?;
+				assert_eq!(r, Resolved::NotFound);
+				let r = resolve_link(&fs, root_path, b"abs/file").await?;
+				assert_eq!(r, Resolved::NotFound);
+				let r = resolve_link(&fs, root_path, b"loop").await?;
+				assert_eq!(r, Resolved::Loop);
+				Ok::<_, nrfs::Error<_>>(())
+			})
+			.await
+			.unwrap();
+		});
+	}
+}
@@ -35,7 +35,13 @@ pub async fn extract_key(args: ExtractKey) -> Result<(), Box<dyn Error>> {
 		retrieve_key,
 		devices,
 		cache_size: 0,
+		soft_cache_size: None,
+		hard_cache_size: None,
+		cache_policy: Default::default(),
 		allow_repair: false,
+		scrub_only: false,
+		nonce_safety_margin: 0,
+		lazy_object_bitmap: false,
 		magic: *b"NRFS",
 		resource: nros::StdResource::new(),
 	};
@@ -1,10 +1,13 @@
 #![deny(unused_must_use, rust_2018_idioms)]
 #![feature(const_option, iterator_try_collect)]
 
+mod clone;
+mod defrag;
 mod dump;
 mod extract_key;
 mod make;
 mod resize;
+mod verify_key;
 
 use clap::{builder::PossibleValue, Parser};
 
@@ -19,12 +22,16 @@ enum Command {
 	ExtractKey(extract_key::ExtractKey),
 	Dump(dump::Dump),
 	Resize(resize::Resize),
+	Clone(clone::Clone),
+	Defrag(defrag::Defrag),
+	VerifyKey(verify_key::VerifyKey),
 }
 
 #[derive(Clone, Copy, Debug, clap::ValueEnum)]
 enum Compression {
 	None,
 	Lz4,
+	Zstd,
 }
 
 impl From<Compression> for nrfs::Compression {
@@ -32,6 +39,7 @@ impl From<Compression> for nrfs::Compression {
 		match compression {
 			Compression::None => nrfs::Compression::None,
 			Compression::Lz4 => nrfs::Compression::Lz4,
+			Compression::Zstd => nrfs::Compression::Zstd,
 		}
 	}
 }
@@ -39,16 +47,27 @@ impl From<Compression> for nrfs::Compression {
 #[derive(Clone, Debug)]
 enum Encryption {
 	XChacha12Poly1305,
+	Aes256Gcm,
+}
+
+impl From<Encryption> for nrfs::CipherType {
+	fn from(encryption: Encryption) -> Self {
+		match encryption {
+			Encryption::XChacha12Poly1305 => nrfs::CipherType::XChaCha12Poly1305,
+			Encryption::Aes256Gcm => nrfs::CipherType::Aes256Gcm,
+		}
+	}
 }
 
 impl clap::ValueEnum for Encryption {
 	fn value_variants<'a>() -> &'a [Self] {
-		&[Self::XChacha12Poly1305]
+		&[Self::XChacha12Poly1305, Self::Aes256Gcm]
 	}
 
 	fn to_possible_value(&self) -> Option<PossibleValue> {
 		Some(match self {
 			Self::XChacha12Poly1305 => PossibleValue::new("xchacha12poly1305"),
+			Self::Aes256Gcm => PossibleValue::new("aes256gcm"),
 		})
 	}
 }
@@ -71,6 +90,9 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
 			Command::ExtractKey(args) => extract_key::extract_key(args).await,
 			Command::Dump(args) => dump::dump(args).await,
 			Command::Resize(args) => resize::resize(args).await,
+			Command::Clone(args) => clone::clone(args).await,
+			Command::Defrag(args) => defrag::defrag(args).await,
+			Command::VerifyKey(args) => verify_key::verify_key(args).await,
 		}
 	};
 	futures_executor::block_on(fut)
@@ -54,6 +54,9 @@ pub struct Make {
 	/// * Mirror of chains with mixed devices: `a.img,b.img c.img`
 	#[clap(value_parser = parse_mirrors)]
 	paths: Vec<Vec<Box<str>>>,
+	/// Label to identify the filesystem with.
+	#[clap(short, long)]
+	label: Option<String>,
 	/// The directory to copy to the image.
 	#[clap(short, long)]
 	directory: Option<PathBuf>,
@@ -69,6 +72,12 @@ pub struct Make {
 	/// The compression to use.
 	#[clap(short, long, value_enum, default_value = "lz4")]
 	compression: Compression,
+	/// How many multiples of the block size a file may be before its data is moved
+	/// out of its directory.
+	///
+	/// If 0, the default is used. At most 128.
+	#[clap(long, value_parser = clap::value_parser!(u8).range(..=128), default_value_t = 0)]
+	embed_factor: u8,
 	/// Encryption to use on the filesystem.
 	#[clap(short, long, value_enum)]
 	encryption: Option<Encryption>,
@@ -90,11 +99,19 @@ pub struct Make {
 }
 
 #[derive(Clone)]
-enum KeyDerivationFunction {
+pub(crate) enum KeyDerivationFunction {
 	None,
 	Argon2id { m: NonZeroU32, t: NonZeroU32, p: NonZeroU8 },
 }
 
+impl Default for KeyDerivationFunction {
+	fn default() -> Self {
+		use defaults::argon2id::*;
+		let p = u8::try_from(num_cpus::get()).unwrap_or(0);
+		Self::Argon2id { m: M, t: T, p: NonZeroU8::new(p).unwrap_or(P) }
+	}
+}
+
 impl FromStr for KeyDerivationFunction {
 	type Err = &'static str;
 
@@ -102,10 +119,7 @@ impl FromStr for KeyDerivationFunction {
 		use defaults::argon2id::*;
 		Ok(match s {
 			"none" => KeyDerivationFunction::None,
-			"argon2id" => {
-				let p = u8::try_from(num_cpus::get()).unwrap_or(0);
-				Self::Argon2id { m: M, t: T, p: NonZeroU8::new(p).unwrap_or(P) }
-			}
+			"argon2id" => Self::default(),
 			_ if s.starts_with("argon2id,") => {
 				let mut it = s.split(',').skip(1);
 				let m = it.next().ok_or("expected 'm' argument")?;
@@ -142,6 +156,17 @@ impl FromStr for KeyDerivationFunction {
 	}
 }
 
+/// Ask for a new password twice and exit if they don't match.
+pub(crate) fn prompt_new_password() -> Vec<u8> {
+	let pwd_a = rpassword::prompt_password("Enter new password: ").expect("failed to ask password");
+	let pwd_b = rpassword::prompt_password("Confirm password: ").expect("failed to ask password");
+	if pwd_a != pwd_b {
+		eprintln!("Passwords do not match");
+		std::process::exit(1);
+	}
+	pwd_a.into_bytes()
+}
+
 fn parse_mirrors(s: &str) -> Result<Vec<Box<str>>, &'static str> {
 	Ok(s.split(',').map(From::from).collect())
 }
@@ -150,6 +175,11 @@ pub async fn make(args: Make) -> Result<(), Box<dyn Error>> {
 	let block_size = nrfs::BlockSize::from_raw(args.block_size_p2.try_into().unwrap()).unwrap();
 	let max_record_size =
 		nrfs::MaxRecordSize::from_raw(args.record_size_p2.try_into().unwrap()).unwrap();
+	let compression = nrfs::Compression::from(args.compression);
+	if !compression.is_supported() {
+		eprintln!("{} compression is not enabled in this build", compression);
+		std::process::exit(1);
+	}
 
 	let mirrors = args
 		.paths
@@ -173,25 +203,14 @@ pub async fn make(args: Make) -> Result<(), Box<dyn Error>> {
 
 	let keybuf;
 	let (cipher, key_deriver) = if let Some(enc) = args.encryption {
-		let enc = match enc {
-			Encryption::XChacha12Poly1305 => nrfs::CipherType::XChaCha12Poly1305,
-		};
 		let kdf = match args.key_derivation_function {
 			KeyDerivationFunction::None => todo!("ask for file"),
 			KeyDerivationFunction::Argon2id { m, t, p } => {
-				let pwd_a = rpassword::prompt_password("Enter new password: ")
-					.expect("failed to ask password");
-				let pwd_b = rpassword::prompt_password("Confirm password: ")
-					.expect("failed to ask password");
-				if pwd_a != pwd_b {
-					eprintln!("Passwords do not match");
-					std::process::exit(1);
-				}
-				keybuf = pwd_a.into_bytes();
+				keybuf = prompt_new_password();
 				nrfs::KeyDeriver::Argon2id { password: &keybuf, m, t, p }
 			}
 		};
-		(enc, kdf)
+		(enc.into(), kdf)
 	} else {
 		(
 			nrfs::CipherType::NoneXxh3,
@@ -205,12 +224,16 @@ pub async fn make(args: Make) -> Result<(), Box<dyn Error>> {
 		mirrors,
 		block_size,
 		max_record_size,
-		compression: args.compression.into(),
+		compression,
+		embed_factor: args.embed_factor,
 		cache_size: args.cache_size,
 	};
 
 	eprintln!("Creating filesystem");
 	let nrfs = nrfs::Nrfs::new(config).await?;
+	if let Some(label) = &args.label {
+		nrfs.set_label(label.as_bytes())?;
+	}
 
 	nrfs.run(async {
 		let buf = &mut vec![0; 1 << max_record_size.to_raw()];
@@ -259,7 +282,7 @@ async fn add_files(
 
 		if m.is_file() || (m.is_symlink() && follow_symlinks) {
 			let mut inp = fs::File::open(f.path())?;
-			let f = root.create_file(n).await?.unwrap();
+			let (f, _) = root.create_file(n).await?.unwrap();
 			setattr(&f, &m).await?;
 			let mut offt = 0;
 			loop {
@@ -281,7 +304,7 @@ async fn add_files(
 			let c = fs::read_link(f.path())?;
 			let f = root.create_sym(n).await?.unwrap();
 			setattr(&f, &m).await?;
-			f.write_grow(0, c.to_str().unwrap().as_bytes()).await??;
+			f.set_link_target(c.to_str().unwrap().as_bytes()).await??;
 		} else {
 			todo!()
 		}
@@ -14,6 +14,11 @@ pub struct Resize {
 	/// File containing the key to decrypt the filesystem header with.
 	#[arg(short = 'K', long)]
 	key_file: Option<String>,
+	/// Move data towards the start of the filesystem first.
+	///
+	/// This is necessary if data is stored beyond the new end.
+	#[arg(short, long)]
+	shrink: bool,
 }
 
 pub async fn resize(args: Resize) -> Result<(), Box<dyn Error>> {
@@ -52,10 +57,26 @@ pub async fn resize(args: Resize) -> Result<(), Box<dyn Error>> {
 		})
 		.try_collect()?;
 
-	let conf = nrfs::LoadConfig { retrieve_key, devices, cache_size: 0, allow_repair: false };
+	let conf = nrfs::LoadConfig {
+		retrieve_key,
+		devices,
+		cache_size: 0,
+		cache_policy: Default::default(),
+		allow_repair: false,
+	};
 	let fs = nrfs::Nrfs::load(conf).await?;
 
-	fs.set_block_count(args.blocks).await?;
-	fs.finish_transaction().await?;
-	Ok(())
+	fs.run(async {
+		if args.shrink {
+			let min = fs.shrink_to_fit().await?;
+			eprintln!("Filesystem needs at least {} blocks", min);
+			if min > args.blocks {
+				return Err("filesystem does not fit in new size".into());
+			}
+		}
+		fs.set_block_count(args.blocks).await?;
+		fs.finish_transaction().await?;
+		Ok::<_, Box<dyn Error>>(())
+	})
+	.await
 }
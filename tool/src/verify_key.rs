@@ -0,0 +1,53 @@
+use std::{
+	error::Error,
+	fs::{self, File},
+};
+
+/// Check whether a key or password can decrypt the filesystem header.
+///
+/// The filesystem is not mounted.
+#[derive(clap::Args)]
+pub struct VerifyKey {
+	/// Paths to the filesystem's images.
+	paths: Vec<String>,
+	/// File containing the key to decrypt the filesystem header with.
+	#[arg(short = 'K', long)]
+	key_file: Option<String>,
+}
+
+pub async fn verify_key(args: VerifyKey) -> Result<(), Box<dyn Error>> {
+	let key = args
+		.key_file
+		.map(|key_file| {
+			eprintln!("Loading key from {:?}", &key_file);
+			let key = fs::read(&key_file)?;
+			let key: [u8; 32] = key.try_into().map_err(|_| "key is not 32 bytes long")?;
+			Ok::<_, Box<dyn Error>>(key)
+		})
+		.transpose()?;
+
+	let retrieve_key = &mut |use_password| {
+		if let Some(key) = key {
+			Some(nrfs::KeyPassword::Key(key))
+		} else if use_password {
+			let pwd = rpassword::prompt_password("Password: ").expect("failed to ask password");
+			Some(nrfs::KeyPassword::Password(pwd.into_bytes()))
+		} else {
+			None
+		}
+	};
+
+	let devices = args
+		.paths
+		.into_iter()
+		.map(|p| File::open(p).map(nrfs::dev::FileDev::new))
+		.try_collect::<Vec<_>>()?;
+
+	if nrfs::Nrfs::verify_key(&devices, retrieve_key).await? {
+		eprintln!("Key is valid");
+		Ok(())
+	} else {
+		eprintln!("Key is invalid");
+		std::process::exit(1);
+	}
+}
@@ -1,8 +1,8 @@
 use {
-	super::waker_queue::{self, WakerQueue},
+	super::waker_queue::{WakerQueue, WakerQueueTicket},
 	crate::BTreeMapExt,
 	alloc::collections::BTreeMap,
-	core::{cell::UnsafeCell, mem::MaybeUninit},
+	core::{cell::UnsafeCell, future, mem::MaybeUninit, task::Poll},
 };
 
 /// A set of locks.
@@ -10,6 +10,11 @@ use {
 /// Each lock can be acquired inclusively or can be exclusive,
 /// in which case no other locks may be held.
 ///
+/// Locks are granted in FIFO order:
+/// a task never acquires a lock before another task that started waiting for it earlier.
+/// Hence a stream of exclusive locks cannot starve a task waiting for an inclusive lock
+/// and vice versa.
+///
 /// This may be more memory-efficient if only a few locks are held for many structures at any time.
 #[derive(Debug)]
 pub struct LockSet<K> {
@@ -19,60 +24,131 @@ pub struct LockSet<K> {
 
 #[derive(Default)]
 struct Lock {
-	/// The amount of readers or (sole) writer.
-	///
-	/// If the high bit is set, a task is attempting to acquire an exclusive lock.
+	/// The amount of readers or `-1` if held by a writer.
 	count: isize,
-	/// Tasks waiting for the lock to be released.
-	queue: WakerQueue<()>,
+	/// The amount of tasks that have been woken but haven't acquired the lock yet.
+	woken: usize,
+	/// Tasks waiting for the lock to be released, in order of arrival.
+	///
+	/// The value indicates whether the task is waiting for an exclusive lock.
+	queue: WakerQueue<bool>,
+}
+
+impl Lock {
+	/// Whether other tasks are waiting for the lock.
+	///
+	/// If so, a new task may not acquire the lock even if it is free.
+	fn is_contended(&self) -> bool {
+		self.woken > 0 || self.queue.get_next().is_some()
+	}
+
+	/// Whether no task holds or is waiting for the lock.
+	fn is_idle(&self) -> bool {
+		self.count == 0 && !self.is_contended()
+	}
+
+	/// Wake the task at the front of the queue.
+	///
+	/// If it is waiting for an inclusive lock,
+	/// all following tasks waiting for an inclusive lock are woken too.
+	fn wake_front(&mut self) {
+		debug_assert_eq!(self.count, 0, "lock is still held");
+		let Some(exclusive) = self.queue.get_next().map(|n| *n.value()) else { return };
+		if exclusive {
+			self.queue.wake_next();
+			self.woken += 1;
+		} else {
+			while self.queue.get_next().is_some_and(|n| !*n.value()) {
+				self.queue.wake_next();
+				self.woken += 1;
+			}
+		}
+	}
 }
 
 impl<K: Ord + Eq + Clone> LockSet<K> {
 	/// Acquire an inclusive lock.
 	pub async fn lock_inclusive(&self, key: K) -> LockSetInclusiveGuard<'_, K> {
-		let mut immediate = true;
-		waker_queue::poll(|cx| {
-			// SAFETY:
-			// * The mutable reference will be dropped at the end of this function
-			// * We won't acquire another mutable reference.
-			let locks = unsafe { &mut *self.locks.get() };
-			let lock = locks.entry(key.clone()).or_insert_with(Default::default);
-			// Give priority to exclusive locks.
-			if lock.count & isize::MIN != 0 {
-				immediate = false;
-				Err(lock.queue.push(cx.waker().clone(), ()))
-			} else {
-				// Mark as having acquired the lock.
-				lock.count += 1;
-				Ok(())
-			}
-		})
-		.await;
+		let immediate = self.acquire(&key, false).await;
 		LockSetInclusiveGuard { lock_set: self, key: MaybeUninit::new(key), immediate }
 	}
 
 	/// Acquire an exclusive lock.
 	pub async fn lock_exclusive(&self, key: K) -> LockSetExclusiveGuard<'_, K> {
+		let immediate = self.acquire(&key, true).await;
+		LockSetExclusiveGuard { lock_set: self, key: MaybeUninit::new(key), immediate }
+	}
+
+	/// Wait until a lock can be acquired and mark it as acquired.
+	///
+	/// Returns whether the lock was acquired immediately.
+	async fn acquire(&self, key: &K, exclusive: bool) -> bool {
+		let mut waiter = Waiter { lock_set: self, key, ticket: None };
 		let mut immediate = true;
-		waker_queue::poll(|cx| {
+		future::poll_fn(|cx| {
+			if let Some(ticket) = &mut waiter.ticket {
+				if ticket.set_waker(cx.waker()) {
+					return Poll::Pending;
+				}
+			}
 			// SAFETY:
 			// * The mutable reference will be dropped at the end of this function
 			// * We won't acquire another mutable reference.
 			let locks = unsafe { &mut *self.locks.get() };
 			let lock = locks.entry(key.clone()).or_insert_with(Default::default);
-			if lock.count & isize::MAX != 0 {
+			// If we have been woken it is our turn.
+			let woken = waiter.ticket.take().is_some();
+			if woken {
+				lock.woken -= 1;
+			}
+			let held = if exclusive { lock.count != 0 } else { lock.count < 0 };
+			if held || (!woken && lock.is_contended()) {
 				immediate = false;
-				// Mark as attempting to acquire the lock.
-				lock.count |= isize::MIN;
-				Err(lock.queue.push(cx.waker().clone(), ()))
+				waiter.ticket = Some(lock.queue.push(cx.waker().clone(), exclusive));
+				Poll::Pending
 			} else {
 				// Mark as having acquired the lock.
-				lock.count = isize::MIN | 1;
-				Ok(())
+				lock.count = if exclusive { -1 } else { lock.count + 1 };
+				Poll::Ready(())
 			}
 		})
 		.await;
-		LockSetExclusiveGuard { lock_set: self, key: MaybeUninit::new(key), immediate }
+		immediate
+	}
+}
+
+/// A task waiting for a lock.
+///
+/// If the task is dropped before acquiring the lock,
+/// it is removed from the queue or, if it has been woken already,
+/// its turn is passed on to the next task.
+struct Waiter<'a, K: Ord + Eq + Clone> {
+	lock_set: &'a LockSet<K>,
+	key: &'a K,
+	/// The position of the task in the queue, if it is waiting.
+	ticket: Option<WakerQueueTicket<bool>>,
+}
+
+impl<K: Ord + Eq + Clone> Drop for Waiter<'_, K> {
+	fn drop(&mut self) {
+		let Some(ticket) = self.ticket.take() else { return };
+
+		// SAFETY:
+		// * The mutable reference will be dropped at the end of this function
+		// * We won't acquire another mutable reference.
+		let locks = unsafe { &mut *self.lock_set.locks.get() };
+		let mut lock = locks.occupied(self.key.clone()).expect("no lock entry");
+
+		let l = lock.get_mut();
+		if !l.queue.remove(&ticket) {
+			l.woken -= 1;
+		}
+		if l.count == 0 && l.woken == 0 {
+			l.wake_front();
+		}
+		if l.is_idle() {
+			lock.remove();
+		}
 	}
 }
 
@@ -113,11 +189,14 @@ impl<K: Ord + Eq> Drop for LockSetInclusiveGuard<'_, K> {
 		let mut lock = locks.occupied(key).expect("no lock entry");
 
 		let l = lock.get_mut();
-		debug_assert!(l.count & isize::MAX > 0, "lock not inclusively acquired");
+		debug_assert!(l.count > 0, "lock not inclusively acquired");
 
 		l.count -= 1;
-		if l.count & isize::MAX == 0 && l.queue.wake_next().is_none() {
-			// No other *sleeping* task is or will attempt to hold this lock, so free space.
+		if l.count == 0 {
+			l.wake_front();
+		}
+		if l.is_idle() {
+			// No other task is or will attempt to hold this lock, so free space.
 			lock.remove();
 		}
 	}
@@ -153,14 +232,12 @@ impl<K: Ord + Eq> Drop for LockSetExclusiveGuard<'_, K> {
 		let mut lock = locks.occupied(key).expect("no lock entry");
 
 		let l = lock.get_mut();
-		debug_assert!(l.count & isize::MIN != 0, "lock not exclusively acquired");
+		debug_assert_eq!(l.count, -1, "lock not exclusively acquired");
 
-		l.count -= 1;
-		l.count &= isize::MAX;
+		l.count = 0;
+		l.wake_front();
 
-		l.queue.wake_all();
-
-		if l.count == 0 {
+		if l.is_idle() {
 			lock.remove();
 		}
 	}
@@ -170,10 +247,11 @@ impl<K: Ord + Eq> Drop for LockSetExclusiveGuard<'_, K> {
 mod test {
 	use {
 		super::*,
+		alloc::boxed::Box,
 		core::{
 			future::Future,
 			pin::{pin, Pin},
-			task::{Context, Poll},
+			task::Context,
 		},
 	};
 
@@ -268,4 +346,88 @@ mod test {
 		drop(b);
 		assert!(poll(c.as_mut()).is_ready());
 	}
+
+	/// Locks must be granted in the order they were requested.
+	#[test]
+	fn lock_fifo() {
+		let s = LockSet::default();
+		let Poll::Ready(a) = poll(pin!(s.lock_exclusive(0))) else { panic!() };
+		let mut b = pin!(s.lock_inclusive(0));
+		let mut c = pin!(s.lock_exclusive(0));
+		let mut d = pin!(s.lock_inclusive(0));
+		let mut e = pin!(s.lock_inclusive(0));
+		assert!(poll(b.as_mut()).is_pending());
+		assert!(poll(c.as_mut()).is_pending());
+		assert!(poll(d.as_mut()).is_pending());
+		assert!(poll(e.as_mut()).is_pending());
+
+		drop(a);
+		let Poll::Ready(b) = poll(b.as_mut()) else { panic!("b not acquired") };
+		assert!(poll(d.as_mut()).is_pending(), "d overtook c");
+		assert!(poll(c.as_mut()).is_pending());
+
+		drop(b);
+		let Poll::Ready(c) = poll(c.as_mut()) else { panic!("c not acquired") };
+		assert!(poll(e.as_mut()).is_pending());
+
+		drop(c);
+		let Poll::Ready(e) = poll(e.as_mut()) else { panic!("e not acquired") };
+		let Poll::Ready(d) = poll(d.as_mut()) else { panic!("d not acquired") };
+		drop((d, e));
+		assert!(poll(pin!(s.lock_exclusive(0))).is_ready());
+	}
+
+	/// A task that stops waiting may not block tasks behind it.
+	#[test]
+	fn lock_drop_queued() {
+		let s = LockSet::default();
+		let Poll::Ready(a) = poll(pin!(s.lock_exclusive(0))) else { panic!() };
+		let mut b = Box::pin(s.lock_exclusive(0));
+		assert!(poll(b.as_mut()).is_pending());
+		let mut c = pin!(s.lock_inclusive(0));
+		assert!(poll(c.as_mut()).is_pending());
+		drop(b);
+		drop(a);
+		let Poll::Ready(c) = poll(c.as_mut()) else { panic!("c not acquired") };
+		drop(c);
+		assert!(poll(pin!(s.lock_exclusive(0))).is_ready());
+	}
+
+	/// A task that is dropped after being woken must pass on its turn.
+	#[test]
+	fn lock_drop_woken() {
+		let s = LockSet::default();
+		let Poll::Ready(a) = poll(pin!(s.lock_exclusive(0))) else { panic!() };
+		let mut b = Box::pin(s.lock_exclusive(0));
+		assert!(poll(b.as_mut()).is_pending());
+		let mut c = pin!(s.lock_exclusive(0));
+		assert!(poll(c.as_mut()).is_pending());
+		drop(a);
+		drop(b);
+		let Poll::Ready(c) = poll(c.as_mut()) else { panic!("c not acquired") };
+		drop(c);
+
+		let Poll::Ready(a) = poll(pin!(s.lock_exclusive(0))) else { panic!() };
+		let mut b = Box::pin(s.lock_exclusive(0));
+		assert!(poll(b.as_mut()).is_pending());
+		drop(a);
+		drop(b);
+		assert!(poll(pin!(s.lock_exclusive(0))).is_ready());
+	}
+
+	/// A stream of exclusive locks may not starve a task waiting for an inclusive lock.
+	#[test]
+	fn lock_exclusive_no_starve() {
+		let s = LockSet::default();
+		let Poll::Ready(a) = poll(pin!(s.lock_exclusive(0))) else { panic!() };
+		let mut b = pin!(s.lock_inclusive(0));
+		assert!(poll(b.as_mut()).is_pending());
+		drop(a);
+		// A new task may not barge in before the woken task had a chance to run.
+		let mut c = pin!(s.lock_exclusive(0));
+		assert!(poll(c.as_mut()).is_pending());
+		let Poll::Ready(b) = poll(b.as_mut()) else { panic!("b not acquired") };
+		drop(b);
+		assert!(poll(c.as_mut()).is_ready());
+	}
 }
@@ -87,6 +87,32 @@ impl<V> WakerQueue<V> {
 		Some(head.value)
 	}
 
+	/// Remove the node of a ticket from the queue without waking it.
+	///
+	/// Returns `false` if the node is no longer in the queue, i.e. it has been woken.
+	pub fn remove(&mut self, ticket: &WakerQueueTicket<V>) -> bool {
+		let Some(node) = ticket.node.upgrade() else { return false };
+		let mut prev = None::<Rc<Node<V>>>;
+		let mut cur = self.head.clone();
+		while let Some(n) = cur {
+			let next = n.next.take();
+			if Rc::ptr_eq(&n, &node) {
+				if next.is_none() {
+					self.tail = prev.as_ref().map_or_else(Weak::new, Rc::downgrade);
+				}
+				match &prev {
+					Some(p) => p.next.set(next),
+					None => self.head = next,
+				}
+				return true;
+			}
+			n.next.set(next.clone());
+			prev = Some(n);
+			cur = next;
+		}
+		false
+	}
+
 	pub fn wake_all(&mut self) {
 		while let Some(head) = self.head.take() {
 			let head = Rc::try_unwrap(head).unwrap_or_else(|_| panic!("more than one strong ref"));
@@ -113,7 +139,7 @@ pub struct WakerQueueTicket<V> {
 impl<V> WakerQueueTicket<V> {
 	/// Returns `false` on failure.
 	#[must_use = "node may no longer be in queue"]
-	fn set_waker(&mut self, waker: &Waker) -> bool {
+	pub(crate) fn set_waker(&mut self, waker: &Waker) -> bool {
 		if let Some(node) = self.node.upgrade() {
 			node.waker.set(Some(waker.clone()));
 			true
@@ -166,4 +192,20 @@ mod test {
 		let _ = q.push(noop_waker(), ());
 		q.wake_next().unwrap();
 	}
+
+	#[test]
+	fn push_remove() {
+		let mut q = WakerQueue::default();
+		let a = q.push(noop_waker(), 0);
+		let b = q.push(noop_waker(), 1);
+		let c = q.push(noop_waker(), 2);
+		assert!(q.remove(&c));
+		assert!(q.remove(&a));
+		assert!(!q.remove(&a));
+		let _ = q.push(noop_waker(), 3);
+		assert_eq!(q.wake_next(), Some(1));
+		drop(b);
+		assert_eq!(q.wake_next(), Some(3));
+		assert!(q.wake_next().is_none());
+	}
 }